@@ -4,7 +4,8 @@ use crate::error::Error;
 use crate::levelorder::{level_order_traverse, LevelOrder};
 use crate::nodemap::{category_predicate, Attributes, Namespaces};
 use crate::output::NamespaceDeclarations;
-use crate::xmlvalue::{Value, ValueCategory, ValueType};
+use crate::uri;
+use crate::xmlvalue::{DocumentType, Value, ValueCategory, ValueType};
 use crate::xotdata::{Node, Xot};
 use crate::{NameId, NamespaceId, PrefixId, Prefixes};
 
@@ -354,7 +355,7 @@ impl Xot {
     ///
     /// assert_eq!(attributes.get(a), Some(&"A".to_string()));
     /// ```
-    pub fn attributes(&self, node: Node) -> Attributes {
+    pub fn attributes(&self, node: Node) -> Attributes<'_> {
         Attributes::new(self, node)
     }
 
@@ -384,7 +385,7 @@ impl Xot {
     ///
     /// assert_eq!(namespaces.get(foo_prefix), Some(&foo_ns));
     /// ```
-    pub fn namespaces(&self, node: Node) -> Namespaces {
+    pub fn namespaces(&self, node: Node) -> Namespaces<'_> {
         Namespaces::new(self, node)
     }
 
@@ -946,10 +947,166 @@ impl Xot {
     /// Get (element) node in a document node that has an xml:id attribute of
     /// given value.
     ///
+    /// Attributes declared with type `ID` in an `<!ATTLIST>` declaration in the
+    /// internal DTD subset of a parsed document are also taken into account.
+    ///
+    /// The ids are kept up to date when you manipulate the document. If you
+    /// change an attribute value through a mutable reference, for instance
+    /// with [`Xot::value_mut`], the ids are not updated; use
+    /// [`Xot::rebuild_xml_ids`] in that case.
+    ///
     /// If that id does not exist, returns [`None`].
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    /// let xml_id = xot.add_name_ns("id", xot.xml_namespace());
+    /// let root = xot.parse(r#"<doc><a xml:id="x"/><b/></doc>"#)?;
+    /// let doc_el = xot.document_element(root)?;
+    /// let a = xot.first_child(doc_el).unwrap();
+    /// let b = xot.next_sibling(a).unwrap();
+    ///
+    /// assert_eq!(xot.xml_id_node(root, "x"), Some(a));
+    ///
+    /// xot.set_attribute(b, xml_id, "y");
+    /// assert_eq!(xot.xml_id_node(root, "y"), Some(b));
+    ///
+    /// xot.remove(a)?;
+    /// assert_eq!(xot.xml_id_node(root, "x"), None);
+    /// # Ok::<(), xot::Error>(())
+    /// ```
     pub fn xml_id_node(&self, document_node: Node, value: &str) -> Option<Node> {
-        let value_nodes = self.id_nodes_map.get(&document_node.get())?;
-        value_nodes.get(value).map(|node_id| Node::new(*node_id))
+        let xml_ids = self.id_nodes_map.get(&document_node.get())?;
+        xml_ids.get(value).map(Node::new)
+    }
+
+    /// Get the ids in a document node that are used by more than one element.
+    ///
+    /// A parsed document never has duplicate ids, but they can be introduced
+    /// by manipulating the tree. Each duplicate id is returned with the
+    /// elements that have it. The first element is the one that
+    /// [`Xot::xml_id_node`] returns. The ids are sorted.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    /// let xml_id = xot.add_name_ns("id", xot.xml_namespace());
+    /// let root = xot.parse(r#"<doc><a xml:id="x"/><b/></doc>"#)?;
+    /// let doc_el = xot.document_element(root)?;
+    /// let a = xot.first_child(doc_el).unwrap();
+    /// let b = xot.next_sibling(a).unwrap();
+    ///
+    /// xot.set_attribute(b, xml_id, "x");
+    ///
+    /// assert_eq!(xot.duplicate_xml_ids(root), vec![("x".to_string(), vec![a, b])]);
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn duplicate_xml_ids(&self, document_node: Node) -> Vec<(String, Vec<Node>)> {
+        let Some(xml_ids) = self.id_nodes_map.get(&document_node.get()) else {
+            return Vec::new();
+        };
+        let mut duplicates = xml_ids
+            .duplicates()
+            .map(|(value, node_ids)| {
+                (
+                    value.to_string(),
+                    node_ids.iter().map(|node_id| Node::new(*node_id)).collect(),
+                )
+            })
+            .collect::<Vec<_>>();
+        duplicates.sort_by(|(a, _), (b, _)| a.cmp(b));
+        duplicates
+    }
+
+    /// Get the document type declaration of a document node.
+    ///
+    /// If the node is not a document node or the document doesn't have a
+    /// document type declaration, returns [`None`].
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    /// let root = xot.parse(r#"<!DOCTYPE doc SYSTEM "doc.dtd"><doc/>"#)?;
+    ///
+    /// let document_type = xot.document_type(root).unwrap();
+    /// assert_eq!(document_type.name, "doc");
+    /// assert_eq!(document_type.system_id.as_deref(), Some("doc.dtd"));
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn document_type(&self, document_node: Node) -> Option<&DocumentType> {
+        self.document_types.get(&document_node.get())
+    }
+
+    /// Get the URI of a document node.
+    ///
+    /// If the node is not a document node or no URI was set with
+    /// [`Xot::set_document_uri`], returns [`None`].
+    pub fn document_uri(&self, document_node: Node) -> Option<&str> {
+        self.document_uris
+            .get(&document_node.get())
+            .map(String::as_str)
+    }
+
+    /// Get the base URI of a node.
+    ///
+    /// This resolves the `xml:base` attributes on the node and its ancestors
+    /// against the URI of the document it is in (see
+    /// [`Xot::set_document_uri`]), following [XML
+    /// Base](https://www.w3.org/TR/xmlbase/) and [RFC
+    /// 3986](https://www.rfc-editor.org/rfc/rfc3986#section-5.2). The base
+    /// URI of a node that isn't an element is that of its parent. As this
+    /// looks at the tree as it is now, it stays correct if you move nodes
+    /// around.
+    ///
+    /// If there is no document URI and no `xml:base`, returns [`None`]. If
+    /// there is no document URI, a relative `xml:base` is resolved against
+    /// those on the ancestors as far as possible, so the result can be
+    /// relative.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    /// let root = xot.parse(r#"<doc xml:base="chapters/"><p xml:base="one.xml"/></doc>"#)?;
+    /// xot.set_document_uri(root, Some("http://example.com/book/index.xml"))?;
+    ///
+    /// let doc = xot.document_element(root)?;
+    /// let p = xot.first_child(doc).unwrap();
+    /// assert_eq!(
+    ///     xot.base_uri(doc).as_deref(),
+    ///     Some("http://example.com/book/chapters/")
+    /// );
+    /// assert_eq!(
+    ///     xot.base_uri(p).as_deref(),
+    ///     Some("http://example.com/book/chapters/one.xml")
+    /// );
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn base_uri(&self, node: Node) -> Option<String> {
+        let mut bases = Vec::new();
+        let mut base = None;
+        for ancestor in self.ancestors(node) {
+            match self.value(ancestor) {
+                Value::Element(_) => {
+                    if let Some(value) = self.get_attribute(ancestor, self.xml_base_id) {
+                        bases.push(value);
+                    }
+                }
+                Value::Document => base = self.document_uri(ancestor).map(str::to_string),
+                _ => {}
+            }
+        }
+        // resolve from the outermost inward
+        for value in bases.into_iter().rev() {
+            base = Some(match base {
+                Some(base) => uri::resolve(&base, value),
+                None => value.to_string(),
+            });
+        }
+        base
     }
 }
 
@@ -1,5 +1,6 @@
 use crate::error::Error;
 use crate::id::NameId;
+use crate::validate;
 use crate::xmlvalue::{Attribute, Comment, Element, Namespace, ProcessingInstruction, Text, Value};
 use crate::xotdata::{Node, Xot};
 use crate::{NamespaceId, PrefixId};
@@ -164,6 +165,56 @@ impl Xot {
         self.new_node(text)
     }
 
+    /// Create a new, unattached text node, checking that the text is valid.
+    ///
+    /// Like [`Xot::new_text`], but the text can only contain characters that
+    /// are allowed in XML 1.0. If it doesn't, you get
+    /// [`Error::InvalidCharacter`].
+    ///
+    /// ```rust
+    /// use xot::{Error, Xot};
+    ///
+    /// let mut xot = Xot::new();
+    /// assert!(xot.try_new_text("Hello, world!").is_ok());
+    /// assert!(matches!(xot.try_new_text("\u{0}"), Err(Error::InvalidCharacter('\u{0}'))));
+    /// ```
+    pub fn try_new_text(&mut self, text: &str) -> Result<Node, Error> {
+        validate::check_chars(text)?;
+        Ok(self.new_text(text))
+    }
+
+    /// Create a new, unattached text node that is a CDATA section.
+    ///
+    /// It is only merged with adjacent text nodes that are CDATA sections as
+    /// well.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    /// let root = xot.parse(r#"<doc>a</doc>"#)?;
+    /// let doc_el = xot.document_element(root)?;
+    /// let txt = xot.new_cdata("<b>");
+    /// xot.append(doc_el, txt)?;
+    /// assert_eq!(xot.to_string(root)?, "<doc>a<![CDATA[<b>]]></doc>");
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn new_cdata(&mut self, text: &str) -> Node {
+        let text = Value::Text(Text::new_cdata(text.to_string()));
+        self.new_node(text)
+    }
+
+    /// Create a new, unattached CDATA section, checking that the text is
+    /// valid.
+    ///
+    /// Like [`Xot::new_cdata`], but the text can only contain characters
+    /// that are allowed in XML 1.0. If it doesn't, you get
+    /// [`Error::InvalidCharacter`].
+    pub fn try_new_cdata(&mut self, text: &str) -> Result<Node, Error> {
+        validate::check_chars(text)?;
+        Ok(self.new_cdata(text))
+    }
+
     /// Create a new, unattached comment node given comment text.
     ///
     /// ```rust
@@ -182,6 +233,26 @@ impl Xot {
         self.new_node(comment)
     }
 
+    /// Create a new, unattached comment node, checking that the comment is
+    /// valid.
+    ///
+    /// Like [`Xot::new_comment`], but the comment cannot contain `--` or end
+    /// with `-`, which gives [`Error::InvalidComment`], and it can only
+    /// contain characters that are allowed in XML 1.0, or you get
+    /// [`Error::InvalidCharacter`].
+    ///
+    /// ```rust
+    /// use xot::{Error, Xot};
+    ///
+    /// let mut xot = Xot::new();
+    /// assert!(xot.try_new_comment("Hello, world!").is_ok());
+    /// assert!(matches!(xot.try_new_comment("a -- b"), Err(Error::InvalidComment(_))));
+    /// ```
+    pub fn try_new_comment(&mut self, comment: &str) -> Result<Node, Error> {
+        validate::check_comment(comment)?;
+        Ok(self.new_comment(comment))
+    }
+
     /// Create a new, unattached processing instruction.
     ///
     /// ```rust
@@ -208,6 +279,47 @@ impl Xot {
         self.new_node(pi)
     }
 
+    /// Create a new, unattached processing instruction, checking that it's
+    /// valid.
+    ///
+    /// Like [`Xot::new_processing_instruction`], but the target has to be an
+    /// `NCName` without a namespace other than `xml` (in any case), and the
+    /// data cannot contain `?>` and can only contain characters that are
+    /// allowed in XML 1.0.
+    ///
+    /// ```rust
+    /// use xot::{Error, Xot};
+    ///
+    /// let mut xot = Xot::new();
+    /// let target = xot.add_name("target");
+    /// assert!(xot.try_new_processing_instruction(target, Some("data")).is_ok());
+    /// assert!(matches!(
+    ///     xot.try_new_processing_instruction(target, Some("a ?> b")),
+    ///     Err(Error::InvalidProcessingInstruction(_))
+    /// ));
+    /// let xml = xot.add_name("xml");
+    /// assert!(matches!(
+    ///     xot.try_new_processing_instruction(xml, None),
+    ///     Err(Error::InvalidTarget(_))
+    /// ));
+    /// ```
+    pub fn try_new_processing_instruction(
+        &mut self,
+        target: impl Into<NameId>,
+        data: Option<&str>,
+    ) -> Result<Node, Error> {
+        let target = target.into();
+        let (local_name, namespace) = self.name_ns_str(target);
+        if !namespace.is_empty() {
+            return Err(Error::NamespaceInProcessingInstruction);
+        }
+        validate::check_processing_instruction_target(local_name)?;
+        if let Some(data) = data {
+            validate::check_processing_instruction_data(data)?;
+        }
+        Ok(self.new_processing_instruction(target, data))
+    }
+
     /// Create a new, unattached attribute node.
     ///
     /// You can then use [`Xot::append_attribute_node`] to add it to an element node.
@@ -236,6 +348,21 @@ impl Xot {
         self.new_node(attr)
     }
 
+    /// Create a new, unattached attribute node, checking that the value is
+    /// valid.
+    ///
+    /// Like [`Xot::new_attribute_node`], but the value can only contain
+    /// characters that are allowed in XML 1.0. If it doesn't, you get
+    /// [`Error::InvalidCharacter`].
+    pub fn try_new_attribute_node(
+        &mut self,
+        name: impl Into<NameId>,
+        value: String,
+    ) -> Result<Node, Error> {
+        validate::check_chars(&value)?;
+        Ok(self.new_attribute_node(name, value))
+    }
+
     /// Create a new, unattached namespace declaration node.
     ///
     /// You can then use [`Xot::append_namespace_node`] to add it to an element
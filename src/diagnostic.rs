@@ -0,0 +1,243 @@
+use crate::error::ParseError;
+use crate::parse::Span;
+
+/// A position in source text as a line and column.
+///
+/// Lines and columns start at 1. The column is given both as a count of
+/// UTF-8 bytes and of UTF-16 code units from the start of the line, as
+/// editors differ in which they expect. The Language Server Protocol uses
+/// UTF-16 code units by default, for instance (counting from 0, so subtract
+/// 1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LineColumn {
+    /// The line, starting at 1.
+    pub line: usize,
+    /// The column in UTF-8 bytes, starting at 1.
+    pub column: usize,
+    /// The column in UTF-16 code units, starting at 1.
+    pub utf16_column: usize,
+}
+
+/// An index of the lines in source text.
+///
+/// This lets you turn the byte offsets in a [`Span`] into line and column
+/// positions, and render a diagnostic that shows where in the source the
+/// span is. You can use it with the spans of [`ParseError`] as well as those
+/// from a [`SpanInfo`](crate::SpanInfo).
+///
+/// Use the same source text that you parsed. Note that the positions in
+/// errors from [`Xot::parse_reader`](crate::Xot::parse_reader) are offsets
+/// in the undecoded input, so they don't apply to the decoded text.
+///
+/// ```rust
+/// use xot::{LineColumn, LineIndex, Xot};
+///
+/// let xml = "<doc>\n  <p>text</doc>";
+/// let mut xot = Xot::new();
+/// let error = xot.parse(xml).unwrap_err();
+///
+/// let index = LineIndex::new(xml);
+/// assert_eq!(
+///     index.line_column(error.span().start),
+///     LineColumn { line: 2, column: 12, utf16_column: 12 }
+/// );
+/// assert_eq!(
+///     index.render(error.span(), &error.to_string()),
+///     concat!(
+///         "error: Invalid close tag: doc\n",
+///         " --> 2:12\n",
+///         "  |\n",
+///         "2 |   <p>text</doc>\n",
+///         "  |            ^^^\n",
+///     )
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    source: &'a str,
+    // the byte offset at which each line starts
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    /// Create a line index for source text.
+    pub fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        Self {
+            source,
+            line_starts,
+        }
+    }
+
+    /// The line and column of a byte offset in the source.
+    ///
+    /// An offset beyond the end of the source is taken to be the end.
+    pub fn line_column(&self, offset: usize) -> LineColumn {
+        let offset = self.clamp(offset);
+        let line = self.line(offset);
+        let prefix = &self.source[self.line_starts[line]..offset];
+        LineColumn {
+            line: line + 1,
+            column: prefix.len() + 1,
+            utf16_column: prefix.encode_utf16().count() + 1,
+        }
+    }
+
+    /// The line and column of the start and end of a span.
+    pub fn span_line_columns(&self, span: Span) -> (LineColumn, LineColumn) {
+        (self.line_column(span.start), self.line_column(span.end))
+    }
+
+    /// Render a diagnostic for a span.
+    ///
+    /// This shows the message, the position of the span, and the source
+    /// line it starts on with the span underlined with carets. If the span
+    /// continues on following lines, only the part on its first line is
+    /// underlined. An empty span gets a single caret.
+    pub fn render(&self, span: Span, message: &str) -> String {
+        let start = self.clamp(span.start);
+        let end = self.clamp(span.end).max(start);
+        let line = self.line(start);
+        let position = self.line_column(start);
+        let line_text = self.line_text(line);
+        let line_start = self.line_starts[line];
+
+        let before = &line_text[..(start - line_start).min(line_text.len())];
+        let underlined = &line_text[before.len()..(end - line_start).min(line_text.len())];
+        // keep tabs in the padding, so that the carets line up
+        let padding = before
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let carets = "^".repeat(underlined.chars().count().max(1));
+
+        let line_number = position.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        format!(
+            "error: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            message,
+            gutter,
+            position.line,
+            position.column,
+            gutter,
+            line_number,
+            line_text,
+            gutter,
+            padding,
+            carets
+        )
+    }
+
+    // the index of the line an offset is on
+    fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset) - 1
+    }
+
+    // the text of a line without its line end
+    fn line_text(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line];
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.source.len(), |next| next - 1);
+        let text = &self.source[start..end];
+        text.strip_suffix('\r').unwrap_or(text)
+    }
+
+    // an offset within the source, on a character boundary
+    fn clamp(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+impl ParseError {
+    /// Render a diagnostic for this error.
+    ///
+    /// This shows the error message along with the line of `source` where
+    /// it occurred. See [`LineIndex::render`].
+    pub fn render(&self, source: &str) -> String {
+        LineIndex::new(source).render(self.span(), &self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_column() {
+        let index = LineIndex::new("ab\r\ncd\n\nef");
+        let position = |line, column| LineColumn {
+            line,
+            column,
+            utf16_column: column,
+        };
+        assert_eq!(index.line_column(0), position(1, 1));
+        assert_eq!(index.line_column(2), position(1, 3));
+        assert_eq!(index.line_column(4), position(2, 1));
+        assert_eq!(index.line_column(7), position(3, 1));
+        assert_eq!(index.line_column(9), position(4, 2));
+        assert_eq!(index.line_column(10), position(4, 3));
+        assert_eq!(index.line_column(100), position(4, 3));
+    }
+
+    #[test]
+    fn test_line_column_utf16() {
+        // é is 2 bytes in UTF-8 and 1 code unit in UTF-16, 😀 is 4 bytes in
+        // UTF-8 and 2 code units in UTF-16
+        let index = LineIndex::new("<é>😀x");
+        assert_eq!(
+            index.line_column(4),
+            LineColumn {
+                line: 1,
+                column: 5,
+                utf16_column: 4
+            }
+        );
+        assert_eq!(
+            index.line_column(8),
+            LineColumn {
+                line: 1,
+                column: 9,
+                utf16_column: 6
+            }
+        );
+    }
+
+    #[test]
+    fn test_render_empty_span() {
+        let index = LineIndex::new("<doc>");
+        assert_eq!(
+            index.render(Span::new(5, 5), "Unexpected end"),
+            "error: Unexpected end\n --> 1:6\n  |\n1 | <doc>\n  |      ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_multiline_span_and_tabs() {
+        let source = "<doc>\n\t<p>one\r\ntwo</p>\n</doc>";
+        let index = LineIndex::new(source);
+        let start = source.find("one").unwrap();
+        let end = source.find("two").unwrap() + 3;
+        assert_eq!(
+            index.render(Span::new(start, end), "Text"),
+            "error: Text\n --> 2:5\n  |\n2 | \t<p>one\n  | \t   ^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_parse_error() {
+        let source = "<doc>\n</p>";
+        let error = ParseError::InvalidCloseTag("".to_string(), "p".to_string(), Span::new(8, 9));
+        assert_eq!(
+            error.render(source),
+            "error: Invalid close tag: p\n --> 2:3\n  |\n2 | </p>\n  |   ^\n"
+        );
+    }
+}
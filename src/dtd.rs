@@ -0,0 +1,367 @@
+// Attribute list declarations in the internal DTD subset.
+//
+// xmlparser skips `<!ATTLIST>` declarations without reporting them, so we
+// scan the raw internal subset for them ourselves once the DTD is complete.
+// All declared general entities are known at that point, so we can expand
+// default values immediately.
+use ahash::{HashMap, HashMapExt};
+
+use crate::entity::{parse_attribute, Entities};
+use crate::error::ParseError;
+use crate::parse::Span;
+use crate::XmlVersion;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AttributeType {
+    Cdata,
+    Id,
+    // any other type; these all get tokenized value normalization
+    Tokenized,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct AttributeDefault {
+    pub(crate) value: String,
+    pub(crate) name_span: Span,
+    pub(crate) value_span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct AttributeDeclaration {
+    // the qualified name as it appears in the declaration
+    pub(crate) name: String,
+    pub(crate) attribute_type: AttributeType,
+    pub(crate) default: Option<AttributeDefault>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct AttributeDeclarations {
+    // DTDs aren't namespace aware, so elements are identified by their
+    // qualified name as it appears in the declaration
+    elements: HashMap<String, Vec<AttributeDeclaration>>,
+}
+
+impl AttributeDeclarations {
+    pub(crate) fn new() -> Self {
+        Self {
+            elements: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn get(&self, element: &str) -> &[AttributeDeclaration] {
+        self.elements
+            .get(element)
+            .map(|declarations| declarations.as_slice())
+            .unwrap_or(&[])
+    }
+
+    fn declare(&mut self, element: &str, declaration: AttributeDeclaration) {
+        let declarations = self.elements.entry(element.to_string()).or_default();
+        // when an attribute is declared more than once, the first declaration
+        // is binding
+        if !declarations
+            .iter()
+            .any(|existing| existing.name == declaration.name)
+        {
+            declarations.push(declaration);
+        }
+    }
+}
+
+/// Normalize the value of an attribute that isn't of type CDATA.
+///
+/// <https://www.w3.org/TR/xml/#AVNormalize>
+pub(crate) fn normalize_tokenized(value: &str) -> String {
+    value
+        .split(' ')
+        .filter(|token| !token.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parse the attribute list declarations in the internal DTD subset.
+///
+/// `base_position` is the position of the internal subset in the XML source.
+pub(crate) fn parse_attribute_list_declarations(
+    subset: &str,
+    base_position: usize,
+    entities: &Entities,
+    version: XmlVersion,
+) -> Result<AttributeDeclarations, ParseError> {
+    let mut declarations = AttributeDeclarations::new();
+    let mut scanner = Scanner {
+        text: subset,
+        position: 0,
+        base_position,
+        version,
+    };
+    while !scanner.at_end() {
+        if scanner.starts_with("<!--") {
+            scanner.skip_past("-->");
+        } else if scanner.starts_with("<?") {
+            scanner.skip_past("?>");
+        } else if scanner.starts_with("<!ATTLIST") {
+            scanner.attribute_list_declaration(&mut declarations, entities)?;
+        } else if scanner.starts_with("<!") {
+            scanner.skip_declaration();
+        } else {
+            scanner.advance(1);
+        }
+    }
+    Ok(declarations)
+}
+
+struct Scanner<'a> {
+    text: &'a str,
+    position: usize,
+    base_position: usize,
+    version: XmlVersion,
+}
+
+impl<'a> Scanner<'a> {
+    fn at_end(&self) -> bool {
+        self.position >= self.text.len()
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.rest().starts_with(s)
+    }
+
+    fn advance(&mut self, n: usize) {
+        // never stop in the middle of a character
+        let mut position = self.position + n;
+        while !self.text.is_char_boundary(position) {
+            position += 1;
+        }
+        self.position = position;
+    }
+
+    fn skip_past(&mut self, end: &str) {
+        self.position = self
+            .rest()
+            .find(end)
+            .map(|found| self.position + found + end.len())
+            .unwrap_or(self.text.len());
+    }
+
+    // skip a declaration we're not interested in, taking care not to stop at
+    // a `>` inside a quoted literal
+    fn skip_declaration(&mut self) {
+        let mut quote = None;
+        for (offset, c) in self.rest().char_indices() {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), c) if q == c => quote = None,
+                (None, '>') => {
+                    self.position += offset + 1;
+                    return;
+                }
+                _ => {}
+            }
+        }
+        self.position = self.text.len();
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let rest = self.rest();
+        let trimmed = rest.trim_start_matches([' ', '\t', '\r', '\n']);
+        self.position += rest.len() - trimmed.len();
+        rest.len() != trimmed.len()
+    }
+
+    fn span(&self, start: usize) -> Span {
+        Span::new(
+            self.base_position + start,
+            self.base_position + self.position,
+        )
+    }
+
+    fn name(&mut self) -> Option<(&'a str, Span)> {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| {
+                c.is_whitespace() || matches!(c, '>' | '(' | ')' | '|' | '"' | '\'' | '%')
+            })
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return None;
+        }
+        let start = self.position;
+        self.position += end;
+        Some((&rest[..end], self.span(start)))
+    }
+
+    // a literal, returned without its quotes
+    fn literal(&mut self) -> Option<(&'a str, Span)> {
+        let rest = self.rest();
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let end = rest[1..].find(quote)?;
+        let start = self.position + 1;
+        self.position = start + end;
+        let span = self.span(start);
+        self.position += 1;
+        Some((&rest[1..end + 1], span))
+    }
+
+    fn enumeration(&mut self) -> Option<()> {
+        if !self.starts_with("(") {
+            return None;
+        }
+        let end = self.rest().find(')')?;
+        self.position += end + 1;
+        Some(())
+    }
+
+    fn attribute_list_declaration(
+        &mut self,
+        declarations: &mut AttributeDeclarations,
+        entities: &Entities,
+    ) -> Result<(), ParseError> {
+        let start = self.position;
+        self.advance("<!ATTLIST".len());
+        let invalid = |scanner: &Self| ParseError::InvalidAttributeDeclaration(scanner.span(start));
+        if !self.skip_whitespace() {
+            return Err(invalid(self));
+        }
+        let (element, _) = self.name().ok_or_else(|| invalid(self))?;
+        loop {
+            self.skip_whitespace();
+            if self.starts_with(">") {
+                self.advance(1);
+                return Ok(());
+            }
+            let (name, name_span) = self.name().ok_or_else(|| invalid(self))?;
+            if !self.skip_whitespace() {
+                return Err(invalid(self));
+            }
+            let attribute_type = if self.starts_with("(") {
+                self.enumeration().ok_or_else(|| invalid(self))?;
+                AttributeType::Tokenized
+            } else {
+                match self.name().ok_or_else(|| invalid(self))?.0 {
+                    "CDATA" => AttributeType::Cdata,
+                    "ID" => AttributeType::Id,
+                    "IDREF" | "IDREFS" | "ENTITY" | "ENTITIES" | "NMTOKEN" | "NMTOKENS" => {
+                        AttributeType::Tokenized
+                    }
+                    "NOTATION" => {
+                        if !self.skip_whitespace() {
+                            return Err(invalid(self));
+                        }
+                        self.enumeration().ok_or_else(|| invalid(self))?;
+                        AttributeType::Tokenized
+                    }
+                    _ => return Err(invalid(self)),
+                }
+            };
+            if !self.skip_whitespace() {
+                return Err(invalid(self));
+            }
+            let default = if self.starts_with("#REQUIRED") {
+                self.advance("#REQUIRED".len());
+                None
+            } else if self.starts_with("#IMPLIED") {
+                self.advance("#IMPLIED".len());
+                None
+            } else {
+                if self.starts_with("#FIXED") {
+                    self.advance("#FIXED".len());
+                    if !self.skip_whitespace() {
+                        return Err(invalid(self));
+                    }
+                }
+                let (value, value_span) = self.literal().ok_or_else(|| invalid(self))?;
+                let value =
+                    parse_attribute(value.into(), value_span.start, entities, self.version)?;
+                let value = if attribute_type == AttributeType::Cdata {
+                    value.to_string()
+                } else {
+                    normalize_tokenized(&value)
+                };
+                Some(AttributeDefault {
+                    value,
+                    name_span,
+                    value_span,
+                })
+            };
+            declarations.declare(
+                element,
+                AttributeDeclaration {
+                    name: name.to_string(),
+                    attribute_type,
+                    default,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(subset: &str) -> AttributeDeclarations {
+        parse_attribute_list_declarations(subset, 0, &Entities::new(), XmlVersion::V1_0).unwrap()
+    }
+
+    #[test]
+    fn test_attribute_list_declaration() {
+        let declarations = parse(r#"<!ATTLIST a id ID #REQUIRED b CDATA "B" c (x|y) 'x'>"#);
+        let a = declarations.get("a");
+        assert_eq!(a.len(), 3);
+        assert_eq!(a[0].name, "id");
+        assert_eq!(a[0].attribute_type, AttributeType::Id);
+        assert!(a[0].default.is_none());
+        assert_eq!(a[1].attribute_type, AttributeType::Cdata);
+        assert_eq!(a[1].default.as_ref().unwrap().value, "B");
+        assert_eq!(a[2].attribute_type, AttributeType::Tokenized);
+        assert_eq!(a[2].default.as_ref().unwrap().value, "x");
+    }
+
+    #[test]
+    fn test_attribute_list_declaration_first_binds() {
+        let declarations = parse(r#"<!ATTLIST a b CDATA "1"><!ATTLIST a b CDATA "2" c CDATA "3">"#);
+        let a = declarations.get("a");
+        assert_eq!(a.len(), 2);
+        assert_eq!(a[0].default.as_ref().unwrap().value, "1");
+        assert_eq!(a[1].default.as_ref().unwrap().value, "3");
+    }
+
+    #[test]
+    fn test_attribute_list_declaration_skips_other_markup() {
+        let declarations = parse(
+            r#"<!-- <!ATTLIST a b CDATA "no"> --><!ENTITY e "<!ATTLIST a b CDATA 'no'>"><!ATTLIST a b CDATA #FIXED "yes">"#,
+        );
+        let a = declarations.get("a");
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].default.as_ref().unwrap().value, "yes");
+    }
+
+    #[test]
+    fn test_attribute_list_declaration_tokenized_default() {
+        let declarations = parse(r#"<!ATTLIST a b NMTOKENS "  x   y ">"#);
+        assert_eq!(
+            declarations.get("a")[0].default.as_ref().unwrap().value,
+            "x y"
+        );
+    }
+
+    #[test]
+    fn test_invalid_attribute_list_declaration() {
+        let err = parse_attribute_list_declarations(
+            r#"<!ATTLIST a b WRONG "B">"#,
+            10,
+            &Entities::new(),
+            XmlVersion::V1_0,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::InvalidAttributeDeclaration(Span { start: 10, .. })
+        ));
+    }
+}
@@ -1,6 +1,16 @@
-use encoding_rs::Encoding;
+use std::io::Read;
+
+use encoding_rs::{DecoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
 use xhtmlchardet::detect;
 
+use crate::error::ParseError;
+
+// the amount of input we look at to determine the encoding. This is enough to
+// contain any reasonable XML declaration.
+const SNIFF_SIZE: usize = 1024;
+// the amount of input we decode at a time
+const CHUNK_SIZE: usize = 8192;
+
 pub fn encoding(data: &[u8], hint: Option<String>) -> Option<&'static Encoding> {
     let mut cursor = std::io::Cursor::new(data);
     let charsets = detect(&mut cursor, hint).ok()?;
@@ -19,6 +29,180 @@ pub(crate) fn decode(data: &[u8], hint: Option<String>) -> String {
     s.into_owned()
 }
 
+/// XML decoded from a reader.
+///
+/// We only retain the decoded XML, not the original bytes; we can still map
+/// positions in it back to byte offsets in the original input by encoding
+/// the XML before the position again.
+#[derive(Debug)]
+pub(crate) struct DecodedXml {
+    pub(crate) xml: String,
+    encoding: &'static Encoding,
+    bom_len: usize,
+}
+
+impl DecodedXml {
+    /// The byte offset in the original input of a position in the decoded
+    /// XML.
+    pub(crate) fn byte_offset(&self, position: usize) -> usize {
+        let mut position = position.min(self.xml.len());
+        while !self.xml.is_char_boundary(position) {
+            position -= 1;
+        }
+        let before = &self.xml[..position];
+        let len = if self.encoding == UTF_8 {
+            before.len()
+        } else if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            // encoding_rs never encodes to UTF-16, but it's easy to count
+            before.encode_utf16().count() * 2
+        } else {
+            self.encoding.encode(before).0.len()
+        };
+        self.bom_len + len
+    }
+}
+
+/// Decode XML from a reader.
+///
+/// The encoding is determined from the byte order mark or the XML
+/// declaration, as described in <https://www.w3.org/TR/xml/#sec-guessing>,
+/// and defaults to UTF-8. The input is decoded incrementally, so we never
+/// hold both the complete input and the complete decoded XML in memory.
+pub(crate) fn decode_reader(mut reader: impl Read) -> Result<DecodedXml, ParseError> {
+    let mut start = vec![0; SNIFF_SIZE];
+    let read = read_full(&mut reader, &mut start).map_err(|e| io_error(e, 0))?;
+    start.truncate(read);
+    let (encoding, bom_len) = sniff(&start);
+
+    if encoding == UTF_8 {
+        // the common case: no decoding is needed, so we read the rest of the
+        // input into the buffer that becomes the string
+        let mut bytes = start;
+        if let Err(e) = reader.read_to_end(&mut bytes) {
+            return Err(io_error(e, bytes.len()));
+        }
+        bytes.drain(..bom_len);
+        let xml = String::from_utf8(bytes).map_err(|e| {
+            ParseError::MalformedEncoding(
+                encoding.name().to_string(),
+                bom_len + e.utf8_error().valid_up_to(),
+            )
+        })?;
+        return Ok(DecodedXml {
+            xml,
+            encoding,
+            bom_len,
+        });
+    }
+
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut xml = String::new();
+    let mut consumed = bom_len;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut chunk = &start[bom_len..];
+    let mut last = read < SNIFF_SIZE;
+    loop {
+        loop {
+            let needed = decoder
+                .max_utf8_buffer_length_without_replacement(chunk.len())
+                .unwrap_or(usize::MAX);
+            xml.reserve(needed);
+            let (result, read) =
+                decoder.decode_to_string_without_replacement(chunk, &mut xml, last);
+            match result {
+                DecoderResult::InputEmpty => {
+                    consumed += read;
+                    break;
+                }
+                DecoderResult::OutputFull => {
+                    consumed += read;
+                    chunk = &chunk[read..];
+                }
+                DecoderResult::Malformed(malformed, after) => {
+                    let position = consumed + read - after as usize - malformed as usize;
+                    return Err(ParseError::MalformedEncoding(
+                        encoding.name().to_string(),
+                        position,
+                    ));
+                }
+            }
+        }
+        if last {
+            break;
+        }
+        let read = read_full(&mut reader, &mut buffer).map_err(|e| io_error(e, consumed))?;
+        chunk = &buffer[..read];
+        last = read < CHUNK_SIZE;
+    }
+    Ok(DecodedXml {
+        xml,
+        encoding,
+        bom_len,
+    })
+}
+
+fn io_error(e: std::io::Error, position: usize) -> ParseError {
+    ParseError::Io(e.to_string(), position)
+}
+
+// read until the buffer is full or the input is exhausted
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// determine the encoding from the first bytes of the input. Returns the
+// encoding and the length of the byte order mark, if any.
+fn sniff(start: &[u8]) -> (&'static Encoding, usize) {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(start) {
+        return (encoding, bom_len);
+    }
+    // `<?` without a byte order mark
+    if start.starts_with(&[0x3C, 0x00, 0x3F, 0x00]) {
+        return (UTF_16LE, 0);
+    }
+    if start.starts_with(&[0x00, 0x3C, 0x00, 0x3F]) {
+        return (UTF_16BE, 0);
+    }
+    // as we got here we can read the XML declaration as ASCII, so it can only
+    // declare an ASCII compatible encoding
+    let encoding = declared_encoding(start)
+        .and_then(Encoding::for_label)
+        .filter(|encoding| encoding.is_ascii_compatible())
+        .unwrap_or(UTF_8);
+    (encoding, 0)
+}
+
+// the value of the encoding declaration in the XML declaration, if any
+fn declared_encoding(start: &[u8]) -> Option<&[u8]> {
+    let rest = start.strip_prefix(b"<?xml")?;
+    let end = find(rest, b"?>")?;
+    let declaration = &rest[..end];
+    let rest = &declaration[find(declaration, b"encoding")? + b"encoding".len()..];
+    let rest = rest
+        .trim_ascii_start()
+        .strip_prefix(b"=")?
+        .trim_ascii_start();
+    let quote = *rest.first().filter(|c| **c == b'"' || **c == b'\'')?;
+    let value = &rest[1..];
+    let end = value.iter().position(|c| *c == quote)?;
+    Some(&value[..end])
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -52,4 +236,62 @@ mod tests {
         // windows-1252 is a superset of 8859-1
         assert_eq!(enc.name(), "windows-1252");
     }
+
+    fn decode(data: &[u8]) -> DecodedXml {
+        decode_reader(data).unwrap()
+    }
+
+    #[test]
+    fn test_decode_reader_utf8_bom() {
+        let decoded = decode(b"\xef\xbb\xbf<a>\xc3\xa9</a>");
+        assert_eq!(decoded.xml, "<a>é</a>");
+        assert_eq!(decoded.byte_offset(5), 3 + 5);
+    }
+
+    #[test]
+    fn test_decode_reader_declared_encoding() {
+        let decoded = decode(b"<?xml version=\"1.0\" encoding='ISO-8859-1'?><a>\xe9x</a>");
+        assert!(decoded.xml.ends_with("<a>éx</a>"));
+        // é is two bytes in UTF-8, but one in the input
+        let position = decoded.xml.rfind('x').unwrap();
+        assert_eq!(decoded.byte_offset(position), position - 1);
+    }
+
+    #[test]
+    fn test_decode_reader_utf16() {
+        let mut data = vec![0xFF, 0xFE];
+        for unit in "<a>é</a>".encode_utf16() {
+            data.extend_from_slice(&unit.to_le_bytes());
+        }
+        let decoded = decode(&data);
+        assert_eq!(decoded.xml, "<a>é</a>");
+        assert_eq!(decoded.byte_offset(6), 2 + 5 * 2);
+    }
+
+    #[test]
+    fn test_decode_reader_utf16_without_bom() {
+        let mut data = Vec::new();
+        for unit in "<?xml version='1.0'?><a/>".encode_utf16() {
+            data.extend_from_slice(&unit.to_be_bytes());
+        }
+        assert_eq!(decode(&data).xml, "<?xml version='1.0'?><a/>");
+    }
+
+    #[test]
+    fn test_decode_reader_large() {
+        // larger than a single chunk, with a multi-byte character across
+        // chunk boundaries
+        let text = "\u{1f600}x".repeat(CHUNK_SIZE);
+        let mut data = vec![0xFF, 0xFE];
+        for unit in format!("<a>{}</a>", text).encode_utf16() {
+            data.extend_from_slice(&unit.to_le_bytes());
+        }
+        assert_eq!(decode(&data).xml, format!("<a>{}</a>", text));
+    }
+
+    #[test]
+    fn test_decode_reader_malformed() {
+        let err = decode_reader(&b"<a>\xff</a>"[..]).unwrap_err();
+        assert!(matches!(err, ParseError::MalformedEncoding(_, 3)));
+    }
 }
@@ -1,116 +1,317 @@
 use std::borrow::Cow;
+use std::cell::Cell;
 
-use crate::error::ParseError;
+use ahash::{HashMap, HashMapExt};
+
+use crate::error::{ParseError, ParseLimit};
 use crate::output::Normalizer;
-use crate::Span;
+use crate::validate::is_char;
+use crate::{ParseLimits, Span, XmlVersion};
+
+/// A general entity declared in the internal DTD subset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Entity {
+    /// An internal entity with its (literal) replacement text.
+    Internal(String),
+    /// An external entity. These are recorded, but never loaded.
+    External,
+}
+
+/// The general entities declared in a document.
+///
+/// This also tracks how much expansion the entities have done, for all text
+/// and attribute values of the document together, so that it can be limited.
+/// This protects against documents that nest entities to amplify a small
+/// input into a huge output (the "billion laughs" attack). The entities only
+/// get shared access during parsing, so the counts are cells.
+#[derive(Debug, Clone)]
+pub(crate) struct Entities {
+    entities: HashMap<String, Entity>,
+    limits: ParseLimits,
+    expansion: Cell<usize>,
+    references: Cell<usize>,
+}
+
+impl Entities {
+    pub(crate) fn new() -> Self {
+        Entities {
+            entities: HashMap::new(),
+            limits: ParseLimits::default(),
+            expansion: Cell::new(0),
+            references: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn set_limits(&mut self, limits: &ParseLimits) {
+        self.limits = *limits;
+    }
 
-pub(crate) fn parse_text(content: Cow<str>, base_position: usize) -> Result<Cow<str>, ParseError> {
-    parse_content(content, false, base_position)
+    /// Declare an entity. As per the XML specification, the first declaration
+    /// is binding; subsequent declarations of the same name are ignored.
+    pub(crate) fn declare(&mut self, name: &str, entity: Entity) {
+        self.entities.entry(name.to_string()).or_insert(entity);
+    }
+
+    fn get(&self, name: &str) -> Option<(&str, &Entity)> {
+        self.entities
+            .get_key_value(name)
+            .map(|(name, entity)| (name.as_str(), entity))
+    }
+
+    // account for the expansion of an entity with the given replacement
+    // text, failing if that exceeds the limits
+    fn spend(&self, replacement: &str, span: Span) -> Result<(), ParseError> {
+        self.references.set(self.references.get() + 1);
+        self.limits
+            .check(ParseLimit::EntityReferences, self.references.get(), span)?;
+        self.expansion.set(self.expansion.get() + replacement.len());
+        self.limits
+            .check(ParseLimit::EntityExpansion, self.expansion.get(), span)
+    }
+}
+
+pub(crate) fn parse_text<'a>(
+    content: Cow<'a, str>,
+    base_position: usize,
+    entities: &Entities,
+    version: XmlVersion,
+) -> Result<Cow<'a, str>, ParseError> {
+    parse_content(content, false, base_position, entities, version)
 }
 
-pub(crate) fn parse_attribute(
-    content: Cow<str>,
+pub(crate) fn parse_attribute<'a>(
+    content: Cow<'a, str>,
     base_position: usize,
-) -> Result<Cow<str>, ParseError> {
-    parse_content(content, true, base_position)
+    entities: &Entities,
+    version: XmlVersion,
+) -> Result<Cow<'a, str>, ParseError> {
+    parse_content(content, true, base_position, entities, version)
 }
 
-fn parse_content(
-    content: Cow<str>,
+fn parse_content<'a>(
+    content: Cow<'a, str>,
     attribute: bool,
     base_position: usize,
-) -> Result<Cow<str>, ParseError> {
+    entities: &Entities,
+    version: XmlVersion,
+) -> Result<Cow<'a, str>, ParseError> {
+    let mut expander = Expander {
+        entities,
+        attribute,
+        version,
+        open: Vec::new(),
+        origin: None,
+    };
     let mut result = String::new();
-    let mut chars = content.char_indices().peekable();
-    let mut change = false;
-    while let Some((position, c)) = chars.next() {
-        // https://www.w3.org/TR/xml/#sec-line-ends
-        if c == '\r' {
-            if let Some((_, peeked)) = chars.peek() {
-                if peeked == &'\n' {
-                    // consume next char
-                    chars.next();
+    if expander.expand(&content, base_position, &mut result)? {
+        Ok(result.into())
+    } else {
+        Ok(content)
+    }
+}
+
+// Expands character and entity references in content. Entity replacement
+// text is expanded recursively; any errors found while doing so are reported
+// with the span of the outermost entity reference.
+struct Expander<'e> {
+    entities: &'e Entities,
+    attribute: bool,
+    version: XmlVersion,
+    open: Vec<&'e str>,
+    origin: Option<Span>,
+}
+
+impl<'e> Expander<'e> {
+    // NEL and LINE SEPARATOR are line endings in XML 1.1 only
+    fn is_line_end(&self, c: char) -> bool {
+        self.version == XmlVersion::V1_1 && (c == '\u{85}' || c == '\u{2028}')
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        self.origin.unwrap_or(Span::new(start, end))
+    }
+
+    // Expands content into result. Returns true if the result differs from
+    // the content.
+    fn expand(
+        &mut self,
+        content: &str,
+        base_position: usize,
+        result: &mut String,
+    ) -> Result<bool, ParseError> {
+        let mut chars = content.char_indices().peekable();
+        let mut change = false;
+        while let Some((position, c)) = chars.next() {
+            // https://www.w3.org/TR/xml/#sec-line-ends
+            // https://www.w3.org/TR/xml11/#sec-line-ends
+            if c == '\r' || self.is_line_end(c) {
+                if c == '\r' {
+                    if let Some((_, peeked)) = chars.peek() {
+                        if peeked == &'\n' || (*peeked == '\u{85}' && self.is_line_end(*peeked)) {
+                            // consume next char
+                            chars.next();
+                        }
+                    }
                 }
-            }
-            if !attribute {
-                result.push('\n');
-            } else {
+                if !self.attribute {
+                    result.push('\n');
+                } else {
+                    // https://www.w3.org/TR/xml/#AVNormalize
+                    result.push(' ');
+                }
+                change = true;
+            } else if c == '&' {
+                let mut entity = String::new();
+                let mut is_complete = false;
+                let mut end_position = 0;
+                for (p, c) in chars.by_ref() {
+                    if c == ';' {
+                        is_complete = true;
+                        end_position = p + 1;
+                        break;
+                    }
+                    entity.push(c);
+                }
+                if !is_complete {
+                    return Err(ParseError::UnclosedEntity(
+                        entity,
+                        self.origin
+                            .map(|span| span.start)
+                            .unwrap_or(base_position + position),
+                    ));
+                }
+                change = true;
+                let span = self.span(base_position + position, base_position + end_position);
+
+                if let Some(entity) = entity.strip_prefix('#') {
+                    result.push(parse_character_reference(entity, span, self.version)?);
+                } else {
+                    match entity.as_str() {
+                        "amp" => result.push('&'),
+                        "apos" => result.push('\''),
+                        "gt" => result.push('>'),
+                        "lt" => result.push('<'),
+                        "quot" => result.push('"'),
+                        _ => self.expand_entity(entity, span, result)?,
+                    }
+                }
+            } else if self.attribute && (c == '\t' || c == '\n') {
                 // https://www.w3.org/TR/xml/#AVNormalize
+                // \r and \r\n already handled earlier
                 result.push(' ');
+                change = true;
+            } else {
+                result.push(c);
             }
-            change = true;
-        } else if c == '&' {
-            let mut entity = String::new();
-            let mut is_complete = false;
-            let mut end_position = 0;
-            for (p, c) in chars.by_ref() {
-                if c == ';' {
-                    is_complete = true;
-                    end_position = p + 1;
-                    break;
+            // text grown by entity expansion is checked as it grows, so that
+            // we don't expand it in full first
+            if let Some(origin) = self.origin {
+                if !self.attribute {
+                    self.entities
+                        .limits
+                        .check(ParseLimit::TextLength, result.len(), origin)?;
                 }
-                entity.push(c);
             }
-            if !is_complete {
-                return Err(ParseError::UnclosedEntity(entity, base_position + position));
+        }
+        Ok(change)
+    }
+
+    fn expand_entity(
+        &mut self,
+        name: String,
+        span: Span,
+        result: &mut String,
+    ) -> Result<(), ParseError> {
+        let (name, replacement) = match self.entities.get(&name) {
+            Some((name, Entity::Internal(replacement))) => (name, replacement.as_str()),
+            Some((_, Entity::External)) => {
+                return Err(ParseError::ExternalEntity(name, span));
             }
-            change = true;
-
-            if let Some(entity) = entity.strip_prefix('#') {
-                let first_char = entity.chars().next().ok_or_else(|| {
-                    ParseError::InvalidEntity(
-                        entity.to_string(),
-                        Span::new(base_position + position, base_position + end_position),
-                    )
-                })?;
-                let code = if first_char == 'x' {
-                    u32::from_str_radix(&entity[1..], 16)
-                } else {
-                    entity.parse::<u32>()
-                };
-                let code = code.map_err(|_| {
-                    ParseError::InvalidEntity(
-                        entity.to_string(),
-                        Span::new(base_position + position, base_position + end_position),
-                    )
-                })?;
-                let c = std::char::from_u32(code).ok_or_else(|| {
-                    ParseError::InvalidEntity(
-                        entity.to_string(),
-                        Span::new(base_position + position, base_position + end_position),
-                    )
-                })?;
-                result.push(c);
-            } else {
-                match entity.as_str() {
-                    "amp" => result.push('&'),
-                    "apos" => result.push('\''),
-                    "gt" => result.push('>'),
-                    "lt" => result.push('<'),
-                    "quot" => result.push('"'),
-                    _ => {
-                        return Err(ParseError::InvalidEntity(
-                            entity,
-                            Span::new(base_position + position, base_position + end_position),
-                        ))
-                    }
-                }
+            None => {
+                return Err(ParseError::InvalidEntity(name, span));
             }
-        } else if attribute && (c == '\t' || c == '\n') {
-            // https://www.w3.org/TR/xml/#AVNormalize
-            // \r and \r\n already handled earlier
-            result.push(' ');
-            change = true;
-        } else {
-            result.push(c);
+        };
+        if self.open.contains(&name) {
+            return Err(ParseError::RecursiveEntity(name.to_string(), span));
         }
+        // we only expand replacement text that is character data. Markup is
+        // never allowed in attribute values, and we don't support it in text
+        // content.
+        if replacement.contains('<') {
+            return Err(ParseError::EntityMarkup(name.to_string(), span));
+        }
+        self.entities.spend(replacement, span)?;
+        self.open.push(name);
+        let origin = self.origin.replace(span);
+        // the replacement text has its line endings normalized already, so
+        // any remaining whitespace in attributes is normalized during the
+        // expansion
+        self.expand(replacement, span.start, result)?;
+        self.origin = origin;
+        self.open.pop();
+        Ok(())
     }
+}
 
-    if !change {
-        Ok(content)
+fn parse_character_reference(
+    entity: &str,
+    span: Span,
+    version: XmlVersion,
+) -> Result<char, ParseError> {
+    let first_char = entity
+        .chars()
+        .next()
+        .ok_or_else(|| ParseError::InvalidEntity(entity.to_string(), span))?;
+    let code = if first_char == 'x' {
+        u32::from_str_radix(&entity[1..], 16)
     } else {
-        Ok(result.into())
-    }
+        entity.parse::<u32>()
+    };
+    let code = code.map_err(|_| ParseError::InvalidEntity(entity.to_string(), span))?;
+    std::char::from_u32(code)
+        // the characters that may be written as a character reference are
+        // those that match the `Char` production
+        .filter(|c| is_char(*c, version))
+        .ok_or_else(|| ParseError::InvalidEntity(entity.to_string(), span))
+}
+
+/// Parse the literal value of an internal entity declaration into its
+/// replacement text.
+///
+/// Character references are expanded immediately, but general entity
+/// references are left as they are; they're expanded when the entity is
+/// used. See <https://www.w3.org/TR/xml/#intern-replacement>
+pub(crate) fn parse_entity_value(
+    content: &str,
+    base_position: usize,
+    version: XmlVersion,
+) -> Result<String, ParseError> {
+    if content.contains('%') {
+        return Err(ParseError::DtdUnsupported(Span::new(
+            base_position,
+            base_position + content.len(),
+        )));
+    }
+    let mut result = String::with_capacity(content.len());
+    let mut rest = content;
+    let mut offset = base_position;
+    while let Some(index) = rest.find("&#") {
+        result.push_str(&rest[..index]);
+        let reference = &rest[index..];
+        let end = reference.find(';').ok_or_else(|| {
+            ParseError::UnclosedEntity(reference[1..].to_string(), offset + index)
+        })?;
+        let span = Span::new(offset + index, offset + index + end + 1);
+        result.push(parse_character_reference(
+            &reference[2..end],
+            span,
+            version,
+        )?);
+        rest = &reference[end + 1..];
+        offset = span.end;
+    }
+    result.push_str(rest);
+    Ok(result)
 }
 
 pub(crate) fn serialize_text<'a, N: Normalizer>(
@@ -261,19 +462,25 @@ mod tests {
     #[test]
     fn test_parse() {
         let text = "A &amp; B";
-        assert_eq!(parse_text(text.into(), 0).unwrap(), "A & B");
+        assert_eq!(
+            parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A & B"
+        );
     }
 
     #[test]
     fn test_parse_multiple() {
         let text = "&amp;&apos;&gt;&lt;&quot;";
-        assert_eq!(parse_text(text.into(), 0).unwrap(), "&'><\"");
+        assert_eq!(
+            parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "&'><\""
+        );
     }
 
     #[test]
     fn test_parse_unknown_entity() {
         let text = "&unknown;";
-        let err = parse_text(text.into(), 0);
+        let err = parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0);
         if let Err(ParseError::InvalidEntity(entity, span)) = err {
             assert_eq!(entity, "unknown");
             assert_eq!(span, Span::new(0, 9));
@@ -285,7 +492,7 @@ mod tests {
     #[test]
     fn test_parse_unfinished_entity() {
         let text = "&amp";
-        let err = parse_text(text.into(), 0);
+        let err = parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0);
         if let Err(ParseError::UnclosedEntity(entity, position)) = err {
             assert_eq!(entity, "amp");
             assert_eq!(position, 0);
@@ -294,10 +501,131 @@ mod tests {
         }
     }
 
+    fn entities() -> Entities {
+        let mut entities = Entities::new();
+        entities.declare("foo", Entity::Internal("FOO".to_string()));
+        entities.declare("nested", Entity::Internal("[&foo;]".to_string()));
+        entities.declare("spaces", Entity::Internal("A\tB".to_string()));
+        entities.declare("loop", Entity::Internal("&loop2;".to_string()));
+        entities.declare("loop2", Entity::Internal("&loop;".to_string()));
+        entities.declare("markup", Entity::Internal("<p/>".to_string()));
+        entities.declare("external", Entity::External);
+        entities
+    }
+
+    #[test]
+    fn test_parse_declared_entity() {
+        let text = "A &foo; B";
+        assert_eq!(
+            parse_text(text.into(), 0, &entities(), XmlVersion::V1_0).unwrap(),
+            "A FOO B"
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_declared_entity() {
+        let text = "&nested;";
+        assert_eq!(
+            parse_text(text.into(), 0, &entities(), XmlVersion::V1_0).unwrap(),
+            "[FOO]"
+        );
+    }
+
+    #[test]
+    fn test_parse_declared_entity_first_declaration_binds() {
+        let mut entities = entities();
+        entities.declare("foo", Entity::Internal("BAR".to_string()));
+        let text = "&foo;";
+        assert_eq!(
+            parse_text(text.into(), 0, &entities, XmlVersion::V1_0).unwrap(),
+            "FOO"
+        );
+    }
+
+    #[test]
+    fn test_parse_declared_entity_attribute_normalized() {
+        let text = "&spaces;";
+        assert_eq!(
+            parse_attribute(text.into(), 0, &entities(), XmlVersion::V1_0).unwrap(),
+            "A B"
+        );
+        assert_eq!(
+            parse_text(text.into(), 0, &entities(), XmlVersion::V1_0).unwrap(),
+            "A\tB"
+        );
+    }
+
+    #[test]
+    fn test_parse_recursive_entity() {
+        let text = "A &loop; B";
+        let err = parse_text(text.into(), 0, &entities(), XmlVersion::V1_0);
+        if let Err(ParseError::RecursiveEntity(entity, span)) = err {
+            assert_eq!(entity, "loop");
+            // the span is that of the outermost reference
+            assert_eq!(span, Span::new(2, 8));
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn test_parse_entity_expansion_limit() {
+        let mut entities = entities();
+        entities.limits.max_entity_expansion = Some(10);
+        // the expansion counts the replacement text of each entity
+        assert_eq!(
+            parse_text("&nested;".into(), 0, &entities, XmlVersion::V1_0).unwrap(),
+            "[FOO]"
+        );
+        // the count is shared by all content of a document
+        let err = parse_text("A &nested;".into(), 10, &entities, XmlVersion::V1_0);
+        if let Err(ParseError::LimitExceeded(ParseLimit::EntityExpansion, span)) = err {
+            // the span is that of the outermost reference
+            assert_eq!(span, Span::new(12, 20));
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn test_parse_entity_references_limit() {
+        let mut entities = entities();
+        entities.limits.max_entity_references = Some(3);
+        let err = parse_text("&foo;&foo;&nested;".into(), 0, &entities, XmlVersion::V1_0);
+        if let Err(ParseError::LimitExceeded(ParseLimit::EntityReferences, span)) = err {
+            // the nested reference is one too many
+            assert_eq!(span, Span::new(10, 18));
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn test_parse_markup_entity() {
+        let text = "&markup;";
+        let err = parse_text(text.into(), 0, &entities(), XmlVersion::V1_0);
+        assert!(matches!(err, Err(ParseError::EntityMarkup(_, _))));
+    }
+
+    #[test]
+    fn test_parse_external_entity() {
+        let text = "&external;";
+        let err = parse_text(text.into(), 0, &entities(), XmlVersion::V1_0);
+        assert!(matches!(err, Err(ParseError::ExternalEntity(_, _))));
+    }
+
+    #[test]
+    fn test_parse_entity_value() {
+        assert_eq!(
+            parse_entity_value("A &#38; &foo; &#x3C;", 0, XmlVersion::V1_0).unwrap(),
+            "A & &foo; <"
+        );
+    }
+
     #[test]
     fn test_parse_no_entities() {
         let text = "hello";
-        let result = parse_text(text.into(), 0).unwrap();
+        let result = parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap();
         // this is the same slice
         assert!(std::ptr::eq(text, result.as_ref()));
     }
@@ -305,49 +633,73 @@ mod tests {
     #[test]
     fn test_parse_newline_r() {
         let text = "A \r B";
-        assert_eq!(parse_text(text.into(), 0).unwrap(), "A \n B");
+        assert_eq!(
+            parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A \n B"
+        );
     }
 
     #[test]
     fn test_parse_newline_rn() {
         let text = "A \r\n B";
-        assert_eq!(parse_text(text.into(), 0).unwrap(), "A \n B");
+        assert_eq!(
+            parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A \n B"
+        );
     }
 
     #[test]
     fn test_do_not_normalize_text_tab() {
         let text = "A \t B";
-        assert_eq!(parse_text(text.into(), 0).unwrap(), "A \t B");
+        assert_eq!(
+            parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A \t B"
+        );
     }
 
     #[test]
     fn test_do_not_normalize_text_newline() {
         let text = "A \n B";
-        assert_eq!(parse_text(text.into(), 0).unwrap(), "A \n B");
+        assert_eq!(
+            parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A \n B"
+        );
     }
 
     #[test]
     fn test_normalize_attribute_tab() {
         let text = "A \t B";
-        assert_eq!(parse_attribute(text.into(), 0).unwrap(), "A   B");
+        assert_eq!(
+            parse_attribute(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A   B"
+        );
     }
 
     #[test]
     fn test_normalize_attribute_r_newline() {
         let text = "A \r B";
-        assert_eq!(parse_attribute(text.into(), 0).unwrap(), "A   B");
+        assert_eq!(
+            parse_attribute(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A   B"
+        );
     }
 
     #[test]
     fn test_normalize_attribute_rn_newline() {
         let text = "A \r\n B";
-        assert_eq!(parse_attribute(text.into(), 0).unwrap(), "A   B");
+        assert_eq!(
+            parse_attribute(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A   B"
+        );
     }
 
     #[test]
     fn test_normalize_attribute_newline() {
         let text = "A \n B";
-        assert_eq!(parse_attribute(text.into(), 0).unwrap(), "A   B");
+        assert_eq!(
+            parse_attribute(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A   B"
+        );
     }
 
     #[test]
@@ -468,31 +820,37 @@ mod tests {
     #[test]
     fn test_parse_character_hex_entity() {
         let text = "A &#x26; B";
-        assert_eq!(parse_text(text.into(), 0).unwrap(), "A & B");
+        assert_eq!(
+            parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A & B"
+        );
     }
 
     #[test]
     fn test_parse_character_decimal_entity() {
         let text = "A &#38; B";
-        assert_eq!(parse_text(text.into(), 0).unwrap(), "A & B");
+        assert_eq!(
+            parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).unwrap(),
+            "A & B"
+        );
     }
 
     #[test]
     fn test_parse_character_empty_entity() {
         let text = "A &#; B";
-        assert!(parse_text(text.into(), 0).is_err());
+        assert!(parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).is_err());
     }
 
     #[test]
     fn test_parse_character_empty_hex_entity() {
         let text = "A &x#; B";
-        assert!(parse_text(text.into(), 0).is_err());
+        assert!(parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).is_err());
     }
 
     #[test]
     fn test_parse_character_broken_hex_entity() {
         let text = "A &xflub#; B";
-        assert!(parse_text(text.into(), 0).is_err());
+        assert!(parse_text(text.into(), 0, &Entities::new(), XmlVersion::V1_0).is_err());
     }
 
     #[test]
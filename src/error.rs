@@ -10,14 +10,40 @@ pub enum ParseError {
     /// The XML is not well-formed - you use `&` to open an entity without
     /// closing it with `;`.
     UnclosedEntity(String, usize),
-    /// The entity is not known. Only the basic entities are supported
-    /// right now, not any user defined ones.
+    /// The entity is not known. Only the basic entities and general entities
+    /// declared in the internal DTD subset are supported.
     InvalidEntity(String, Span),
+    /// A reference to an external entity. External entities are never loaded.
+    ExternalEntity(String, Span),
+    /// An entity refers to itself, directly or indirectly.
+    RecursiveEntity(String, Span),
+    /// The replacement text of an entity contains markup. This is not allowed
+    /// in attribute values, and is not supported in text content.
+    ///
+    /// This is a known restriction: any `<` in the replacement text counts as
+    /// markup, including `&#60;` in the entity declaration, which expands to
+    /// `<`. Such documents are well-formed XML, but Xot rejects them. Use
+    /// `&#38;#60;` in the declaration, or `&lt;`, to get a literal `<`.
+    EntityMarkup(String, Span),
     /// You used a namespace prefix that is not declared during parsing.
     UnknownPrefix(String, Span),
     /// You declared an attribute of the same name twice.
     DuplicateAttribute(String, Span),
-    /// Unsupported XML version. Only 1.0 is supported.
+    /// Two attributes have the same local name and namespace, though they
+    /// use different prefixes.
+    DuplicateExpandedAttribute(String, Span),
+    /// The `xml` prefix is bound to a namespace other than the XML
+    /// namespace, or the `xmlns` prefix is declared. Both prefixes are
+    /// reserved.
+    ReservedPrefix(String, Span),
+    /// A prefix other than `xml` is bound to the XML namespace, or a prefix
+    /// or the default namespace is bound to the `xmlns` namespace
+    /// (`http://www.w3.org/2000/xmlns/`).
+    ReservedNamespace(String, Span),
+    /// A prefix is undeclared with `xmlns:p=""`. This is only allowed in
+    /// XML 1.1.
+    PrefixUndeclaration(String, Span),
+    /// Unsupported XML version. Only 1.0 and 1.1 are supported.
     UnsupportedVersion(String, Span),
     /// Unsupported standalone declaration. This error is deprecated since version 0.29, and both
     /// "yes" and "no" values are accepted for the standalone declaration.
@@ -26,18 +52,71 @@ pub enum ParseError {
         note = "The value of the standalone declaration is now ignored"
     )]
     UnsupportedNotStandalone(Span),
-    /// XML DTD is not supported.
+    /// This DTD feature is not supported. Parameter entity references are not
+    /// supported, for instance.
     DtdUnsupported(Span),
+    /// An attribute list declaration (`<!ATTLIST>`) in the internal DTD
+    /// subset is not well-formed.
+    InvalidAttributeDeclaration(Span),
     /// No top-level element in the document.
     NoElementAtTopLevel(usize),
     /// Multiple top-level elements in the document.
     MultipleElementsAtTopLevel(Span),
     /// Text at top level is not allowed in a well-formed document.
     TextAtTopLevel(Span),
-    /// Duplicate xml:id is not allowed
+    /// Duplicate xml:id is not allowed. This also applies to attributes
+    /// declared with type `ID` in the internal DTD subset.
     DuplicateId(String, Span),
+    /// A limit set in [`ParseLimits`](crate::ParseLimits) was exceeded. The
+    /// span is that of the node where this happened.
+    LimitExceeded(ParseLimit, Span),
     /// xmlparser error
     XmlParser(xmlparser::Error, usize),
+    /// The input contains a byte sequence that isn't valid in its encoding.
+    /// The position is the byte offset in the input.
+    MalformedEncoding(String, usize),
+    /// An IO error while reading the input. The position is the byte offset
+    /// in the input where reading failed.
+    ///
+    /// We take the string version of the IO error so as to keep errors
+    /// clonable.
+    Io(String, usize),
+}
+
+/// A limit on the resources used by parsing.
+///
+/// This is reported by [`ParseError::LimitExceeded`]; see
+/// [`ParseLimits`](crate::ParseLimits) for the limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParseLimit {
+    /// The nesting depth of elements.
+    Depth,
+    /// The number of attributes and namespace declarations on an element.
+    Attributes,
+    /// The length of a text node.
+    TextLength,
+    /// The number of nodes.
+    Nodes,
+    /// The number of names, namespaces and prefixes added.
+    Names,
+    /// The total length of the replacement text of expanded entities.
+    EntityExpansion,
+    /// The number of references to declared entities.
+    EntityReferences,
+}
+
+impl std::fmt::Display for ParseLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseLimit::Depth => write!(f, "nesting depth"),
+            ParseLimit::Attributes => write!(f, "attributes per element"),
+            ParseLimit::TextLength => write!(f, "text length"),
+            ParseLimit::Nodes => write!(f, "number of nodes"),
+            ParseLimit::Names => write!(f, "number of names"),
+            ParseLimit::EntityExpansion => write!(f, "entity expansion"),
+            ParseLimit::EntityReferences => write!(f, "number of entity references"),
+        }
+    }
 }
 
 impl ParseError {
@@ -48,17 +127,73 @@ impl ParseError {
             ParseError::InvalidCloseTag(_, _, span) => *span,
             ParseError::UnclosedEntity(_, position) => Span::new(*position, *position),
             ParseError::InvalidEntity(_, span) => *span,
+            ParseError::ExternalEntity(_, span) => *span,
+            ParseError::RecursiveEntity(_, span) => *span,
+            ParseError::EntityMarkup(_, span) => *span,
             ParseError::UnknownPrefix(_, span) => *span,
             ParseError::DuplicateAttribute(_, span) => *span,
+            ParseError::DuplicateExpandedAttribute(_, span) => *span,
+            ParseError::ReservedPrefix(_, span) => *span,
+            ParseError::ReservedNamespace(_, span) => *span,
+            ParseError::PrefixUndeclaration(_, span) => *span,
             ParseError::UnsupportedVersion(_, span) => *span,
             #[allow(deprecated)]
             ParseError::UnsupportedNotStandalone(span) => *span,
             ParseError::DtdUnsupported(span) => *span,
+            ParseError::InvalidAttributeDeclaration(span) => *span,
             ParseError::NoElementAtTopLevel(position) => Span::new(*position, *position),
             ParseError::MultipleElementsAtTopLevel(span) => *span,
             ParseError::TextAtTopLevel(span) => *span,
             ParseError::DuplicateId(_, span) => *span,
+            ParseError::LimitExceeded(_, span) => *span,
             ParseError::XmlParser(_, position) => Span::new(*position, *position),
+            ParseError::MalformedEncoding(_, position) => Span::new(*position, *position),
+            ParseError::Io(_, position) => Span::new(*position, *position),
+        }
+    }
+
+    // maps all positions in the error with `f`
+    pub(crate) fn map_positions(self, f: impl Fn(usize) -> usize) -> ParseError {
+        let span = |span: Span| Span::new(f(span.start), f(span.end));
+        match self {
+            ParseError::UnclosedTag(s) => ParseError::UnclosedTag(span(s)),
+            ParseError::InvalidCloseTag(a, b, s) => ParseError::InvalidCloseTag(a, b, span(s)),
+            ParseError::UnclosedEntity(a, position) => ParseError::UnclosedEntity(a, f(position)),
+            ParseError::InvalidEntity(a, s) => ParseError::InvalidEntity(a, span(s)),
+            ParseError::ExternalEntity(a, s) => ParseError::ExternalEntity(a, span(s)),
+            ParseError::RecursiveEntity(a, s) => ParseError::RecursiveEntity(a, span(s)),
+            ParseError::EntityMarkup(a, s) => ParseError::EntityMarkup(a, span(s)),
+            ParseError::UnknownPrefix(a, s) => ParseError::UnknownPrefix(a, span(s)),
+            ParseError::DuplicateAttribute(a, s) => ParseError::DuplicateAttribute(a, span(s)),
+            ParseError::DuplicateExpandedAttribute(a, s) => {
+                ParseError::DuplicateExpandedAttribute(a, span(s))
+            }
+            ParseError::ReservedPrefix(a, s) => ParseError::ReservedPrefix(a, span(s)),
+            ParseError::ReservedNamespace(a, s) => ParseError::ReservedNamespace(a, span(s)),
+            ParseError::PrefixUndeclaration(a, s) => ParseError::PrefixUndeclaration(a, span(s)),
+            ParseError::UnsupportedVersion(a, s) => ParseError::UnsupportedVersion(a, span(s)),
+            #[allow(deprecated)]
+            ParseError::UnsupportedNotStandalone(s) => {
+                ParseError::UnsupportedNotStandalone(span(s))
+            }
+            ParseError::DtdUnsupported(s) => ParseError::DtdUnsupported(span(s)),
+            ParseError::InvalidAttributeDeclaration(s) => {
+                ParseError::InvalidAttributeDeclaration(span(s))
+            }
+            ParseError::NoElementAtTopLevel(position) => {
+                ParseError::NoElementAtTopLevel(f(position))
+            }
+            ParseError::MultipleElementsAtTopLevel(s) => {
+                ParseError::MultipleElementsAtTopLevel(span(s))
+            }
+            ParseError::TextAtTopLevel(s) => ParseError::TextAtTopLevel(span(s)),
+            ParseError::DuplicateId(a, s) => ParseError::DuplicateId(a, span(s)),
+            ParseError::LimitExceeded(a, s) => ParseError::LimitExceeded(a, span(s)),
+            ParseError::XmlParser(e, position) => ParseError::XmlParser(e, f(position)),
+            ParseError::MalformedEncoding(a, position) => {
+                ParseError::MalformedEncoding(a, f(position))
+            }
+            ParseError::Io(a, position) => ParseError::Io(a, f(position)),
         }
     }
 }
@@ -82,6 +217,22 @@ pub enum Error {
     /// You aren't allowed to use this string as a processing instruction
     /// target. Happens if you use `XML` or any case variation of this.
     InvalidTarget(String),
+    /// You aren't allowed to use this string as a name or prefix, as it's not
+    /// an `NCName`: a name without a colon.
+    InvalidName(String),
+    /// You aren't allowed to use this prefix, as it's reserved. Happens if
+    /// you use `xmlns`.
+    ReservedPrefix(String),
+    /// You aren't allowed to add this namespace, as it's reserved. Happens if
+    /// you use the XML namespace, the `xmlns` namespace, or the empty string,
+    /// which means no namespace.
+    ReservedNamespace(String),
+    /// The character isn't allowed in XML 1.0, such as `U+0000` or most
+    /// other control characters.
+    InvalidCharacter(char),
+    /// You aren't allowed to use this string as processing instruction
+    /// data. Happens if you include `?>`.
+    InvalidProcessingInstruction(String),
     /// The node you tried to act on is not an element.
     NotElement(Node),
     /// Indextree error that can happen during manipulation.
@@ -120,6 +271,27 @@ pub enum Error {
     /// Multiple document elements at top level
     MultipleElementsAtTopLevel,
 
+    /// The encoding in the XML declaration is not supported for
+    /// serialization.
+    UnsupportedEncoding(String),
+    /// A character cannot be represented in the output encoding, in a place
+    /// where a character reference is not allowed, such as in a name or a
+    /// comment.
+    UnencodableCharacter(char, String),
+
+    // xinclude
+    /// An `xi:include` element is invalid, for instance because it has
+    /// neither an `href` nor an `xpointer` attribute.
+    InvalidInclude(String),
+    /// An `xi:include` element includes a resource that is already being
+    /// included, so inclusion would never end.
+    IncludeLoop(String),
+    /// The `xpointer` of an `xi:include` element is invalid or doesn't
+    /// identify an element.
+    XPointer(String),
+    /// A resolver could not find a resource to include.
+    ResourceNotFound(String),
+
     /// IO error
     ///
     /// We take the string version of the IO error so as to keep errors comparable,
@@ -155,6 +327,13 @@ impl std::fmt::Display for Error {
             Error::InvalidOperation(s) => write!(f, "Invalid operation: {}", s),
             Error::InvalidComment(s) => write!(f, "Invalid comment: {}", s),
             Error::InvalidTarget(s) => write!(f, "Invalid target: {}", s),
+            Error::InvalidName(s) => write!(f, "Invalid name: {:?}", s),
+            Error::ReservedPrefix(s) => write!(f, "Reserved prefix: {}", s),
+            Error::ReservedNamespace(s) => write!(f, "Reserved namespace: {:?}", s),
+            Error::InvalidCharacter(c) => write!(f, "Invalid character: {:?}", c),
+            Error::InvalidProcessingInstruction(s) => {
+                write!(f, "Invalid processing instruction data: {}", s)
+            }
             Error::NotElement(_) => write!(f, "Not an element"),
             Error::NodeError(e) => write!(f, "Node error: {}", e),
             Error::MissingPrefix(_) => write!(f, "Missing prefix"),
@@ -164,12 +343,20 @@ impl std::fmt::Display for Error {
             Error::NamespaceInProcessingInstruction => {
                 write!(f, "Namespace in processing instruction target")
             }
-            Error::Parse(e) => write!(f, "Parse error: {:?}", e),
+            Error::Parse(e) => write!(f, "Parse error: {}", e),
             Error::UnknownPrefix(s) => write!(f, "Unknown prefix: {}", s),
             Error::IllegalAtTopLevel(_) => write!(f, "Illegal content under document node (attribute, namespace or document node"),
             Error::TextAtTopLevel(_) => write!(f, "Text node under document not. Not allowed in a well-formed document, but allowed in a fragment"),
             Error::NoElementAtTopLevel => write!(f, "No element under document root. Not allowed in a well-formed document, but allowed in a fragment"),
             Error::MultipleElementsAtTopLevel => write!(f, "Multiple elements under document root. Not allowed in a well-formed document, but allowed in a fragment"),
+            Error::UnsupportedEncoding(s) => write!(f, "Unsupported encoding: {}", s),
+            Error::UnencodableCharacter(c, s) => {
+                write!(f, "Character {:?} cannot be represented in {}", c, s)
+            }
+            Error::InvalidInclude(s) => write!(f, "Invalid xi:include: {}", s),
+            Error::IncludeLoop(s) => write!(f, "Inclusion loop: {}", s),
+            Error::XPointer(s) => write!(f, "XPointer error: {}", s),
+            Error::ResourceNotFound(s) => write!(f, "Resource not found: {}", s),
             Error::Io(s) => write!(f, "IO error: {}", s),
         }
     }
@@ -179,22 +366,44 @@ impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ParseError::UnclosedTag(_) => write!(f, "Unclosed tag"),
-            ParseError::InvalidCloseTag(s, s2, _) => write!(f, "Invalid close tag: {} {}", s, s2),
+            ParseError::InvalidCloseTag(prefix, name, _) if prefix.is_empty() => {
+                write!(f, "Invalid close tag: {}", name)
+            }
+            ParseError::InvalidCloseTag(prefix, name, _) => {
+                write!(f, "Invalid close tag: {}:{}", prefix, name)
+            }
             ParseError::UnclosedEntity(s, _) => write!(f, "Unclosed entity: {}", s),
             ParseError::InvalidEntity(s, _) => write!(f, "Invalid entity: {}", s),
+            ParseError::ExternalEntity(s, _) => write!(f, "External entity: {}", s),
+            ParseError::RecursiveEntity(s, _) => write!(f, "Recursive entity: {}", s),
+            ParseError::EntityMarkup(s, _) => write!(f, "Markup in entity: {}", s),
             ParseError::UnknownPrefix(s, _) => write!(f, "Unknown prefix: {}", s),
             ParseError::DuplicateAttribute(s, _) => write!(f, "Duplicate attribute: {}", s),
+            ParseError::DuplicateExpandedAttribute(s, _) => {
+                write!(f, "Duplicate attribute after namespace resolution: {}", s)
+            }
+            ParseError::ReservedPrefix(s, _) => write!(f, "Reserved prefix: {}", s),
+            ParseError::ReservedNamespace(s, _) => write!(f, "Reserved namespace: {}", s),
+            ParseError::PrefixUndeclaration(s, _) => {
+                write!(f, "Prefix undeclaration not allowed in XML 1.0: {}", s)
+            }
             ParseError::UnsupportedVersion(s, _) => write!(f, "Unsupported version: {}", s),
             #[allow(deprecated)]
             ParseError::UnsupportedNotStandalone(_) => write!(f, "Unsupported standalone"),
-            ParseError::DtdUnsupported(_) => write!(f, "DTD is not supported"),
+            ParseError::DtdUnsupported(_) => write!(f, "DTD feature is not supported"),
+            ParseError::InvalidAttributeDeclaration(_) => {
+                write!(f, "Invalid attribute list declaration")
+            }
             ParseError::NoElementAtTopLevel(_) => write!(f, "No element at top level"),
             ParseError::MultipleElementsAtTopLevel(_) => {
                 write!(f, "Multiple elements at top level")
             }
             ParseError::TextAtTopLevel(_) => write!(f, "Text at top level"),
-            ParseError::DuplicateId(s, _) => write!(f, "Duplicate xml:id: {}", s),
+            ParseError::DuplicateId(s, _) => write!(f, "Duplicate id: {}", s),
+            ParseError::LimitExceeded(limit, _) => write!(f, "Limit exceeded: {}", limit),
             ParseError::XmlParser(e, _position) => write!(f, "Parser error: {}", e),
+            ParseError::MalformedEncoding(s, _) => write!(f, "Malformed {} input", s),
+            ParseError::Io(s, _) => write!(f, "IO error: {}", s),
         }
     }
 }
@@ -1,9 +1,13 @@
 use ahash::HashMap;
 use std::borrow::Borrow;
 use std::hash::Hash;
+use std::sync::Arc;
+
+use super::shared::SharedIdMap;
 
 pub(crate) trait IdIndex<T> {
-    fn to_id(index: usize) -> T;
+    // None if the index doesn't fit in an id
+    fn to_id(index: usize) -> Option<T>;
     fn from_id(id: T) -> usize;
 }
 
@@ -11,6 +15,9 @@ pub(crate) trait IdIndex<T> {
 pub(crate) struct IdMap<K: Copy + IdIndex<K>, V: Eq + std::hash::Hash + Clone> {
     by_id: Vec<V>,
     by_value: HashMap<V, K>,
+    // if set, the values live in tables shared with other Xots, and the
+    // local tables are unused
+    shared: Option<Arc<SharedIdMap<K, V>>>,
 }
 
 impl<K: Copy + IdIndex<K>, V: Eq + std::hash::Hash + Clone> IdMap<K, V> {
@@ -18,23 +25,54 @@ impl<K: Copy + IdIndex<K>, V: Eq + std::hash::Hash + Clone> IdMap<K, V> {
         IdMap {
             by_id: Vec::new(),
             by_value: HashMap::default(),
+            shared: None,
+        }
+    }
+
+    pub(crate) fn shared(shared: Arc<SharedIdMap<K, V>>) -> Self {
+        IdMap {
+            by_id: Vec::new(),
+            by_value: HashMap::default(),
+            shared: Some(shared),
         }
     }
 
+    pub(crate) fn is_shared(&self) -> bool {
+        self.shared.is_some()
+    }
+
     pub(crate) fn get_id_mut<Q>(&mut self, value: &Q) -> K
     where
         V: Borrow<Q>,
         Q: Hash + Eq + ToOwned<Owned = V> + ?Sized,
     {
+        self.insert(value).0
+    }
+
+    // like get_id_mut, but also returns whether the value was added
+    pub(crate) fn insert<Q>(&mut self, value: &Q) -> (K, bool)
+    where
+        V: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = V> + ?Sized,
+    {
+        if let Some(shared) = &self.shared {
+            return shared.insert(value);
+        }
         let id = self.by_value.get(value);
         if let Some(id) = id {
-            *id
+            (*id, false)
         } else {
-            let id = K::to_id(self.by_id.len());
+            // we never wrap around, as that would alias an existing value
+            let Some(id) = K::to_id(self.by_id.len()) else {
+                panic!(
+                    "Too many distinct values: at most {} are supported",
+                    self.by_id.len()
+                );
+            };
             let cloned = value.to_owned();
             self.by_value.insert(cloned.clone(), id);
             self.by_id.push(cloned);
-            id
+            (id, true)
         }
     }
 
@@ -43,13 +81,51 @@ impl<K: Copy + IdIndex<K>, V: Eq + std::hash::Hash + Clone> IdMap<K, V> {
         V: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
+        if let Some(shared) = &self.shared {
+            return shared.get_id(value);
+        }
         self.by_value.get(value).copied()
     }
 
     #[inline]
     pub(crate) fn get_value(&self, id: K) -> &V {
+        if let Some(shared) = &self.shared {
+            return shared.get_value(id);
+        }
         &self.by_id[K::from_id(id)]
     }
+
+    pub(crate) fn len(&self) -> usize {
+        if let Some(shared) = &self.shared {
+            return shared.len();
+        }
+        self.by_id.len()
+    }
+
+    // Drop all values for which `keep` is false, and renumber the remaining
+    // ones in their original order. `map_value` is used to update the kept
+    // values. Returns the new id of each old id, indexed by old id.
+    // Shared maps cannot be compacted.
+    pub(crate) fn compact(&mut self, keep: &[bool], map_value: impl Fn(V) -> V) -> Vec<Option<K>> {
+        debug_assert!(self.shared.is_none());
+        let by_id = std::mem::take(&mut self.by_id);
+        self.by_value.clear();
+        by_id
+            .into_iter()
+            .zip(keep)
+            .map(|(value, keep)| {
+                if !keep {
+                    return None;
+                }
+                // this cannot overflow, as we have fewer values than before
+                let id = K::to_id(self.by_id.len()).unwrap();
+                let value = map_value(value);
+                self.by_value.insert(value.clone(), id);
+                self.by_id.push(value);
+                Some(id)
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -62,8 +138,8 @@ mod tests {
         struct Id(u32);
 
         impl IdIndex<Id> for Id {
-            fn to_id(index: usize) -> Id {
-                Id(index as u32)
+            fn to_id(index: usize) -> Option<Id> {
+                u32::try_from(index).ok().map(Id)
             }
 
             fn from_id(id: Id) -> usize {
@@ -84,6 +160,58 @@ mod tests {
         assert_eq!(id1, id4);
     }
 
+    #[test]
+    fn test_id_map_compact() {
+        #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+        struct Id(u32);
+
+        impl IdIndex<Id> for Id {
+            fn to_id(index: usize) -> Option<Id> {
+                u32::try_from(index).ok().map(Id)
+            }
+
+            fn from_id(id: Id) -> usize {
+                id.0 as usize
+            }
+        }
+
+        let mut map = IdMap::<Id, String>::new();
+        map.get_id_mut("a");
+        map.get_id_mut("b");
+        map.get_id_mut("c");
+        let remapping = map.compact(&[true, false, true], |value| value.to_uppercase());
+        assert_eq!(remapping, vec![Some(Id(0)), None, Some(Id(1))]);
+        assert_eq!(map.get_value(Id(1)), "C");
+        assert_eq!(map.get_id("C"), Some(Id(1)));
+        assert_eq!(map.get_id("b"), None);
+        assert_eq!(map.get_id_mut("d"), Id(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many distinct values")]
+    fn test_id_map_full() {
+        #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+        struct Id(u8);
+
+        impl IdIndex<Id> for Id {
+            fn to_id(index: usize) -> Option<Id> {
+                u8::try_from(index).ok().map(Id)
+            }
+
+            fn from_id(id: Id) -> usize {
+                id.0 as usize
+            }
+        }
+
+        let mut map = IdMap::<Id, String>::new();
+        for i in 0..=u8::MAX as usize {
+            map.get_id_mut(i.to_string().as_str());
+        }
+        // all ids are distinct
+        assert_eq!(map.get_value(Id(u8::MAX)), &u8::MAX.to_string());
+        map.get_id_mut("one too many");
+    }
+
     // #[test]
     // fn test_id_map_with_cow() {
     //     use std::borrow::Cow;
@@ -3,10 +3,15 @@ mod idmap;
 mod name;
 mod namespace;
 mod prefix;
+mod remapping;
+mod shared;
 
+pub(crate) use idmap::IdIndex;
 pub use name::NameId;
 pub(crate) use name::{Name, NameLookup};
 pub use namespace::NamespaceId;
 pub(crate) use namespace::NamespaceLookup;
 pub use prefix::PrefixId;
 pub(crate) use prefix::PrefixLookup;
+pub use remapping::IdRemapping;
+pub use shared::NameTables;
@@ -0,0 +1,60 @@
+use super::idmap::IdIndex;
+use super::name::NameId;
+use super::namespace::NamespaceId;
+use super::prefix::PrefixId;
+
+/// The new ids of names, namespaces and prefixes after compaction.
+///
+/// This is returned by [`Xot::compact_names`](crate::Xot::compact_names).
+/// Use it to update any ids you hold on to; an id that was dropped maps to
+/// `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdRemapping {
+    pub(crate) names: Vec<Option<NameId>>,
+    pub(crate) namespaces: Vec<Option<NamespaceId>>,
+    pub(crate) prefixes: Vec<Option<PrefixId>>,
+}
+
+impl IdRemapping {
+    /// The new id of a name, or `None` if it was dropped.
+    pub fn name(&self, name: NameId) -> Option<NameId> {
+        self.names.get(NameId::from_id(name)).copied().flatten()
+    }
+
+    /// The new id of a namespace, or `None` if it was dropped.
+    pub fn namespace(&self, namespace: NamespaceId) -> Option<NamespaceId> {
+        self.namespaces
+            .get(NamespaceId::from_id(namespace))
+            .copied()
+            .flatten()
+    }
+
+    /// The new id of a prefix, or `None` if it was dropped.
+    pub fn prefix(&self, prefix: PrefixId) -> Option<PrefixId> {
+        self.prefixes
+            .get(PrefixId::from_id(prefix))
+            .copied()
+            .flatten()
+    }
+
+    /// The number of names that were dropped.
+    pub fn dropped_names(&self) -> usize {
+        self.names.iter().filter(|name| name.is_none()).count()
+    }
+
+    /// The number of namespaces that were dropped.
+    pub fn dropped_namespaces(&self) -> usize {
+        self.namespaces
+            .iter()
+            .filter(|namespace| namespace.is_none())
+            .count()
+    }
+
+    /// The number of prefixes that were dropped.
+    pub fn dropped_prefixes(&self) -> usize {
+        self.prefixes
+            .iter()
+            .filter(|prefix| prefix.is_none())
+            .count()
+    }
+}
@@ -0,0 +1,275 @@
+// Interning tables that can be shared between multiple Xot instances, also
+// across threads. Values are only ever appended, and never move once they
+// are stored, so references to them can be handed out without holding a
+// lock.
+use ahash::HashMap;
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::idmap::{IdIndex, IdMap};
+use super::name::{Name, NameId};
+use super::namespace::NamespaceId;
+use super::prefix::PrefixId;
+
+// the size of the first chunk; each next chunk is twice as big
+const FIRST_CHUNK_SIZE: usize = 64;
+// enough chunks to hold more values than fit in an id
+const CHUNKS: usize = 32;
+
+// A vector that can be appended to through a shared reference. Values are
+// stored in chunks that are never reallocated.
+#[derive(Debug)]
+struct AppendOnlyVec<V> {
+    chunks: Vec<OnceLock<Box<[OnceLock<V>]>>>,
+    len: AtomicUsize,
+}
+
+impl<V> AppendOnlyVec<V> {
+    fn new() -> Self {
+        Self {
+            chunks: (0..CHUNKS).map(|_| OnceLock::new()).collect(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn location(index: usize) -> (usize, usize) {
+        let chunk = (usize::BITS - 1 - (index / FIRST_CHUNK_SIZE + 1).leading_zeros()) as usize;
+        let offset = index - FIRST_CHUNK_SIZE * ((1 << chunk) - 1);
+        (chunk, offset)
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    fn get(&self, index: usize) -> Option<&V> {
+        let (chunk, offset) = Self::location(index);
+        self.chunks.get(chunk)?.get()?.get(offset)?.get()
+    }
+
+    // this must only be called by one thread at a time
+    fn push(&self, value: V) {
+        let index = self.len.load(Ordering::Acquire);
+        let (chunk, offset) = Self::location(index);
+        let chunk = self.chunks[chunk].get_or_init(|| {
+            (0..FIRST_CHUNK_SIZE << chunk)
+                .map(|_| OnceLock::new())
+                .collect()
+        });
+        // the slot is empty, as only one thread pushes at a time
+        let _ = chunk[offset].set(value);
+        self.len.store(index + 1, Ordering::Release);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SharedIdMap<K, V> {
+    by_id: AppendOnlyVec<V>,
+    by_value: RwLock<HashMap<V, K>>,
+}
+
+impl<K: Copy + IdIndex<K>, V: Eq + Hash + Clone> SharedIdMap<K, V> {
+    fn new() -> Self {
+        Self {
+            by_id: AppendOnlyVec::new(),
+            by_value: RwLock::new(HashMap::default()),
+        }
+    }
+
+    pub(crate) fn get_id_mut<Q>(&self, value: &Q) -> K
+    where
+        V: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = V> + ?Sized,
+    {
+        self.insert(value).0
+    }
+
+    // like get_id_mut, but also returns whether this call added the value
+    pub(crate) fn insert<Q>(&self, value: &Q) -> (K, bool)
+    where
+        V: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = V> + ?Sized,
+    {
+        if let Some(id) = self.get_id(value) {
+            return (id, false);
+        }
+        let mut by_value = self.by_value.write().unwrap();
+        // another thread may have added it in the meantime
+        if let Some(id) = by_value.get(value) {
+            return (*id, false);
+        }
+        let len = self.by_id.len();
+        // we never wrap around, as that would alias an existing value
+        let Some(id) = K::to_id(len) else {
+            panic!("Too many distinct values: at most {} are supported", len);
+        };
+        let value = value.to_owned();
+        self.by_id.push(value.clone());
+        by_value.insert(value, id);
+        (id, true)
+    }
+
+    pub(crate) fn get_id<Q>(&self, value: &Q) -> Option<K>
+    where
+        V: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.by_value.read().unwrap().get(value).copied()
+    }
+
+    pub(crate) fn get_value(&self, id: K) -> &V {
+        self.by_id
+            .get(K::from_id(id))
+            .expect("id is not in the name tables")
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.by_id.len()
+    }
+}
+
+/// Name tables that are shared between multiple Xot instances.
+///
+/// Normally each [`Xot`](crate::Xot) has its own tables of names, namespaces
+/// and prefixes, so the same name has a different [`NameId`] in different
+/// Xots. If you create Xots with [`Xot::with_name_tables`](crate::Xot::with_name_tables)
+/// using the same `NameTables`, they share these tables, and ids can be
+/// compared between them. This works across threads, so you can for
+/// instance parse documents in parallel and compare their names without
+/// looking at strings.
+///
+/// You can also add names, namespaces and prefixes directly, so that you can
+/// compute the ids of constants before you create any Xot.
+///
+/// Cloning `NameTables` is cheap; the clones refer to the same tables.
+///
+/// ```rust
+/// use xot::{NameTables, Xot};
+///
+/// let tables = NameTables::new();
+/// let doc = tables.add_name("doc");
+///
+/// let handle = std::thread::spawn({
+///     let tables = tables.clone();
+///     move || {
+///         let mut xot = Xot::with_name_tables(&tables);
+///         let root = xot.parse("<doc/>").unwrap();
+///         xot.node_name(xot.document_element(root).unwrap()).unwrap()
+///     }
+/// });
+/// assert_eq!(handle.join().unwrap(), doc);
+/// ```
+#[derive(Debug, Clone)]
+pub struct NameTables {
+    pub(crate) names: Arc<SharedIdMap<NameId, Name>>,
+    pub(crate) namespaces: Arc<SharedIdMap<NamespaceId, String>>,
+    pub(crate) prefixes: Arc<SharedIdMap<PrefixId, String>>,
+}
+
+impl NameTables {
+    /// Create new, empty name tables.
+    pub fn new() -> Self {
+        Self {
+            names: Arc::new(SharedIdMap::new()),
+            namespaces: Arc::new(SharedIdMap::new()),
+            prefixes: Arc::new(SharedIdMap::new()),
+        }
+    }
+
+    /// Add a namespace. If it already exists, return its id.
+    pub fn add_namespace(&self, namespace: &str) -> NamespaceId {
+        self.namespaces.get_id_mut(namespace)
+    }
+
+    /// Add a prefix. If it already exists, return its id.
+    pub fn add_prefix(&self, prefix: &str) -> PrefixId {
+        self.prefixes.get_id_mut(prefix)
+    }
+
+    /// Add a name without a namespace. If it already exists, return its id.
+    pub fn add_name(&self, name: &str) -> NameId {
+        let no_namespace = self.add_namespace("");
+        self.add_name_ns(name, no_namespace)
+    }
+
+    /// Add a name in a namespace. If it already exists, return its id.
+    pub fn add_name_ns(&self, name: &str, namespace: NamespaceId) -> NameId {
+        self.names.get_id_mut(&Name::new(name, namespace))
+    }
+
+    // the lookups for a Xot that uses these tables
+    pub(crate) fn lookups(
+        &self,
+    ) -> (
+        IdMap<NamespaceId, String>,
+        IdMap<PrefixId, String>,
+        IdMap<NameId, Name>,
+    ) {
+        (
+            IdMap::shared(self.namespaces.clone()),
+            IdMap::shared(self.prefixes.clone()),
+            IdMap::shared(self.names.clone()),
+        )
+    }
+}
+
+impl Default for NameTables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_only_vec_location() {
+        assert_eq!(AppendOnlyVec::<()>::location(0), (0, 0));
+        assert_eq!(AppendOnlyVec::<()>::location(63), (0, 63));
+        assert_eq!(AppendOnlyVec::<()>::location(64), (1, 0));
+        assert_eq!(AppendOnlyVec::<()>::location(191), (1, 127));
+        assert_eq!(AppendOnlyVec::<()>::location(192), (2, 0));
+        // the last chunk can hold the largest id
+        let (chunk, offset) = AppendOnlyVec::<()>::location(u32::MAX as usize);
+        assert!(chunk < CHUNKS);
+        assert!(offset < FIRST_CHUNK_SIZE << chunk);
+    }
+
+    #[test]
+    fn test_append_only_vec() {
+        let v = AppendOnlyVec::new();
+        for i in 0..1000 {
+            v.push(i);
+        }
+        assert_eq!(v.len(), 1000);
+        assert_eq!(v.get(0), Some(&0));
+        assert_eq!(v.get(999), Some(&999));
+        assert_eq!(v.get(1000), None);
+    }
+
+    #[test]
+    fn test_shared_id_map_threads() {
+        let tables = NameTables::new();
+        let handles = (0..4)
+            .map(|_| {
+                let tables = tables.clone();
+                std::thread::spawn(move || {
+                    (0..500)
+                        .map(|i| tables.add_prefix(&format!("p{}", i)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+        // every thread got the same ids
+        assert!(results.iter().all(|result| result == &results[0]));
+        assert_eq!(tables.prefixes.len(), 500);
+        assert_eq!(tables.prefixes.get_value(results[0][42]), "p42");
+    }
+}
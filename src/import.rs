@@ -0,0 +1,67 @@
+// Importing nodes from another Xot. Names, namespaces and prefixes are
+// interned per Xot, so the ids in imported values need to be translated.
+use ahash::{HashMap, HashMapExt};
+
+use crate::id::{Name, NameId, NamespaceId, PrefixId};
+use crate::xmlvalue::Value;
+use crate::xotdata::Xot;
+
+pub(crate) struct Importer<'o> {
+    other: &'o Xot,
+    names: HashMap<NameId, NameId>,
+    namespaces: HashMap<NamespaceId, NamespaceId>,
+    prefixes: HashMap<PrefixId, PrefixId>,
+}
+
+impl<'o> Importer<'o> {
+    pub(crate) fn new(other: &'o Xot) -> Self {
+        Self {
+            other,
+            names: HashMap::new(),
+            namespaces: HashMap::new(),
+            prefixes: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn namespace(&mut self, xot: &mut Xot, namespace: NamespaceId) -> NamespaceId {
+        *self.namespaces.entry(namespace).or_insert_with(|| {
+            xot.namespace_lookup
+                .get_id_mut(self.other.namespace_lookup.get_value(namespace).as_str())
+        })
+    }
+
+    pub(crate) fn prefix(&mut self, xot: &mut Xot, prefix: PrefixId) -> PrefixId {
+        *self.prefixes.entry(prefix).or_insert_with(|| {
+            xot.prefix_lookup
+                .get_id_mut(self.other.prefix_lookup.get_value(prefix).as_str())
+        })
+    }
+
+    pub(crate) fn name(&mut self, xot: &mut Xot, name: NameId) -> NameId {
+        if let Some(imported) = self.names.get(&name) {
+            return *imported;
+        }
+        let other_name = self.other.name_lookup.get_value(name);
+        let namespace_id = self.namespace(xot, other_name.namespace_id);
+        let imported = xot
+            .name_lookup
+            .get_id_mut(&Name::new(other_name.name.as_str(), namespace_id));
+        self.names.insert(name, imported);
+        imported
+    }
+
+    // translate the ids in a value of the other Xot
+    pub(crate) fn value(&mut self, xot: &mut Xot, mut value: Value) -> Value {
+        match &mut value {
+            Value::Element(element) => element.name_id = self.name(xot, element.name_id),
+            Value::Attribute(attribute) => attribute.name_id = self.name(xot, attribute.name_id),
+            Value::ProcessingInstruction(pi) => pi.target = self.name(xot, pi.target),
+            Value::Namespace(namespace) => {
+                namespace.prefix_id = self.prefix(xot, namespace.prefix_id);
+                namespace.namespace_id = self.namespace(xot, namespace.namespace_id);
+            }
+            Value::Document | Value::Text(_) | Value::Comment(_) => {}
+        }
+        value
+    }
+}
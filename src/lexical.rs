@@ -0,0 +1,513 @@
+// Lexical detail of parsed XML, recorded in lossless mode so that untouched
+// nodes can be serialized exactly as they were in the source.
+//
+// The records don't refer to names, namespaces or prefixes by id, but by
+// their text in the source. The serializer only uses a record if what it
+// would otherwise generate is equivalent, so a record that has gone stale
+// because the tree was changed (or a node id was reused) is simply ignored.
+use std::borrow::Cow;
+
+use ahash::{HashMap, HashMapExt};
+use indextree::NodeId;
+
+use crate::id::{NamespaceId, PrefixId};
+use crate::parse::Span;
+use crate::pull;
+use crate::xmlvalue::Value;
+use crate::xotdata::{Node, Xot};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Lexical {
+    source: String,
+    nodes: HashMap<NodeId, NodeLexical>,
+    document: Option<DocumentLexical>,
+}
+
+#[derive(Debug, Clone)]
+enum NodeLexical {
+    Element(ElementLexical),
+    // text or a CDATA section
+    Text {
+        raw: Span,
+        value: Option<String>,
+    },
+    ProcessingInstruction {
+        raw: Span,
+        target: Span,
+        data: Option<Span>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ElementLexical {
+    // the qualified name in the start tag
+    qname: Span,
+    items: Vec<Item>,
+    // the whitespace before the end of the start tag, and `>` or `/>`
+    close: Span,
+    // the end tag, unless the element is written as `<a/>`
+    end_tag: Option<Span>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ItemKind {
+    Namespace,
+    Attribute,
+}
+
+// a namespace declaration or attribute in a start tag
+#[derive(Debug, Clone)]
+struct Item {
+    kind: ItemKind,
+    // the item including the whitespace before it. An attribute defaulted
+    // by the DTD has none.
+    raw: Option<Span>,
+    qname: Span,
+    // the value between the quotes
+    value: Span,
+    // the value after expansion and normalization, if it's not the same as
+    // the text in the source
+    expanded: Option<String>,
+}
+
+// the text around the top-level nodes of a document
+#[derive(Debug, Clone)]
+struct DocumentLexical {
+    // the text before the first node: the XML declaration, the document
+    // type declaration and whitespace
+    prolog: Span,
+    // the text before any next node
+    gaps: HashMap<NodeId, Span>,
+    // the text after the last node
+    epilog: Span,
+}
+
+impl Lexical {
+    fn str(&self, span: Span) -> &str {
+        &self.source[span.range()]
+    }
+
+    fn value<'a>(&'a self, item: &'a Item) -> &'a str {
+        item.expanded
+            .as_deref()
+            .unwrap_or_else(|| self.str(item.value))
+    }
+
+    pub(crate) fn element(&self, node: Node) -> Option<&ElementLexical> {
+        match self.nodes.get(&node.get()) {
+            Some(NodeLexical::Element(element)) => Some(element),
+            _ => None,
+        }
+    }
+
+    // the raw text of a text node or CDATA section, if it still has the
+    // given content
+    pub(crate) fn text(&self, node: Node, content: &str) -> Option<&str> {
+        match self.nodes.get(&node.get()) {
+            Some(NodeLexical::Text { raw, value }) => {
+                let raw = self.str(*raw);
+                let unchanged = match value {
+                    Some(value) => value == content,
+                    None => raw == content,
+                };
+                unchanged.then_some(raw)
+            }
+            _ => None,
+        }
+    }
+
+    // the raw text of a processing instruction, if it still has the given
+    // target and data
+    pub(crate) fn processing_instruction(
+        &self,
+        node: Node,
+        target: &str,
+        data: Option<&str>,
+    ) -> Option<&str> {
+        match self.nodes.get(&node.get()) {
+            Some(NodeLexical::ProcessingInstruction {
+                raw,
+                target: target_span,
+                data: data_span,
+            }) => (self.str(*target_span) == target
+                && data_span.map(|span| self.str(span)) == data)
+                .then(|| self.str(*raw)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn prolog(&self) -> Option<&str> {
+        self.document
+            .as_ref()
+            .map(|document| self.str(document.prolog))
+    }
+
+    pub(crate) fn epilog(&self) -> Option<&str> {
+        self.document
+            .as_ref()
+            .map(|document| self.str(document.epilog))
+    }
+
+    // the text before a top-level node
+    pub(crate) fn gap(&self, node: Node) -> &str {
+        self.document
+            .as_ref()
+            .and_then(|document| document.gaps.get(&node.get()))
+            .map_or("", |span| self.str(*span))
+    }
+
+    pub(crate) fn qname(&self, element: &ElementLexical) -> &str {
+        self.str(element.qname)
+    }
+
+    // the start tag up to its close
+    pub(crate) fn start_tag(&self, element: &ElementLexical) -> &str {
+        &self.source[element.qname.start - 1..element.close.start]
+    }
+
+    // the end of the start tag, given whether the element is written with an
+    // end tag
+    pub(crate) fn close(&self, element: &ElementLexical, end_tag: bool) -> Cow<'_, str> {
+        let close = self.str(element.close);
+        match close.strip_suffix("/>") {
+            Some(whitespace) if end_tag => format!("{}>", whitespace).into(),
+            _ => close.into(),
+        }
+    }
+
+    pub(crate) fn has_end_tag(&self, element: &ElementLexical) -> bool {
+        element.end_tag.is_some()
+    }
+
+    pub(crate) fn end_tag(&self, element: &ElementLexical) -> Option<&str> {
+        element.end_tag.map(|span| self.str(span))
+    }
+
+    // the namespace declarations as prefix and namespace, in order
+    pub(crate) fn namespaces<'a>(
+        &'a self,
+        element: &'a ElementLexical,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        element
+            .items
+            .iter()
+            .filter(|item| item.kind == ItemKind::Namespace)
+            .map(|item| (declared_prefix(self.str(item.qname)), self.value(item)))
+    }
+
+    // the attributes as qualified name and value, in order
+    pub(crate) fn attributes<'a>(
+        &'a self,
+        element: &'a ElementLexical,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        element
+            .items
+            .iter()
+            .filter(|item| item.kind == ItemKind::Attribute)
+            .map(|item| (self.str(item.qname), self.value(item)))
+    }
+
+    // the raw text of a namespace declaration, if it's declared like this
+    pub(crate) fn namespace_item(
+        &self,
+        element: &ElementLexical,
+        prefix: &str,
+        namespace: &str,
+    ) -> Option<&str> {
+        element
+            .items
+            .iter()
+            .find(|item| {
+                item.kind == ItemKind::Namespace
+                    && declared_prefix(self.str(item.qname)) == prefix
+                    && self.value(item) == namespace
+            })
+            .and_then(|item| item.raw)
+            .map(|raw| self.str(raw))
+    }
+
+    // an attribute with a qualified name. This gives the raw text of the
+    // attribute if it has the value, which is empty if the attribute was
+    // defaulted by the DTD. Otherwise it gives the text before the value
+    // including the opening quote, so that the attribute can be written with
+    // a new value.
+    pub(crate) fn attribute_item(
+        &self,
+        element: &ElementLexical,
+        qname: &str,
+        value: &str,
+    ) -> Option<AttributeItem<'_>> {
+        let item = element
+            .items
+            .iter()
+            .find(|item| item.kind == ItemKind::Attribute && self.str(item.qname) == qname)?;
+        if self.value(item) == value {
+            Some(AttributeItem::Unchanged(
+                item.raw.map_or("", |raw| self.str(raw)),
+            ))
+        } else {
+            let raw = item.raw?;
+            Some(AttributeItem::Changed(
+                &self.source[raw.start..item.value.start],
+            ))
+        }
+    }
+}
+
+pub(crate) enum AttributeItem<'a> {
+    Unchanged(&'a str),
+    // the text before the value, ending in the quote
+    Changed(&'a str),
+}
+
+// the prefix declared by `xmlns` or `xmlns:prefix`
+fn declared_prefix(qname: &str) -> &str {
+    qname
+        .strip_prefix("xmlns")
+        .unwrap_or(qname)
+        .trim_start_matches(':')
+}
+
+// records lexical detail during a parse
+pub(crate) struct LexicalBuilder {
+    source: String,
+    nodes: HashMap<NodeId, NodeLexical>,
+    // text nodes with their raw text, if it is in one piece
+    texts: HashMap<NodeId, Option<Span>>,
+    // the top-level nodes with their raw text, in order. This includes
+    // nodes that were dropped, which have no id.
+    top_level: Vec<(Option<NodeId>, Span)>,
+}
+
+impl LexicalBuilder {
+    pub(crate) fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            nodes: HashMap::new(),
+            texts: HashMap::new(),
+            top_level: Vec::new(),
+        }
+    }
+
+    // scans the start tag of an element, given the span of its name, and
+    // matches the namespace declarations and attributes in it with those of
+    // the start element event
+    pub(crate) fn element(
+        &self,
+        qname: Span,
+        namespaces: &[(PrefixId, NamespaceId)],
+        attributes: &[pull::Attribute],
+        xot: &Xot,
+    ) -> Option<ElementLexical> {
+        let (scanned, close) = scan_start_tag(&self.source, qname.end)?;
+        let mut items = Vec::new();
+        for (raw, item_qname, value) in scanned {
+            let name = &self.source[item_qname.range()];
+            let raw_value = &self.source[value.range()];
+            let (kind, expanded) = if name == "xmlns" || name.starts_with("xmlns:") {
+                let prefix = declared_prefix(name);
+                let namespace = namespaces
+                    .iter()
+                    .find(|(prefix_id, _)| xot.prefix_str(*prefix_id) == prefix)
+                    .map_or(raw_value, |(_, namespace_id)| {
+                        xot.namespace_str(*namespace_id)
+                    });
+                (ItemKind::Namespace, namespace)
+            } else {
+                let attribute = attributes
+                    .iter()
+                    .find(|attribute| attribute.name_span == item_qname);
+                // a duplicate attribute that was dropped
+                let Some(attribute) = attribute else {
+                    continue;
+                };
+                (ItemKind::Attribute, attribute.value.as_ref())
+            };
+            items.push(Item {
+                kind,
+                raw: Some(raw),
+                qname: item_qname,
+                value,
+                expanded: (expanded != raw_value).then(|| expanded.to_string()),
+            });
+        }
+        // the attributes that were defaulted by the DTD aren't in the tag
+        for attribute in attributes {
+            if !items.iter().any(|item| item.qname == attribute.name_span) {
+                items.push(Item {
+                    kind: ItemKind::Attribute,
+                    raw: None,
+                    qname: attribute.name_span,
+                    value: attribute.value_span,
+                    expanded: Some(attribute.value.to_string()),
+                });
+            }
+        }
+        Some(ElementLexical {
+            qname,
+            items,
+            close,
+            end_tag: None,
+        })
+    }
+
+    pub(crate) fn element_start(&mut self, node_id: NodeId, element: Option<ElementLexical>) {
+        if let Some(element) = element {
+            self.nodes.insert(node_id, NodeLexical::Element(element));
+        }
+    }
+
+    pub(crate) fn element_end(&mut self, node_id: NodeId, span: Span) {
+        if let Some(NodeLexical::Element(element)) = self.nodes.get_mut(&node_id) {
+            // for an empty element, the span is that of the `/>`
+            if !self.source[element.close.range()].ends_with("/>") {
+                element.end_tag = Some(span);
+            }
+        }
+    }
+
+    // the raw text of a text node or CDATA section, which may be added to
+    // that of the previous text or CDATA section that was consolidated with
+    // it
+    pub(crate) fn text(&mut self, node_id: NodeId, raw: Span) {
+        self.texts
+            .entry(node_id)
+            .and_modify(|existing| {
+                *existing = match existing {
+                    Some(existing) if existing.end == raw.start => {
+                        Some(Span::new(existing.start, raw.end))
+                    }
+                    // something in between was dropped
+                    _ => None,
+                }
+            })
+            .or_insert(Some(raw));
+    }
+
+    pub(crate) fn processing_instruction_raw(&self, target: Span, data: Option<Span>) -> Span {
+        let end = data.unwrap_or(target).end;
+        let close = self.source[end..]
+            .find("?>")
+            .map_or(end, |index| end + index + 2);
+        Span::new(target.start - 2, close)
+    }
+
+    pub(crate) fn processing_instruction(
+        &mut self,
+        node_id: NodeId,
+        target: Span,
+        data: Option<Span>,
+    ) -> Span {
+        let raw = self.processing_instruction_raw(target, data);
+        self.nodes.insert(
+            node_id,
+            NodeLexical::ProcessingInstruction { raw, target, data },
+        );
+        raw
+    }
+
+    // a node at the top level with its raw text, or more of it
+    pub(crate) fn top_level(&mut self, node_id: Option<NodeId>, raw: Span) {
+        match self.top_level.last_mut() {
+            Some((last, span)) if node_id.is_some() && *last == node_id => span.end = raw.end,
+            _ => self.top_level.push((node_id, raw)),
+        }
+    }
+
+    pub(crate) fn finish(mut self, xot: &Xot, has_document_type: bool) -> Lexical {
+        for (node_id, raw) in self.texts {
+            if xot.arena()[node_id].is_removed() {
+                continue;
+            }
+            let (Some(raw), Value::Text(text)) = (raw, xot.arena()[node_id].get()) else {
+                continue;
+            };
+            let value = text.get();
+            self.nodes.insert(
+                node_id,
+                NodeLexical::Text {
+                    raw,
+                    value: (value != &self.source[raw.range()]).then(|| value.to_string()),
+                },
+            );
+        }
+        let mut gaps = HashMap::new();
+        let mut end = 0;
+        let mut prolog = None;
+        // the boundaries of nodes that were dropped or removed while parsing
+        // are used too, so that their text isn't taken to be part of a gap
+        for (node_id, raw) in &self.top_level {
+            let gap = Span::new(end, raw.start);
+            if prolog.is_none() {
+                prolog = Some(gap);
+            } else if let Some(node_id) = node_id {
+                gaps.insert(*node_id, gap);
+            }
+            end = raw.end;
+        }
+        let prolog = prolog.unwrap_or(Span::new(0, self.source.len()));
+        let epilog = Span::new(end.max(prolog.end), self.source.len());
+        // the document type declaration is in the prolog or a gap, unless
+        // the parser got it from elsewhere
+        let document = (!has_document_type
+            || std::iter::once(&prolog)
+                .chain(gaps.values())
+                .any(|span| self.source[span.range()].contains("<!DOCTYPE")))
+        .then_some(DocumentLexical {
+            prolog,
+            gaps,
+            epilog,
+        });
+        Lexical {
+            source: self.source,
+            nodes: self.nodes,
+            document,
+        }
+    }
+}
+
+type ScannedItem = (Span, Span, Span);
+
+// scans the namespace declarations and attributes of a start tag, starting
+// after its name. This gives the raw text, qualified name and value of each,
+// and the close of the tag. The tag has been checked by the tokenizer
+// already.
+fn scan_start_tag(source: &str, mut position: usize) -> Option<(Vec<ScannedItem>, Span)> {
+    let bytes = source.as_bytes();
+    let is_whitespace = |b: u8| matches!(b, b' ' | b'\t' | b'\r' | b'\n');
+    let skip_whitespace = |mut position: usize| {
+        while position < bytes.len() && is_whitespace(bytes[position]) {
+            position += 1;
+        }
+        position
+    };
+    let mut items = Vec::new();
+    loop {
+        let start = position;
+        position = skip_whitespace(position);
+        match bytes.get(position)? {
+            b'>' => return Some((items, Span::new(start, position + 1))),
+            b'/' => return Some((items, Span::new(start, position + 2))),
+            _ => {}
+        }
+        let qname_start = position;
+        while position < bytes.len() && !is_whitespace(bytes[position]) && bytes[position] != b'=' {
+            position += 1;
+        }
+        let qname = Span::new(qname_start, position);
+        position = skip_whitespace(position);
+        if bytes.get(position)? != &b'=' {
+            return None;
+        }
+        position = skip_whitespace(position + 1);
+        let quote = *bytes.get(position)?;
+        let value_start = position + 1;
+        let value_end = value_start + source[value_start..].find(quote as char)?;
+        position = value_end + 1;
+        items.push((
+            Span::new(start, position),
+            qname,
+            Span::new(value_start, value_end),
+        ));
+    }
+}
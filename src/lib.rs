@@ -58,15 +58,22 @@
 //!
 //! You can also create Xot nodes from a fixed structure, the [`fixed`]
 //! submodule.
+//!
+//! If you want to process XML without building a tree, you can use the pull
+//! parser in the [`pull`] submodule.
 
 mod access;
 mod creation;
+mod diagnostic;
+mod dtd;
 mod encoding;
 mod entity;
 mod error;
 pub mod fixed;
 mod id;
+mod import;
 mod levelorder;
+mod lexical;
 mod manipulation;
 mod nameaccess;
 mod nodemap;
@@ -74,24 +81,31 @@ pub mod output;
 mod parse;
 #[cfg(feature = "proptest")]
 pub mod proptest;
+pub mod pull;
 mod serialize;
 
 mod unpretty;
+mod uri;
+mod validate;
 mod valueaccess;
+pub mod xinclude;
 pub mod xmlname;
+mod xmlid;
 mod xmlvalue;
 mod xotdata;
 
 pub use access::{Axis, NodeEdge};
-pub use error::{Error, ParseError};
-pub use id::{NameId, NamespaceId, PrefixId};
+pub use diagnostic::{LineColumn, LineIndex};
+pub use error::{Error, ParseError, ParseLimit};
+pub use id::{IdRemapping, NameId, NameTables, NamespaceId, PrefixId};
 pub use levelorder::LevelOrder;
 pub use nodemap::{
     Attributes, Entry, MutableAttributes, MutableNamespaces, MutableNodeMap, Namespaces, NodeMap,
 };
-pub use parse::{Span, SpanInfo, SpanInfoKey};
+pub use parse::{ParseLimits, ParseOptions, Span, SpanInfo, SpanInfoKey};
 pub use serialize::Html5;
 pub use xmlvalue::{
-    Attribute, Comment, Element, Namespace, Prefixes, ProcessingInstruction, Text, Value, ValueType,
+    Attribute, Comment, DocumentType, Element, Namespace, Prefixes, ProcessingInstruction, Text,
+    Value, ValueType, XmlVersion,
 };
 pub use xotdata::{Node, Xot};
@@ -1,11 +1,13 @@
 use crate::unpretty::remove_insignificant_whitespace;
 use crate::xotdata::{Node, Xot};
-use crate::{xmlname, MutableAttributes, MutableNamespaces, NamespaceId, PrefixId};
+use crate::{xmlname, MutableAttributes, MutableNamespaces, NamespaceId, PrefixId, Prefixes};
 
 use crate::access::NodeEdge;
 use crate::error::Error;
 use crate::id::NameId;
-use crate::xmlvalue::{Value, ValueCategory, ValueType};
+use crate::import::Importer;
+use crate::xmlid::XmlIds;
+use crate::xmlvalue::{DocumentType, Value, ValueCategory, ValueType};
 
 /// ## Manipulation
 ///
@@ -90,11 +92,13 @@ impl Xot {
     /// ```
     pub fn append(&mut self, parent: Node, child: Node) -> Result<(), Error> {
         self.add_structure_check(Some(parent), child)?;
+        self.unindex_ids(child);
         self.remove_consolidate_text_nodes(self.previous_sibling(child), self.next_sibling(child));
         if self.add_consolidate_text_nodes(child, self.last_child(parent), None) {
             return Ok(());
         }
         parent.get().checked_append(child.get(), self.arena_mut())?;
+        self.index_ids(child);
         Ok(())
     }
 
@@ -331,6 +335,7 @@ impl Xot {
     /// It is now the new first node of the parent.
     pub fn prepend(&mut self, parent: Node, child: Node) -> Result<(), Error> {
         self.add_structure_check(Some(parent), child)?;
+        self.unindex_ids(child);
         self.remove_consolidate_text_nodes(self.previous_sibling(child), self.next_sibling(child));
         if self.add_consolidate_text_nodes(child, None, self.first_child(parent)) {
             return Ok(());
@@ -349,7 +354,7 @@ impl Xot {
                 .get()
                 .checked_prepend(child.get(), self.arena_mut())?;
         }
-
+        self.index_ids(child);
         Ok(())
     }
 
@@ -374,6 +379,7 @@ impl Xot {
     /// ```
     pub fn insert_after(&mut self, reference_node: Node, new_sibling: Node) -> Result<(), Error> {
         self.add_structure_check(self.parent(reference_node), new_sibling)?;
+        self.unindex_ids(new_sibling);
         self.remove_consolidate_text_nodes(
             self.previous_sibling(new_sibling),
             self.next_sibling(new_sibling),
@@ -388,12 +394,14 @@ impl Xot {
         reference_node
             .get()
             .checked_insert_after(new_sibling.get(), self.arena_mut())?;
+        self.index_ids(new_sibling);
         Ok(())
     }
 
     /// Insert a new sibling before a reference node.
     pub fn insert_before(&mut self, reference_node: Node, new_sibling: Node) -> Result<(), Error> {
         self.add_structure_check(self.parent(reference_node), new_sibling)?;
+        self.unindex_ids(new_sibling);
         self.remove_consolidate_text_nodes(
             self.previous_sibling(new_sibling),
             self.next_sibling(new_sibling),
@@ -408,6 +416,7 @@ impl Xot {
         reference_node
             .get()
             .checked_insert_before(new_sibling.get(), self.arena_mut())?;
+        self.index_ids(new_sibling);
         Ok(())
     }
 
@@ -436,6 +445,7 @@ impl Xot {
     pub fn detach(&mut self, node: Node) -> Result<(), Error> {
         let prev_node = self.previous_sibling(node);
         let next_node = self.next_sibling(node);
+        self.unindex_ids(node);
         node.get().detach(self.arena_mut());
         self.remove_consolidate_text_nodes(prev_node, next_node);
         Ok(())
@@ -467,6 +477,14 @@ impl Xot {
     pub fn remove(&mut self, node: Node) -> Result<(), Error> {
         let prev_node = self.previous_sibling(node);
         let next_node = self.next_sibling(node);
+        self.unindex_ids(node);
+        if self.is_document(node) {
+            // a removed document no longer needs its side tables
+            self.id_nodes_map.remove(&node.get());
+            self.document_types.remove(&node.get());
+            self.lexical.remove(&node.get());
+            self.document_uris.remove(&node.get());
+        }
         node.get().remove_subtree(self.arena_mut());
         self.remove_consolidate_text_nodes(prev_node, next_node);
         Ok(())
@@ -476,12 +494,15 @@ impl Xot {
     ///
     /// If this node is not an element, panic.
     pub fn set_element_name(&mut self, node: Node, name_id: NameId) {
-        match self.value_mut(node) {
-            Value::Element(element) => {
-                element.set_name(name_id);
-            }
-            _ => panic!("Node is not an element, so cannot set element name"),
+        if !self.is_element(node) {
+            panic!("Node is not an element, so cannot set element name");
+        }
+        // whether an attribute is an id may depend on the element name
+        self.unindex_attribute_ids(node);
+        if let Value::Element(element) = self.value_mut(node) {
+            element.set_name(name_id);
         }
+        self.index_attribute_ids(node);
     }
 
     /// Mutable namespaces accessor.
@@ -502,7 +523,7 @@ impl Xot {
     ///
     /// assert_eq!(xot.to_string(root).unwrap(), r#"<p xmlns:foo="FOO">Example</p>"#);
     /// ```
-    pub fn namespaces_mut(&mut self, node: Node) -> MutableNamespaces {
+    pub fn namespaces_mut(&mut self, node: Node) -> MutableNamespaces<'_> {
         if !self.is_element(node) {
             panic!("Node is not an element, so cannot set namespaces");
         }
@@ -541,7 +562,7 @@ impl Xot {
     ///
     /// assert_eq!(xot.to_string(root).unwrap(), r#"<p a="A">Example</p>"#);
     /// ```
-    pub fn attributes_mut(&mut self, node: Node) -> MutableAttributes {
+    pub fn attributes_mut(&mut self, node: Node) -> MutableAttributes<'_> {
         if !self.is_element(node) {
             panic!("Node is not an element, so cannot set attributes");
         }
@@ -562,6 +583,96 @@ impl Xot {
         self.attributes_mut(node).remove(name);
     }
 
+    /// Set the document type declaration of a document node.
+    ///
+    /// Pass [`None`] to remove the document type declaration. This fails
+    /// if the node is not a document node.
+    ///
+    /// ```rust
+    /// use xot::{DocumentType, Xot};
+    ///
+    /// let mut xot = Xot::new();
+    /// let root = xot.parse("<doc/>")?;
+    ///
+    /// let mut document_type = DocumentType::new("doc");
+    /// document_type.system_id = Some("doc.dtd".to_string());
+    /// xot.set_document_type(root, Some(document_type))?;
+    ///
+    /// assert_eq!(
+    ///     xot.to_string(root)?,
+    ///     "<!DOCTYPE doc SYSTEM \"doc.dtd\">\n<doc/>"
+    /// );
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn set_document_type(
+        &mut self,
+        document_node: Node,
+        document_type: Option<DocumentType>,
+    ) -> Result<(), Error> {
+        if !self.is_document(document_node) {
+            return Err(Error::NotDocument(document_node));
+        }
+        if let Some(document_type) = document_type {
+            self.document_types
+                .insert(document_node.get(), document_type);
+        } else {
+            self.document_types.remove(&document_node.get());
+        }
+        Ok(())
+    }
+
+    /// Set the URI of a document node.
+    ///
+    /// This is the base URI against which [`Xot::base_uri`] resolves
+    /// `xml:base` attributes in the document. Pass [`None`] to remove it.
+    /// This fails if the node is not a document node.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    /// let root = xot.parse("<doc/>")?;
+    ///
+    /// xot.set_document_uri(root, Some("http://example.com/doc.xml"))?;
+    /// assert_eq!(xot.document_uri(root), Some("http://example.com/doc.xml"));
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn set_document_uri(
+        &mut self,
+        document_node: Node,
+        uri: Option<&str>,
+    ) -> Result<(), Error> {
+        if !self.is_document(document_node) {
+            return Err(Error::NotDocument(document_node));
+        }
+        if let Some(uri) = uri {
+            self.document_uris
+                .insert(document_node.get(), uri.to_string());
+        } else {
+            self.document_uris.remove(&document_node.get());
+        }
+        Ok(())
+    }
+
+    /// Rebuild the ids of a document node.
+    ///
+    /// The ids used by [`Xot::xml_id_node`] are kept up to date as you
+    /// manipulate the tree. But if you change an attribute value through a
+    /// mutable reference (for instance with [`Xot::value_mut`]), or change an
+    /// element name that way, the ids can get out of date. This rebuilds them.
+    ///
+    /// Use [`Xot::duplicate_xml_ids`] to find out whether there are duplicate
+    /// ids afterward.
+    ///
+    /// This fails if the node is not a document node.
+    pub fn rebuild_xml_ids(&mut self, document_node: Node) -> Result<(), Error> {
+        if !self.is_document(document_node) {
+            return Err(Error::NotDocument(document_node));
+        }
+        self.reindex_ids(document_node);
+        Ok(())
+    }
+
     /// Clone a node and its descendants into a new unattached tree.
     ///
     /// The cloned nodes are not attached to anything. If you clone a document
@@ -639,6 +750,13 @@ impl Xot {
             cloned_node
         } else {
             // it's a document
+            if let Some(document_type) = self.document_types.get(&node.get()) {
+                self.document_types.insert(top.get(), document_type.clone());
+            }
+            if let Some(uri) = self.document_uris.get(&node.get()) {
+                self.document_uris.insert(top.get(), uri.clone());
+            }
+            self.clone_ids(node, top);
             top
         }
     }
@@ -686,10 +804,157 @@ impl Xot {
         clone
     }
 
+    /// Import a node and its descendants from another Xot into a new
+    /// unattached tree.
+    ///
+    /// Names, namespaces and prefixes are only meaningful within a single
+    /// Xot, so they're added to this Xot as needed. Like with
+    /// [`Xot::clone_with_prefixes`], if the imported node is an element,
+    /// namespace prefixes it requires that are declared on its ancestors are
+    /// declared on it. If you import a document node, the whole document is
+    /// imported, including its document type declaration and its id index.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut worker = Xot::new();
+    /// let root = worker.parse(r#"<doc xmlns:x="http://example.com"><x:a f="F"/></doc>"#)?;
+    /// let a_el = worker.first_child(worker.document_element(root)?).unwrap();
+    ///
+    /// let mut xot = Xot::new();
+    /// let imported = xot.import_node(&worker, a_el);
+    /// assert_eq!(xot.to_string(imported)?, r#"<x:a xmlns:x="http://example.com" f="F"/>"#);
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn import_node(&mut self, other: &Xot, node: Node) -> Node {
+        let prefixes = other.inherited_prefixes(node);
+        let values = other.all_traverse(node).filter_map(|edge| match edge {
+            NodeEdge::Start(node) => Some(other.value(node).clone()),
+            NodeEdge::End(_) => None,
+        });
+        self.import_values(other, node, prefixes, values)
+    }
+
+    /// Import a node and its descendants from another Xot, consuming it.
+    ///
+    /// This is like [`Xot::import_node`], but as the other Xot is consumed,
+    /// the text and attribute values of the imported nodes are moved rather
+    /// than copied.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut worker = Xot::new();
+    /// let root = worker.parse(r#"<doc><a>Text</a></doc>"#)?;
+    ///
+    /// let mut xot = Xot::new();
+    /// let imported = xot.absorb_node(worker, root);
+    /// assert_eq!(xot.to_string(imported)?, r#"<doc><a>Text</a></doc>"#);
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn absorb_node(&mut self, mut other: Xot, node: Node) -> Node {
+        let prefixes = other.inherited_prefixes(node);
+        let nodes = other
+            .all_traverse(node)
+            .filter_map(|edge| match edge {
+                NodeEdge::Start(node) => Some(node),
+                NodeEdge::End(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let values = nodes
+            .into_iter()
+            .map(|node| std::mem::replace(other.arena[node.get()].get_mut(), Value::Document))
+            .collect::<Vec<_>>();
+        self.import_values(&other, node, prefixes, values.into_iter())
+    }
+
+    // build a tree from the values of node and its descendants in another
+    // Xot, in traversal order
+    fn import_values(
+        &mut self,
+        other: &Xot,
+        node: Node,
+        prefixes: Prefixes,
+        mut values: impl Iterator<Item = Value>,
+    ) -> Node {
+        let mut importer = Importer::new(other);
+        let mut top = None;
+        // the open elements (or document) in the other Xot, and their imports
+        let mut open: Vec<(Node, Node)> = Vec::new();
+        for edge in other.all_traverse(node) {
+            match edge {
+                NodeEdge::Start(other_node) => {
+                    // there's a value for each start edge
+                    let value = importer.value(self, values.next().unwrap());
+                    let is_container = matches!(value, Value::Document | Value::Element(_));
+                    let new_node = self.new_node(value);
+                    if let Some((_, parent)) = open.last() {
+                        self.any_append(*parent, new_node).unwrap();
+                    } else {
+                        top = Some(new_node);
+                        if self.is_document(new_node) {
+                            self.import_document_info(other, other_node, new_node, &mut importer);
+                        }
+                    }
+                    if is_container {
+                        open.push((other_node, new_node));
+                    }
+                }
+                NodeEdge::End(other_node) => {
+                    if open.last().map(|(open_node, _)| *open_node) == Some(other_node) {
+                        open.pop();
+                    }
+                }
+            }
+        }
+        // there's always a start edge for the node itself
+        let top = top.unwrap();
+        if self.is_element(top) {
+            for (prefix, namespace) in prefixes {
+                let prefix = importer.prefix(self, prefix);
+                let namespace = importer.namespace(self, namespace);
+                let mut namespaces = self.namespaces_mut(top);
+                if !namespaces.contains_key(prefix) {
+                    namespaces.insert(prefix, namespace);
+                }
+            }
+        }
+        top
+    }
+
+    // import the document type declaration and id attribute declarations of
+    // a document, before its content is imported and indexed
+    fn import_document_info(
+        &mut self,
+        other: &Xot,
+        other_document: Node,
+        document: Node,
+        importer: &mut Importer,
+    ) {
+        if let Some(document_type) = other.document_type(other_document) {
+            self.document_types
+                .insert(document.get(), document_type.clone());
+        }
+        if let Some(uri) = other.document_uri(other_document) {
+            self.document_uris.insert(document.get(), uri.to_string());
+        }
+        let mut xml_ids = XmlIds::new();
+        if let Some(other_xml_ids) = other.id_nodes_map.get(&other_document.get()) {
+            for (element, attribute) in other_xml_ids.id_attributes() {
+                xml_ids.declare_id_attribute(
+                    importer.name(self, *element),
+                    importer.name(self, *attribute),
+                );
+            }
+        }
+        self.id_nodes_map.insert(document.get(), xml_ids);
+    }
+
     // removes a node and puts normal content into containing node.
     // any non-normal content (i.e. attributes and namespaces) are really
     // destroyed
     fn remove_element(&mut self, node: Node) {
+        self.unindex_attribute_ids(node);
         // first remove any abnormal children, if any
         for abnormal_child in self.abnormal_children(node).collect::<Vec<_>>() {
             self.remove_dangerously(abnormal_child);
@@ -808,6 +1073,7 @@ impl Xot {
             let wrapper = self.new_element(name_id);
             // detach the node, use low-level detach as we don't want to consolidate
             // text nodes
+            self.unindex_ids(node);
             node.get().detach(self.arena_mut());
             // append the node to the wrapper
             self.append(wrapper, node)?;
@@ -861,6 +1127,7 @@ impl Xot {
         let previous_node = self.previous_sibling(replaced_node);
         // remove the replaced node, use low-level remove_tree to avoid
         // text node reconciliation and document element detection
+        self.unindex_ids(replaced_node);
         replaced_node.get().remove_subtree(self.arena_mut());
         // now insert the replacing node
         if let Some(previous_node) = previous_node {
@@ -934,10 +1201,10 @@ impl Xot {
         if !self.text_consolidation {
             return false;
         }
-        let added_text = if let Value::Text(t) = self.value(node) {
-            Some(t.get().to_string())
+        let (added_text, cdata) = if let Value::Text(t) = self.value(node) {
+            (Some(t.get().to_string()), t.is_cdata())
         } else {
-            None
+            (None, false)
         };
         if added_text.is_none() {
             return false;
@@ -947,7 +1214,11 @@ impl Xot {
         // if consolidation is turned off, then we could have two adjacent
         // text nodes. Prefer to consolidate with the previous node.
         let consolidated = if let Some(prev_node) = prev_node {
-            if let Value::Text(prev) = self.value_mut(prev_node) {
+            // only text of the same form (CDATA or not) is consolidated
+            if let Some(prev) = self
+                .text_mut(prev_node)
+                .filter(|prev| prev.is_cdata() == cdata)
+            {
                 let mut s = prev.get().to_string();
                 s.push_str(&added_text);
                 prev.set(s);
@@ -967,7 +1238,10 @@ impl Xot {
         // we couldn't consolidate with the previous node, try to consolidate
         // with the next node
         if let Some(next_node) = next_node {
-            if let Value::Text(next) = self.value_mut(next_node) {
+            if let Some(next) = self
+                .text_mut(next_node)
+                .filter(|next| next.is_cdata() == cdata)
+            {
                 let mut s = added_text;
                 s.push_str(next.get());
                 next.set(s);
@@ -1004,6 +1278,9 @@ impl Xot {
         if prev_text.is_none() || next_text.is_none() {
             return false;
         }
+        if prev_text.unwrap().is_cdata() != next_text.unwrap().is_cdata() {
+            return false;
+        }
         let to_add = next_text.unwrap().get().to_string();
 
         let prev_text_mut = self.text_mut(prev_node).unwrap();
@@ -4,8 +4,9 @@ use genawaiter::yield_;
 
 use crate::access::NodeEdge;
 use crate::error::Error;
-use crate::id::{Name, NameId, NamespaceId, PrefixId};
+use crate::id::{IdIndex, IdRemapping, Name, NameId, NamespaceId, PrefixId};
 use crate::output::FullnameSerializer;
+use crate::validate;
 use crate::xmlvalue::Prefixes;
 use crate::xotdata::{Node, Xot};
 use crate::{xmlname, Value};
@@ -23,6 +24,10 @@ use crate::{xmlname, Value};
 ///
 /// * You get type-checks and can't mix up names, namespaces and prefixes.
 ///
+/// A single Xot can hold up to 2<sup>32</sup> distinct names, and as many
+/// namespaces and prefixes. Adding more panics; ids are never reused for
+/// another value.
+///
 /// Names, namespaces and prefixes are shared in a single Xot, so are the same
 /// in multiple trees. This makes it safe to copy and move nodes between trees.
 /// If you care about the readability of the serialized XML you do need to
@@ -84,6 +89,27 @@ impl Xot {
         self.add_name_ns(name, self.no_namespace_id)
     }
 
+    /// Add name without a namespace, checking that it's valid.
+    ///
+    /// Like [`Xot::add_name`], but the name has to be an `NCName`: a valid
+    /// XML name without a colon. If it isn't, you get
+    /// [`Error::InvalidName`].
+    ///
+    /// ```rust
+    /// use xot::{Error, Xot};
+    ///
+    /// let mut xot = Xot::new();
+    ///
+    /// assert!(xot.try_add_name("a").is_ok());
+    /// assert!(matches!(xot.try_add_name("a b"), Err(Error::InvalidName(_))));
+    /// assert!(matches!(xot.try_add_name("a:b"), Err(Error::InvalidName(_))));
+    /// assert!(matches!(xot.try_add_name(""), Err(Error::InvalidName(_))));
+    /// ```
+    pub fn try_add_name(&mut self, name: &str) -> Result<NameId, Error> {
+        validate::check_ncname(name)?;
+        Ok(self.add_name(name))
+    }
+
     /// Look up name with a namespace.
     ///
     /// ```rust
@@ -147,6 +173,19 @@ impl Xot {
         self.name_lookup.get_id_mut(&Name::new(name, namespace_id))
     }
 
+    /// Add name with a namespace, checking that it's valid.
+    ///
+    /// Like [`Xot::add_name_ns`], but the name has to be an `NCName`. If it
+    /// isn't, you get [`Error::InvalidName`].
+    pub fn try_add_name_ns(
+        &mut self,
+        name: &str,
+        namespace_id: NamespaceId,
+    ) -> Result<NameId, Error> {
+        validate::check_ncname(name)?;
+        Ok(self.add_name_ns(name, namespace_id))
+    }
+
     /// Look up namespace.
     ///
     /// This is the immutable version of [`Xot::add_namespace`]; it returns
@@ -162,6 +201,32 @@ impl Xot {
         self.namespace_lookup.get_id_mut(namespace)
     }
 
+    /// Add namespace, checking that it's valid.
+    ///
+    /// Like [`Xot::add_namespace`], but the namespace can't contain
+    /// characters that aren't allowed in XML 1.0, and you get
+    /// [`Error::ReservedNamespace`] for the XML namespace, the `xmlns`
+    /// namespace and the empty string. The XML namespace is always available
+    /// as [`Xot::xml_namespace`], and the empty string is
+    /// [`Xot::no_namespace`].
+    ///
+    /// ```rust
+    /// use xot::{Error, Xot};
+    ///
+    /// let mut xot = Xot::new();
+    ///
+    /// assert!(xot.try_add_namespace("http://example.com").is_ok());
+    /// assert!(matches!(
+    ///     xot.try_add_namespace("http://www.w3.org/2000/xmlns/"),
+    ///     Err(Error::ReservedNamespace(_))
+    /// ));
+    /// assert!(matches!(xot.try_add_namespace(""), Err(Error::ReservedNamespace(_))));
+    /// ```
+    pub fn try_add_namespace(&mut self, namespace: &str) -> Result<NamespaceId, Error> {
+        validate::check_namespace(namespace)?;
+        Ok(self.add_namespace(namespace))
+    }
+
     /// Look up prefix.
     ///
     /// This is the immutable version of [`Xot::add_prefix`]; it returns
@@ -177,6 +242,22 @@ impl Xot {
         self.prefix_lookup.get_id_mut(prefix)
     }
 
+    /// Add prefix, checking that it's valid.
+    ///
+    /// Like [`Xot::add_prefix`], but the prefix has to be an `NCName`, or
+    /// empty for the default namespace. If it isn't, you get
+    /// [`Error::InvalidName`]. The `xmlns` prefix is reserved, so you get
+    /// [`Error::ReservedPrefix`] for it.
+    pub fn try_add_prefix(&mut self, prefix: &str) -> Result<PrefixId, Error> {
+        if prefix == "xmlns" {
+            return Err(Error::ReservedPrefix(prefix.to_string()));
+        }
+        if !prefix.is_empty() {
+            validate::check_ncname(prefix)?;
+        }
+        Ok(self.add_prefix(prefix))
+    }
+
     /// No namespace
     ///
     /// Returns the namespace id used when an element or attribute
@@ -229,6 +310,14 @@ impl Xot {
         self.xml_id_id
     }
 
+    /// xml:base
+    ///
+    /// Returns the name id used for the `xml:base` attribute.
+    #[inline]
+    pub fn xml_base_name(&self) -> NameId {
+        self.xml_base_id
+    }
+
     /// Given a name id, and a context node (to provide namespace prefix
     /// lookup), return a [`xmlname::RefName`]. If you import the trait
     /// [`xmlname::NameStrInfo`] you can look up more information about the
@@ -258,7 +347,7 @@ impl Xot {
     ///
     /// # Ok::<(), xot::Error>(())
     /// ```
-    pub fn name_ref(&self, name_id: NameId, context: Node) -> Result<xmlname::RefName, Error> {
+    pub fn name_ref(&self, name_id: NameId, context: Node) -> Result<xmlname::RefName<'_>, Error> {
         xmlname::RefName::from_node(self, context, name_id)
     }
 
@@ -472,7 +561,7 @@ impl Xot {
     ///
     /// # Ok::<(), xot::Error>(())
     /// ```
-    pub fn node_name_ref(&self, node: Node) -> Result<Option<xmlname::RefName>, Error> {
+    pub fn node_name_ref(&self, node: Node) -> Result<Option<xmlname::RefName<'_>>, Error> {
         if let Some(name) = self.node_name(node) {
             Ok(Some(self.name_ref(name, node)?))
         } else {
@@ -796,6 +885,131 @@ impl Xot {
         namespace_traverse(self, node)
     }
 
+    /// Compact the tables of names, namespaces and prefixes.
+    ///
+    /// Names, namespaces and prefixes stay in a Xot once they are added, even
+    /// when no node uses them anymore. In a long-running Xot that processes
+    /// many different documents this means the tables keep growing. This
+    /// drops all names, namespaces and prefixes that aren't used by any node
+    /// that hasn't been removed, and renumbers the remaining ones.
+    ///
+    /// Names in `retain` are kept even if they are not used, along with their
+    /// namespaces. The names, namespaces and prefixes Xot itself defines,
+    /// such as [`Xot::xml_id_name`], are always kept and keep their ids.
+    ///
+    /// All nodes are updated to use the new ids. Any other ids you hold on to
+    /// become invalid; use the returned [`IdRemapping`] to update them.
+    /// Don't compact while a [`pull::Parser`](crate::pull::Parser) is in use
+    /// with this Xot, as it holds on to ids as well.
+    ///
+    /// A Xot created with [`Xot::with_name_tables`] shares its tables with
+    /// other Xots, so they cannot be compacted; in that case nothing is
+    /// dropped and the returned remapping leaves all ids unchanged.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    /// let record = xot.add_name("record");
+    /// let doc = xot.parse(r#"<unused xmlns="http://example.com"/>"#)?;
+    /// xot.remove(doc)?;
+    ///
+    /// let doc = xot.parse("<record/>")?;
+    /// let remapping = xot.compact_names(&[]);
+    /// assert!(xot.name("unused").is_none());
+    /// assert!(xot.namespace("http://example.com").is_none());
+    ///
+    /// let record = remapping.name(record).unwrap();
+    /// assert_eq!(xot.local_name_str(record), "record");
+    /// assert_eq!(xot.to_string(doc)?, "<record/>");
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn compact_names(&mut self, retain: &[NameId]) -> IdRemapping {
+        if self.name_lookup.is_shared() {
+            return IdRemapping {
+                names: (0..self.name_lookup.len()).map(NameId::to_id).collect(),
+                namespaces: (0..self.namespace_lookup.len())
+                    .map(NamespaceId::to_id)
+                    .collect(),
+                prefixes: (0..self.prefix_lookup.len()).map(PrefixId::to_id).collect(),
+            };
+        }
+        let mut names = vec![false; self.name_lookup.len()];
+        let mut namespaces = vec![false; self.namespace_lookup.len()];
+        let mut prefixes = vec![false; self.prefix_lookup.len()];
+
+        let mut used_names = vec![self.xml_space_id, self.xml_id_id, self.xml_base_id];
+        used_names.extend_from_slice(retain);
+        for xml_ids in self.id_nodes_map.values() {
+            for (element, attribute) in xml_ids.id_attributes() {
+                used_names.push(*element);
+                used_names.push(*attribute);
+            }
+        }
+        namespaces[NamespaceId::from_id(self.no_namespace_id)] = true;
+        namespaces[NamespaceId::from_id(self.xml_namespace_id)] = true;
+        prefixes[PrefixId::from_id(self.empty_prefix_id)] = true;
+        prefixes[PrefixId::from_id(self.xml_prefix_id)] = true;
+        for node in self.arena.iter().filter(|node| !node.is_removed()) {
+            match node.get() {
+                Value::Element(element) => used_names.push(element.name()),
+                Value::Attribute(attribute) => used_names.push(attribute.name()),
+                Value::ProcessingInstruction(pi) => used_names.push(pi.target()),
+                Value::Namespace(namespace) => {
+                    prefixes[PrefixId::from_id(namespace.prefix())] = true;
+                    namespaces[NamespaceId::from_id(namespace.namespace())] = true;
+                }
+                Value::Document | Value::Text(_) | Value::Comment(_) => {}
+            }
+        }
+        for name in used_names {
+            names[NameId::from_id(name)] = true;
+            let namespace = self.name_lookup.get_value(name).namespace_id;
+            namespaces[NamespaceId::from_id(namespace)] = true;
+        }
+
+        let namespaces = self
+            .namespace_lookup
+            .compact(&namespaces, |namespace| namespace);
+        let prefixes = self.prefix_lookup.compact(&prefixes, |prefix| prefix);
+        let names = self.name_lookup.compact(&names, |name| {
+            // the namespace of a kept name is always kept
+            let namespace_id = namespaces[NamespaceId::from_id(name.namespace_id)].unwrap();
+            Name::new(name.name, namespace_id)
+        });
+        let remapping = IdRemapping {
+            names,
+            namespaces,
+            prefixes,
+        };
+
+        // all of these are kept
+        let name = |name| remapping.name(name).unwrap();
+        for node in self.arena.iter_mut().filter(|node| !node.is_removed()) {
+            match node.get_mut() {
+                Value::Element(element) => element.name_id = name(element.name_id),
+                Value::Attribute(attribute) => attribute.name_id = name(attribute.name_id),
+                Value::ProcessingInstruction(pi) => pi.target = name(pi.target),
+                Value::Namespace(namespace) => {
+                    namespace.prefix_id = remapping.prefix(namespace.prefix_id).unwrap();
+                    namespace.namespace_id = remapping.namespace(namespace.namespace_id).unwrap();
+                }
+                Value::Document | Value::Text(_) | Value::Comment(_) => {}
+            }
+        }
+        for xml_ids in self.id_nodes_map.values_mut() {
+            xml_ids.remap_id_attributes(&remapping);
+        }
+        self.no_namespace_id = remapping.namespace(self.no_namespace_id).unwrap();
+        self.xml_namespace_id = remapping.namespace(self.xml_namespace_id).unwrap();
+        self.empty_prefix_id = remapping.prefix(self.empty_prefix_id).unwrap();
+        self.xml_prefix_id = remapping.prefix(self.xml_prefix_id).unwrap();
+        self.xml_space_id = name(self.xml_space_id);
+        self.xml_id_id = name(self.xml_id_id);
+        self.xml_base_id = name(self.xml_base_id);
+        remapping
+    }
+
     pub(crate) fn base_prefixes(&self) -> Prefixes {
         let mut prefixes = Prefixes::new();
         prefixes.insert(self.xml_prefix_id, self.xml_namespace_id);
@@ -72,6 +72,14 @@ impl ValueAdapter<NameId, String> for AttributeAdapter {
             _ => unreachable!(),
         }
     }
+
+    fn before_change(xot: &mut Xot, node: Node) {
+        xot.unindex_attribute_id(node);
+    }
+
+    fn after_change(xot: &mut Xot, node: Node) {
+        xot.index_attribute_id(node);
+    }
 }
 
 /// Attributes of an element.
@@ -17,6 +17,11 @@ pub trait ValueAdapter<K, V> {
     fn value_mut(value: &mut Value) -> &mut V;
     fn create(key: K, value: V) -> Value;
     fn update(value: &mut Value, value: V) -> Option<V>;
+    // called before the value of a node in the map is changed, and after a
+    // node is added to the map or its value changed. This lets us keep the
+    // id index up to date.
+    fn before_change(_xot: &mut Xot, _node: Node) {}
+    fn after_change(_xot: &mut Xot, _node: Node) {}
 }
 
 /// A `NodeMap` is a struct with a hash-map like API and is used to
@@ -252,6 +257,10 @@ where
     // TODO: end of duplication
 
     /// Return a mutable reference to the value stored for `key`, if it is present, else `None`.
+    ///
+    /// Changing an `xml:id` attribute value this way does not update the id
+    /// index; use [`insert`](#method.insert) instead, or call
+    /// [`Xot::rebuild_xml_ids`] afterward.
     pub fn get_mut(&mut self, key: impl Into<K> + Copy) -> Option<&mut V> {
         let node = self.get_node(key)?;
         Some(A::value_mut(self.xot.value_mut(node)))
@@ -281,8 +290,11 @@ where
         let node = self.get_node(key);
         if let Some(node) = node {
             // if we already have a node
+            A::before_change(self.xot, node);
             let node_value = self.xot.value_mut(node);
-            A::update(node_value, value)
+            let old_value = A::update(node_value, value);
+            A::after_change(self.xot, node);
+            old_value
         } else {
             // we need to insert a new node
             let new_value = A::create(key.into(), value);
@@ -299,6 +311,7 @@ where
                     .checked_prepend(node, &mut self.xot.arena)
                     .unwrap();
             }
+            A::after_change(self.xot, Node::new(node));
             None
         }
     }
@@ -323,8 +336,10 @@ where
         let existing_node = self.get_node(key);
         if let Some(existing_node) = existing_node {
             // if we already have a node
+            A::before_change(self.xot, existing_node);
             let node_value = self.xot.value_mut(existing_node);
             A::update(node_value, value);
+            A::after_change(self.xot, existing_node);
             existing_node
         } else {
             let insertion_point = A::insertion_point(self.xot, self.parent);
@@ -339,6 +354,7 @@ where
                     .checked_prepend(node.get(), &mut self.xot.arena)
                     .unwrap();
             }
+            A::after_change(self.xot, node);
             node
         }
     }
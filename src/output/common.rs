@@ -1,5 +1,19 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
 use crate::NameId;
 
+/// A character map: characters in text and attribute values that are
+/// replaced by a literal string during serialization.
+///
+/// The replacement string is output as is: it is not escaped or normalized.
+/// This can be used to produce output that otherwise cannot be produced,
+/// such as entity references or markup.
+///
+/// This is the `use-character-maps` serialization parameter as described in
+/// <https://www.w3.org/TR/xslt-xquery-serialization/#character-maps>
+pub type CharacterMap = BTreeMap<char, String>;
+
 /// Indentation: pretty-print XML or HTML.
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Indentation {
@@ -19,4 +33,39 @@ pub struct TokenSerializeParameters {
     /// `>` is not escaped, except for the special case of `]]>` outside of CDATA,
     /// which is mandated by the XML specification to always be escaped.
     pub unescaped_gt: bool,
+
+    /// Characters in text and attribute values that are replaced by a
+    /// literal string.
+    pub character_map: CharacterMap,
+}
+
+// Apply a character map to content. The parts of the content in between
+// mapped characters are serialized using `serialize`; the replacement
+// strings are inserted literally.
+//
+// If `serialize` produces a CDATA section, each part gets its own CDATA
+// section, so that the replacement strings end up outside of them.
+pub(crate) fn map_characters<'a>(
+    content: &'a str,
+    character_map: &CharacterMap,
+    mut serialize: impl FnMut(Cow<'a, str>) -> Cow<'a, str>,
+) -> Cow<'a, str> {
+    if character_map.is_empty() || !content.chars().any(|c| character_map.contains_key(&c)) {
+        return serialize(content.into());
+    }
+    let mut result = String::new();
+    let mut start = 0;
+    for (position, c) in content.char_indices() {
+        if let Some(replacement) = character_map.get(&c) {
+            if start < position {
+                result.push_str(&serialize(content[start..position].into()));
+            }
+            result.push_str(replacement);
+            start = position + c.len_utf8();
+        }
+    }
+    if start < content.len() {
+        result.push_str(&serialize(content[start..].into()));
+    }
+    result.into()
 }
@@ -0,0 +1,360 @@
+// Encoding of serialized XML into encodings other than UTF-8.
+//
+// The serializer itself produces UTF-8. It replaces characters that can't be
+// represented in the output encoding by character references where that's
+// allowed (text and attribute values), using `escape_characters`. Everything then goes through an
+// `EncodingWriter`, which encodes it.
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use encoding_rs::{Encoder, EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+// the labels of ISO-8859-1 and US-ASCII in the WHATWG Encoding Standard
+const LATIN1_LABELS: [&str; 11] = [
+    "cp819",
+    "csisolatin1",
+    "ibm819",
+    "iso-8859-1",
+    "iso-ir-100",
+    "iso8859-1",
+    "iso88591",
+    "iso_8859-1",
+    "iso_8859-1:1987",
+    "l1",
+    "latin1",
+];
+const ASCII_LABELS: [&str; 3] = ["ansi_x3.4-1968", "ascii", "us-ascii"];
+
+/// The encoding of serialized XML.
+///
+/// encoding_rs follows the WHATWG Encoding Standard, which takes the labels
+/// of ISO-8859-1 and US-ASCII to mean windows-1252. That's fine for
+/// decoding, but windows-1252 has characters such as `€` at bytes 0x80 to
+/// 0x9F, where ISO-8859-1 has C1 control characters and US-ASCII has
+/// nothing at all. So we encode those ourselves, as one byte per
+/// character up to and including the last character they can represent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputEncoding {
+    Encoding(&'static Encoding),
+    SingleByte { name: &'static str, last: char },
+}
+
+impl OutputEncoding {
+    /// The encoding for a label such as `ISO-8859-1`, if it's known.
+    pub(crate) fn for_label(label: &str) -> Option<Self> {
+        let label = label
+            .trim_matches(|c: char| c.is_ascii_whitespace())
+            .to_ascii_lowercase();
+        if LATIN1_LABELS.contains(&label.as_str()) {
+            Some(OutputEncoding::SingleByte {
+                name: "ISO-8859-1",
+                last: '\u{FF}',
+            })
+        } else if ASCII_LABELS.contains(&label.as_str()) {
+            Some(OutputEncoding::SingleByte {
+                name: "US-ASCII",
+                last: '\u{7F}',
+            })
+        } else {
+            Encoding::for_label(label.as_bytes()).map(OutputEncoding::Encoding)
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            OutputEncoding::Encoding(encoding) => encoding.name(),
+            OutputEncoding::SingleByte { name, .. } => name,
+        }
+    }
+
+    pub(crate) fn is_utf8(&self) -> bool {
+        *self == OutputEncoding::Encoding(UTF_8)
+    }
+
+    fn is_utf16(&self) -> bool {
+        *self == OutputEncoding::Encoding(UTF_16LE) || *self == OutputEncoding::Encoding(UTF_16BE)
+    }
+}
+
+/// Whether a character can be represented in an encoding.
+pub(crate) fn can_encode(encoding: OutputEncoding, c: char) -> bool {
+    let encoding = match encoding {
+        OutputEncoding::SingleByte { last, .. } => return c <= last,
+        _ if encoding.is_utf8() || encoding.is_utf16() => return true,
+        OutputEncoding::Encoding(encoding) => encoding,
+    };
+    if c.is_ascii() && encoding.is_ascii_compatible() {
+        return true;
+    }
+    let mut s = [0; 4];
+    let s = c.encode_utf8(&mut s);
+    let mut encoder = encoding.new_encoder();
+    let mut buffer = [0; 16];
+    let (result, _, _) = encoder.encode_from_utf8_without_replacement(s, &mut buffer, true);
+    matches!(result, EncoderResult::InputEmpty)
+}
+
+/// Replace characters for which `needs_reference` is true with character
+/// references.
+///
+/// This is used for characters that can't be represented in the encoding, as
+/// well as for characters that XML 1.1 requires to be written as references.
+///
+/// If `cdata` is set, the text is a CDATA section. A character reference
+/// isn't recognized within it, so we close the section around it.
+pub(crate) fn escape_characters<'a>(
+    text: Cow<'a, str>,
+    cdata: bool,
+    needs_reference: impl Fn(char) -> bool,
+) -> Cow<'a, str> {
+    if !text.chars().any(&needs_reference) {
+        return text;
+    }
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if !needs_reference(c) {
+            result.push(c);
+        } else if cdata {
+            result.push_str(&format!("]]>&#x{:X};<![CDATA[", c as u32));
+        } else {
+            result.push_str(&format!("&#x{:X};", c as u32));
+        }
+    }
+    Cow::Owned(result)
+}
+
+/// A writer that encodes the UTF-8 written to it into another encoding.
+///
+/// A byte order mark is written for UTF-16. A character that can't be
+/// represented in the encoding results in an error; the character is
+/// retained in `unencodable`.
+pub(crate) struct EncodingWriter<'w, W: Write> {
+    w: &'w mut W,
+    encoding: OutputEncoding,
+    encoder: Encoder,
+    // the start of a UTF-8 sequence that was split over writes
+    incomplete: Vec<u8>,
+    buffer: Vec<u8>,
+    started: bool,
+    pub(crate) unencodable: Option<char>,
+}
+
+impl<'w, W: Write> EncodingWriter<'w, W> {
+    pub(crate) fn new(w: &'w mut W, encoding: OutputEncoding) -> Self {
+        let encoder = match encoding {
+            OutputEncoding::Encoding(encoding) => encoding.new_encoder(),
+            // not used
+            OutputEncoding::SingleByte { .. } => UTF_8.new_encoder(),
+        };
+        Self {
+            w,
+            encoding,
+            encoder,
+            incomplete: Vec::new(),
+            buffer: Vec::new(),
+            started: false,
+            unencodable: None,
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            if self.encoding == OutputEncoding::Encoding(UTF_16LE) {
+                self.w.write_all(&[0xFF, 0xFE])?;
+            } else if self.encoding == OutputEncoding::Encoding(UTF_16BE) {
+                self.w.write_all(&[0xFE, 0xFF])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn encode(&mut self, s: &str, last: bool) -> io::Result<()> {
+        self.start()?;
+        // encoding_rs doesn't encode into UTF-16, but that's easy to do
+        // ourselves
+        if self.encoding == OutputEncoding::Encoding(UTF_16LE) {
+            for unit in s.encode_utf16() {
+                self.w.write_all(&unit.to_le_bytes())?;
+            }
+            return Ok(());
+        }
+        if self.encoding == OutputEncoding::Encoding(UTF_16BE) {
+            for unit in s.encode_utf16() {
+                self.w.write_all(&unit.to_be_bytes())?;
+            }
+            return Ok(());
+        }
+        if let OutputEncoding::SingleByte { last, .. } = self.encoding {
+            self.buffer.clear();
+            for c in s.chars() {
+                if c > last {
+                    self.w.write_all(&self.buffer)?;
+                    return Err(self.unmappable(c));
+                }
+                self.buffer.push(c as u8);
+            }
+            return self.w.write_all(&self.buffer);
+        }
+        let mut s = s;
+        loop {
+            self.buffer.clear();
+            let needed = self
+                .encoder
+                .max_buffer_length_from_utf8_without_replacement(s.len())
+                .unwrap_or(s.len());
+            self.buffer.reserve(needed);
+            let (result, read) =
+                self.encoder
+                    .encode_from_utf8_to_vec_without_replacement(s, &mut self.buffer, last);
+            self.w.write_all(&self.buffer)?;
+            s = &s[read..];
+            match result {
+                EncoderResult::InputEmpty => return Ok(()),
+                EncoderResult::OutputFull => {}
+                EncoderResult::Unmappable(c) => return Err(self.unmappable(c)),
+            }
+        }
+    }
+
+    fn unmappable(&mut self, c: char) -> io::Error {
+        self.unencodable = Some(c);
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Character {:?} cannot be represented in {}",
+                c,
+                self.encoding.name()
+            ),
+        )
+    }
+
+    /// Finish encoding. This must be called once everything is written.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        if !self.incomplete.is_empty() {
+            return Err(invalid_utf8());
+        }
+        self.encode("", true)?;
+        self.w.flush()
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid UTF-8")
+}
+
+impl<W: Write> Write for EncodingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = std::mem::take(&mut self.incomplete);
+        bytes.extend_from_slice(buf);
+        let valid = match std::str::from_utf8(&bytes) {
+            Ok(s) => s.len(),
+            // the end of the buffer is an incomplete sequence
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => return Err(invalid_utf8()),
+        };
+        // we just checked that this is valid
+        let s = std::str::from_utf8(&bytes[..valid]).unwrap();
+        self.encode(s, false)?;
+        self.incomplete = bytes[valid..].to_vec();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    const WINDOWS_1252_OUTPUT: OutputEncoding = OutputEncoding::Encoding(WINDOWS_1252);
+
+    #[test]
+    fn test_for_label() {
+        assert_eq!(
+            OutputEncoding::for_label("Windows-1252"),
+            Some(WINDOWS_1252_OUTPUT)
+        );
+        assert_eq!(
+            OutputEncoding::for_label(" Latin1").map(|encoding| encoding.name()),
+            Some("ISO-8859-1")
+        );
+        assert_eq!(
+            OutputEncoding::for_label("US-ASCII").map(|encoding| encoding.name()),
+            Some("US-ASCII")
+        );
+        assert_eq!(OutputEncoding::for_label("unknown"), None);
+    }
+
+    #[test]
+    fn test_can_encode() {
+        assert!(can_encode(WINDOWS_1252_OUTPUT, 'a'));
+        assert!(can_encode(WINDOWS_1252_OUTPUT, 'é'));
+        assert!(can_encode(WINDOWS_1252_OUTPUT, '€'));
+        assert!(!can_encode(WINDOWS_1252_OUTPUT, 'あ'));
+        assert!(can_encode(OutputEncoding::Encoding(SHIFT_JIS), 'あ'));
+        assert!(can_encode(OutputEncoding::Encoding(UTF_16LE), '😀'));
+        let latin1 = OutputEncoding::for_label("ISO-8859-1").unwrap();
+        assert!(can_encode(latin1, 'é'));
+        assert!(can_encode(latin1, '\u{80}'));
+        assert!(!can_encode(latin1, '€'));
+        let ascii = OutputEncoding::for_label("US-ASCII").unwrap();
+        assert!(can_encode(ascii, 'a'));
+        assert!(!can_encode(ascii, 'é'));
+    }
+
+    #[test]
+    fn test_escape_characters() {
+        let unencodable = |c| !can_encode(WINDOWS_1252_OUTPUT, c);
+        assert_eq!(
+            escape_characters("éあ".into(), false, unencodable),
+            "é&#x3042;"
+        );
+        assert_eq!(
+            escape_characters("<![CDATA[あ]]>".into(), true, unencodable),
+            "<![CDATA[]]>&#x3042;<![CDATA[]]>"
+        );
+    }
+
+    #[test]
+    fn test_encoding_writer_split_sequence() {
+        let mut out = Vec::new();
+        let mut w = EncodingWriter::new(&mut out, WINDOWS_1252_OUTPUT);
+        let bytes = "aé".as_bytes();
+        w.write_all(&bytes[..2]).unwrap();
+        w.write_all(&bytes[2..]).unwrap();
+        w.finish().unwrap();
+        assert_eq!(out, b"a\xe9");
+    }
+
+    #[test]
+    fn test_encoding_writer_utf16_bom() {
+        let mut out = Vec::new();
+        let mut w = EncodingWriter::new(&mut out, OutputEncoding::Encoding(UTF_16BE));
+        w.write_all(b"a").unwrap();
+        w.finish().unwrap();
+        assert_eq!(out, b"\xfe\xff\x00a");
+    }
+
+    #[test]
+    fn test_encoding_writer_unencodable() {
+        let mut out = Vec::new();
+        let mut w = EncodingWriter::new(&mut out, WINDOWS_1252_OUTPUT);
+        assert!(w.write_all("あ".as_bytes()).is_err());
+        assert_eq!(w.unencodable, Some('あ'));
+    }
+
+    #[test]
+    fn test_encoding_writer_latin1() {
+        let mut out = Vec::new();
+        let latin1 = OutputEncoding::for_label("ISO-8859-1").unwrap();
+        let mut w = EncodingWriter::new(&mut out, latin1);
+        w.write_all("é\u{80}".as_bytes()).unwrap();
+        assert!(w.write_all("€".as_bytes()).is_err());
+        assert_eq!(w.unencodable, Some('€'));
+        assert_eq!(out, b"\xe9\x80");
+    }
+}
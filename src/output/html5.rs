@@ -10,7 +10,7 @@ use crate::Xot;
 
 use crate::NameId;
 
-use super::Indentation;
+use super::{CharacterMap, Indentation};
 
 /// Parameters for HTML generation.
 ///
@@ -49,5 +49,7 @@ pub struct Parameters {
     /// These should only be used for elements in non-XML content, like MathML
     /// or SVG.
     pub cdata_section_elements: Vec<NameId>,
-    // TODO: character maps
+    /// Characters in text and attribute values that are replaced by a literal
+    /// string, without escaping.
+    pub character_map: CharacterMap,
 }
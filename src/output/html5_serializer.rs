@@ -9,12 +9,13 @@ use crate::xotdata::{Node, Xot};
 
 use super::fullname::FullnameSerializer;
 use super::html5elements::Html5Elements;
-use super::{Output, OutputToken, Pretty};
+use super::{map_characters, CharacterMap, Output, OutputToken, Pretty};
 
 pub(crate) struct Html5Serializer<'a, N: Normalizer> {
     xot: &'a Xot,
     html5_elements: &'a Html5Elements,
     cdata_section_names: &'a [NameId],
+    character_map: &'a CharacterMap,
     fullname_serializer: FullnameSerializer<'a>,
     normalizer: N,
 }
@@ -55,6 +56,7 @@ impl<'a, N: Normalizer> Html5Serializer<'a, N> {
         html5_elements: &'a Html5Elements,
         node: Node,
         cdata_section_names: &'a [NameId],
+        character_map: &'a CharacterMap,
         normalizer: N,
     ) -> Self {
         let extra_declarations = xot.namespaces_in_scope(node).collect();
@@ -63,6 +65,7 @@ impl<'a, N: Normalizer> Html5Serializer<'a, N> {
             xot,
             html5_elements,
             cdata_section_names,
+            character_map,
             fullname_serializer,
             normalizer,
         }
@@ -234,7 +237,7 @@ impl<'a, N: Normalizer> Html5Serializer<'a, N> {
                         .fullname_serializer
                         .attribute_prefix(*name_id)?
                         .is_none()
-                        && local_name.to_ascii_lowercase() == value.to_ascii_lowercase()
+                        && local_name.eq_ignore_ascii_case(value)
                     {
                         return Ok(OutputToken {
                             space: true,
@@ -242,36 +245,45 @@ impl<'a, N: Normalizer> Html5Serializer<'a, N> {
                         });
                     }
                 }
-                let value = if namespace != self.xot.no_namespace() {
-                    serialize_attribute((*value).into(), &self.normalizer)
-                } else {
-                    serialize_attribute_html((*value).into(), &self.normalizer)
-                };
+                let value = map_characters(value, self.character_map, |value| {
+                    if namespace != self.xot.no_namespace() {
+                        serialize_attribute(value, &self.normalizer)
+                    } else {
+                        serialize_attribute_html(value, &self.normalizer)
+                    }
+                });
                 OutputToken {
                     space: true,
                     text: format!("{}=\"{}\"", fullname, value),
                 }
             }
-            Text(text) => {
+            Text(text) | Cdata(text) => {
                 // a text node is always a child of an element
                 let parent = self.xot.parent(node).unwrap();
                 let element = self.xot.element(parent).unwrap();
-                let value = if self
+                let no_escape = self
                     .html5_elements
                     .no_escape_names
-                    .matches(self.xot, element.name())
-                {
-                    serialize_text_no_escape((*text).into(), &self.normalizer).to_string()
-                } else if self.cdata_section_names.contains(&element.name()) {
-                    serialize_cdata((*text).into(), &self.normalizer).to_string()
-                } else if self
+                    .matches(self.xot, element.name());
+                let html = self
                     .html5_elements
-                    .is_html_element(self.xot, element.name())
-                {
-                    serialize_text_html((*text).into(), &self.normalizer).to_string()
-                } else {
-                    serialize_text((*text).into(), &self.normalizer, false).to_string()
-                };
+                    .is_html_element(self.xot, element.name());
+                // HTML elements can't contain CDATA sections, so there a
+                // CDATA text node is serialized as ordinary text
+                let cdata = self.cdata_section_names.contains(&element.name())
+                    || (matches!(output, Cdata(_)) && !html);
+                let value = map_characters(text, self.character_map, |text| {
+                    if no_escape {
+                        serialize_text_no_escape(text, &self.normalizer)
+                    } else if cdata {
+                        serialize_cdata(text, &self.normalizer)
+                    } else if html {
+                        serialize_text_html(text, &self.normalizer)
+                    } else {
+                        serialize_text(text, &self.normalizer, false)
+                    }
+                })
+                .to_string();
                 OutputToken {
                     space: false,
                     text: value,
@@ -975,4 +987,30 @@ mod tests {
 "#
         );
     }
+
+    #[test]
+    fn test_character_map() {
+        let mut xot = Xot::new();
+        let root = xot
+            .parse("<html><body title=\"a\u{a0}b&amp;\">\u{a0}&lt;\u{2014}</body></html>")
+            .unwrap();
+        let s = xot
+            .html5()
+            .serialize_string(
+                Parameters {
+                    character_map: [
+                        ('\u{a0}', "&#160;".to_string()),
+                        ('\u{2014}', "&mdash;".to_string()),
+                    ]
+                    .into(),
+                    ..Default::default()
+                },
+                root,
+            )
+            .unwrap();
+        assert_eq!(
+            s,
+            r#"<!DOCTYPE html><html><body title="a&#160;b&amp;">&#160;&lt;&mdash;</body></html>"#
+        );
+    }
 }
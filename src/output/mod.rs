@@ -2,6 +2,7 @@
 //!
 //! This module lets you control serialization in various ways.
 mod common;
+mod encoder;
 mod fullname;
 pub mod html5;
 mod html5_serializer;
@@ -14,7 +15,9 @@ mod serializer;
 pub mod xml;
 mod xml_serializer;
 
-pub use common::{Indentation, TokenSerializeParameters};
+pub(crate) use common::map_characters;
+pub use common::{CharacterMap, Indentation, TokenSerializeParameters};
+pub(crate) use encoder::{EncodingWriter, OutputEncoding};
 pub(crate) use fullname::FullnameSerializer;
 pub(crate) use fullname::NamespaceDeclarations;
 pub(crate) use html5_serializer::Html5Serializer;
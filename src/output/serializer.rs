@@ -28,13 +28,15 @@ pub enum Output<'a> {
     Attribute(NameId, &'a str),
     /// Text, i.e. `foo`
     Text(&'a str),
+    /// Text that is a CDATA section, i.e. `<![CDATA[foo]]>`
+    Cdata(&'a str),
     /// Comment, i.e. `<!-- foo -->`
     Comment(&'a str),
     /// Processing instruction, i.e. `<?foo bar?>`
     ProcessingInstruction(NameId, Option<&'a str>),
 }
 
-pub(crate) fn gen_outputs(xot: &Xot, node: Node) -> impl Iterator<Item = (Node, Output)> + '_ {
+pub(crate) fn gen_outputs(xot: &Xot, node: Node) -> impl Iterator<Item = (Node, Output<'_>)> + '_ {
     gen!({
         for edge in xot.traverse(node) {
             match edge {
@@ -56,7 +58,7 @@ pub(crate) fn gen_outputs(xot: &Xot, node: Node) -> impl Iterator<Item = (Node,
     .into_iter()
 }
 
-fn gen_edge_start(xot: &Xot, top_node: Node, node: Node) -> impl Iterator<Item = Output> + '_ {
+fn gen_edge_start(xot: &Xot, top_node: Node, node: Node) -> impl Iterator<Item = Output<'_>> + '_ {
     gen!({
         let value = xot.value(node);
 
@@ -86,6 +88,9 @@ fn gen_edge_start(xot: &Xot, top_node: Node, node: Node) -> impl Iterator<Item =
 
                 yield_!(Output::StartTagClose);
             }
+            Value::Text(text) if text.is_cdata() => {
+                yield_!(Output::Cdata(text.get()));
+            }
             Value::Text(text) => {
                 yield_!(Output::Text(text.get()));
             }
@@ -103,7 +108,7 @@ fn gen_edge_start(xot: &Xot, top_node: Node, node: Node) -> impl Iterator<Item =
     .into_iter()
 }
 
-fn gen_edge_end(xot: &Xot, node: Node) -> impl Iterator<Item = Output> + '_ {
+fn gen_edge_end(xot: &Xot, node: Node) -> impl Iterator<Item = Output<'_>> + '_ {
     gen!({
         let value = xot.value(node);
         if let Value::Element(element) = value {
@@ -152,4 +157,17 @@ mod tests {
         assert_eq!(v, Output::EndTag(*doc_el));
         assert!(iter.next().is_none());
     }
+
+    #[test]
+    fn test_iter_cdata() {
+        let mut xot = Xot::new();
+        let root = xot.parse(r#"<doc/>"#).unwrap();
+        let doc = xot.document_element(root).unwrap();
+        let cdata = xot.new_cdata("a<b");
+        xot.append(doc, cdata).unwrap();
+        let outputs = gen_outputs(&xot, doc)
+            .map(|(_, output)| output)
+            .collect::<Vec<_>>();
+        assert!(outputs.contains(&Output::Cdata("a<b")));
+    }
 }
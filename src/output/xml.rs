@@ -17,26 +17,27 @@
 // * `normalization-form` is not directly supported, but if you enable the `icu`
 //   feature you can use [`Xot::serialize_xml_string_with_normalizer`] to control
 //   normalization with an ICU normalizer.
-// * There is no way to declare the `version` parameter, as only XML 1.0 is
-//   permitted at this time.
-// * You can only influence encoding parameter of the XML declaration, and
-//   this does not trigger actual encoding; output is always UTF-8 and it's up
-//   to you to do any further re-encoding.
+// * The `version` parameter is part of the XML declaration, so XML 1.1 output
+//   requires an XML declaration, as it should.
+// * The `encoding` parameter is part of the XML declaration. It only triggers
+//   actual encoding when serializing to bytes; string output is always UTF-8.
 // * The `item-separator` parameter is specific to XPath/XSLT sequences and is
 //   not supported directly by Xot.
 // * The `media-type` property is only meaningful in the context of a larger
 //   system and is not supported directly by Xot.
-// * `undeclare-prefixes` is only supported by XML 1.1, which Xot does not
-//   support at present.
+// * `undeclare-prefixes` is only supported by XML 1.1, so it's part of the XML
+//   declaration as well. Since a Xot element inherits the namespaces of its
+//   ancestors, only prefixes that are explicitly undeclared in the tree (by
+//   parsing XML 1.1) are undeclared in the output.
 
 #[cfg(doc)]
 use crate::Xot;
 
 use std::io::Write;
 
-use crate::NameId;
+use crate::{NameId, XmlVersion};
 
-use super::Indentation;
+use super::{CharacterMap, Indentation};
 
 /// Parameters for XML generation.
 ///
@@ -52,13 +53,20 @@ pub struct Parameters {
     /// The XML declaration, if any.
     pub declaration: Option<Declaration>,
     /// The doctype declaration, if any.
+    ///
+    /// If this is not given and a document node is serialized, its document
+    /// type declaration is serialized, if it has one. See
+    /// [`Xot::document_type`].
     pub doctype: Option<DocType>,
     /// Whether to escape the `>` character in text content. By default this is
     /// true, which means that `>` is escaped as `&gt;`. If you set this to true,
     /// `>` is not escaped, except for the special case of `]]>` outside of CDATA,
     /// which is mandated by the XML specification to always be escaped.
     pub unescaped_gt: bool,
-    // TODO: character maps
+    /// Characters in text and attribute values that are replaced by a literal
+    /// string, without escaping. In a CDATA section, the section is closed
+    /// around the replacement.
+    pub character_map: CharacterMap,
 }
 
 /// How to format the XML declaration.
@@ -79,19 +87,31 @@ pub struct Parameters {
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Declaration {
     /// This causes an encoding declaration to be included in the XML declaration.
-    /// The text given here is taken literally. It does not affect the encoding of
-    /// the output of serialization; that is always UTF-8.
+    /// The text given here is taken literally.
+    ///
+    /// When serializing to bytes, with [`Xot::serialize_xml_write`] or
+    /// [`Xot::serialize_xml_bytes`], the output is encoded in this encoding.
+    /// Serialization to a string is always UTF-8.
     pub encoding: Option<String>,
     /// This causes a standalone declaration to be included in the XML declaration.
     pub standalone: Option<bool>,
-    // TODO: if in the future we add XML 1.1 support, we can add the version here.
-    // This way without a declaration it is automatically an XML 1.0 document. Potentially
-    // also include `undeclare-prefixes` here, as that's only supported in XML 1.1.
+    /// The XML version. Without a declaration, the output is XML 1.0.
+    ///
+    /// For XML 1.1, control characters as well as NEL (`U+0085`) and LINE
+    /// SEPARATOR (`U+2028`) in text and attribute values are serialized as
+    /// character references.
+    pub version: XmlVersion,
+    /// Whether to serialize the undeclaration of namespace prefixes
+    /// (`xmlns:prefix=""`). This is only possible in XML 1.1; with XML 1.0
+    /// undeclarations are never serialized.
+    pub undeclare_prefixes: bool,
 }
 
 impl Declaration {
     pub(crate) fn serialize(&self, w: &mut impl Write) -> Result<(), std::io::Error> {
-        w.write_all(b"<?xml version=\"1.0\"")?;
+        w.write_all(b"<?xml version=\"")?;
+        w.write_all(self.version.as_str().as_bytes())?;
+        w.write_all(b"\"")?;
         if let Some(encoding) = &self.encoding {
             w.write_all(b" encoding=\"")?;
             w.write_all(encoding.as_bytes())?;
@@ -372,4 +392,343 @@ mod tests {
             r#"<doc><p><![CDATA[hello]]]]><![CDATA[> world]]></p></doc>"#
         );
     }
+
+    #[test]
+    fn test_xml_output_retained_doctype() {
+        let m = Parameters {
+            ..Default::default()
+        };
+        let mut xot = Xot::new();
+        let doc = xot
+            .parse(r#"<!DOCTYPE doc PUBLIC "-//Example//DTD Doc//EN" "doc.dtd"><doc/>"#)
+            .unwrap();
+
+        assert_eq!(
+            xot.serialize_xml_string(m, doc).unwrap(),
+            r#"<!DOCTYPE doc PUBLIC "-//Example//DTD Doc//EN" "doc.dtd">
+<doc/>"#
+        );
+    }
+
+    #[test]
+    fn test_xml_output_retained_doctype_internal_subset() {
+        let m = Parameters {
+            ..Default::default()
+        };
+        let mut xot = Xot::new();
+        let doc = xot
+            .parse(r#"<!DOCTYPE doc [ <!ENTITY e "E"> ]><doc>&e;</doc>"#)
+            .unwrap();
+
+        assert_eq!(
+            xot.serialize_xml_string(m, doc).unwrap(),
+            r#"<!DOCTYPE doc [ <!ENTITY e "E"> ]>
+<doc>E</doc>"#
+        );
+    }
+
+    #[test]
+    fn test_xml_output_explicit_doctype_overrides_retained() {
+        let m = Parameters {
+            doctype: Some(DocType::System {
+                system: "other.dtd".to_string(),
+            }),
+            ..Default::default()
+        };
+        let mut xot = Xot::new();
+        let doc = xot
+            .parse(r#"<!DOCTYPE doc SYSTEM "doc.dtd"><doc/>"#)
+            .unwrap();
+
+        assert_eq!(
+            xot.serialize_xml_string(m, doc).unwrap(),
+            r#"<!DOCTYPE doc SYSTEM "other.dtd">
+<doc/>"#
+        );
+    }
+
+    #[test]
+    fn test_xml_output_retained_doctype_not_for_element() {
+        let m = Parameters {
+            ..Default::default()
+        };
+        let mut xot = Xot::new();
+        let doc = xot
+            .parse(r#"<!DOCTYPE doc SYSTEM "doc.dtd"><doc/>"#)
+            .unwrap();
+        let doc_el = xot.document_element(doc).unwrap();
+
+        assert_eq!(xot.serialize_xml_string(m, doc_el).unwrap(), r#"<doc/>"#);
+    }
+
+    fn encoding_parameters(encoding: &str) -> Parameters {
+        Parameters {
+            declaration: Some(Declaration {
+                encoding: Some(encoding.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_xml_output_encoding_iso_8859_1() {
+        let mut xot = Xot::new();
+        let doc = xot.parse(r#"<doc a="é">é€</doc>"#).unwrap();
+
+        assert_eq!(
+            xot.serialize_xml_bytes(encoding_parameters("ISO-8859-1"), doc)
+                .unwrap(),
+            // unlike windows-1252, ISO-8859-1 has no euro sign
+            b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n<doc a=\"\xe9\">\xe9&#x20AC;</doc>"
+        );
+    }
+
+    #[test]
+    fn test_xml_output_encoding_windows_1252() {
+        let mut xot = Xot::new();
+        let doc = xot.parse(r#"<doc a="é">é€</doc>"#).unwrap();
+
+        assert_eq!(
+            xot.serialize_xml_bytes(encoding_parameters("windows-1252"), doc)
+                .unwrap(),
+            b"<?xml version=\"1.0\" encoding=\"windows-1252\"?>\n<doc a=\"\xe9\">\xe9\x80</doc>"
+        );
+    }
+
+    #[test]
+    fn test_xml_output_encoding_us_ascii() {
+        let mut xot = Xot::new();
+        let doc = xot.parse(r#"<doc a="é">é€</doc>"#).unwrap();
+
+        assert_eq!(
+            xot.serialize_xml_bytes(encoding_parameters("US-ASCII"), doc)
+                .unwrap(),
+            b"<?xml version=\"1.0\" encoding=\"US-ASCII\"?>\n<doc a=\"&#xE9;\">&#xE9;&#x20AC;</doc>"
+        );
+    }
+
+    #[test]
+    fn test_xml_output_encoding_character_references() {
+        let mut xot = Xot::new();
+        let doc = xot
+            .parse("<doc a=\"\u{3042}\" xmlns:x=\"http://example.com/\u{3042}\">\u{3042}</doc>")
+            .unwrap();
+
+        assert_eq!(
+            xot.serialize_xml_bytes(encoding_parameters("windows-1252"), doc)
+                .unwrap(),
+            b"<?xml version=\"1.0\" encoding=\"windows-1252\"?>\n<doc xmlns:x=\"http://example.com/&#x3042;\" a=\"&#x3042;\">&#x3042;</doc>"
+        );
+    }
+
+    #[test]
+    fn test_xml_output_encoding_cdata() {
+        let mut xot = Xot::new();
+        let doc = xot.parse("<doc>a\u{3042}b</doc>").unwrap();
+        let doc_name = xot.name("doc").unwrap();
+        let m = Parameters {
+            cdata_section_elements: vec![doc_name],
+            ..encoding_parameters("windows-1252")
+        };
+
+        assert_eq!(
+            xot.serialize_xml_bytes(m, doc).unwrap(),
+            b"<?xml version=\"1.0\" encoding=\"windows-1252\"?>\n<doc><![CDATA[a]]>&#x3042;<![CDATA[b]]></doc>"
+        );
+    }
+
+    #[test]
+    fn test_xml_output_encoding_shift_jis() {
+        let mut xot = Xot::new();
+        let doc = xot.parse("<doc>\u{3042}</doc>").unwrap();
+
+        assert_eq!(
+            xot.serialize_xml_bytes(encoding_parameters("Shift_JIS"), doc)
+                .unwrap(),
+            b"<?xml version=\"1.0\" encoding=\"Shift_JIS\"?>\n<doc>\x82\xa0</doc>"
+        );
+    }
+
+    #[test]
+    fn test_xml_output_encoding_utf16() {
+        let mut xot = Xot::new();
+        let doc = xot.parse("<doc>\u{3042}</doc>").unwrap();
+
+        let bytes = xot
+            .serialize_xml_bytes(encoding_parameters("UTF-16"), doc)
+            .unwrap();
+        let mut expected = vec![0xFF, 0xFE];
+        for unit in
+            "<?xml version=\"1.0\" encoding=\"UTF-16\"?>\n<doc>\u{3042}</doc>".encode_utf16()
+        {
+            expected.extend_from_slice(&unit.to_le_bytes());
+        }
+        assert_eq!(bytes, expected);
+        // and we can parse it again
+        let parsed = xot.parse_reader(&bytes[..]).unwrap();
+        assert_eq!(xot.to_string(parsed).unwrap(), "<doc>\u{3042}</doc>");
+    }
+
+    #[test]
+    fn test_xml_output_encoding_unencodable_name() {
+        let mut xot = Xot::new();
+        let doc = xot.parse("<\u{3042}/>").unwrap();
+
+        assert!(matches!(
+            xot.serialize_xml_bytes(encoding_parameters("windows-1252"), doc),
+            Err(crate::Error::UnencodableCharacter('\u{3042}', _))
+        ));
+    }
+
+    #[test]
+    fn test_xml_output_encoding_unsupported() {
+        let mut xot = Xot::new();
+        let doc = xot.parse("<doc/>").unwrap();
+
+        assert!(matches!(
+            xot.serialize_xml_bytes(encoding_parameters("no-such-encoding"), doc),
+            Err(crate::Error::UnsupportedEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn test_xml_output_encoding_string_unaffected() {
+        let mut xot = Xot::new();
+        let doc = xot.parse("<doc>\u{3042}</doc>").unwrap();
+
+        assert_eq!(
+            xot.serialize_xml_string(encoding_parameters("windows-1252"), doc)
+                .unwrap(),
+            "<?xml version=\"1.0\" encoding=\"windows-1252\"?>\n<doc>\u{3042}</doc>"
+        );
+    }
+
+    #[test]
+    fn test_xml_output_character_map() {
+        let mut xot = Xot::new();
+        let doc = xot.parse(r#"<doc a="«&amp;»">«&lt;»</doc>"#).unwrap();
+        let m = Parameters {
+            character_map: [('«', "<span>".to_string()), ('»', "</span>".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            xot.serialize_xml_string(m, doc).unwrap(),
+            r#"<doc a="<span>&amp;</span>"><span>&lt;</span></doc>"#
+        );
+    }
+
+    #[test]
+    fn test_xml_output_character_map_cdata() {
+        let mut xot = Xot::new();
+        let doc = xot.parse("<doc>a\u{a0}b\u{a0}</doc>").unwrap();
+        let doc_name = xot.name("doc").unwrap();
+        let m = Parameters {
+            cdata_section_elements: vec![doc_name],
+            character_map: [('\u{a0}', "&nbsp;".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            xot.serialize_xml_string(m, doc).unwrap(),
+            "<doc><![CDATA[a]]>&nbsp;<![CDATA[b]]>&nbsp;</doc>"
+        );
+    }
+
+    #[test]
+    fn test_xml_output_character_map_not_in_comment() {
+        let mut xot = Xot::new();
+        let doc = xot.parse("<doc><!--«--><?pi «?></doc>").unwrap();
+        let m = Parameters {
+            character_map: [('«', "&laquo;".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            xot.serialize_xml_string(m, doc).unwrap(),
+            "<doc><!--«--><?pi «?></doc>"
+        );
+    }
+
+    fn xml11_parameters(undeclare_prefixes: bool) -> Parameters {
+        Parameters {
+            declaration: Some(Declaration {
+                version: XmlVersion::V1_1,
+                undeclare_prefixes,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_xml_output_xml11_character_references() {
+        let mut xot = Xot::new();
+        let doc = xot
+            .parse("<?xml version=\"1.1\"?><doc a=\"&#x1;\">&#x1;\u{85}&#x7F;\t&#x2028;</doc>")
+            .unwrap();
+
+        let xml = xot
+            .serialize_xml_string(xml11_parameters(false), doc)
+            .unwrap();
+        assert_eq!(
+            xml,
+            "<?xml version=\"1.1\"?>\n<doc a=\"&#x1;\">&#x1;\n&#x7F;\t&#x2028;</doc>"
+        );
+        // this round-trips
+        let parsed = xot.parse(&xml).unwrap();
+        assert_eq!(
+            xot.serialize_xml_string(xml11_parameters(false), parsed)
+                .unwrap(),
+            xml
+        );
+    }
+
+    #[test]
+    fn test_xml_output_xml11_cdata() {
+        let mut xot = Xot::new();
+        let doc = xot
+            .parse("<?xml version=\"1.1\"?><doc>a&#x1;b</doc>")
+            .unwrap();
+        let doc_name = xot.name("doc").unwrap();
+        let m = Parameters {
+            cdata_section_elements: vec![doc_name],
+            ..xml11_parameters(false)
+        };
+
+        assert_eq!(
+            xot.serialize_xml_string(m, doc).unwrap(),
+            "<?xml version=\"1.1\"?>\n<doc><![CDATA[a]]>&#x1;<![CDATA[b]]></doc>"
+        );
+    }
+
+    #[test]
+    fn test_xml_output_xml10_no_character_references() {
+        let mut xot = Xot::new();
+        let doc = xot.parse("<doc>\u{85}\u{2028}</doc>").unwrap();
+
+        assert_eq!(xot.to_string(doc).unwrap(), "<doc>\u{85}\u{2028}</doc>");
+    }
+
+    #[test]
+    fn test_xml_output_undeclare_prefixes() {
+        let mut xot = Xot::new();
+        let doc = xot
+            .parse(r#"<?xml version="1.1"?><p:doc xmlns:p="http://example.com"><a xmlns:p=""/></p:doc>"#)
+            .unwrap();
+
+        assert_eq!(
+            xot.serialize_xml_string(xml11_parameters(true), doc)
+                .unwrap(),
+            "<?xml version=\"1.1\"?>\n<p:doc xmlns:p=\"http://example.com\"><a xmlns:p=\"\"/></p:doc>"
+        );
+        assert_eq!(
+            xot.serialize_xml_string(xml11_parameters(false), doc)
+                .unwrap(),
+            "<?xml version=\"1.1\"?>\n<p:doc xmlns:p=\"http://example.com\"><a/></p:doc>"
+        );
+        // XML 1.0 can't undeclare prefixes
+        assert_eq!(
+            xot.to_string(doc).unwrap(),
+            r#"<p:doc xmlns:p="http://example.com"><a/></p:doc>"#
+        );
+    }
 }
@@ -1,12 +1,18 @@
+use std::borrow::Cow;
 use std::io;
 
 use crate::entity::{serialize_attribute, serialize_cdata, serialize_text};
 use crate::error::Error;
 use crate::id::NameId;
+use crate::lexical::{AttributeItem, ElementLexical, Lexical};
 use crate::output::Normalizer;
+use crate::validate::is_restricted_char;
 use crate::xotdata::{Node, Xot};
+use crate::XmlVersion;
 
+use super::encoder::{can_encode, escape_characters, OutputEncoding};
 use super::fullname::FullnameSerializer;
+use super::map_characters;
 use super::{Output, OutputToken, Pretty, TokenSerializeParameters};
 
 pub(crate) struct XmlSerializer<'a, N: Normalizer> {
@@ -14,6 +20,19 @@ pub(crate) struct XmlSerializer<'a, N: Normalizer> {
     fullname_serializer: FullnameSerializer<'a>,
     normalizer: N,
     parameters: TokenSerializeParameters,
+    // the encoding of the output, if it's not UTF-8
+    encoding: Option<OutputEncoding>,
+    version: XmlVersion,
+    undeclare_prefixes: bool,
+    // the node being serialized
+    top: Node,
+    // in lossless mode, the lexical detail of the document
+    lexical: Option<&'a Lexical>,
+    // whether the text around top-level nodes is serialized
+    lossless_document: bool,
+    // whether the start tag being serialized is taken from the source as a
+    // whole
+    raw_start_tag: bool,
 }
 
 impl<'a, N: Normalizer> XmlSerializer<'a, N> {
@@ -30,9 +49,133 @@ impl<'a, N: Normalizer> XmlSerializer<'a, N> {
             fullname_serializer,
             normalizer,
             parameters,
+            encoding: None,
+            version: XmlVersion::V1_0,
+            undeclare_prefixes: false,
+            top: node,
+            lexical: None,
+            lossless_document: false,
+            raw_start_tag: false,
         }
     }
 
+    // nodes that haven't changed since a lossless parse are serialized as
+    // they were in the source. If `document` is set and we serialize a
+    // document node, this includes the text around the top-level nodes.
+    pub(crate) fn with_lossless(mut self, document: bool) -> Self {
+        self.lexical = self.xot.lexical.get(&self.xot.root(self.top).get());
+        self.lossless_document = document && self.xot.is_document(self.top);
+        self
+    }
+
+    // the text before the first top-level node, in lossless mode
+    pub(crate) fn prolog(&self) -> Option<&'a str> {
+        if !self.lossless_document {
+            return None;
+        }
+        self.lexical.and_then(|lexical| lexical.prolog())
+    }
+
+    // the text after the last top-level node, in lossless mode
+    pub(crate) fn epilog(&self) -> Option<&'a str> {
+        if !self.lossless_document {
+            return None;
+        }
+        self.lexical.and_then(|lexical| lexical.epilog())
+    }
+
+    // the text before a top-level node, in lossless mode
+    fn gap(&self, node: Node) -> &'a str {
+        match self.lexical {
+            Some(lexical) if self.lossless_document && self.xot.parent(node) == Some(self.top) => {
+                lexical.gap(node)
+            }
+            _ => "",
+        }
+    }
+
+    fn element_lexical(&self, node: Node) -> Option<(&'a Lexical, &'a ElementLexical)> {
+        let lexical = self.lexical?;
+        Some((lexical, lexical.element(node)?))
+    }
+
+    // whether the namespace declarations and attributes of an element are
+    // the same as in the source
+    fn is_start_tag_unchanged(
+        &self,
+        node: Node,
+        lexical: &Lexical,
+        element: &ElementLexical,
+    ) -> Result<bool, Error> {
+        let namespaces = self.xot.namespaces(node);
+        // the top element may need additional declarations
+        if node == self.top
+            && self
+                .xot
+                .namespaces_in_scope(node)
+                .any(|(prefix_id, namespace_id)| {
+                    !namespaces.contains_key(prefix_id) && namespace_id != self.xot.xml_namespace()
+                })
+        {
+            return Ok(false);
+        }
+        let declared = namespaces.iter().map(|(prefix_id, namespace_id)| {
+            (
+                self.xot.prefix_str(prefix_id),
+                self.xot.namespace_str(*namespace_id),
+            )
+        });
+        if !declared.eq(lexical.namespaces(element)) {
+            return Ok(false);
+        }
+        let attributes = self
+            .xot
+            .attributes(node)
+            .iter()
+            .map(|(name_id, value)| {
+                Ok((
+                    self.fullname_serializer.attribute_fullname(name_id)?,
+                    value.as_str(),
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(attributes
+            .iter()
+            .map(|(fullname, value)| (fullname.as_ref(), *value))
+            .eq(lexical.attributes(element)))
+    }
+
+    // characters that cannot be represented in the encoding are replaced by
+    // character references in text and attribute values
+    pub(crate) fn with_encoding(mut self, encoding: OutputEncoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    pub(crate) fn with_version(mut self, version: XmlVersion, undeclare_prefixes: bool) -> Self {
+        self.version = version;
+        self.undeclare_prefixes = undeclare_prefixes;
+        self
+    }
+
+    fn needs_reference(&self, c: char) -> bool {
+        if self.version == XmlVersion::V1_1 && is_restricted_char(c) {
+            return true;
+        }
+        if let Some(encoding) = self.encoding {
+            !can_encode(encoding, c)
+        } else {
+            false
+        }
+    }
+
+    fn escape_characters<'b>(&self, text: Cow<'b, str>, cdata: bool) -> Cow<'b, str> {
+        if self.encoding.is_none() && self.version == XmlVersion::V1_0 {
+            return text;
+        }
+        escape_characters(text, cdata, |c| self.needs_reference(c))
+    }
+
     pub(crate) fn serialize<W: io::Write>(
         &mut self,
         w: &mut W,
@@ -73,9 +216,9 @@ impl<'a, N: Normalizer> XmlSerializer<'a, N> {
     ) -> Result<(), Error> {
         let data = self.render_output(node, &output)?;
         if data.space {
-            w.write_all(b" ").unwrap();
+            w.write_all(b" ")?;
         }
-        w.write_all(data.text.as_bytes()).unwrap();
+        w.write_all(data.text.as_bytes())?;
         Ok(())
     }
 
@@ -84,21 +227,65 @@ impl<'a, N: Normalizer> XmlSerializer<'a, N> {
         node: Node,
         output: &Output<'a>,
     ) -> Result<OutputToken, Error> {
+        let mut token = self.render_token(node, output)?;
+        // the first token of a top-level node is preceded by the text before
+        // the node in the source
+        if matches!(
+            output,
+            Output::StartTagOpen(_)
+                | Output::Text(_)
+                | Output::Cdata(_)
+                | Output::Comment(_)
+                | Output::ProcessingInstruction(..)
+        ) {
+            token.text.insert_str(0, self.gap(node));
+        }
+        Ok(token)
+    }
+
+    fn render_token(&mut self, node: Node, output: &Output<'a>) -> Result<OutputToken, Error> {
         use Output::*;
+        // text taken from the source is kept as it was written
+        if let Text(text) | Cdata(text) = output {
+            if let Some(raw) = self.lexical.and_then(|lexical| lexical.text(node, text)) {
+                return Ok(OutputToken {
+                    space: false,
+                    text: raw.to_string(),
+                });
+            }
+        }
         let r = match output {
             StartTagOpen(element) => {
                 self.fullname_serializer
                     .push(self.xot.namespace_declarations(node));
+                let fullname = self.fullname_serializer.element_fullname(element.name_id)?;
+                if let Some((lexical, element)) = self.element_lexical(node) {
+                    if lexical.qname(element) == fullname
+                        && self.is_start_tag_unchanged(node, lexical, element)?
+                    {
+                        self.raw_start_tag = true;
+                        return Ok(OutputToken {
+                            space: false,
+                            text: lexical.start_tag(element).to_string(),
+                        });
+                    }
+                }
                 OutputToken {
                     space: false,
-                    text: format!(
-                        "<{}",
-                        self.fullname_serializer.element_fullname(element.name_id)?
-                    ),
+                    text: format!("<{}", fullname),
                 }
             }
             StartTagClose => {
-                if self.xot.first_child(node).is_none() {
+                self.raw_start_tag = false;
+                let has_children = self.xot.first_child(node).is_some();
+                if let Some((lexical, element)) = self.element_lexical(node) {
+                    let end_tag = has_children || lexical.has_end_tag(element);
+                    return Ok(OutputToken {
+                        space: false,
+                        text: lexical.close(element, end_tag).into_owned(),
+                    });
+                }
+                if !has_children {
                     OutputToken {
                         space: false,
                         text: "/>".to_string(),
@@ -111,13 +298,22 @@ impl<'a, N: Normalizer> XmlSerializer<'a, N> {
                 }
             }
             EndTag(element) => {
-                let r = if self.xot.first_child(node).is_some() {
+                let element_lexical = self.element_lexical(node);
+                let end_tag = self.xot.first_child(node).is_some()
+                    || element_lexical
+                        .is_some_and(|(lexical, element)| lexical.has_end_tag(element));
+                let r = if end_tag {
+                    let fullname = self.fullname_serializer.element_fullname(element.name_id)?;
+                    let raw = element_lexical.and_then(|(lexical, element)| {
+                        if lexical.qname(element) == fullname {
+                            lexical.end_tag(element)
+                        } else {
+                            None
+                        }
+                    });
                     OutputToken {
                         space: false,
-                        text: format!(
-                            "</{}>",
-                            self.fullname_serializer.element_fullname(element.name_id)?
-                        ),
+                        text: raw.map_or_else(|| format!("</{}>", fullname), str::to_string),
                     }
                 } else {
                     OutputToken {
@@ -130,14 +326,38 @@ impl<'a, N: Normalizer> XmlSerializer<'a, N> {
                 r
             }
             Prefix(prefix_id, namespace_id) => {
-                // we don't want to output the xml prefix
-                if *namespace_id == self.xot.xml_namespace() {
+                // we don't want to output the xml prefix, nor anything that
+                // is in a start tag taken from the source
+                if *namespace_id == self.xot.xml_namespace() || self.raw_start_tag {
+                    return Ok(OutputToken {
+                        space: false,
+                        text: "".to_string(),
+                    });
+                }
+                // a prefix bound to no namespace is undeclared
+                if *namespace_id == self.xot.no_namespace()
+                    && *prefix_id != self.xot.empty_prefix_id
+                    && !(self.version == XmlVersion::V1_1 && self.undeclare_prefixes)
+                {
                     return Ok(OutputToken {
                         space: false,
                         text: "".to_string(),
                     });
                 }
-                let namespace = self.xot.namespace_str(*namespace_id);
+                if let Some((lexical, element)) = self.element_lexical(node) {
+                    if let Some(raw) = lexical.namespace_item(
+                        element,
+                        self.xot.prefix_str(*prefix_id),
+                        self.xot.namespace_str(*namespace_id),
+                    ) {
+                        return Ok(OutputToken {
+                            space: false,
+                            text: raw.to_string(),
+                        });
+                    }
+                }
+                let namespace =
+                    self.escape_characters(self.xot.namespace_str(*namespace_id).into(), false);
                 if *prefix_id == self.xot.empty_prefix_id {
                     OutputToken {
                         space: true,
@@ -152,14 +372,34 @@ impl<'a, N: Normalizer> XmlSerializer<'a, N> {
                 }
             }
             Attribute(name_id, value) => {
+                if self.raw_start_tag {
+                    return Ok(OutputToken {
+                        space: false,
+                        text: "".to_string(),
+                    });
+                }
                 let fullname = self.fullname_serializer.attribute_fullname(*name_id)?;
-                OutputToken {
-                    space: true,
-                    text: format!(
-                        "{}=\"{}\"",
-                        fullname,
-                        serialize_attribute((*value).into(), &self.normalizer)
-                    ),
+                let serialized = map_characters(value, &self.parameters.character_map, |value| {
+                    self.escape_characters(serialize_attribute(value, &self.normalizer), false)
+                });
+                let item = self.element_lexical(node).and_then(|(lexical, element)| {
+                    lexical.attribute_item(element, &fullname, value)
+                });
+                match item {
+                    Some(AttributeItem::Unchanged(raw)) => OutputToken {
+                        space: false,
+                        text: raw.to_string(),
+                    },
+                    // keep the whitespace and quotes of an attribute whose
+                    // value changed
+                    Some(AttributeItem::Changed(before)) => OutputToken {
+                        space: false,
+                        text: format!("{}{}{}", before, serialized, &before[before.len() - 1..]),
+                    },
+                    None => OutputToken {
+                        space: true,
+                        text: format!("{}=\"{}\"", fullname, serialized),
+                    },
                 }
             }
             Text(text) => {
@@ -177,20 +417,35 @@ impl<'a, N: Normalizer> XmlSerializer<'a, N> {
                 if is_cdata_element {
                     OutputToken {
                         space: false,
-                        text: serialize_cdata((*text).into(), &self.normalizer).to_string(),
+                        text: map_characters(text, &self.parameters.character_map, |text| {
+                            self.escape_characters(serialize_cdata(text, &self.normalizer), true)
+                        })
+                        .to_string(),
                     }
                 } else {
                     OutputToken {
                         space: false,
-                        text: serialize_text(
-                            (*text).into(),
-                            &self.normalizer,
-                            self.parameters.unescaped_gt,
-                        )
+                        text: map_characters(text, &self.parameters.character_map, |text| {
+                            self.escape_characters(
+                                serialize_text(
+                                    text,
+                                    &self.normalizer,
+                                    self.parameters.unescaped_gt,
+                                ),
+                                false,
+                            )
+                        })
                         .to_string(),
                     }
                 }
             }
+            Cdata(text) => OutputToken {
+                space: false,
+                text: map_characters(text, &self.parameters.character_map, |text| {
+                    self.escape_characters(serialize_cdata(text, &self.normalizer), true)
+                })
+                .to_string(),
+            },
             Comment(text) => OutputToken {
                 space: false,
                 text: format!("<!--{}-->", text),
@@ -200,7 +455,15 @@ impl<'a, N: Normalizer> XmlSerializer<'a, N> {
                 if !ns.is_empty() {
                     return Err(Error::NamespaceInProcessingInstruction);
                 }
-                if let Some(data) = data {
+                let raw = self
+                    .lexical
+                    .and_then(|lexical| lexical.processing_instruction(node, target, *data));
+                if let Some(raw) = raw {
+                    OutputToken {
+                        space: false,
+                        text: raw.to_string(),
+                    }
+                } else if let Some(data) = data {
                     OutputToken {
                         space: false,
                         text: format!("<?{} {}?>", target, data),
@@ -1,129 +1,236 @@
-use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use std::io::Read;
+
+use ahash::{HashMap, HashMapExt};
 use indextree::NodeId;
-use xmlparser::{ElementEnd, StrSpan, Token, Tokenizer};
+use xmlparser::StrSpan;
 
-use crate::encoding::decode;
-use crate::entity::{parse_attribute, parse_text};
-use crate::error::ParseError;
-use crate::id::{Name, NameId, PrefixId};
-use crate::xmlvalue::{Attribute, Comment, Element, Namespace, ProcessingInstruction, Text, Value};
+use crate::encoding::{decode, decode_reader};
+use crate::error::{Error, ParseError, ParseLimit};
+use crate::id::{NameId, NamespaceId, PrefixId};
+use crate::lexical::LexicalBuilder;
+use crate::pull::{self, Event};
+use crate::xmlid::XmlIds;
+use crate::xmlvalue::{
+    Attribute, Comment, DocumentType, Element, Namespace, ProcessingInstruction, Text, Value,
+    ValueType,
+};
 use crate::xotdata::{Node, Xot};
-use crate::NamespaceId;
 
-type Namespaces = Vec<(PrefixId, NamespaceId)>;
+/// Options that control how XML is parsed.
+///
+/// You use these with [`Xot::parse_with_options`] and
+/// [`Xot::parse_fragment_with_options`]. The default options give the same
+/// result as [`Xot::parse_with_span_info`].
+///
+/// ```rust
+/// use xot::{ParseOptions, Xot};
+///
+/// let mut xot = Xot::new();
+/// let options = ParseOptions {
+///     strip_whitespace: true,
+///     drop_comments: true,
+///     ..Default::default()
+/// };
+/// let (doc, _) = xot.parse_with_options("<doc>\n  <!-- note -->\n  <p>text</p>\n</doc>", &options)?;
+/// assert_eq!(xot.to_string(doc)?, "<doc><p>text</p></doc>");
+/// # Ok::<(), xot::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Remove insignificant whitespace while parsing. This gives the same
+    /// result as calling [`Xot::remove_insignificant_whitespace`] after the
+    /// parse, but avoids a second pass over the tree. Defaults to false.
+    pub strip_whitespace: bool,
+    /// Leave out comments. Defaults to false.
+    pub drop_comments: bool,
+    /// Leave out processing instructions. Defaults to false.
+    pub drop_processing_instructions: bool,
+    /// Fail with [`ParseError::DuplicateId`] if an id is used more than once.
+    /// If this is false, the first element with the id is used by
+    /// [`Xot::xml_id_node`]. Defaults to true.
+    pub check_duplicate_ids: bool,
+    /// Merge adjacent text, such as text and CDATA sections, into a single
+    /// text node. Text around a dropped comment or processing instruction is
+    /// merged too. This only affects parsing; see
+    /// [`Xot::set_text_consolidation`] for manipulation. Defaults to true.
+    pub consolidate_text: bool,
+    /// Record span information. If this is false, the returned [`SpanInfo`]
+    /// is empty. Defaults to true.
+    pub span_info: bool,
+    /// Keep CDATA sections as text nodes that are marked as CDATA (see
+    /// [`Text::is_cdata`](crate::Text::is_cdata)), so that they are
+    /// serialized as CDATA sections again. They are only consolidated with
+    /// adjacent CDATA sections. If this is false, CDATA sections become
+    /// ordinary text. Defaults to false.
+    pub preserve_cdata: bool,
+    /// Record the lexical detail of the source, so that serializing with
+    /// [`Xot::to_string`] gives back the original text for nodes that you
+    /// haven't changed. This covers the XML declaration and whitespace
+    /// outside the document element, whitespace and quotes in tags, `<a/>`
+    /// versus `<a></a>`, and character and entity references. CDATA
+    /// sections are preserved as with `preserve_cdata`. Defaults to false.
+    ///
+    /// The source text is kept along with the document, until you remove
+    /// it with [`Xot::remove`].
+    pub lossless: bool,
+    /// Limits on the resources used by parsing. By default only entity
+    /// expansion is limited.
+    pub limits: ParseLimits,
+}
 
-struct AttributeBuilder {
-    prefix: String,
-    name: String,
-    value: String,
-    name_span: Span,
-    value_span: Span,
-    prefix_span: Span,
+/// Limits on the resources used by parsing.
+///
+/// When you parse XML from an untrusted source, a crafted document could
+/// exhaust memory. With these limits parsing fails with
+/// [`ParseError::LimitExceeded`] instead. A limit that is `None` isn't
+/// enforced.
+///
+/// By default only entity expansion is limited, as a few bytes of entity
+/// declarations can expand to gigabytes of text (the "billion laughs"
+/// attack). Real-world documents are very unlikely to reach the default
+/// limits. What the other limits should be depends on what your
+/// application can handle, so they aren't enforced by default.
+///
+/// ```rust
+/// use xot::{ParseError, ParseLimit, ParseLimits, ParseOptions, Xot};
+///
+/// let mut xot = Xot::new();
+/// let options = ParseOptions {
+///     limits: ParseLimits {
+///         max_depth: Some(2),
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+/// assert!(xot.parse_with_options("<a><b/></a>", &options).is_ok());
+/// let result = xot.parse_with_options("<a><b><c/></b></a>", &options);
+/// assert!(matches!(
+///     result,
+///     Err(ParseError::LimitExceeded(ParseLimit::Depth, _))
+/// ));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// The maximum nesting depth of elements. The document element is at
+    /// depth 1.
+    pub max_depth: Option<usize>,
+    /// The maximum number of attributes and namespace declarations on an
+    /// element, including attributes defaulted by the DTD.
+    pub max_attributes: Option<usize>,
+    /// The maximum length of a text node in bytes, after consolidation of
+    /// adjacent text. This is checked while entities are expanded too, so
+    /// text is rejected before it's expanded in full.
+    pub max_text_length: Option<usize>,
+    /// The maximum number of nodes in the parsed document, including
+    /// attribute and namespace nodes.
+    pub max_nodes: Option<usize>,
+    /// The maximum number of names, namespaces and prefixes that parsing
+    /// adds to the [`Xot`]. Names that it already has don't count. If you use
+    /// [`NameTables`](crate::NameTables), names that other Xots add during
+    /// the parse don't count either.
+    pub max_names: Option<usize>,
+    /// The maximum number of bytes of entity replacement text that a
+    /// document expands. Each reference to a declared entity adds the
+    /// length of its replacement text, including references in the
+    /// replacement text of other entities. The default is 10 MB.
+    pub max_entity_expansion: Option<usize>,
+    /// The maximum number of references to declared entities that a
+    /// document expands, including references in the replacement text of
+    /// other entities. References to the predefined entities such as `&amp;`
+    /// and character references don't count. The default is a million.
+    pub max_entity_references: Option<usize>,
 }
 
-struct ElementBuilder {
-    prefix: String,
-    name: String,
-    namespaces: Namespaces,
-    attributes: Vec<AttributeBuilder>,
-    prefix_span: Span,
-    span: Span,
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            max_attributes: None,
+            max_text_length: None,
+            max_nodes: None,
+            max_names: None,
+            max_entity_expansion: Some(10_000_000),
+            max_entity_references: Some(1_000_000),
+        }
+    }
 }
 
-impl ElementBuilder {
-    fn new(prefix: StrSpan<'_>, name: StrSpan<'_>) -> Self {
-        ElementBuilder {
-            prefix: prefix.to_string(),
-            name: name.to_string(),
-            namespaces: Namespaces::new(),
-            attributes: Vec::new(),
-            prefix_span: prefix.into(),
-            span: Span::from_prefix_name(prefix, name),
+impl ParseLimits {
+    pub(crate) fn check(
+        &self,
+        limit: ParseLimit,
+        value: usize,
+        span: Span,
+    ) -> Result<(), ParseError> {
+        let max = match limit {
+            ParseLimit::Depth => self.max_depth,
+            ParseLimit::Attributes => self.max_attributes,
+            ParseLimit::TextLength => self.max_text_length,
+            ParseLimit::Nodes => self.max_nodes,
+            ParseLimit::Names => self.max_names,
+            ParseLimit::EntityExpansion => self.max_entity_expansion,
+            ParseLimit::EntityReferences => self.max_entity_references,
+        };
+        match max {
+            Some(max) if value > max => Err(ParseError::LimitExceeded(limit, span)),
+            _ => Ok(()),
         }
     }
 }
 
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            strip_whitespace: false,
+            drop_comments: false,
+            drop_processing_instructions: false,
+            check_duplicate_ids: true,
+            consolidate_text: true,
+            span_info: true,
+            preserve_cdata: false,
+            lossless: false,
+            limits: ParseLimits::default(),
+        }
+    }
+}
+
+// builds a Xot tree from pull parser events
 struct DocumentBuilder {
     tree: NodeId,
     current_node_id: NodeId,
-    name_id_builder: NameIdBuilder,
-    element_builder: Option<ElementBuilder>,
-    seen_ids: HashSet<String>,
-    id_nodes: HashMap<String, NodeId>,
-    xml_id_id: NameId,
+    xml_ids: XmlIds,
+    document_type: Option<DocumentType>,
+    options: ParseOptions,
+    // whether xml:space="preserve" is in effect, for each open element
+    preserve_space: Vec<bool>,
+    // in lenient mode, errors are recorded here instead of returned
+    lenient: bool,
+    errors: Vec<ParseError>,
+    // in lossless mode, lexical detail is recorded here
+    lexical: Option<LexicalBuilder>,
+    // the number of nodes added
+    node_count: usize,
 }
 
 impl DocumentBuilder {
-    fn new(xot: &mut Xot) -> Self {
+    fn new(xot: &mut Xot, options: &ParseOptions) -> Self {
         let document = xot.arena.new_node(Value::Document);
-        let mut name_id_builder = NameIdBuilder::new(xot.base_prefixes().into_iter().collect());
-        let base_prefixes = vec![(xot.empty_prefix_id, xot.no_namespace_id)];
-        name_id_builder.push(base_prefixes);
         DocumentBuilder {
             tree: document,
             current_node_id: document,
-            name_id_builder,
-            element_builder: None,
-            seen_ids: HashSet::new(),
-            id_nodes: HashMap::new(),
-            xml_id_id: xot.xml_id_id,
+            xml_ids: XmlIds::new(),
+            document_type: None,
+            options: options.clone(),
+            preserve_space: vec![false],
+            lenient: false,
+            errors: Vec::new(),
+            lexical: None,
+            node_count: 1,
         }
     }
 
-    fn element(&mut self, prefix: StrSpan<'_>, name: StrSpan<'_>) {
-        self.element_builder = Some(ElementBuilder::new(prefix, name));
-    }
-
-    fn prefix(&mut self, prefix: &str, namespace_uri: &str, xot: &mut Xot) {
-        let prefix_id = xot.prefix_lookup.get_id_mut(prefix);
-        let namespace_id = xot.namespace_lookup.get_id_mut(namespace_uri);
-        self.element_builder
-            .as_mut()
-            .unwrap()
-            .namespaces
-            .push((prefix_id, namespace_id));
-    }
-
-    fn attribute(
-        &mut self,
-        prefix: StrSpan<'_>,
-        name: StrSpan<'_>,
-        value: StrSpan<'_>,
-    ) -> Result<(), ParseError> {
-        let attributes = &mut self.element_builder.as_mut().unwrap().attributes;
-        let is_duplicate = attributes.iter().any(|attribute_builder| {
-            attribute_builder.prefix == prefix.as_str() && attribute_builder.name == name.as_str()
-        });
-        if is_duplicate {
-            let attr_name = if prefix.is_empty() {
-                name.to_string()
-            } else {
-                format!("{}:{}", prefix, name)
-            };
-            let span = Span::from_prefix_name(prefix, name);
-            return Err(ParseError::DuplicateAttribute(attr_name, span));
-        }
-        let value_span = value.into();
-        let value = parse_attribute(value.as_str().into(), value.start())?.to_string();
-        // if this is an xml:id we want to apply xml:id normalization as described here
-        // https://www.w3.org/TR/xml-id/#id-avn
-        let value = if name == "id" && prefix == "xml" {
-            normalize_xml_id(&value)
-        } else {
-            value
-        };
-        attributes.push(AttributeBuilder {
-            prefix: prefix.to_string(),
-            name: name.to_string(),
-            value,
-            name_span: Span::from_prefix_name(prefix, name),
-            value_span,
-            prefix_span: prefix.into(),
-        });
-        Ok(())
-    }
-
     fn add(&mut self, value: Value, xot: &mut Xot) -> NodeId {
+        self.node_count += 1;
         let node_id = xot.arena.new_node(value);
         self.current_node_id.append(node_id, &mut xot.arena);
         node_id
@@ -131,237 +238,279 @@ impl DocumentBuilder {
 
     fn open_element(
         &mut self,
+        name_id: NameId,
+        namespaces: Vec<(PrefixId, NamespaceId)>,
+        attributes: Vec<pull::Attribute>,
         xot: &mut Xot,
-    ) -> Result<(NodeId, Span, AttributeSpans), ParseError> {
-        let element_builder = self.element_builder.take().unwrap();
-        let span = element_builder.span;
-
-        self.name_id_builder
-            .push(element_builder.namespaces.clone());
-
-        let name_id = self.name_id_builder.element_name_id(
-            &element_builder.prefix,
-            &element_builder.name,
-            element_builder.prefix_span,
-            xot,
-        )?;
+    ) -> Result<(NodeId, AttributeSpans), ParseError> {
         let element_value = Value::Element(Element { name_id });
         let node_id = self.add(element_value, xot);
         self.current_node_id = node_id;
 
+        self.node_count += namespaces.len() + attributes.len();
         // add namespace nodes
-        for (prefix_id, namespace_id) in &element_builder.namespaces {
+        for (prefix_id, namespace_id) in namespaces {
             let namespace_node = xot.arena.new_node(Value::Namespace(Namespace {
-                prefix_id: *prefix_id,
-                namespace_id: *namespace_id,
+                prefix_id,
+                namespace_id,
             }));
             self.current_node_id.append(namespace_node, &mut xot.arena);
         }
         // add attribute nodes
         let mut attribute_spans = Vec::new();
-        for attribute_builder in element_builder.attributes {
-            let name_id = self.name_id_builder.attribute_name_id(
-                &attribute_builder.prefix,
-                &attribute_builder.name,
-                attribute_builder.prefix_span,
-                xot,
-            )?;
-            // if we see xml:id, check that they aren't a duplicate
-            // and keep track of all node ids that have an xml:id
-            if name_id == self.xml_id_id {
-                if self.seen_ids.contains(&attribute_builder.value) {
-                    return Err(ParseError::DuplicateId(
-                        attribute_builder.value,
-                        attribute_builder.value_span,
-                    ));
+        let mut preserve_space = *self.preserve_space.last().unwrap();
+        for attribute in attributes {
+            if attribute.name == xot.xml_space_id {
+                match attribute.value.as_ref() {
+                    "preserve" => preserve_space = true,
+                    "default" => preserve_space = false,
+                    _ => {}
+                }
+            }
+            // if we see xml:id or an attribute declared as an ID, check that
+            // they aren't a duplicate and keep track of all node ids that have
+            // an id
+            if attribute.is_id {
+                if self.xml_ids.contains(&attribute.value) {
+                    let error =
+                        ParseError::DuplicateId(attribute.value.to_string(), attribute.value_span);
+                    if self.options.check_duplicate_ids {
+                        if !self.lenient {
+                            return Err(error);
+                        }
+                        self.errors.push(error);
+                    }
+                    // the first element with the id keeps it
+                } else {
+                    self.xml_ids.insert(&attribute.value, node_id);
                 }
-                self.seen_ids.insert(attribute_builder.value.clone());
-                // use entry api to add node id for the value given
-                self.id_nodes
-                    .insert(attribute_builder.value.clone(), node_id);
             }
 
             let attribute_node = xot.arena.new_node(Value::Attribute(Attribute {
-                name_id,
-                value: attribute_builder.value,
+                name_id: attribute.name,
+                value: attribute.value.into_owned(),
             }));
-            attribute_spans.push((
-                name_id,
-                attribute_builder.name_span,
-                attribute_builder.value_span,
-            ));
+            attribute_spans.push((attribute.name, attribute.name_span, attribute.value_span));
             self.current_node_id.append(attribute_node, &mut xot.arena);
         }
 
-        Ok((node_id, span, attribute_spans))
+        self.preserve_space.push(preserve_space);
+        Ok((node_id, attribute_spans))
     }
 
     // consolidates a text node with previous node if possible. If consolidation
     // took place returns the node id , otherwise none.
-    fn consolidate_text(&mut self, content: &str, xot: &mut Xot) -> Option<NodeId> {
+    fn consolidate_text(&mut self, content: &str, cdata: bool, xot: &mut Xot) -> Option<NodeId> {
         // let's look at the last node we added
         let last = xot.arena[self.current_node_id].last_child();
         if let Some(last) = last {
             let value = xot.arena.get_mut(last).unwrap().get_mut();
-            if let Value::Text(last_text) = value {
-                last_text.get_mut().push_str(content);
-                return Some(last);
+            // only text of the same form (CDATA or not) is consolidated
+            match value {
+                Value::Text(last_text) if last_text.is_cdata() == cdata => {
+                    last_text.get_mut().push_str(content);
+                    return Some(last);
+                }
+                _ => {}
             }
         }
         None
     }
 
-    fn text(&mut self, content: &StrSpan, xot: &mut Xot) -> Result<NodeId, ParseError> {
-        let content = parse_text(content.as_str().into(), content.start())?;
-        if let Some(last) = self.consolidate_text(&content, xot) {
-            return Ok(last);
+    fn text(&mut self, content: &str, xot: &mut Xot) -> NodeId {
+        if self.options.consolidate_text {
+            if let Some(last) = self.consolidate_text(content, false, xot) {
+                return last;
+            }
         }
-        Ok(self.add(Value::Text(Text::new(content.to_string())), xot))
+        self.add(Value::Text(Text::new(content.to_string())), xot)
     }
 
-    fn cdata_text(&mut self, content: &str, xot: &mut Xot) -> Result<NodeId, ParseError> {
-        if let Some(last) = self.consolidate_text(content, xot) {
-            return Ok(last);
+    // a CDATA section, if it is to be preserved
+    fn cdata(&mut self, content: &str, xot: &mut Xot) -> NodeId {
+        if self.options.consolidate_text {
+            if let Some(last) = self.consolidate_text(content, true, xot) {
+                return last;
+            }
         }
-        Ok(self.add(Value::Text(Text::new(content.to_string())), xot))
+        self.add(Value::Text(Text::new_cdata(content.to_string())), xot)
     }
 
-    fn close_element_immediate(&mut self, xot: &mut Xot) -> NodeId {
-        let current_node = xot.arena.get(self.current_node_id).unwrap();
-        if matches!(current_node.get(), Value::Element(_)) {
-            self.name_id_builder.pop();
+    fn close_element(&mut self, span_info: &mut SpanInfo, xot: &mut Xot) -> NodeId {
+        let preserve_space = self.preserve_space.pop().unwrap();
+        if self.options.strip_whitespace && !preserve_space {
+            self.strip_whitespace(self.current_node_id, span_info, xot);
         }
         let closed_node_id = self.current_node_id;
-        self.current_node_id = current_node.parent().expect("Cannot close document node");
+        self.current_node_id = xot.arena[closed_node_id]
+            .parent()
+            .expect("Cannot close document node");
         closed_node_id
     }
 
-    fn close_element(
-        &mut self,
-        prefix: StrSpan,
-        name: StrSpan,
-        xot: &mut Xot,
-    ) -> Result<NodeId, ParseError> {
-        let name_id = self
-            .name_id_builder
-            .element_name_id(&prefix, &name, prefix.into(), xot)?;
-        let current_node = xot.arena.get(self.current_node_id).unwrap();
-        if let Value::Element(element) = current_node.get() {
-            if element.name_id != name_id {
-                return Err(ParseError::InvalidCloseTag(
-                    prefix.to_string(),
-                    name.to_string(),
-                    Span::from_prefix_name(prefix, name),
-                ));
+    // removes the whitespace-only text children of a node if it has no other
+    // text, like `Xot::remove_insignificant_whitespace`. as we do this once a
+    // node is complete, we know all its text.
+    fn strip_whitespace(&mut self, node_id: NodeId, span_info: &mut SpanInfo, xot: &mut Xot) {
+        let texts = node_id
+            .children(&xot.arena)
+            .filter(|child| matches!(xot.arena[*child].get(), Value::Text(_)))
+            .collect::<Vec<_>>();
+        let is_whitespace = |child: &NodeId| match xot.arena[*child].get() {
+            Value::Text(text) => text.get().chars().all(|c| c.is_whitespace()),
+            _ => unreachable!(),
+        };
+        if !texts.iter().all(is_whitespace) {
+            return;
+        }
+        for text in texts {
+            span_info.remove(SpanInfoKey::Text(text.into()));
+            text.remove_subtree(&mut xot.arena);
+        }
+    }
+
+    // completes the tree once all events have been handled
+    fn finish(&mut self, span_info: &mut SpanInfo, xot: &mut Xot) {
+        // text at the top level can only occur in a fragment
+        if self.options.strip_whitespace {
+            self.strip_whitespace(self.tree, span_info, xot);
+        }
+        if let Some(lexical) = self.lexical.take() {
+            let lexical = lexical.finish(xot, self.document_type.is_some());
+            xot.lexical.insert(self.tree, lexical);
+        }
+    }
+
+    // records the raw text of a node at the top level, or of one that was
+    // dropped
+    fn top_level(&mut self, node_id: Option<NodeId>, raw: Span) {
+        if self.current_node_id == self.tree {
+            if let Some(lexical) = &mut self.lexical {
+                lexical.top_level(node_id, raw);
             }
-            self.name_id_builder.pop();
         }
-        let closed_node_id = self.current_node_id;
-        self.current_node_id = current_node.parent().expect("Cannot close document node");
-        Ok(closed_node_id)
     }
 
-    fn comment(&mut self, content: &str, xot: &mut Xot) -> Result<NodeId, ParseError> {
+    fn comment(&mut self, content: &str, xot: &mut Xot) -> NodeId {
         // XXX are there illegal comments, like those with -- inside? or
         // won't they pass the parser?
-        Ok(self.add(Value::Comment(Comment::new(content.to_string())), xot))
+        self.add(Value::Comment(Comment::new(content.to_string())), xot)
     }
 
     fn processing_instruction(
         &mut self,
-        target: &str,
+        target: NameId,
         content: Option<&str>,
         xot: &mut Xot,
-    ) -> Result<NodeId, ParseError> {
-        // XXX are there illegal processing instructions, like those with
-        // ?> inside? or won't they pass the parser? What about those with xml?
-        let target = xot.add_name(target);
-        Ok(self.add(
+    ) -> NodeId {
+        self.add(
             Value::ProcessingInstruction(ProcessingInstruction::new(
                 target,
                 content.map(|s| s.to_string()),
             )),
             xot,
-        ))
-    }
-
-    fn is_current_node_document(&self, xot: &Xot) -> bool {
-        matches!(xot.arena[self.current_node_id].get(), Value::Document)
-    }
-}
-
-struct NameIdBuilder {
-    namespace_stack: Vec<Namespaces>,
-}
-
-impl NameIdBuilder {
-    fn new(prefixes: Namespaces) -> Self {
-        let namespace_stack = vec![prefixes];
-        Self { namespace_stack }
-    }
-
-    fn push(&mut self, namespaces: Namespaces) {
-        self.namespace_stack.push(namespaces);
+        )
     }
 
-    fn pop(&mut self) {
-        // should always be able to pop as there's a bottom entry
-        self.namespace_stack.pop();
-    }
-
-    fn element_name_id(
-        &mut self,
-        prefix: &str,
-        name: &str,
-        prefix_span: Span,
-        xot: &mut Xot,
-    ) -> Result<NameId, ParseError> {
-        let prefix_id = xot.prefix_lookup.get_id_mut(prefix);
-        if let Ok(name_id) = self.name_id_with_prefix_id(prefix_id, name, xot) {
-            Ok(name_id)
-        } else {
-            Err(ParseError::UnknownPrefix(prefix.to_string(), prefix_span))
-        }
-    }
-
-    fn attribute_name_id(
+    // adds the node for an event to the tree
+    fn event(
         &mut self,
-        prefix: &str,
-        name: &str,
-        prefix_span: Span,
+        event: Event,
+        span_info: &mut SpanInfo,
         xot: &mut Xot,
-    ) -> Result<NameId, ParseError> {
-        // an unprefixed attribute is in no namespace, not
-        // in the default namespace
-        // https://stackoverflow.com/questions/3312390/xml-default-namespaces-for-unqualified-attribute-names
-        let prefix_id = xot.prefix_lookup.get_id_mut(prefix);
-        if prefix_id == xot.empty_prefix_id {
-            let name = Name::new(name.to_string(), xot.no_namespace_id);
-            return Ok(xot.name_lookup.get_id_mut(&name));
-        }
-        if let Ok(name_id) = self.name_id_with_prefix_id(prefix_id, name, xot) {
-            Ok(name_id)
-        } else {
-            Err(ParseError::UnknownPrefix(prefix.to_string(), prefix_span))
+    ) -> Result<(), ParseError> {
+        match event {
+            Event::DocumentType(document_type) => {
+                self.document_type = Some(document_type);
+            }
+            Event::StartElement {
+                name,
+                namespaces,
+                attributes,
+                span,
+            } => {
+                let element_lexical = self
+                    .lexical
+                    .as_ref()
+                    .and_then(|lexical| lexical.element(span, &namespaces, &attributes, xot));
+                let parent_id = self.current_node_id;
+                let (node_id, attribute_spans) =
+                    self.open_element(name, namespaces, attributes, xot)?;
+                if let Some(lexical) = &mut self.lexical {
+                    if parent_id == self.tree {
+                        lexical.top_level(Some(node_id), Span::new(span.start - 1, span.end));
+                    }
+                    lexical.element_start(node_id, element_lexical);
+                }
+                span_info.add(SpanInfoKey::ElementStart(node_id.into()), span);
+                span_info.add_attribute_spans(node_id, attribute_spans);
+            }
+            Event::EndElement { span, .. } => {
+                let node_id = self.close_element(span_info, xot);
+                if let Some(lexical) = &mut self.lexical {
+                    lexical.element_end(node_id, span);
+                }
+                self.top_level(Some(node_id), span);
+                span_info.add(SpanInfoKey::ElementEnd(node_id.into()), span);
+            }
+            Event::Text { text, span } => {
+                let node_id = self.text(&text, xot);
+                if let Some(lexical) = &mut self.lexical {
+                    lexical.text(node_id, span);
+                }
+                self.top_level(Some(node_id), span);
+                span_info.extend_text_span(node_id.into(), span);
+            }
+            Event::Cdata { text, span } => {
+                let node_id = if self.options.preserve_cdata || self.options.lossless {
+                    self.cdata(text, xot)
+                } else {
+                    self.text(text, xot)
+                };
+                // the raw text includes the CDATA markers
+                let raw = Span::new(span.start - 9, span.end + 3);
+                if let Some(lexical) = &mut self.lexical {
+                    lexical.text(node_id, raw);
+                }
+                self.top_level(Some(node_id), raw);
+                span_info.extend_text_span(node_id.into(), span);
+            }
+            Event::Comment { span, .. } if self.options.drop_comments => {
+                self.top_level(None, Span::new(span.start - 4, span.end + 3));
+            }
+            Event::ProcessingInstruction {
+                target_span,
+                data_span,
+                ..
+            } if self.options.drop_processing_instructions => {
+                if let Some(lexical) = &self.lexical {
+                    let raw = lexical.processing_instruction_raw(target_span, data_span);
+                    self.top_level(None, raw);
+                }
+            }
+            Event::Comment { text, span } => {
+                let node_id = self.comment(text, xot);
+                self.top_level(Some(node_id), Span::new(span.start - 4, span.end + 3));
+                span_info.add(SpanInfoKey::Comment(node_id.into()), span);
+            }
+            Event::ProcessingInstruction {
+                target,
+                data,
+                target_span,
+                data_span,
+            } => {
+                let node_id = self.processing_instruction(target, data, xot);
+                if let Some(lexical) = &mut self.lexical {
+                    let raw = lexical.processing_instruction(node_id, target_span, data_span);
+                    self.top_level(Some(node_id), raw);
+                }
+                span_info.add(SpanInfoKey::PiTarget(node_id.into()), target_span);
+                if let Some(data_span) = data_span {
+                    span_info.add(SpanInfoKey::PiContent(node_id.into()), data_span);
+                }
+            }
         }
-    }
-
-    fn name_id_with_prefix_id(
-        &mut self,
-        prefix_id: PrefixId,
-        name: &str,
-        xot: &mut Xot,
-    ) -> Result<NameId, ()> {
-        // go through namespace stack backwards, find the first namespace
-        // that matches this prefix
-        let namespace_id = self.namespace_stack.iter().rev().find_map(|ns| {
-            ns.iter()
-                .rev()
-                .find_map(|(p, ns)| if *p == prefix_id { Some(*ns) } else { None })
-        });
-        let namespace_id = namespace_id.ok_or(())?;
-        let name = Name::new(name.to_string(), namespace_id);
-        Ok(xot.name_lookup.get_id_mut(&name))
+        Ok(())
     }
 }
 
@@ -388,7 +537,7 @@ impl Span {
         Span { start, end }
     }
 
-    fn from_prefix_name(prefix: StrSpan<'_>, name: StrSpan<'_>) -> Self {
+    pub(crate) fn from_prefix_name(prefix: StrSpan<'_>, name: StrSpan<'_>) -> Self {
         if prefix.is_empty() {
             Self::new(name.start(), name.end())
         } else {
@@ -510,12 +659,22 @@ pub enum SpanInfoKey {
 /// in the span.
 pub struct SpanInfo {
     map: HashMap<SpanInfoKey, Span>,
+    // if false, nothing is recorded
+    record: bool,
 }
 
 impl SpanInfo {
     fn new() -> Self {
         SpanInfo {
             map: HashMap::new(),
+            record: true,
+        }
+    }
+
+    fn with_record(record: bool) -> Self {
+        SpanInfo {
+            map: HashMap::new(),
+            record,
         }
     }
 
@@ -525,10 +684,19 @@ impl SpanInfo {
     }
 
     fn add(&mut self, key: SpanInfoKey, span: Span) {
-        self.map.insert(key, span);
+        if self.record {
+            self.map.insert(key, span);
+        }
+    }
+
+    fn remove(&mut self, key: SpanInfoKey) {
+        self.map.remove(&key);
     }
 
     fn extend_text_span(&mut self, node: Node, span: Span) {
+        if !self.record {
+            return;
+        }
         // if we already have span for this (text) node it, we need to store the span with that
         // start and the given ending
         let key = SpanInfoKey::Text(node);
@@ -563,50 +731,31 @@ impl Xot {
     /// [`SpanInfo`](`crate::SpanInfo`) which describes where nodes in the
     /// tree are located in the source text.
     pub fn parse_with_span_info(&mut self, xml: &str) -> Result<(Node, SpanInfo), ParseError> {
-        let tokenizer = Tokenizer::from(xml);
-        let (span_info, builder) = self._parse(tokenizer)?;
-        // we expect both a document as the current node (everything else being
-        // closed) *and* the content of this node containing a single element
-        // if not, we have a problem. We want to produce a parse error for
-        // this, as this is the parser.
-        if builder.is_current_node_document(self) {
-            let document_node = Node::new(builder.tree);
-            let mut element_nodes = Vec::new();
-
-            for child in self.children(document_node) {
-                match self.value(child) {
-                    Value::Element(_) => element_nodes.push(child),
-                    Value::Text(_) => {
-                        return Err(ParseError::TextAtTopLevel(
-                            *span_info.get(SpanInfoKey::Text(child)).unwrap(),
-                        ));
-                    }
-                    _ => {}
-                }
-            }
-            if element_nodes.is_empty() {
-                return Err(ParseError::NoElementAtTopLevel(xml.len()));
-            }
-            if element_nodes.len() > 1 {
-                return Err(ParseError::MultipleElementsAtTopLevel(
-                    *span_info
-                        .get(SpanInfoKey::ElementStart(element_nodes[1]))
-                        .unwrap(),
-                ));
-            }
-            self.id_nodes_map
-                .insert(document_node.get(), builder.id_nodes);
-            Ok((document_node, span_info))
-        } else {
-            let current_node = Node::new(builder.current_node_id);
+        self.parse_with_options(xml, &ParseOptions::default())
+    }
 
-            // the top level node's span is the problem
-            Err(ParseError::UnclosedTag(
-                *span_info
-                    .get(SpanInfoKey::ElementStart(current_node))
-                    .unwrap(),
-            ))
+    /// Parse a string containing XML into a document node, with options.
+    ///
+    /// [`ParseOptions`] let you control what ends up in the tree, and
+    /// whether span information is recorded. With the default options this
+    /// is the same as [`Xot::parse_with_span_info`].
+    pub fn parse_with_options(
+        &mut self,
+        xml: &str,
+        options: &ParseOptions,
+    ) -> Result<(Node, SpanInfo), ParseError> {
+        let parser = pull::Parser::new(self, xml);
+        // the pull parser checks that there's a single document element,
+        // and that everything else is closed
+        let (span_info, builder) = self._parse(parser, xml, options)?;
+        let document_node = Node::new(builder.tree);
+        self.id_nodes_map
+            .insert(document_node.get(), builder.xml_ids);
+        if let Some(document_type) = builder.document_type {
+            self.document_types
+                .insert(document_node.get(), document_type);
         }
+        Ok((document_node, span_info))
     }
 
     /// Parse a string containing an XML fragment into a document node.
@@ -628,151 +777,236 @@ impl Xot {
         &mut self,
         xml: &str,
     ) -> Result<(Node, SpanInfo), ParseError> {
-        let tokenizer = Tokenizer::from_fragment(xml, 0..xml.len());
-        let (span_info, builder) = self._parse(tokenizer)?;
-        if builder.is_current_node_document(self) {
-            let document_node = Node::new(builder.tree);
-            self.id_nodes_map
-                .insert(document_node.get(), builder.id_nodes);
-            Ok((document_node, span_info))
-        } else {
-            let current_node = Node::new(builder.current_node_id);
+        self.parse_fragment_with_options(xml, &ParseOptions::default())
+    }
+
+    /// Parse a string containing an XML fragment into a document node, with
+    /// options.
+    ///
+    /// This is like [`Xot::parse_with_options`], but for a fragment as in
+    /// [`Xot::parse_fragment`].
+    pub fn parse_fragment_with_options(
+        &mut self,
+        xml: &str,
+        options: &ParseOptions,
+    ) -> Result<(Node, SpanInfo), ParseError> {
+        let parser = pull::Parser::new_fragment(self, xml);
+        let (span_info, builder) = self._parse(parser, xml, options)?;
+        let document_node = Node::new(builder.tree);
+        self.id_nodes_map
+            .insert(document_node.get(), builder.xml_ids);
+        Ok((document_node, span_info))
+    }
 
-            // the top level node's span is the problem
-            Err(ParseError::UnclosedTag(
-                *span_info
-                    .get(SpanInfoKey::ElementStart(current_node))
-                    .unwrap(),
-            ))
+    /// Parse a string containing XML into a document node, recovering from
+    /// errors.
+    ///
+    /// Unlike [`Xot::parse`], this doesn't stop at the first error. Instead
+    /// it records the error and continues, and returns the best tree it
+    /// could make along with all errors, ordered by their position. This
+    /// is useful when the XML is being edited by hand, as you can still
+    /// work with the tree and show all problems at once.
+    ///
+    /// The parser recovers as follows:
+    ///
+    /// * Elements that aren't closed are closed at the end of their parent,
+    ///   or at the end of the document.
+    ///
+    /// * A close tag that doesn't match any open element is dropped.
+    ///
+    /// * A name with an undeclared prefix is taken to be in no namespace.
+    ///
+    /// * Of duplicate attributes, the first one is kept. Of duplicate ids,
+    ///   the first one is used by [`Xot::xml_id_node`].
+    ///
+    /// * Text and attribute values with entities that cannot be expanded are
+    ///   taken as they are.
+    ///
+    /// * Text at the top level is dropped, and multiple top-level elements
+    ///   are kept.
+    ///
+    /// * When the XML cannot be tokenized, parsing continues with the next
+    ///   tag, so the text in between is lost.
+    ///
+    /// If there are no errors, the result is the same as that of
+    /// [`Xot::parse`].
+    ///
+    /// ```rust
+    /// use xot::{ParseError, Xot};
+    ///
+    /// let mut xot = Xot::new();
+    /// let (document, errors) = xot.parse_lenient("<a><b>text</a></c>");
+    ///
+    /// assert_eq!(xot.to_string(document).unwrap(), "<a><b>text</b></a>");
+    /// assert!(matches!(errors[0], ParseError::UnclosedTag(_)));
+    /// assert!(matches!(errors[1], ParseError::InvalidCloseTag(..)));
+    /// ```
+    pub fn parse_lenient(&mut self, xml: &str) -> (Node, Vec<ParseError>) {
+        let (node, _, errors) = self.parse_lenient_with_span_info(xml);
+        (node, errors)
+    }
+
+    /// Parse a string containing XML into a document node, recovering from
+    /// errors. Retain span information.
+    ///
+    /// This is like [`Xot::parse_lenient`], but also returns
+    /// [`SpanInfo`](`crate::SpanInfo`). The end of an element that was
+    /// closed implicitly is an empty span where it was closed.
+    pub fn parse_lenient_with_span_info(&mut self, xml: &str) -> (Node, SpanInfo, Vec<ParseError>) {
+        let mut parser = pull::Parser::new(self, xml).lenient();
+        let mut builder = DocumentBuilder::new(self, &ParseOptions::default());
+        builder.lenient = true;
+        // nothing fails in lenient mode
+        let span_info = self
+            ._parse_into(&mut parser, &mut builder)
+            .expect("Lenient parsing cannot fail");
+        let mut errors = parser.take_errors();
+        errors.append(&mut builder.errors);
+        // errors are found by the parser and the builder; put them in order
+        errors.sort_by_key(|error| error.span().start);
+        let document_node = Node::new(builder.tree);
+        self.id_nodes_map
+            .insert(document_node.get(), builder.xml_ids);
+        if let Some(document_type) = builder.document_type {
+            self.document_types
+                .insert(document_node.get(), document_type);
         }
+        (document_node, span_info, errors)
     }
 
     fn _parse(
         &mut self,
-        mut tokenizer: Tokenizer<'_>,
+        parser: pull::Parser<'_>,
+        xml: &str,
+        options: &ParseOptions,
     ) -> Result<(SpanInfo, DocumentBuilder), ParseError> {
-        use Token::*;
-
-        let mut builder = DocumentBuilder::new(self);
-        let mut span_info = SpanInfo::new();
+        let mut parser = parser.limits(&options.limits);
+        let mut builder = DocumentBuilder::new(self, options);
+        if options.lossless {
+            builder.lexical = Some(LexicalBuilder::new(xml));
+        }
+        match self._parse_into(&mut parser, &mut builder) {
+            Ok(span_info) => Ok((span_info, builder)),
+            Err(err) => {
+                // don't leave the partial tree behind
+                builder.tree.remove_subtree(&mut self.arena);
+                Err(err)
+            }
+        }
+    }
 
-        let mut position;
-        loop {
-            // getting the position unconditionally is required to get
-            // the right one for the error handling, which is a bit unfortunate
-            // https://github.com/RazrFalcon/xmlparser/issues/30
-            position = tokenizer.stream().pos();
-            if let Some(token) = tokenizer.next() {
-                let token = match token {
-                    Ok(token) => token,
-                    Err(e) => {
-                        return Err(ParseError::XmlParser(e, position));
-                    }
-                };
-                match token {
-                    Attribute {
-                        prefix,
-                        local,
-                        value,
-                        span: _,
-                    } => {
-                        if prefix.as_str() == "xmlns" {
-                            builder.prefix(local.as_str(), value.as_str(), self);
-                        } else if local.as_str() == "xmlns" {
-                            builder.prefix("", value.as_str(), self);
-                        } else {
-                            builder.attribute(prefix, local, value)?;
-                        }
-                    }
-                    Text { text } => {
-                        let node_id = builder.text(&text, self)?;
-                        span_info.extend_text_span(node_id.into(), text.into());
-                    }
-                    Cdata { text, span: _ } => {
-                        let node_id = builder.cdata_text(text.as_str(), self)?;
-                        span_info.extend_text_span(node_id.into(), text.into());
-                    }
-                    ElementStart {
-                        prefix,
-                        local,
-                        span: _,
-                    } => {
-                        builder.element(prefix, local);
-                    }
+    fn _parse_into(
+        &mut self,
+        parser: &mut pull::Parser<'_>,
+        builder: &mut DocumentBuilder,
+    ) -> Result<SpanInfo, ParseError> {
+        let mut span_info = SpanInfo::with_record(builder.options.span_info);
+        let limits = builder.options.limits;
 
-                    ElementEnd {
-                        end,
-                        span: end_span,
-                    } => {
-                        use self::ElementEnd::*;
-
-                        match end {
-                            Open => {
-                                let (node_id, span, attribute_spans) =
-                                    builder.open_element(self)?;
-                                span_info.add(SpanInfoKey::ElementStart(node_id.into()), span);
-                                span_info.add_attribute_spans(node_id, attribute_spans);
-                            }
-                            Close(prefix, local) => {
-                                let node_id = builder.close_element(prefix, local, self)?;
-                                span_info
-                                    .add(SpanInfoKey::ElementEnd(node_id.into()), end_span.into());
-                            }
-                            Empty => {
-                                let (node_id, span, attribute_spans) =
-                                    builder.open_element(self)?;
-                                span_info.add(SpanInfoKey::ElementStart(node_id.into()), span);
-                                span_info.add_attribute_spans(node_id, attribute_spans);
-                                let node_id = builder.close_element_immediate(self);
-                                span_info
-                                    .add(SpanInfoKey::ElementEnd(node_id.into()), end_span.into());
-                            }
-                        }
-                    }
-                    Comment { text, span: _ } => {
-                        let node_id = builder.comment(text.as_str(), self)?;
-                        span_info.add(SpanInfoKey::Comment(node_id.into()), text.into());
-                    }
-                    ProcessingInstruction {
-                        target,
-                        content,
-                        span: _,
-                    } => {
-                        let node_id = builder.processing_instruction(
-                            target.as_str(),
-                            content.map(|s| s.as_str()),
-                            self,
-                        )?;
-                        span_info.add(SpanInfoKey::PiTarget(node_id.into()), target.into());
-                        if let Some(content) = content {
-                            span_info.add(SpanInfoKey::PiContent(node_id.into()), content.into());
-                        }
-                    }
-                    Declaration { version, .. } => {
-                        if version.as_str() != "1.0" {
-                            return Err(ParseError::UnsupportedVersion(
-                                version.to_string(),
-                                version.into(),
-                            ));
-                        }
-                    }
-                    DtdStart { span, .. } => {
-                        return Err(ParseError::DtdUnsupported(span.into()));
-                    }
-                    DtdEnd { span, .. } => {
-                        return Err(ParseError::DtdUnsupported(span.into()));
-                    }
-                    EmptyDtd { span, .. } => {
-                        return Err(ParseError::DtdUnsupported(span.into()));
-                    }
-                    EntityDeclaration { span, .. } => {
-                        return Err(ParseError::DtdUnsupported(span.into()));
-                    }
+        while let Some(event) = parser.next_event(self)? {
+            // where a limit is exceeded. a document type declaration has no
+            // span, so it's taken to be at the start
+            let span = match &event {
+                Event::DocumentType(_) => Span::new(0, 0),
+                Event::StartElement { span, .. }
+                | Event::EndElement { span, .. }
+                | Event::Text { span, .. }
+                | Event::Cdata { span, .. }
+                | Event::Comment { span, .. } => *span,
+                Event::ProcessingInstruction { target_span, .. } => *target_span,
+            };
+            if let Event::StartElement {
+                namespaces,
+                attributes,
+                ..
+            } = &event
+            {
+                limits.check(ParseLimit::Depth, parser.depth(), span)?;
+                limits.check(
+                    ParseLimit::Attributes,
+                    namespaces.len() + attributes.len(),
+                    span,
+                )?;
+            }
+            let is_text = matches!(event, Event::Text { .. } | Event::Cdata { .. });
+            builder.event(event, &mut span_info, self)?;
+            limits.check(ParseLimit::Nodes, builder.node_count, span)?;
+            limits.check(ParseLimit::Names, parser.names_added(), span)?;
+            if is_text {
+                // the text may have been consolidated with earlier text
+                let last = self.arena[builder.current_node_id].last_child();
+                if let Some(Value::Text(text)) = last.map(|node_id| self.arena[node_id].get()) {
+                    limits.check(ParseLimit::TextLength, text.get().len(), span)?;
                 }
-            } else {
-                return Ok((span_info, builder));
             }
         }
+        builder.finish(&mut span_info, self);
+        for (element, attribute) in parser.id_attributes() {
+            builder.xml_ids.declare_id_attribute(element, attribute);
+        }
+        Ok(span_info)
+    }
+
+    // builds a document with the element opened by `start` as its document
+    // element, consuming the events of its content from the parser
+    pub(crate) fn parse_subtree(
+        &mut self,
+        parser: &mut pull::Parser<'_>,
+        start: Event<'_>,
+    ) -> Result<(Node, SpanInfo), ParseError> {
+        let Event::StartElement {
+            name,
+            mut namespaces,
+            attributes,
+            span,
+        } = start
+        else {
+            panic!("Can only build a subtree for a start element event");
+        };
+        // the namespaces declared on ancestors are declared on the top
+        // element, so that the subtree is self-contained
+        for (prefix_id, namespace_id) in parser.inherited_namespaces(self) {
+            namespaces.push((prefix_id, namespace_id));
+        }
+        let start = Event::StartElement {
+            name,
+            namespaces,
+            attributes,
+            span,
+        };
+
+        let mut builder = DocumentBuilder::new(self, &ParseOptions::default());
+        let mut span_info = SpanInfo::new();
+        let mut depth = 0;
+        let mut event = Some(start);
+        while let Some(current) = event {
+            match current {
+                Event::StartElement { .. } => depth += 1,
+                Event::EndElement { .. } => depth -= 1,
+                _ => {}
+            }
+            if let Err(err) = builder.event(current, &mut span_info, self) {
+                builder.tree.remove_subtree(&mut self.arena);
+                return Err(err);
+            }
+            if depth == 0 {
+                break;
+            }
+            event = match parser.next_event(self) {
+                Ok(event) => event,
+                Err(err) => {
+                    builder.tree.remove_subtree(&mut self.arena);
+                    return Err(err);
+                }
+            };
+        }
+        for (element, attribute) in parser.id_attributes() {
+            builder.xml_ids.declare_id_attribute(element, attribute);
+        }
+        let document_node = Node::new(builder.tree);
+        self.id_nodes_map
+            .insert(document_node.get(), builder.xml_ids);
+        Ok((document_node, span_info))
     }
 
     /// Parse a string containing XML into a document node.
@@ -812,6 +1046,102 @@ impl Xot {
             .map(|(node, _)| node)
     }
 
+    /// Parse a string containing an XML fragment and append the result to
+    /// a node.
+    ///
+    /// The fragment is parsed like with [`Xot::parse_fragment`], but in the
+    /// context of `parent`: the namespace prefixes that are in scope for
+    /// `parent` can be used in the fragment. The parsed nodes are appended
+    /// to `parent` as its last children. If parsing fails, `parent` is left
+    /// unchanged.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    /// let root = xot.parse(r#"<doc xmlns:ex="http://example.com"><p/></doc>"#)?;
+    /// let doc_el = xot.document_element(root)?;
+    /// let p = xot.first_child(doc_el).unwrap();
+    ///
+    /// xot.parse_fragment_into(p, r#"Hello <ex:b>world</ex:b>"#)?;
+    /// assert_eq!(
+    ///     xot.to_string(root)?,
+    ///     r#"<doc xmlns:ex="http://example.com"><p>Hello <ex:b>world</ex:b></p></doc>"#
+    /// );
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn parse_fragment_into(&mut self, parent: Node, xml: &str) -> Result<(), Error> {
+        if !(self.is_element(parent) || self.is_document(parent)) {
+            return Err(Error::InvalidOperation(
+                "Can only parse a fragment into an element or document node".to_string(),
+            ));
+        }
+        let fragment = self.parse_fragment_in_scope(parent, xml)?;
+        for child in self.children(fragment).collect::<Vec<_>>() {
+            self.append(parent, child)?;
+        }
+        self.remove(fragment)
+    }
+
+    /// Replace a node with the nodes parsed from a string containing an XML
+    /// fragment.
+    ///
+    /// The fragment is parsed in the context of the parent of `node`, like
+    /// with [`Xot::parse_fragment_into`], and the parsed nodes take the
+    /// place of `node`, which is removed along with its descendants. If
+    /// parsing fails, the tree is left unchanged.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    /// let root = xot.parse(r#"<doc xmlns:ex="http://example.com"><a/><c/></doc>"#)?;
+    /// let doc_el = xot.document_element(root)?;
+    /// let a = xot.first_child(doc_el).unwrap();
+    ///
+    /// xot.replace_with_xml(a, r#"<ex:a/><b/>"#)?;
+    /// assert_eq!(
+    ///     xot.to_string(root)?,
+    ///     r#"<doc xmlns:ex="http://example.com"><ex:a/><b/><c/></doc>"#
+    /// );
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn replace_with_xml(&mut self, node: Node, xml: &str) -> Result<(), Error> {
+        if !matches!(
+            self.value_type(node),
+            ValueType::Element
+                | ValueType::Text
+                | ValueType::Comment
+                | ValueType::ProcessingInstruction
+        ) {
+            return Err(Error::InvalidOperation(
+                "Can only replace an element, text, comment or processing instruction node"
+                    .to_string(),
+            ));
+        }
+        let parent = self.parent(node).ok_or_else(|| {
+            Error::InvalidOperation("Cannot replace a node without a parent".to_string())
+        })?;
+        let fragment = self.parse_fragment_in_scope(parent, xml)?;
+        for child in self.children(fragment).collect::<Vec<_>>() {
+            self.insert_before(node, child)?;
+        }
+        self.remove(fragment)?;
+        self.remove(node)
+    }
+
+    // parse a fragment into a new document node, with the namespaces that
+    // are in scope for `context`
+    fn parse_fragment_in_scope(&mut self, context: Node, xml: &str) -> Result<Node, ParseError> {
+        let namespaces = self.namespaces_in_scope(context).collect();
+        let parser = pull::Parser::new_fragment_in_scope(self, xml, namespaces);
+        let (_, builder) = self._parse(parser, xml, &ParseOptions::default())?;
+        let document_node = Node::new(builder.tree);
+        self.id_nodes_map
+            .insert(document_node.get(), builder.xml_ids);
+        Ok(document_node)
+    }
+
     /// Parse bytes containing XML into a node.
     ///
     /// This attempts to decode the data in the bytes into a Rust string
@@ -846,9 +1176,106 @@ impl Xot {
         let xml = decode(bytes, None);
         self.parse(&xml)
     }
+
+    /// Parse bytes containing XML into a document node, with options.
+    ///
+    /// This decodes the bytes like [`Xot::parse_bytes`], and then parses the
+    /// result like [`Xot::parse_with_options`]. Spans are positions in the
+    /// decoded XML.
+    ///
+    /// ```rust
+    /// use xot::{ParseError, ParseLimit, ParseLimits, ParseOptions, Xot};
+    ///
+    /// let mut xot = Xot::new();
+    /// let options = ParseOptions {
+    ///     limits: ParseLimits {
+    ///         max_attributes: Some(1),
+    ///         ..Default::default()
+    ///     },
+    ///     ..Default::default()
+    /// };
+    /// let result = xot.parse_bytes_with_options(b"<p a=\"1\" b=\"2\"/>", &options);
+    /// assert!(matches!(
+    ///     result,
+    ///     Err(ParseError::LimitExceeded(ParseLimit::Attributes, _))
+    /// ));
+    /// ```
+    pub fn parse_bytes_with_options(
+        &mut self,
+        bytes: &[u8],
+        options: &ParseOptions,
+    ) -> Result<(Node, SpanInfo), ParseError> {
+        let xml = decode(bytes, None);
+        self.parse_with_options(&xml, options)
+    }
+
+    /// Parse XML from a reader into a document node.
+    ///
+    /// The encoding is determined from the byte order mark or the encoding
+    /// declaration in the XML declaration, and defaults to UTF-8. The input
+    /// is decoded as it is read, so unlike with [`Xot::parse_bytes`] you
+    /// don't need the input in memory in addition to the decoded XML.
+    ///
+    /// Positions in errors are byte offsets in the input, not positions in
+    /// the decoded XML. A byte sequence that isn't valid in the encoding
+    /// results in [`ParseError::MalformedEncoding`], and a failure to read
+    /// in [`ParseError::Io`].
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    ///
+    /// let input: &[u8] = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><p>\xe9</p>";
+    /// let document = xot.parse_reader(input)?;
+    ///
+    /// let doc_el = xot.document_element(document)?;
+    /// assert_eq!(xot.text_content_str(doc_el), Some("é"));
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn parse_reader(&mut self, reader: impl Read) -> Result<Node, ParseError> {
+        let decoded = decode_reader(reader)?;
+        self.parse(&decoded.xml)
+            .map_err(|e| e.map_positions(|position| decoded.byte_offset(position)))
+    }
+
+    /// Parse XML from a reader into a document node, with options.
+    ///
+    /// This decodes the input like [`Xot::parse_reader`], and then parses
+    /// the result like [`Xot::parse_with_options`]. Positions in errors are
+    /// byte offsets in the input, but spans in the [`SpanInfo`] are positions
+    /// in the decoded XML.
+    ///
+    /// ```rust
+    /// use xot::{ParseError, ParseLimit, ParseLimits, ParseOptions, Xot};
+    ///
+    /// let mut xot = Xot::new();
+    /// let options = ParseOptions {
+    ///     limits: ParseLimits {
+    ///         max_depth: Some(1),
+    ///         ..Default::default()
+    ///     },
+    ///     ..Default::default()
+    /// };
+    /// let input: &[u8] = b"<p><b/></p>";
+    /// let result = xot.parse_reader_with_options(input, &options);
+    /// assert!(matches!(
+    ///     result,
+    ///     Err(ParseError::LimitExceeded(ParseLimit::Depth, _))
+    /// ));
+    /// ```
+    pub fn parse_reader_with_options(
+        &mut self,
+        reader: impl Read,
+        options: &ParseOptions,
+    ) -> Result<(Node, SpanInfo), ParseError> {
+        let decoded = decode_reader(reader)?;
+        self.parse_with_options(&decoded.xml, options)
+            .map_err(|e| e.map_positions(|position| decoded.byte_offset(position)))
+    }
 }
 
-fn normalize_xml_id(value: &str) -> String {
+pub(crate) fn normalize_xml_id(value: &str) -> String {
     // strip both leading and trailing space characters
     let value = value.strip_prefix(' ').unwrap_or(value);
     let value = value.strip_suffix(' ').unwrap_or(value);
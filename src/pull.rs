@@ -0,0 +1,1214 @@
+//! A pull parser that reads XML as a stream of events.
+//!
+//! Parsing into a Xot tree requires the whole document to be in memory as
+//! nodes. If you only need to look at parts of a large document, you can use
+//! a pull [`Parser`] instead: it yields [`Event`]s one by one and doesn't
+//! build a tree.
+//!
+//! The pull parser uses the names, namespaces and prefixes of a [`Xot`], so
+//! element and attribute names in events are [`NameId`]s that you can compare
+//! with names you obtained from [`Xot::add_name`] and friends. Namespace
+//! prefixes are resolved, entities are expanded and attribute defaults from
+//! the internal DTD subset are applied, just like [`Xot::parse`] does.
+//!
+//! ```rust
+//! use xot::Xot;
+//! use xot::pull::{Event, Parser};
+//!
+//! let mut xot = Xot::new();
+//! let item = xot.add_name("item");
+//!
+//! let mut parser = Parser::new(&xot, "<list><item>A</item><item>B</item></list>");
+//! let mut texts = Vec::new();
+//! let mut in_item = false;
+//! while let Some(event) = parser.next_event(&mut xot)? {
+//!     match event {
+//!         Event::StartElement { name, .. } if name == item => in_item = true,
+//!         Event::EndElement { name, .. } if name == item => in_item = false,
+//!         Event::Text { text, .. } if in_item => texts.push(text.to_string()),
+//!         _ => {}
+//!     }
+//! }
+//! assert_eq!(texts, vec!["A", "B"]);
+//! # Ok::<(), xot::ParseError>(())
+//! ```
+use std::borrow::Cow;
+
+use ahash::{HashSet, HashSetExt};
+use xmlparser::{ElementEnd, EntityDefinition, ExternalId, StrSpan, Token, Tokenizer};
+
+use crate::dtd::{
+    normalize_tokenized, parse_attribute_list_declarations, AttributeDeclarations, AttributeType,
+};
+use crate::entity::{parse_attribute, parse_entity_value, parse_text, Entities, Entity};
+use crate::error::ParseError;
+use crate::id::{Name, NameId, NamespaceId, PrefixId};
+use crate::parse::{normalize_xml_id, ParseLimits, Span, SpanInfo};
+use crate::validate::{XMLNS_NAMESPACE, XML_NAMESPACE};
+use crate::xmlvalue::{DocumentType, XmlVersion};
+use crate::xotdata::{Node, Xot};
+
+type Namespaces = Vec<(PrefixId, NamespaceId)>;
+
+/// An event produced by the pull [`Parser`].
+///
+/// The lifetime `'a` is that of the XML source: text is borrowed from it
+/// whenever possible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// The document type declaration.
+    DocumentType(DocumentType),
+    /// An element is opened.
+    ///
+    /// Every start element event is matched by an [`Event::EndElement`],
+    /// also for an empty element like `<a/>`.
+    StartElement {
+        /// The name of the element.
+        name: NameId,
+        /// The namespace declarations on this element.
+        namespaces: Vec<(PrefixId, NamespaceId)>,
+        /// The attributes of this element, including those defaulted by the
+        /// DTD.
+        attributes: Vec<Attribute<'a>>,
+        /// The span of the name in the start tag.
+        span: Span,
+    },
+    /// An element is closed.
+    EndElement {
+        /// The name of the element.
+        name: NameId,
+        /// The span of the end tag, or of the `/>` of an empty element.
+        span: Span,
+    },
+    /// Text, with entities expanded.
+    ///
+    /// Text isn't consolidated, so you can get multiple text events in a row,
+    /// for instance when text is interrupted by a CDATA section.
+    Text {
+        /// The text.
+        text: Cow<'a, str>,
+        /// The span of the text in the source.
+        span: Span,
+    },
+    /// The content of a CDATA section.
+    Cdata {
+        /// The text in the CDATA section.
+        text: &'a str,
+        /// The span of the text in the source.
+        span: Span,
+    },
+    /// A comment.
+    Comment {
+        /// The text of the comment.
+        text: &'a str,
+        /// The span of the text in the source.
+        span: Span,
+    },
+    /// A processing instruction.
+    ProcessingInstruction {
+        /// The target.
+        target: NameId,
+        /// The data, if any.
+        data: Option<&'a str>,
+        /// The span of the target in the source.
+        target_span: Span,
+        /// The span of the data in the source, if any.
+        data_span: Option<Span>,
+    },
+}
+
+/// An attribute of a [`Event::StartElement`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute<'a> {
+    /// The name of the attribute.
+    pub name: NameId,
+    /// The value of the attribute, with entities expanded and normalized.
+    pub value: Cow<'a, str>,
+    /// The span of the attribute name. If the attribute was defaulted by the
+    /// DTD, this points to the declaration.
+    pub name_span: Span,
+    /// The span of the attribute value. If the attribute was defaulted by
+    /// the DTD, this points to the declaration.
+    pub value_span: Span,
+    /// Whether this is an id attribute: either `xml:id` or an attribute
+    /// declared with type `ID` in the DTD.
+    pub is_id: bool,
+}
+
+struct AttributeBuilder<'a> {
+    prefix: &'a str,
+    name: &'a str,
+    value: Cow<'a, str>,
+    name_span: Span,
+    value_span: Span,
+    prefix_span: Span,
+    // declared with type ID in the DTD
+    is_id: bool,
+}
+
+struct ElementBuilder<'a> {
+    prefix: &'a str,
+    name: &'a str,
+    namespaces: Namespaces,
+    attributes: Vec<AttributeBuilder<'a>>,
+    prefix_span: Span,
+    span: Span,
+}
+
+impl<'a> ElementBuilder<'a> {
+    fn new(prefix: StrSpan<'a>, name: StrSpan<'a>) -> Self {
+        ElementBuilder {
+            prefix: prefix.as_str(),
+            name: name.as_str(),
+            namespaces: Namespaces::new(),
+            attributes: Vec::new(),
+            prefix_span: prefix.into(),
+            span: Span::from_prefix_name(prefix, name),
+        }
+    }
+
+    fn qualified_name(&self) -> String {
+        qualified_name(self.prefix, self.name)
+    }
+}
+
+fn qualified_name(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}:{}", prefix, name)
+    }
+}
+
+/// A pull parser for XML.
+///
+/// You create one for a string containing XML with [`Parser::new`], or with
+/// [`Parser::new_fragment`] for an XML fragment. You then call
+/// [`Parser::next_event`] repeatedly to obtain events.
+///
+/// The parser performs the same well-formedness checks as [`Xot::parse`]
+/// (or [`Xot::parse_fragment`]), except the check for duplicate `xml:id`
+/// values, as that requires remembering all ids seen.
+pub struct Parser<'a> {
+    tokenizer: Tokenizer<'a>,
+    source: &'a str,
+    fragment: bool,
+    version: XmlVersion,
+    name_id_builder: NameIdBuilder,
+    element_builder: Option<ElementBuilder<'a>>,
+    entities: Entities,
+    attribute_declarations: AttributeDeclarations,
+    id_attributes: HashSet<(NameId, NameId)>,
+    document_type: Option<DocumentType>,
+    // comments and processing instructions in the internal DTD subset are
+    // not part of the document
+    in_dtd: bool,
+    internal_subset_start: usize,
+    // the open elements, with the span of their start tag
+    open_elements: Vec<(NameId, Span)>,
+    seen_element: bool,
+    // the spans of end element events still to be reported, innermost
+    // last: the end of an empty element, which we report after its start,
+    // and elements that are closed implicitly in lenient mode
+    pending_ends: Vec<Span>,
+    done: bool,
+    // in lenient mode, errors are recorded and parsing continues
+    lenient: bool,
+    errors: Vec<ParseError>,
+}
+
+impl<'a> Parser<'a> {
+    /// Create a pull parser for an XML document.
+    pub fn new(xot: &Xot, xml: &'a str) -> Self {
+        Self::with_tokenizer(xot, Tokenizer::from(xml), xml, false)
+    }
+
+    /// Create a pull parser for an XML fragment.
+    ///
+    /// Like [`Xot::parse_fragment`], this allows text and multiple elements
+    /// at the top level.
+    pub fn new_fragment(xot: &Xot, xml: &'a str) -> Self {
+        Self::with_tokenizer(xot, Tokenizer::from_fragment(xml, 0..xml.len()), xml, true)
+    }
+
+    // a pull parser for an XML fragment in which the given namespaces are
+    // in scope, as if it were content of an element that declares them
+    pub(crate) fn new_fragment_in_scope(xot: &Xot, xml: &'a str, namespaces: Namespaces) -> Self {
+        let mut parser = Self::new_fragment(xot, xml);
+        parser.name_id_builder.push(namespaces);
+        parser
+    }
+
+    fn with_tokenizer(
+        xot: &Xot,
+        tokenizer: Tokenizer<'a>,
+        source: &'a str,
+        fragment: bool,
+    ) -> Self {
+        let mut name_id_builder = NameIdBuilder::new(xot.base_prefixes().into_iter().collect());
+        name_id_builder.push(vec![(xot.empty_prefix_id, xot.no_namespace_id)]);
+        Parser {
+            tokenizer,
+            source,
+            fragment,
+            version: XmlVersion::V1_0,
+            name_id_builder,
+            element_builder: None,
+            entities: Entities::new(),
+            attribute_declarations: AttributeDeclarations::new(),
+            id_attributes: HashSet::new(),
+            document_type: None,
+            in_dtd: false,
+            internal_subset_start: 0,
+            open_elements: Vec::new(),
+            seen_element: false,
+            pending_ends: Vec::new(),
+            done: false,
+            lenient: false,
+            errors: Vec::new(),
+        }
+    }
+
+    // make the parser recover from errors: they are recorded, and can be
+    // obtained with `take_errors`
+    pub(crate) fn lenient(mut self) -> Self {
+        self.lenient = true;
+        self
+    }
+
+    // limit entity expansion and the length of text while parsing. the
+    // other limits are checked when building the tree
+    pub(crate) fn limits(mut self, limits: &ParseLimits) -> Self {
+        self.entities.set_limits(limits);
+        self
+    }
+
+    // the number of names, namespaces and prefixes added while parsing
+    pub(crate) fn names_added(&self) -> usize {
+        self.name_id_builder.added
+    }
+
+    pub(crate) fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    // in lenient mode, records the error and continues with `fallback`.
+    // otherwise the error is returned.
+    fn recover<T>(
+        &mut self,
+        result: Result<T, ParseError>,
+        fallback: impl FnOnce() -> T,
+    ) -> Result<T, ParseError> {
+        match result {
+            Err(error) if self.lenient => {
+                self.errors.push(error);
+                Ok(fallback())
+            }
+            result => result,
+        }
+    }
+
+    // like `recover` for a name that can't be resolved. in lenient mode
+    // the name is taken to be in no namespace
+    fn recover_name(
+        &mut self,
+        result: Result<NameId, ParseError>,
+        name: &str,
+        xot: &mut Xot,
+    ) -> Result<NameId, ParseError> {
+        match result {
+            Err(error) if self.lenient => {
+                self.errors.push(error);
+                Ok(self.name_id_builder.name_id(name, xot.no_namespace_id, xot))
+            }
+            result => result,
+        }
+    }
+
+    // continue tokenizing at `position`. this is done in fragment mode, as
+    // we may be anywhere in the document.
+    fn restart(&mut self, position: usize) {
+        self.tokenizer = Tokenizer::from_fragment(self.source, position..self.source.len());
+        self.in_dtd = false;
+    }
+
+    /// The XML version of the document.
+    ///
+    /// This is the version in the XML declaration, or XML 1.0 if there is
+    /// none. It is known once the first event has been obtained.
+    pub fn version(&self) -> XmlVersion {
+        self.version
+    }
+
+    /// The number of elements that are currently open.
+    pub fn depth(&self) -> usize {
+        self.open_elements.len()
+    }
+
+    /// Build a tree for the element that was just opened.
+    ///
+    /// Pass the [`Event::StartElement`] you just obtained from
+    /// [`Parser::next_event`]. This consumes all events up to and including
+    /// the matching [`Event::EndElement`] and builds a document node with
+    /// this element as its document element, so you can use the full Xot API
+    /// on it. After this, [`Parser::next_event`] continues with whatever
+    /// follows the element.
+    ///
+    /// Namespace declarations that are in scope for the element because they
+    /// are declared on its ancestors are declared on the element itself.
+    ///
+    /// Once you're done with the tree, you can free it with [`Xot::remove`].
+    ///
+    /// Panics if `start` is not a start element event.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    /// use xot::pull::{Event, Parser};
+    ///
+    /// let mut xot = Xot::new();
+    /// let record = xot.add_name("record");
+    /// let xml = r#"<records xmlns:x="http://example.com"><record><x:a/></record></records>"#;
+    ///
+    /// let mut parser = Parser::new(&xot, xml);
+    /// while let Some(event) = parser.next_event(&mut xot)? {
+    ///     if matches!(event, Event::StartElement { name, .. } if name == record) {
+    ///         let document = parser.build_subtree(&mut xot, event)?;
+    ///         assert_eq!(
+    ///             xot.to_string(document).unwrap(),
+    ///             r#"<record xmlns:x="http://example.com"><x:a/></record>"#
+    ///         );
+    ///         xot.remove(document).unwrap();
+    ///     }
+    /// }
+    /// # Ok::<(), xot::ParseError>(())
+    /// ```
+    pub fn build_subtree(&mut self, xot: &mut Xot, start: Event<'a>) -> Result<Node, ParseError> {
+        xot.parse_subtree(self, start).map(|(node, _)| node)
+    }
+
+    /// Build a tree for the element that was just opened. Retain span
+    /// information.
+    ///
+    /// This is like [`Parser::build_subtree`], but also returns
+    /// [`SpanInfo`] which describes where the nodes in the tree are located
+    /// in the source text.
+    pub fn build_subtree_with_span_info(
+        &mut self,
+        xot: &mut Xot,
+        start: Event<'a>,
+    ) -> Result<(Node, SpanInfo), ParseError> {
+        xot.parse_subtree(self, start)
+    }
+
+    /// Build a tree for each element with the given name and hand it to `f`.
+    ///
+    /// Every element named `name` is built with [`Parser::build_subtree`] and
+    /// passed to `f` as a document node. The tree is freed once `f` returns,
+    /// so the memory used for nodes stays bounded by the size of the largest
+    /// element. The parser borrows the whole XML source, though, so that
+    /// still has to fit in memory.
+    /// Elements with this name nested inside a matched element are part of
+    /// its tree, and are not passed to `f` separately.
+    ///
+    /// This consumes all remaining events, unless `f` returns an error: then
+    /// the tree is freed and that error is returned right away. The error
+    /// type can be any that a [`ParseError`] converts into, such as
+    /// [`Error`](crate::Error).
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    /// use xot::pull::Parser;
+    ///
+    /// let mut xot = Xot::new();
+    /// let record = xot.add_name("record");
+    /// let xml = "<records><record>A</record><record>B</record></records>";
+    ///
+    /// let mut texts = Vec::new();
+    /// let mut parser = Parser::new(&xot, xml);
+    /// parser.for_each_subtree(&mut xot, record, |xot, document| {
+    ///     let record = xot.document_element(document)?;
+    ///     texts.push(xot.text_content_str(record).unwrap().to_string());
+    ///     Ok::<(), xot::Error>(())
+    /// })?;
+    /// assert_eq!(texts, vec!["A", "B"]);
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn for_each_subtree<E: From<ParseError>>(
+        &mut self,
+        xot: &mut Xot,
+        name: NameId,
+        mut f: impl FnMut(&mut Xot, Node) -> Result<(), E>,
+    ) -> Result<(), E> {
+        while let Some(event) = self.next_event(xot)? {
+            if matches!(event, Event::StartElement { name: event_name, .. } if event_name == name) {
+                let document = self.build_subtree(xot, event)?;
+                let result = f(xot, document);
+                // removing a document node can't fail
+                xot.remove(document).unwrap();
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    // the namespace declarations in scope for the element that was just
+    // opened, apart from those it declares itself. the innermost declaration
+    // of a prefix wins.
+    pub(crate) fn inherited_namespaces(&self, xot: &Xot) -> Namespaces {
+        let stack = &self.name_id_builder.namespace_stack;
+        // the bottom two entries are always in scope and never need to be
+        // declared, and the top entry is that of the element itself
+        if stack.len() <= 3 {
+            return Namespaces::new();
+        }
+        let own = &stack[stack.len() - 1];
+        let mut inherited = Namespaces::new();
+        for namespaces in stack[2..stack.len() - 1].iter().rev() {
+            for (prefix_id, namespace_id) in namespaces.iter().rev() {
+                if own.iter().any(|(declared, _)| declared == prefix_id)
+                    || inherited.iter().any(|(seen, _)| seen == prefix_id)
+                {
+                    continue;
+                }
+                inherited.push((*prefix_id, *namespace_id));
+            }
+        }
+        // an undeclared default namespace doesn't need declaring
+        inherited.retain(|(prefix_id, namespace_id)| {
+            *prefix_id != xot.empty_prefix_id || *namespace_id != xot.no_namespace_id
+        });
+        inherited.reverse();
+        inherited
+    }
+
+    // the (element name, attribute name) pairs declared to be of type ID in
+    // the DTD, for elements seen so far
+    pub(crate) fn id_attributes(&self) -> impl Iterator<Item = (NameId, NameId)> + '_ {
+        self.id_attributes.iter().copied()
+    }
+
+    /// Get the next event.
+    ///
+    /// This returns `Ok(None)` when the end of the XML has been reached.
+    /// Once an error has been returned, the parser is done and further calls
+    /// return `Ok(None)` too.
+    ///
+    /// The parser adds any names, namespaces and prefixes it encounters to
+    /// `xot`. You should use the same `Xot` for every call.
+    pub fn next_event(&mut self, xot: &mut Xot) -> Result<Option<Event<'a>>, ParseError> {
+        if self.done {
+            return Ok(None);
+        }
+        let result = self.next_event_inner(xot);
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+        result
+    }
+
+    fn next_event_inner(&mut self, xot: &mut Xot) -> Result<Option<Event<'a>>, ParseError> {
+        use Token::*;
+
+        if let Some(event) = self.pending_end_event() {
+            return Ok(Some(event));
+        }
+        loop {
+            // getting the position unconditionally is required to get
+            // the right one for the error handling, which is a bit unfortunate
+            // https://github.com/RazrFalcon/xmlparser/issues/30
+            let position = self.tokenizer.stream().pos();
+            let Some(token) = self.tokenizer.next() else {
+                if let Some(element_builder) = &self.element_builder {
+                    // the input ends within a start tag. in lenient mode the
+                    // element is kept, and closed at the end
+                    let span = element_builder.span;
+                    self.recover(Err(ParseError::UnclosedTag(span)), || ())?;
+                    let event = self.open_element(xot)?;
+                    let end = Span::new(self.source.len(), self.source.len());
+                    self.pending_ends.push(end);
+                    return Ok(Some(event));
+                }
+                self.end()?;
+                return Ok(self.pending_end_event());
+            };
+            let token = match token {
+                Ok(token) => token,
+                Err(e) if self.lenient => {
+                    self.errors.push(ParseError::XmlParser(e, position));
+                    // skip to the next tag after the error
+                    let rest = &self.source[position..];
+                    let skip = rest.chars().next().map_or(0, char::len_utf8);
+                    let next = rest[skip..]
+                        .find('<')
+                        .map_or(self.source.len(), |index| position + skip + index);
+                    self.restart(next);
+                    // an element whose start tag is broken is still opened
+                    if self.element_builder.is_some() {
+                        return self.open_element(xot).map(Some);
+                    }
+                    continue;
+                }
+                Err(e) => return Err(ParseError::XmlParser(e, position)),
+            };
+            match token {
+                Attribute {
+                    prefix,
+                    local,
+                    value,
+                    span: _,
+                } => {
+                    if prefix.as_str() == "xmlns" {
+                        let span = Span::from_prefix_name(prefix, local);
+                        self.prefix(local.as_str(), value, span, xot)?;
+                    } else if local.as_str() == "xmlns" {
+                        self.prefix("", value, local.into(), xot)?;
+                    } else {
+                        self.attribute(prefix, local, value)?;
+                    }
+                }
+                Text { text } => {
+                    let span = text.into();
+                    if self.open_elements.is_empty() && !self.fragment {
+                        // after a restart the tokenizer reports whitespace
+                        // between top-level nodes as text
+                        if text.trim().is_empty() {
+                            continue;
+                        }
+                        self.recover(Err(ParseError::TextAtTopLevel(span)), || ())?;
+                        continue;
+                    }
+                    let parsed = parse_text(
+                        text.as_str().into(),
+                        text.start(),
+                        &self.entities,
+                        self.version,
+                    );
+                    // in lenient mode the text is taken as it is
+                    let text = self.recover(parsed, || text.as_str().into())?;
+                    return Ok(Some(Event::Text { text, span }));
+                }
+                Cdata { text, span: _ } => {
+                    if self.open_elements.is_empty() && !self.fragment {
+                        self.recover(Err(ParseError::TextAtTopLevel(text.into())), || ())?;
+                        continue;
+                    }
+                    return Ok(Some(Event::Cdata {
+                        text: text.as_str(),
+                        span: text.into(),
+                    }));
+                }
+                ElementStart {
+                    prefix,
+                    local,
+                    span: _,
+                } => {
+                    if self.open_elements.is_empty() && self.seen_element && !self.fragment {
+                        // in lenient mode the element is kept
+                        self.recover(
+                            Err(ParseError::MultipleElementsAtTopLevel(
+                                Span::from_prefix_name(prefix, local),
+                            )),
+                            || (),
+                        )?;
+                    }
+                    self.seen_element = true;
+                    self.element_builder = Some(ElementBuilder::new(prefix, local));
+                }
+                ElementEnd {
+                    end,
+                    span: end_span,
+                } => {
+                    use self::ElementEnd::*;
+
+                    match end {
+                        Open => {
+                            return self.open_element(xot).map(Some);
+                        }
+                        Close(prefix, local) => {
+                            self.close_element(prefix, local, end_span, xot)?;
+                            if let Some(event) = self.pending_end_event() {
+                                return Ok(Some(event));
+                            }
+                        }
+                        Empty => {
+                            let event = self.open_element(xot)?;
+                            self.pending_ends.push(end_span.into());
+                            return Ok(Some(event));
+                        }
+                    }
+                }
+                Comment { .. } if self.in_dtd => {}
+                Comment { text, span: _ } => {
+                    return Ok(Some(Event::Comment {
+                        text: text.as_str(),
+                        span: text.into(),
+                    }));
+                }
+                ProcessingInstruction { .. } if self.in_dtd => {}
+                ProcessingInstruction {
+                    target,
+                    content,
+                    span: _,
+                } => {
+                    // XXX are there illegal processing instructions, like
+                    // those with ?> inside? or won't they pass the parser?
+                    // What about those with xml?
+                    return Ok(Some(Event::ProcessingInstruction {
+                        target: self.name_id_builder.name_id(
+                            target.as_str(),
+                            xot.no_namespace_id,
+                            xot,
+                        ),
+                        data: content.map(|content| content.as_str()),
+                        target_span: target.into(),
+                        data_span: content.map(|content| content.into()),
+                    }));
+                }
+                Declaration { version, .. } => {
+                    self.version = match version.as_str() {
+                        "1.0" => XmlVersion::V1_0,
+                        "1.1" => XmlVersion::V1_1,
+                        _ => self.recover(
+                            Err(ParseError::UnsupportedVersion(
+                                version.to_string(),
+                                version.into(),
+                            )),
+                            || XmlVersion::V1_0,
+                        )?,
+                    };
+                }
+                // an external DTD subset is never loaded
+                DtdStart {
+                    name,
+                    external_id,
+                    span,
+                } => {
+                    self.document_type = Some(document_type(name, external_id));
+                    self.in_dtd = true;
+                    self.internal_subset_start = span.end();
+                }
+                DtdEnd { span } => {
+                    self.in_dtd = false;
+                    let internal_subset = &self.source[self.internal_subset_start..span.start()];
+                    let attribute_declarations = parse_attribute_list_declarations(
+                        internal_subset,
+                        self.internal_subset_start,
+                        &self.entities,
+                        self.version,
+                    );
+                    self.attribute_declarations =
+                        self.recover(attribute_declarations, AttributeDeclarations::new)?;
+                    if let Some(mut document_type) = self.document_type.take() {
+                        document_type.internal_subset = Some(internal_subset.to_string());
+                        return Ok(Some(Event::DocumentType(document_type)));
+                    }
+                }
+                EmptyDtd {
+                    name,
+                    external_id,
+                    span: _,
+                } => {
+                    return Ok(Some(Event::DocumentType(document_type(name, external_id))));
+                }
+                EntityDeclaration {
+                    name,
+                    definition,
+                    span,
+                } => {
+                    let declared = self.entity_declaration(name, definition, span);
+                    self.recover(declared, || ())?;
+                }
+            }
+        }
+    }
+
+    fn end(&mut self) -> Result<(), ParseError> {
+        if self.lenient {
+            // all open elements are closed at the end
+            let end = Span::new(self.source.len(), self.source.len());
+            for (_, span) in &self.open_elements {
+                self.errors.push(ParseError::UnclosedTag(*span));
+                self.pending_ends.push(end);
+            }
+        } else if let Some((_, span)) = self.open_elements.last() {
+            // the innermost open element's span is the problem
+            return Err(ParseError::UnclosedTag(*span));
+        }
+        if !self.seen_element && !self.fragment {
+            return self.recover(
+                Err(ParseError::NoElementAtTopLevel(self.source.len())),
+                || (),
+            );
+        }
+        Ok(())
+    }
+
+    // the end element event for the innermost open element, if it is to be
+    // closed
+    fn pending_end_event(&mut self) -> Option<Event<'a>> {
+        let span = self.pending_ends.pop()?;
+        let (name, _) = self.open_elements.pop().unwrap();
+        self.name_id_builder.pop();
+        Some(Event::EndElement { name, span })
+    }
+
+    fn prefix(
+        &mut self,
+        prefix: &str,
+        namespace_uri: StrSpan<'a>,
+        span: Span,
+        xot: &mut Xot,
+    ) -> Result<(), ParseError> {
+        // the constraints of https://www.w3.org/TR/xml-names/#xmlReserved
+        // and https://www.w3.org/TR/xml-names/#nsc-NoPrefixUndecl. in
+        // lenient mode the declaration is dropped.
+        let error = if prefix == "xmlns" || (prefix == "xml" && namespace_uri != XML_NAMESPACE) {
+            Some(ParseError::ReservedPrefix(prefix.to_string(), span))
+        } else if (prefix != "xml" && namespace_uri == XML_NAMESPACE)
+            || namespace_uri == XMLNS_NAMESPACE
+        {
+            Some(ParseError::ReservedNamespace(
+                namespace_uri.to_string(),
+                namespace_uri.into(),
+            ))
+        } else if !prefix.is_empty()
+            && namespace_uri.as_str().is_empty()
+            && self.version == XmlVersion::V1_0
+        {
+            Some(ParseError::PrefixUndeclaration(prefix.to_string(), span))
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            return self.recover(Err(error), || ());
+        }
+        let namespace_uri = namespace_uri.as_str();
+        let prefix_id = self.name_id_builder.prefix_id(prefix, xot);
+        let namespaces = &self.element_builder.as_ref().unwrap().namespaces;
+        if namespaces.iter().any(|(id, _)| *id == prefix_id) {
+            let attr_name = if prefix.is_empty() {
+                "xmlns".to_string()
+            } else {
+                format!("xmlns:{}", prefix)
+            };
+            // in lenient mode the first declaration wins
+            return self.recover(Err(ParseError::DuplicateAttribute(attr_name, span)), || ());
+        }
+        let namespace_id = self.name_id_builder.namespace_id(namespace_uri, xot);
+        self.element_builder
+            .as_mut()
+            .unwrap()
+            .namespaces
+            .push((prefix_id, namespace_id));
+        Ok(())
+    }
+
+    fn attribute(
+        &mut self,
+        prefix: StrSpan<'a>,
+        name: StrSpan<'a>,
+        value: StrSpan<'a>,
+    ) -> Result<(), ParseError> {
+        let is_duplicate = self
+            .element_builder
+            .as_ref()
+            .unwrap()
+            .attributes
+            .iter()
+            .any(|attribute_builder| {
+                attribute_builder.prefix == prefix.as_str()
+                    && attribute_builder.name == name.as_str()
+            });
+        if is_duplicate {
+            let attr_name = qualified_name(&prefix, &name);
+            let span = Span::from_prefix_name(prefix, name);
+            // in lenient mode the first attribute wins
+            return self.recover(Err(ParseError::DuplicateAttribute(attr_name, span)), || ());
+        }
+        let value_span = value.into();
+        let parsed = parse_attribute(
+            value.as_str().into(),
+            value.start(),
+            &self.entities,
+            self.version,
+        );
+        // in lenient mode the value is taken as it is
+        let value = self.recover(parsed, || value.as_str().into())?;
+        // if this is an xml:id we want to apply xml:id normalization as described here
+        // https://www.w3.org/TR/xml-id/#id-avn
+        let value = if name == "id" && prefix == "xml" {
+            Cow::Owned(normalize_xml_id(&value))
+        } else {
+            value
+        };
+        let attributes = &mut self.element_builder.as_mut().unwrap().attributes;
+        attributes.push(AttributeBuilder {
+            prefix: prefix.as_str(),
+            name: name.as_str(),
+            value,
+            name_span: Span::from_prefix_name(prefix, name),
+            value_span,
+            prefix_span: prefix.into(),
+            is_id: false,
+        });
+        Ok(())
+    }
+
+    fn open_element(&mut self, xot: &mut Xot) -> Result<Event<'a>, ParseError> {
+        let mut element_builder = self.element_builder.take().unwrap();
+        let span = element_builder.span;
+        let element_name = element_builder.qualified_name();
+        self.apply_attribute_declarations(&element_name, &mut element_builder, xot);
+
+        self.name_id_builder
+            .push(element_builder.namespaces.clone());
+
+        let name = self.name_id_builder.element_name_id(
+            element_builder.prefix,
+            element_builder.name,
+            element_builder.prefix_span,
+            xot,
+        );
+        // in lenient mode an unknown prefix is ignored
+        let name = self.recover_name(name, element_builder.name, xot)?;
+        self.open_elements.push((name, span));
+
+        // remember which attributes of this element are declared as IDs, so
+        // that an id index can be maintained
+        for declaration in self.attribute_declarations.get(&element_name) {
+            if declaration.attribute_type != AttributeType::Id {
+                continue;
+            }
+            let (prefix, attribute_name) = declaration
+                .name
+                .split_once(':')
+                .unwrap_or(("", &declaration.name));
+            if let Ok(attribute_name_id) =
+                self.name_id_builder
+                    .attribute_name_id(prefix, attribute_name, span, xot)
+            {
+                self.id_attributes.insert((name, attribute_name_id));
+            }
+        }
+
+        let mut attributes = Vec::with_capacity(element_builder.attributes.len());
+        // names we made up for attributes with an unknown prefix
+        let mut unresolved = Vec::new();
+        for attribute_builder in element_builder.attributes {
+            let name = self.name_id_builder.attribute_name_id(
+                attribute_builder.prefix,
+                attribute_builder.name,
+                attribute_builder.prefix_span,
+                xot,
+            );
+            let resolved = name.is_ok();
+            let name = self.recover_name(name, attribute_builder.name, xot)?;
+            if !resolved {
+                unresolved.push(name);
+            }
+            // attributes with different prefixes for the same namespace are
+            // duplicates too. ignoring unknown prefixes can result in
+            // duplicate attributes as well.
+            if attributes
+                .iter()
+                .any(|attribute: &Attribute| attribute.name == name)
+            {
+                let qualified_name =
+                    qualified_name(attribute_builder.prefix, attribute_builder.name);
+                let error = if unresolved.contains(&name) {
+                    ParseError::DuplicateAttribute(qualified_name, attribute_builder.name_span)
+                } else {
+                    ParseError::DuplicateExpandedAttribute(
+                        qualified_name,
+                        attribute_builder.name_span,
+                    )
+                };
+                // in lenient mode the first attribute wins
+                self.recover(Err(error), || ())?;
+                continue;
+            }
+            attributes.push(Attribute {
+                name,
+                value: attribute_builder.value,
+                name_span: attribute_builder.name_span,
+                value_span: attribute_builder.value_span,
+                is_id: name == xot.xml_id_id || attribute_builder.is_id,
+            });
+        }
+
+        Ok(Event::StartElement {
+            name,
+            namespaces: element_builder.namespaces,
+            attributes,
+            span,
+        })
+    }
+
+    // applies the attribute list declarations of the DTD to an element:
+    // missing attributes with a default value are added, and the values of
+    // attributes that are not of type CDATA are normalized
+    fn apply_attribute_declarations(
+        &mut self,
+        element_name: &str,
+        element_builder: &mut ElementBuilder<'a>,
+        xot: &mut Xot,
+    ) {
+        let declarations = self.attribute_declarations.get(element_name);
+        for declaration in declarations {
+            let specified = element_builder.attributes.iter_mut().find(|attribute| {
+                qualified_name(attribute.prefix, attribute.name) == declaration.name
+            });
+            if let Some(attribute) = specified {
+                match declaration.attribute_type {
+                    AttributeType::Cdata => {}
+                    AttributeType::Id => {
+                        attribute.value = normalize_tokenized(&attribute.value).into();
+                        attribute.is_id = true;
+                    }
+                    AttributeType::Tokenized => {
+                        attribute.value = normalize_tokenized(&attribute.value).into();
+                    }
+                }
+                continue;
+            }
+            let Some(default) = &declaration.default else {
+                continue;
+            };
+            // a default namespace declaration
+            let (prefix, name) = declaration
+                .name
+                .split_once(':')
+                .unwrap_or(("", &declaration.name));
+            let namespace_prefix = match (prefix, name) {
+                ("xmlns", name) => Some(name),
+                ("", "xmlns") => Some(""),
+                _ => None,
+            };
+            if let Some(namespace_prefix) = namespace_prefix {
+                let prefix_id = self.name_id_builder.prefix_id(namespace_prefix, xot);
+                if !element_builder
+                    .namespaces
+                    .iter()
+                    .any(|(declared, _)| *declared == prefix_id)
+                {
+                    let namespace_id = self.name_id_builder.namespace_id(&default.value, xot);
+                    element_builder.namespaces.push((prefix_id, namespace_id));
+                }
+                continue;
+            }
+            // the spans of a defaulted attribute point to its declaration.
+            // the names are borrowed from the source, as the declaration is
+            // part of it.
+            let name_start = default.name_span.start;
+            let name_end = default.name_span.end;
+            let (prefix, name) = match self.source[name_start..name_end].split_once(':') {
+                Some((prefix, name)) => (prefix, name),
+                None => ("", &self.source[name_start..name_end]),
+            };
+            element_builder.attributes.push(AttributeBuilder {
+                prefix,
+                name,
+                value: Cow::Owned(default.value.clone()),
+                name_span: default.name_span,
+                value_span: default.value_span,
+                prefix_span: Span::new(name_start, name_start + prefix.len()),
+                is_id: declaration.attribute_type == AttributeType::Id,
+            });
+        }
+    }
+
+    // schedules the end element events for a close tag
+    fn close_element(
+        &mut self,
+        prefix: StrSpan<'a>,
+        name: StrSpan<'a>,
+        span: StrSpan<'a>,
+        xot: &mut Xot,
+    ) -> Result<(), ParseError> {
+        let name_id = self
+            .name_id_builder
+            .element_name_id(&prefix, &name, prefix.into(), xot);
+        let name_id = self.recover_name(name_id, &name, xot)?;
+        match self.open_elements.last() {
+            Some((open_name_id, _)) if *open_name_id == name_id => {
+                self.pending_ends.push(span.into());
+                return Ok(());
+            }
+            _ => {}
+        }
+        let error = ParseError::InvalidCloseTag(
+            prefix.to_string(),
+            name.to_string(),
+            Span::from_prefix_name(prefix, name),
+        );
+        if !self.lenient {
+            return Err(error);
+        }
+        // the tokenizer keeps track of the depth too; as we deviate from it
+        // we continue in fragment mode
+        self.restart(span.end());
+        let Some(index) = self
+            .open_elements
+            .iter()
+            .rposition(|(open_name_id, _)| *open_name_id == name_id)
+        else {
+            // a close tag that matches no open element is dropped
+            self.errors.push(error);
+            return Ok(());
+        };
+        // the elements inside the matching element are closed just before
+        // the close tag
+        self.pending_ends.push(span.into());
+        let implicit_end = Span::new(span.start(), span.start());
+        for (_, start_span) in &self.open_elements[index + 1..] {
+            self.errors.push(ParseError::UnclosedTag(*start_span));
+            self.pending_ends.push(implicit_end);
+        }
+        Ok(())
+    }
+
+    fn entity_declaration(
+        &mut self,
+        name: StrSpan<'_>,
+        definition: EntityDefinition<'_>,
+        span: StrSpan<'_>,
+    ) -> Result<(), ParseError> {
+        // parameter entities can only be referenced within the DTD, and as
+        // we don't support parameter entity references we can ignore them
+        let is_parameter_entity = span.as_str()["<!ENTITY".len()..]
+            .trim_start()
+            .starts_with('%');
+        if is_parameter_entity {
+            return Ok(());
+        }
+        let entity = match definition {
+            EntityDefinition::EntityValue(value) => Entity::Internal(parse_entity_value(
+                value.as_str(),
+                value.start(),
+                self.version,
+            )?),
+            // we never load external entities
+            EntityDefinition::ExternalId(_) => Entity::External,
+        };
+        self.entities.declare(name.as_str(), entity);
+        Ok(())
+    }
+}
+
+fn document_type(name: StrSpan<'_>, external_id: Option<ExternalId<'_>>) -> DocumentType {
+    let (public_id, system_id) = match external_id {
+        Some(ExternalId::Public(public_id, system_id)) => {
+            (Some(public_id.to_string()), Some(system_id.to_string()))
+        }
+        Some(ExternalId::System(system_id)) => (None, Some(system_id.to_string())),
+        None => (None, None),
+    };
+    DocumentType {
+        name: name.to_string(),
+        public_id,
+        system_id,
+        internal_subset: None,
+    }
+}
+
+struct NameIdBuilder {
+    namespace_stack: Vec<Namespaces>,
+    // the number of names, namespaces and prefixes this parse added. other
+    // Xots may add to shared name tables at the same time, so this is
+    // counted here.
+    added: usize,
+}
+
+impl NameIdBuilder {
+    fn new(prefixes: Namespaces) -> Self {
+        let namespace_stack = vec![prefixes];
+        Self {
+            namespace_stack,
+            added: 0,
+        }
+    }
+
+    fn prefix_id(&mut self, prefix: &str, xot: &mut Xot) -> PrefixId {
+        let (prefix_id, added) = xot.prefix_lookup.insert(prefix);
+        self.added += usize::from(added);
+        prefix_id
+    }
+
+    fn namespace_id(&mut self, namespace: &str, xot: &mut Xot) -> NamespaceId {
+        let (namespace_id, added) = xot.namespace_lookup.insert(namespace);
+        self.added += usize::from(added);
+        namespace_id
+    }
+
+    fn name_id(&mut self, name: &str, namespace_id: NamespaceId, xot: &mut Xot) -> NameId {
+        let (name_id, added) = xot.name_lookup.insert(&Name::new(name, namespace_id));
+        self.added += usize::from(added);
+        name_id
+    }
+
+    fn push(&mut self, namespaces: Namespaces) {
+        self.namespace_stack.push(namespaces);
+    }
+
+    fn pop(&mut self) {
+        // should always be able to pop as there's a bottom entry
+        self.namespace_stack.pop();
+    }
+
+    fn element_name_id(
+        &mut self,
+        prefix: &str,
+        name: &str,
+        prefix_span: Span,
+        xot: &mut Xot,
+    ) -> Result<NameId, ParseError> {
+        let prefix_id = self.prefix_id(prefix, xot);
+        if let Ok(name_id) = self.name_id_with_prefix_id(prefix_id, name, xot) {
+            Ok(name_id)
+        } else {
+            Err(ParseError::UnknownPrefix(prefix.to_string(), prefix_span))
+        }
+    }
+
+    fn attribute_name_id(
+        &mut self,
+        prefix: &str,
+        name: &str,
+        prefix_span: Span,
+        xot: &mut Xot,
+    ) -> Result<NameId, ParseError> {
+        // an unprefixed attribute is in no namespace, not
+        // in the default namespace
+        // https://stackoverflow.com/questions/3312390/xml-default-namespaces-for-unqualified-attribute-names
+        let prefix_id = self.prefix_id(prefix, xot);
+        if prefix_id == xot.empty_prefix_id {
+            return Ok(self.name_id(name, xot.no_namespace_id, xot));
+        }
+        if let Ok(name_id) = self.name_id_with_prefix_id(prefix_id, name, xot) {
+            Ok(name_id)
+        } else {
+            Err(ParseError::UnknownPrefix(prefix.to_string(), prefix_span))
+        }
+    }
+
+    fn name_id_with_prefix_id(
+        &mut self,
+        prefix_id: PrefixId,
+        name: &str,
+        xot: &mut Xot,
+    ) -> Result<NameId, ()> {
+        // go through namespace stack backwards, find the first namespace
+        // that matches this prefix
+        let namespace_id = self.namespace_stack.iter().rev().find_map(|ns| {
+            ns.iter()
+                .rev()
+                .find_map(|(p, ns)| if *p == prefix_id { Some(*ns) } else { None })
+        });
+        let namespace_id = namespace_id.ok_or(())?;
+        // a prefix bound to no namespace is undeclared (XML 1.1 only)
+        if namespace_id == xot.no_namespace_id && prefix_id != xot.empty_prefix_id {
+            return Err(());
+        }
+        Ok(self.name_id(name, namespace_id, xot))
+    }
+}
@@ -2,13 +2,13 @@ use std::io::Write;
 
 use crate::error::Error;
 use crate::output::{
-    gen_outputs, Html5Elements, Html5Serializer, Output, OutputToken, TokenSerializeParameters,
-    XmlSerializer,
+    gen_outputs, EncodingWriter, Html5Elements, Html5Serializer, Output, OutputEncoding,
+    OutputToken, TokenSerializeParameters, XmlSerializer,
 };
 use crate::output::{NoopNormalizer, Normalizer};
 use crate::output::{Pretty, PrettyOutputToken};
 use crate::xmlname::NameStrInfo;
-use crate::{output, NameId, Value};
+use crate::{output, NameId, Value, XmlVersion};
 
 use crate::xotdata::{Node, Xot};
 
@@ -83,6 +83,7 @@ impl<'a> Html5<'a> {
             &self.html5_elements,
             node,
             &parameters.cdata_section_elements,
+            &parameters.character_map,
             normalizer,
         );
         if let Some(indentation) = parameters.indentation {
@@ -99,7 +100,8 @@ impl Xot {
     /// Write node as XML.
     ///
     /// This uses the default serialization parameters: no XML declaration, no
-    /// doctype, no control over pretty printing or CDATA.
+    /// doctype other than that of the document, no control over pretty
+    /// printing or CDATA.
     ///
     /// To serialize with this control use [`Xot::serialize_xml_write`].
     ///
@@ -127,7 +129,8 @@ impl Xot {
     /// Serialize node as XML string.
     ///
     /// This uses the default serialization parameters: no XML declaration, no
-    /// doctype, no control over pretty printing or CDATA.
+    /// doctype other than that of the document, no control over pretty
+    /// printing or CDATA.
     ///
     /// To serialize with this control use [`Xot::serialize_xml_string`].
     ///
@@ -187,8 +190,10 @@ impl Xot {
     /// # Ok::<(), xot::Error>(())
     /// ```
     ///
-    /// XML declaration with an encoding declaration (does not affect output
-    /// encoding):
+    /// XML declaration with an encoding declaration. As the result is a Rust
+    /// string, this does not affect the output encoding; use
+    /// [`Xot::serialize_xml_bytes`] or [`Xot::serialize_xml_write`] to encode
+    /// the output:
     ///
     /// ```rust
     /// use xot::{Xot, output};
@@ -227,7 +232,9 @@ impl Xot {
         parameters: output::xml::Parameters,
         node: Node,
     ) -> Result<String, Error> {
-        self.serialize_xml_string_with_normalizer(parameters, node, NoopNormalizer)
+        let mut buf = Vec::new();
+        self.serialize_xml_unencoded(parameters, node, &mut buf, NoopNormalizer, None, true)?;
+        Ok(String::from_utf8(buf).unwrap())
     }
 
     /// Serialize a string using a normalizer for any text and attribute values.
@@ -259,22 +266,87 @@ assert_eq!(s, "<doc>\u{1E0D}\u{0307}</doc>");
         normalizer: N,
     ) -> Result<String, Error> {
         let mut buf = Vec::new();
-        self.serialize_xml_write_with_normalizer(parameters, node, &mut buf, normalizer)?;
+        self.serialize_xml_unencoded(parameters, node, &mut buf, normalizer, None, false)?;
         Ok(String::from_utf8(buf).unwrap())
     }
 
+    /// Serialize to XML as bytes, with options.
+    ///
+    /// Unlike [`Xot::serialize_xml_string`], this encodes the output in the
+    /// encoding given in the XML declaration. See
+    /// [`Xot::serialize_xml_write`] for details.
+    ///
+    /// ```rust
+    /// use xot::{Xot, output};
+    ///
+    /// let mut xot = Xot::new();
+    /// let root = xot.parse("<p>é</p>")?;
+    ///
+    /// let xml = xot.serialize_xml_bytes(output::xml::Parameters {
+    ///     declaration: Some(output::xml::Declaration {
+    ///         encoding: Some("ISO-8859-1".to_string()),
+    ///         ..Default::default()
+    ///     }),
+    ///     ..Default::default()
+    /// }, root)?;
+    /// assert_eq!(xml, b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n<p>\xe9</p>");
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn serialize_xml_bytes(
+        &self,
+        parameters: output::xml::Parameters,
+        node: Node,
+    ) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        self.serialize_xml_write(parameters, node, &mut buf)?;
+        Ok(buf)
+    }
+
     /// Serialize to XML via a [`Write`], with options.
     ///
     /// This is like [`Xot::serialize_xml_string`] but writes to a [`Write`]. This
     /// is more efficient if you want to write directly to a file, for
     /// instance, as no string needs to be created in memory.
+    ///
+    /// If the XML declaration has an encoding, the output is encoded in that
+    /// encoding, for instance `ISO-8859-1`, `Windows-1252`, `Shift_JIS` or
+    /// `UTF-16` (which is written with a byte order mark). Characters in
+    /// text and attribute values that cannot be represented in the encoding
+    /// are written as numeric character references. If such a character
+    /// occurs elsewhere, for instance in an element name or a comment, you
+    /// get [`Error::UnencodableCharacter`]. An encoding that isn't known
+    /// results in [`Error::UnsupportedEncoding`].
+    ///
+    /// Encoding labels are as in the [WHATWG Encoding
+    /// Standard](https://encoding.spec.whatwg.org/), except that
+    /// `ISO-8859-1` and `US-ASCII` mean just that, rather than
+    /// windows-1252. So `€` is written as a character reference in
+    /// `ISO-8859-1`, but as a byte in `windows-1252`.
+    ///
+    /// ```rust
+    /// use xot::{Xot, output};
+    ///
+    /// let mut xot = Xot::new();
+    /// let root = xot.parse("<p>é\u{3042}</p>")?;
+    ///
+    /// let mut buf = Vec::new();
+    /// xot.serialize_xml_write(output::xml::Parameters {
+    ///     declaration: Some(output::xml::Declaration {
+    ///         encoding: Some("windows-1252".to_string()),
+    ///         ..Default::default()
+    ///     }),
+    ///     ..Default::default()
+    /// }, root, &mut buf)?;
+    /// assert_eq!(buf, b"<?xml version=\"1.0\" encoding=\"windows-1252\"?>\n<p>\xe9&#x3042;</p>");
+    /// # Ok::<(), xot::Error>(())
+    /// ```
     pub fn serialize_xml_write(
         &self,
         parameters: output::xml::Parameters,
         node: Node,
         w: &mut impl Write,
     ) -> Result<(), Error> {
-        self.serialize_xml_write_with_normalizer(parameters, node, w, NoopNormalizer)
+        self.serialize_xml_encoded(parameters, node, w, NoopNormalizer, true)
     }
 
     /// Write XML with a normalizer for text and attribute values.
@@ -287,38 +359,150 @@ assert_eq!(s, "<doc>\u{1E0D}\u{0307}</doc>");
         w: &mut impl Write,
         normalizer: N,
     ) -> Result<(), Error> {
-        if let Some(declaration) = parameters.declaration {
-            declaration.serialize(w)?;
-        }
-        if let Some(doctype) = parameters.doctype {
-            // if we are in a document node, we look for the document_element,
-            // otherwise we take the current element, if possible
-            let node = match self.value(node) {
-                Value::Document => self.document_element(node)?,
-                Value::Element(_) => node,
-                _ => return Err(Error::NotElement(node)),
-            };
-            // now take the full name of the element; we can unwrap as we
-            // know it's an element now
-            let name = self.node_name_ref(node)?.unwrap();
-            let name = name.full_name();
-            doctype.serialize(name.as_ref(), w)?;
+        self.serialize_xml_encoded(parameters, node, w, normalizer, false)
+    }
+
+    // serialize XML in the encoding given by the declaration. Without a
+    // normalizer, this is lossless for a document parsed losslessly.
+    fn serialize_xml_encoded<N: Normalizer>(
+        &self,
+        parameters: output::xml::Parameters,
+        node: Node,
+        w: &mut impl Write,
+        normalizer: N,
+        lossless: bool,
+    ) -> Result<(), Error> {
+        let encoding = match parameters
+            .declaration
+            .as_ref()
+            .and_then(|declaration| declaration.encoding.as_ref())
+        {
+            Some(label) => Some(
+                OutputEncoding::for_label(label)
+                    .ok_or_else(|| Error::UnsupportedEncoding(label.clone()))?,
+            ),
+            None => None,
+        };
+        match encoding {
+            Some(encoding) if !encoding.is_utf8() => {
+                let mut w = EncodingWriter::new(w, encoding);
+                let result = self
+                    .serialize_xml_unencoded(
+                        parameters,
+                        node,
+                        &mut w,
+                        normalizer,
+                        Some(encoding),
+                        lossless,
+                    )
+                    .and_then(|_| Ok(w.finish()?));
+                result.map_err(|e| match w.unencodable {
+                    Some(c) => Error::UnencodableCharacter(c, encoding.name().to_string()),
+                    None => e,
+                })
+            }
+            _ => self.serialize_xml_unencoded(parameters, node, w, normalizer, None, lossless),
         }
-        let outputs = gen_outputs(self, node);
+    }
+
+    // serialize XML as UTF-8. If an encoding is given, the output is going
+    // to be encoded, so characters that cannot be represented in it are
+    // replaced with character references.
+    //
+    // If lossless is set, nodes of a document that was parsed losslessly are
+    // serialized as they were in the source, unless the parameters ask for
+    // output that is different from the source.
+    fn serialize_xml_unencoded<N: Normalizer>(
+        &self,
+        parameters: output::xml::Parameters,
+        node: Node,
+        w: &mut impl Write,
+        normalizer: N,
+        encoding: Option<OutputEncoding>,
+        lossless: bool,
+    ) -> Result<(), Error> {
+        let lossless = lossless
+            && encoding.is_none()
+            && parameters.indentation.is_none()
+            && parameters.cdata_section_elements.is_empty()
+            && parameters.character_map.is_empty()
+            && parameters
+                .declaration
+                .as_ref()
+                .is_none_or(|declaration| declaration.version == XmlVersion::V1_0);
+        // the source has its own declarations
+        let lossless_document =
+            lossless && parameters.declaration.is_none() && parameters.doctype.is_none();
         let mut serializer = XmlSerializer::new(
             self,
             node,
             TokenSerializeParameters {
                 cdata_section_elements: parameters.cdata_section_elements,
                 unescaped_gt: parameters.unescaped_gt,
+                character_map: parameters.character_map,
             },
             normalizer,
         );
+        if lossless {
+            serializer = serializer.with_lossless(lossless_document);
+        }
+        if let Some(prolog) = serializer.prolog() {
+            w.write_all(prolog.as_bytes())?;
+        } else {
+            self.serialize_xml_prolog(
+                parameters.declaration.as_ref(),
+                parameters.doctype.as_ref(),
+                node,
+                w,
+            )?;
+        }
+        let outputs = gen_outputs(self, node);
+        if let Some(encoding) = encoding {
+            serializer = serializer.with_encoding(encoding);
+        }
+        if let Some(declaration) = &parameters.declaration {
+            serializer =
+                serializer.with_version(declaration.version, declaration.undeclare_prefixes);
+        }
         if let Some(indentation) = parameters.indentation {
             serializer.serialize_pretty(w, outputs, &indentation.suppress)?;
         } else {
             serializer.serialize(w, outputs)?;
         }
+        if let Some(epilog) = serializer.epilog() {
+            w.write_all(epilog.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // serialize the XML declaration and document type declaration
+    fn serialize_xml_prolog(
+        &self,
+        declaration: Option<&output::xml::Declaration>,
+        doctype: Option<&output::xml::DocType>,
+        node: Node,
+        w: &mut impl Write,
+    ) -> Result<(), Error> {
+        if let Some(declaration) = declaration {
+            declaration.serialize(w)?;
+        }
+        if let Some(doctype) = doctype {
+            // if we are in a document node, we look for the document_element,
+            // otherwise we take the current element, if possible
+            let node = match self.value(node) {
+                Value::Document => self.document_element(node)?,
+                Value::Element(_) => node,
+                _ => return Err(Error::NotElement(node)),
+            };
+            // now take the full name of the element; we can unwrap as we
+            // know it's an element now
+            let name = self.node_name_ref(node)?.unwrap();
+            let name = name.full_name();
+            doctype.serialize(name.as_ref(), w)?;
+        } else if let Some(document_type) = self.document_type(node) {
+            // without an explicit doctype, we retain the one of the document
+            document_type.serialize(w)?;
+        }
         Ok(())
     }
 
@@ -329,7 +513,7 @@ assert_eq!(s, "<doc>\u{1E0D}\u{0307}</doc>");
     ///
     /// If you need to generate multiple HTML 5 serializations, it's slightly
     /// more efficient not to re-create this each time.
-    pub fn html5(&mut self) -> Html5 {
+    pub fn html5(&mut self) -> Html5<'_> {
         Html5::new(self)
     }
 
@@ -348,7 +532,7 @@ assert_eq!(s, "<doc>\u{1E0D}\u{0307}</doc>");
     /// using Xot you can guarantee that the XML is well-formed, entities and
     /// namespaces have been expanded, and you have access to Xot names using
     /// familiar Xot APIs.
-    pub fn outputs(&self, node: Node) -> impl Iterator<Item = (Node, Output)> {
+    pub fn outputs(&self, node: Node) -> impl Iterator<Item = (Node, Output<'_>)> {
         gen_outputs(self, node)
     }
 
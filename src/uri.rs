@@ -0,0 +1,299 @@
+// URI reference resolution, as described in RFC 3986, section 5.2. This is
+// used to resolve `xml:base`. The URIs aren't validated, and aren't
+// normalized beyond what resolution requires.
+
+// the components of a URI reference
+struct Components<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl<'a> Components<'a> {
+    // this splits like the regular expression in RFC 3986, appendix B
+    fn parse(uri: &'a str) -> Self {
+        let (rest, fragment) = match uri.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment)),
+            None => (uri, None),
+        };
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+        let (scheme, rest) = match rest.find([':', '/']) {
+            Some(index) if index > 0 && rest[index..].starts_with(':') => {
+                (Some(&rest[..index]), &rest[index + 1..])
+            }
+            _ => (None, rest),
+        };
+        let (authority, path) = match rest.strip_prefix("//") {
+            Some(rest) => {
+                let index = rest.find('/').unwrap_or(rest.len());
+                (Some(&rest[..index]), &rest[index..])
+            }
+            None => (None, rest),
+        };
+        Components {
+            scheme,
+            authority,
+            path,
+            query,
+            fragment,
+        }
+    }
+}
+
+// resolve a URI reference against a base URI
+pub(crate) fn resolve(base: &str, reference: &str) -> String {
+    let base = Components::parse(base);
+    let reference = Components::parse(reference);
+    let (scheme, authority, path, query) = if reference.scheme.is_some() {
+        (
+            reference.scheme,
+            reference.authority,
+            remove_dot_segments(reference.path),
+            reference.query,
+        )
+    } else if reference.authority.is_some() {
+        (
+            base.scheme,
+            reference.authority,
+            remove_dot_segments(reference.path),
+            reference.query,
+        )
+    } else if reference.path.is_empty() {
+        (
+            base.scheme,
+            base.authority,
+            base.path.to_string(),
+            reference.query.or(base.query),
+        )
+    } else if reference.path.starts_with('/') {
+        (
+            base.scheme,
+            base.authority,
+            remove_dot_segments(reference.path),
+            reference.query,
+        )
+    } else {
+        (
+            base.scheme,
+            base.authority,
+            remove_dot_segments(&merge(&base, reference.path)),
+            reference.query,
+        )
+    };
+
+    let mut result = String::new();
+    if let Some(scheme) = scheme {
+        result.push_str(scheme);
+        result.push(':');
+    }
+    if let Some(authority) = authority {
+        result.push_str("//");
+        result.push_str(authority);
+    }
+    result.push_str(&path);
+    if let Some(query) = query {
+        result.push('?');
+        result.push_str(query);
+    }
+    if let Some(fragment) = reference.fragment {
+        result.push('#');
+        result.push_str(fragment);
+    }
+    result
+}
+
+// a reference to a target URI, relative to a base URI where possible, such
+// that resolving it against the base gives the target again
+pub(crate) fn relative(base: &str, target: &str) -> String {
+    let base_components = Components::parse(base);
+    let target_components = Components::parse(target);
+    if base_components.scheme != target_components.scheme
+        || base_components.authority != target_components.authority
+        || base_components.path.starts_with('/') != target_components.path.starts_with('/')
+        || base_components.path.contains("..")
+    {
+        return target.to_string();
+    }
+    let mut base_segments = base_components.path.split('/').collect::<Vec<_>>();
+    // the last segment of the base is not a directory
+    base_segments.pop();
+    let target_segments = target_components.path.split('/').collect::<Vec<_>>();
+    let (target_directories, _) = target_segments.split_at(target_segments.len() - 1);
+    let common = base_segments
+        .iter()
+        .zip(target_directories)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut path = "../".repeat(base_segments.len() - common);
+    path.push_str(&target_segments[common..].join("/"));
+    // make sure the path isn't taken to be empty or to have a scheme
+    if path.is_empty() || (path.split('/').next().unwrap_or("").contains(':')) {
+        path.insert_str(0, "./");
+    }
+    let mut result = path;
+    if let Some(query) = target_components.query {
+        result.push('?');
+        result.push_str(query);
+    }
+    if let Some(fragment) = target_components.fragment {
+        result.push('#');
+        result.push_str(fragment);
+    }
+    result
+}
+
+// merge a relative path with the path of the base, RFC 3986 section 5.2.3
+fn merge(base: &Components, path: &str) -> String {
+    if base.authority.is_some() && base.path.is_empty() {
+        format!("/{}", path)
+    } else {
+        match base.path.rfind('/') {
+            Some(index) => format!("{}{}", &base.path[..=index], path),
+            None => path.to_string(),
+        }
+    }
+}
+
+// RFC 3986 section 5.2.4
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output = String::new();
+    // removes the last segment and its preceding slash, if any
+    let pop = |output: &mut String| {
+        let index = output.rfind('/').unwrap_or(0);
+        output.truncate(index);
+    };
+    while !input.is_empty() {
+        if let Some(rest) = input.strip_prefix("../") {
+            input = rest;
+        } else if let Some(rest) = input.strip_prefix("./") {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") {
+            input = &input[3..];
+            pop(&mut output);
+        } else if input == "/.." {
+            input = "/";
+            pop(&mut output);
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            // move the first segment, with its leading slash if any
+            let start = usize::from(input.starts_with('/'));
+            let end = input[start..].find('/').map_or(input.len(), |i| i + start);
+            output.push_str(&input[..end]);
+            input = &input[end..];
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "http://a/b/c/d;p?q";
+
+    #[test]
+    fn test_resolve_normal() {
+        // RFC 3986 section 5.4.1
+        let examples = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            ("g?y#s", "http://a/b/c/g?y#s"),
+            (";x", "http://a/b/c/;x"),
+            ("g;x", "http://a/b/c/g;x"),
+            ("g;x?y#s", "http://a/b/c/g;x?y#s"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../", "http://a/"),
+            ("../../g", "http://a/g"),
+        ];
+        for (reference, expected) in examples {
+            assert_eq!(resolve(BASE, reference), expected, "{}", reference);
+        }
+    }
+
+    #[test]
+    fn test_resolve_abnormal() {
+        // RFC 3986 section 5.4.2
+        let examples = [
+            ("../../../g", "http://a/g"),
+            ("../../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            (".g", "http://a/b/c/.g"),
+            ("g..", "http://a/b/c/g.."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("./g/.", "http://a/b/c/g/"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/./y", "http://a/b/c/g;x=1/y"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+            ("g?y/./x", "http://a/b/c/g?y/./x"),
+            ("g?y/../x", "http://a/b/c/g?y/../x"),
+            ("g#s/./x", "http://a/b/c/g#s/./x"),
+            ("g#s/../x", "http://a/b/c/g#s/../x"),
+            ("http:g", "http:g"),
+        ];
+        for (reference, expected) in examples {
+            assert_eq!(resolve(BASE, reference), expected, "{}", reference);
+        }
+    }
+
+    #[test]
+    fn test_resolve_relative_base() {
+        assert_eq!(resolve("docs/a.xml", "b.xml"), "docs/b.xml");
+        assert_eq!(resolve("a.xml", "b.xml"), "b.xml");
+        assert_eq!(
+            resolve("file:///tmp/a.xml", "b/c.xml"),
+            "file:///tmp/b/c.xml"
+        );
+    }
+
+    #[test]
+    fn test_relative() {
+        let examples = [
+            ("http://a/b/c/d", "http://a/b/c/e", "e"),
+            ("http://a/b/c/d", "http://a/b/e/f", "../e/f"),
+            ("http://a/b/c/d", "http://a/x", "../../x"),
+            ("http://a/b/c/d", "http://x/b/c/e", "http://x/b/c/e"),
+            ("http://a/b/c/d", "ftp://a/b/c/e", "ftp://a/b/c/e"),
+            ("http://a/b/c/d", "http://a/b/c/", "./"),
+            ("http://a/b/c/d", "http://a/b/c/e?q#f", "e?q#f"),
+            ("docs/a.xml", "docs/parts/b.xml", "parts/b.xml"),
+            ("a.xml", "b.xml", "b.xml"),
+            ("docs/a.xml", "x:y", "x:y"),
+            ("docs/a.xml", "docs/x:y", "./x:y"),
+        ];
+        for (base, target, expected) in examples {
+            let reference = relative(base, target);
+            assert_eq!(reference, expected, "{} {}", base, target);
+            assert_eq!(resolve(base, &reference), target, "{} {}", base, target);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// The character classes of XML, shared by the parser and the serializer,
+// and validation of values for the `try_` constructors and setters, so that
+// a tree built in code serializes to XML that can be parsed again. The
+// validation follows XML 1.0 (fifth edition) and Namespaces in XML 1.0.
+
+use xmlparser::XmlCharExt;
+
+use crate::error::Error;
+use crate::xmlvalue::XmlVersion;
+
+pub(crate) const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+pub(crate) const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+// https://www.w3.org/TR/xml/#NT-Char
+// https://www.w3.org/TR/xml11/#NT-Char. XML 1.1 adds the control
+// characters other than NUL.
+pub(crate) fn is_char(c: char, version: XmlVersion) -> bool {
+    match version {
+        XmlVersion::V1_0 => c.is_xml_char(),
+        XmlVersion::V1_1 => !matches!(c, '\u{0}' | '\u{FFFE}' | '\u{FFFF}'),
+    }
+}
+
+// In XML 1.1, these characters must be serialized as character references.
+// NEL and LINE SEPARATOR are not restricted, but would be normalized to a
+// newline when parsed again.
+// https://www.w3.org/TR/xml11/#NT-RestrictedChar
+pub(crate) fn is_restricted_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1}'..='\u{8}'
+            | '\u{B}'..='\u{C}'
+            | '\u{E}'..='\u{1F}'
+            | '\u{7F}'..='\u{9F}'
+            | '\u{2028}'
+    )
+}
+
+// https://www.w3.org/TR/xml-names/#NT-NCName
+pub(crate) fn is_ncname(s: &str) -> bool {
+    let mut chars = s.chars();
+    // the name characters of xmlparser include ':', which an NCName can't
+    chars
+        .next()
+        .is_some_and(|c| c != ':' && c.is_xml_name_start())
+        && chars.all(|c| c != ':' && c.is_xml_name())
+}
+
+pub(crate) fn check_ncname(s: &str) -> Result<(), Error> {
+    if is_ncname(s) {
+        Ok(())
+    } else {
+        Err(Error::InvalidName(s.to_string()))
+    }
+}
+
+pub(crate) fn check_chars(s: &str) -> Result<(), Error> {
+    match s.chars().find(|c| !is_char(*c, XmlVersion::V1_0)) {
+        Some(c) => Err(Error::InvalidCharacter(c)),
+        None => Ok(()),
+    }
+}
+
+// a namespace is a URI. the empty namespace means no namespace, and the
+// XML and xmlns namespaces are reserved.
+// https://www.w3.org/TR/xml-names/#xmlReserved
+pub(crate) fn check_namespace(s: &str) -> Result<(), Error> {
+    if s.is_empty() || s == XML_NAMESPACE || s == XMLNS_NAMESPACE {
+        return Err(Error::ReservedNamespace(s.to_string()));
+    }
+    check_chars(s)
+}
+
+// a comment cannot contain `--`, and it cannot end with `-` as it would be
+// followed by `-->`
+pub(crate) fn check_comment(s: &str) -> Result<(), Error> {
+    if s.contains("--") || s.ends_with('-') {
+        return Err(Error::InvalidComment(s.to_string()));
+    }
+    check_chars(s)
+}
+
+// a processing instruction target is a name other than `xml` in any case
+pub(crate) fn check_processing_instruction_target(s: &str) -> Result<(), Error> {
+    if s.eq_ignore_ascii_case("xml") {
+        return Err(Error::InvalidTarget(s.to_string()));
+    }
+    check_ncname(s)
+}
+
+// processing instruction data cannot contain `?>`, as that ends it
+pub(crate) fn check_processing_instruction_data(s: &str) -> Result<(), Error> {
+    if s.contains("?>") {
+        return Err(Error::InvalidProcessingInstruction(s.to_string()));
+    }
+    check_chars(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_ncname() {
+        for name in ["a", "_a", "a-b.c1", "é", "a\u{B7}b", "\u{10000}"] {
+            assert!(is_ncname(name), "{:?}", name);
+        }
+        for name in [
+            "", "1a", "-a", ".a", "a b", "a:b", ":a", "a\u{0}", "\u{B7}a",
+        ] {
+            assert!(!is_ncname(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_check_chars() {
+        assert!(check_chars("a\tb\r\n\u{FFFD}\u{10FFFF}").is_ok());
+        assert!(matches!(
+            check_chars("a\u{0}b"),
+            Err(Error::InvalidCharacter('\u{0}'))
+        ));
+        assert!(matches!(
+            check_chars("\u{1B}"),
+            Err(Error::InvalidCharacter('\u{1B}'))
+        ));
+        assert!(matches!(
+            check_chars("\u{FFFE}"),
+            Err(Error::InvalidCharacter('\u{FFFE}'))
+        ));
+    }
+
+    #[test]
+    fn test_is_char() {
+        assert!(is_char('\u{1}', XmlVersion::V1_1));
+        assert!(!is_char('\u{1}', XmlVersion::V1_0));
+        for version in [XmlVersion::V1_0, XmlVersion::V1_1] {
+            assert!(is_char('\u{85}', version));
+            assert!(!is_char('\u{0}', version));
+            assert!(!is_char('\u{FFFF}', version));
+        }
+    }
+
+    #[test]
+    fn test_check_namespace() {
+        assert!(check_namespace("http://example.com").is_ok());
+        for namespace in ["", XML_NAMESPACE, XMLNS_NAMESPACE] {
+            assert!(matches!(
+                check_namespace(namespace),
+                Err(Error::ReservedNamespace(_))
+            ));
+        }
+        assert!(matches!(
+            check_namespace("urn:\u{0}"),
+            Err(Error::InvalidCharacter('\u{0}'))
+        ));
+    }
+
+    #[test]
+    fn test_check_comment() {
+        assert!(check_comment(" a - b ").is_ok());
+        assert!(check_comment("a--b").is_err());
+        assert!(check_comment("a-").is_err());
+        assert!(check_comment("a\u{0}").is_err());
+    }
+
+    #[test]
+    fn test_check_processing_instruction() {
+        assert!(check_processing_instruction_target("php").is_ok());
+        assert!(matches!(
+            check_processing_instruction_target("XmL"),
+            Err(Error::InvalidTarget(_))
+        ));
+        assert!(matches!(
+            check_processing_instruction_target("a b"),
+            Err(Error::InvalidName(_))
+        ));
+        assert!(check_processing_instruction_data("a ? > b").is_ok());
+        assert!(matches!(
+            check_processing_instruction_data("a ?> b"),
+            Err(Error::InvalidProcessingInstruction(_))
+        ));
+    }
+}
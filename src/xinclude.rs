@@ -0,0 +1,599 @@
+//! XInclude processing.
+//!
+//! [XInclude](https://www.w3.org/TR/xinclude/) lets a document include
+//! other documents, or parts of them, using `xi:include` elements in the
+//! `http://www.w3.org/2001/XInclude` namespace. [`process`] replaces these
+//! elements in a tree with the content they refer to.
+//!
+//! Resources are loaded through a [`Resolver`], so you're in control of
+//! where they come from: [`FileResolver`] reads them from the filesystem,
+//! and [`MemoryResolver`] serves them from memory. A relative `href` is
+//! resolved against the base URI of the `xi:include` element (see
+//! [`Xot::base_uri`]), so set the URI of the document with
+//! [`Xot::set_document_uri`] to resolve it against the location of the
+//! document.
+//!
+//! What is supported:
+//!
+//! - `parse="xml"` (the default) and `parse="text"`, which takes the
+//!   `encoding` attribute into account.
+//!
+//! - `xpointer` with a shorthand pointer (an id) or the `element()` scheme.
+//!   Other schemes are skipped. Without `href`, the `xpointer` points into
+//!   the including document itself.
+//!
+//! - `xi:fallback`, which is used instead when a resource cannot be loaded
+//!   or parsed, or when the `xpointer` doesn't identify an element.
+//!
+//! Included documents are processed for XInclude too, and including a
+//! resource that's already being included is an [`Error::IncludeLoop`].
+//! Included elements get an `xml:base` attribute if their base URI differs
+//! from the one of the parent they're included into, so that relative
+//! references in them keep working.
+//!
+//! ```rust
+//! use xot::Xot;
+//! use xot::xinclude::{self, MemoryResolver};
+//!
+//! let mut resolver = MemoryResolver::new();
+//! resolver.add("http://example.com/chapter.xml", "<chapter>Chapter</chapter>");
+//! resolver.add("http://example.com/note.txt", "A & B");
+//!
+//! let mut xot = Xot::new();
+//! let root = xot.parse(concat!(
+//!     r#"<book xmlns:xi="http://www.w3.org/2001/XInclude">"#,
+//!     r#"<xi:include href="chapter.xml"/>"#,
+//!     r#"<note><xi:include href="note.txt" parse="text"/></note>"#,
+//!     r#"<xi:include href="missing.xml"><xi:fallback><missing/></xi:fallback></xi:include>"#,
+//!     r#"</book>"#,
+//! ))?;
+//! xot.set_document_uri(root, Some("http://example.com/book.xml"))?;
+//!
+//! xinclude::process(&mut xot, root, &mut resolver)?;
+//! assert_eq!(
+//!     xot.to_string(root)?,
+//!     concat!(
+//!         r#"<book xmlns:xi="http://www.w3.org/2001/XInclude">"#,
+//!         r#"<chapter xml:base="chapter.xml">Chapter</chapter>"#,
+//!         r#"<note>A &amp; B</note>"#,
+//!         r#"<missing/>"#,
+//!         r#"</book>"#,
+//!     )
+//! );
+//! # Ok::<(), xot::Error>(())
+//! ```
+use ahash::HashMap;
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::error::Error;
+use crate::id::NameId;
+use crate::uri;
+use crate::xotdata::{Node, Xot};
+
+/// The XInclude namespace.
+pub const NAMESPACE: &str = "http://www.w3.org/2001/XInclude";
+
+/// Loads the resources that are included.
+///
+/// This is implemented for closures too, so you can pass a
+/// `|uri: &str| -> Result<Vec<u8>, Error>` to [`process`].
+pub trait Resolver {
+    /// Load the resource with the given URI.
+    ///
+    /// The URI is the `href` of an `xi:include` element, resolved against
+    /// its base URI. It's relative if there is no base URI.
+    ///
+    /// When this returns an error, the `xi:fallback` of the include element
+    /// is used instead. If there isn't any, the error is returned by
+    /// [`process`].
+    fn load(&mut self, uri: &str) -> Result<Vec<u8>, Error>;
+}
+
+impl<F> Resolver for F
+where
+    F: FnMut(&str) -> Result<Vec<u8>, Error>,
+{
+    fn load(&mut self, uri: &str) -> Result<Vec<u8>, Error> {
+        self(uri)
+    }
+}
+
+/// A resolver that reads resources from the filesystem.
+///
+/// URIs are taken to be file paths, without any `file://` prefix. They
+/// aren't percent-decoded.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileResolver;
+
+impl Resolver for FileResolver {
+    fn load(&mut self, uri: &str) -> Result<Vec<u8>, Error> {
+        let path = uri.strip_prefix("file://").unwrap_or(uri);
+        Ok(std::fs::read(path)?)
+    }
+}
+
+/// A resolver that serves resources from memory.
+///
+/// Loading a URI that wasn't added is an [`Error::ResourceNotFound`].
+#[derive(Debug, Default, Clone)]
+pub struct MemoryResolver {
+    resources: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryResolver {
+    /// Create a resolver without resources.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a resource under a URI.
+    ///
+    /// This replaces any resource that was added under the same URI before.
+    pub fn add(&mut self, uri: impl Into<String>, content: impl Into<Vec<u8>>) {
+        self.resources.insert(uri.into(), content.into());
+    }
+}
+
+impl Resolver for MemoryResolver {
+    fn load(&mut self, uri: &str) -> Result<Vec<u8>, Error> {
+        self.resources
+            .get(uri)
+            .cloned()
+            .ok_or_else(|| Error::ResourceNotFound(uri.to_string()))
+    }
+}
+
+/// Process the `xi:include` elements in a node and its descendants.
+///
+/// Each include element is replaced by the content it refers to, or by the
+/// content of its `xi:fallback` if that content cannot be loaded. The
+/// `xi:include` elements in the fallback content are processed as well.
+///
+/// An element included from the same document is a copy, so its `xml:id`
+/// attributes, and those of its descendants, are left out: the ids belong to
+/// the original. Ids in content included from other documents are kept, so
+/// these can still clash.
+///
+/// When an error is returned, the include elements that were processed
+/// before it have already been replaced.
+pub fn process(xot: &mut Xot, node: Node, resolver: &mut impl Resolver) -> Result<(), Error> {
+    let names = Names::new(xot);
+    let location = document_location(xot, xot.root(node));
+    let mut processor = Processor {
+        resolver,
+        names,
+        stack: vec![(location, None)],
+    };
+    processor.process_tree(xot, node)
+}
+
+struct Names {
+    include: NameId,
+    fallback: NameId,
+    href: NameId,
+    parse: NameId,
+    xpointer: NameId,
+    encoding: NameId,
+}
+
+impl Names {
+    fn new(xot: &mut Xot) -> Self {
+        let namespace = xot.add_namespace(NAMESPACE);
+        Self {
+            include: xot.add_name_ns("include", namespace),
+            fallback: xot.add_name_ns("fallback", namespace),
+            href: xot.add_name("href"),
+            parse: xot.add_name("parse"),
+            xpointer: xot.add_name("xpointer"),
+            encoding: xot.add_name("encoding"),
+        }
+    }
+}
+
+// an error while including a resource. the fallback is used for resource
+// errors, but fatal errors are always returned.
+enum Failure {
+    Resource(Error),
+    Fatal(Error),
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Self {
+        Failure::Fatal(e)
+    }
+}
+
+struct Processor<'a, R: Resolver> {
+    resolver: &'a mut R,
+    names: Names,
+    // the locations and xpointers of the inclusions that are being
+    // processed, so we can detect loops
+    stack: Vec<(String, Option<String>)>,
+}
+
+impl<R: Resolver> Processor<'_, R> {
+    fn process_tree(&mut self, xot: &mut Xot, node: Node) -> Result<(), Error> {
+        // we don't descend into include elements; any include elements in
+        // their fallback are processed only if the fallback is used
+        let mut includes = Vec::new();
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop() {
+            if self.is_element_named(xot, node, self.names.include) {
+                includes.push(node);
+            } else {
+                let children = xot.children(node).collect::<Vec<_>>();
+                pending.extend(children.into_iter().rev());
+            }
+        }
+        for include in includes {
+            self.include(xot, include)?;
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, xot: &mut Xot, include: Node) -> Result<(), Error> {
+        let attribute = |name| xot.get_attribute(include, name).map(str::to_string);
+        let href = attribute(self.names.href).unwrap_or_default();
+        let parse = attribute(self.names.parse);
+        let xpointer = attribute(self.names.xpointer);
+        let encoding = attribute(self.names.encoding);
+
+        let text = match parse.as_deref() {
+            None | Some("xml") => false,
+            Some("text") => true,
+            Some(parse) => {
+                return Err(Error::InvalidInclude(format!(
+                    "unknown parse value: {}",
+                    parse
+                )))
+            }
+        };
+        if href.is_empty() && xpointer.is_none() {
+            return Err(Error::InvalidInclude(
+                "href or xpointer is required".to_string(),
+            ));
+        }
+        if text && xpointer.is_some() {
+            return Err(Error::InvalidInclude(
+                "xpointer is not allowed with parse=\"text\"".to_string(),
+            ));
+        }
+        if href.contains('#') {
+            return Err(Error::InvalidInclude(format!(
+                "href has a fragment identifier: {}",
+                href
+            )));
+        }
+        let fallback = self.fallback(xot, include)?;
+
+        let result = if href.is_empty() {
+            // we checked that there is an xpointer
+            self.include_same_document(xot, include, xpointer.unwrap_or_default())
+        } else {
+            let location = match xot.base_uri(include) {
+                Some(base) => uri::resolve(&base, &href),
+                None => href,
+            };
+            if text {
+                self.include_text(xot, include, &location, encoding.as_deref())
+            } else {
+                self.include_xml(xot, include, location, xpointer)
+            }
+        };
+        match result {
+            Ok(()) => {}
+            Err(Failure::Resource(error)) => match fallback {
+                Some(fallback) => self.use_fallback(xot, include, fallback)?,
+                None => return Err(error),
+            },
+            Err(Failure::Fatal(error)) => return Err(error),
+        }
+        xot.remove(include)
+    }
+
+    fn include_text(
+        &mut self,
+        xot: &mut Xot,
+        include: Node,
+        location: &str,
+        encoding: Option<&str>,
+    ) -> Result<(), Failure> {
+        let bytes = self.resolver.load(location).map_err(Failure::Resource)?;
+        let encoding = match encoding {
+            Some(label) => Encoding::for_label(label.as_bytes())
+                .ok_or_else(|| Failure::Resource(Error::UnsupportedEncoding(label.to_string())))?,
+            None => UTF_8,
+        };
+        // this uses a byte order mark if there is one
+        let (text, _, _) = encoding.decode(&bytes);
+        if !text.is_empty() {
+            let text = xot.new_text(&text);
+            xot.insert_before(include, text)?;
+        }
+        Ok(())
+    }
+
+    fn include_xml(
+        &mut self,
+        xot: &mut Xot,
+        include: Node,
+        location: String,
+        xpointer: Option<String>,
+    ) -> Result<(), Failure> {
+        let key = (location, xpointer);
+        if self.stack.contains(&key) {
+            return Err(Failure::Fatal(Error::IncludeLoop(key.0)));
+        }
+        let bytes = self.resolver.load(&key.0).map_err(Failure::Resource)?;
+        let document = xot
+            .parse_bytes(&bytes)
+            .map_err(|e| Failure::Resource(e.into()))?;
+        xot.set_document_uri(document, Some(&key.0))?;
+
+        // the included document is processed before we select from it
+        self.stack.push(key);
+        let result = self.process_tree(xot, document).map_err(Failure::Fatal);
+        let (_, xpointer) = self.stack.pop().unwrap();
+        let result = result.and_then(|()| match xpointer {
+            Some(xpointer) => {
+                let element = select(xot, document, &xpointer).map_err(Failure::Resource)?;
+                let copy = self.copy(xot, include, element);
+                xot.insert_before(include, copy)?;
+                Ok(())
+            }
+            None => {
+                for child in xot.children(document).collect::<Vec<_>>() {
+                    self.fix_base(xot, include, child, child);
+                    xot.insert_before(include, child)?;
+                }
+                Ok(())
+            }
+        });
+        xot.remove(document)?;
+        result
+    }
+
+    fn include_same_document(
+        &mut self,
+        xot: &mut Xot,
+        include: Node,
+        xpointer: String,
+    ) -> Result<(), Failure> {
+        let root = xot.root(include);
+        let key = (document_location(xot, root), Some(xpointer));
+        if self.stack.contains(&key) {
+            return Err(Failure::Fatal(Error::IncludeLoop(key.0)));
+        }
+        let element = select(xot, root, key.1.as_deref().unwrap()).map_err(Failure::Resource)?;
+        // the copy is processed in place, so that includes in it that point
+        // into this document work
+        let copy = self.copy(xot, include, element);
+        // the copy would repeat the ids of the original, and a document
+        // with duplicate ids can't be parsed again, so they're left out
+        let xml_id = xot.xml_id_name();
+        for node in xot.descendants(copy).collect::<Vec<_>>() {
+            if xot.is_element(node) {
+                xot.remove_attribute(node, xml_id);
+            }
+        }
+        xot.insert_before(include, copy)?;
+        self.stack.push(key);
+        let result = self.process_tree(xot, copy);
+        self.stack.pop();
+        Ok(result?)
+    }
+
+    fn use_fallback(&mut self, xot: &mut Xot, include: Node, fallback: Node) -> Result<(), Error> {
+        for child in xot.children(fallback).collect::<Vec<_>>() {
+            xot.insert_before(include, child)?;
+            if xot.is_element(child) {
+                self.process_tree(xot, child)?;
+            }
+        }
+        Ok(())
+    }
+
+    // copy an element to include, with the namespace declarations it needs
+    fn copy(&mut self, xot: &mut Xot, include: Node, element: Node) -> Node {
+        let copy = xot.clone_with_prefixes(element);
+        self.fix_base(xot, include, element, copy);
+        copy
+    }
+
+    // give an included element an xml:base if its base URI differs from
+    // the one it would get where it's included
+    fn fix_base(&self, xot: &mut Xot, include: Node, source: Node, target: Node) {
+        if !xot.is_element(target) {
+            return;
+        }
+        let Some(base) = xot.base_uri(source) else {
+            return;
+        };
+        let parent_base = xot.parent(include).and_then(|parent| xot.base_uri(parent));
+        if parent_base.as_deref() == Some(base.as_str()) {
+            return;
+        }
+        let value = match parent_base {
+            Some(parent_base) => uri::relative(&parent_base, &base),
+            None => base,
+        };
+        let xml_base = xot.xml_base_name();
+        xot.set_attribute(target, xml_base, value);
+    }
+
+    // the xi:fallback child of an include element, if any
+    fn fallback(&self, xot: &Xot, include: Node) -> Result<Option<Node>, Error> {
+        let mut fallbacks = xot
+            .children(include)
+            .filter(|child| self.is_element_named(xot, *child, self.names.fallback));
+        let fallback = fallbacks.next();
+        if fallbacks.next().is_some() {
+            return Err(Error::InvalidInclude(
+                "more than one xi:fallback".to_string(),
+            ));
+        }
+        Ok(fallback)
+    }
+
+    fn is_element_named(&self, xot: &Xot, node: Node, name: NameId) -> bool {
+        xot.element(node)
+            .is_some_and(|element| element.name() == name)
+    }
+}
+
+// the URI of the document a node is in, or the empty string if there is
+// none
+fn document_location(xot: &Xot, root: Node) -> String {
+    if xot.is_document(root) {
+        xot.document_uri(root).unwrap_or_default().to_string()
+    } else {
+        String::new()
+    }
+}
+
+// a part of an xpointer that we understand
+enum Pointer {
+    // a shorthand pointer
+    Id(String),
+    // the element() scheme, with an optional id and a child sequence
+    Element(Option<String>, Vec<usize>),
+}
+
+impl Pointer {
+    fn select(&self, xot: &Xot, root: Node) -> Option<Node> {
+        match self {
+            Pointer::Id(id) => find_id(xot, root, id),
+            Pointer::Element(id, steps) => {
+                let mut node = match id {
+                    Some(id) => find_id(xot, root, id)?,
+                    None => root,
+                };
+                for step in steps {
+                    node = xot
+                        .children(node)
+                        .filter(|child| xot.is_element(*child))
+                        .nth(step - 1)?;
+                }
+                xot.is_element(node).then_some(node)
+            }
+        }
+    }
+}
+
+fn find_id(xot: &Xot, root: Node, id: &str) -> Option<Node> {
+    if xot.is_document(root) {
+        xot.xml_id_node(root, id)
+    } else {
+        None
+    }
+}
+
+// select the element an xpointer identifies; the first pointer part that
+// identifies one wins
+fn select(xot: &Xot, root: Node, xpointer: &str) -> Result<Node, Error> {
+    parse_xpointer(xpointer)
+        .and_then(|pointers| {
+            pointers
+                .iter()
+                .find_map(|pointer| pointer.select(xot, root))
+        })
+        .ok_or_else(|| Error::XPointer(xpointer.to_string()))
+}
+
+fn parse_xpointer(xpointer: &str) -> Option<Vec<Pointer>> {
+    let xpointer = xpointer.trim();
+    if !xpointer.contains('(') {
+        if xpointer.is_empty() || xpointer.contains(char::is_whitespace) {
+            return None;
+        }
+        return Some(vec![Pointer::Id(xpointer.to_string())]);
+    }
+    let mut pointers = Vec::new();
+    let mut rest = xpointer;
+    while !rest.is_empty() {
+        let open = rest.find('(')?;
+        let scheme = rest[..open].trim();
+        let (data, after) = scheme_data(&rest[open + 1..])?;
+        // schemes other than element() are skipped
+        if scheme == "element" {
+            pointers.push(parse_element_scheme(&data)?);
+        }
+        rest = after.trim_start();
+    }
+    Some(pointers)
+}
+
+// the data of a pointer part up to its closing parenthesis, with escapes
+// removed, and the text after it
+fn scheme_data(s: &str) -> Option<(String, &str)> {
+    let mut data = String::new();
+    let mut depth = 0;
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '^' => {
+                let (_, escaped) = chars.next()?;
+                if !matches!(escaped, '^' | '(' | ')') {
+                    return None;
+                }
+                data.push(escaped);
+            }
+            '(' => {
+                depth += 1;
+                data.push(c);
+            }
+            ')' if depth == 0 => return Some((data, &s[i + 1..])),
+            ')' => {
+                depth -= 1;
+                data.push(c);
+            }
+            _ => data.push(c),
+        }
+    }
+    None
+}
+
+// the data of element(), such as `intro/2/1` or `/1/3`
+fn parse_element_scheme(data: &str) -> Option<Pointer> {
+    let mut parts = data.split('/');
+    let id = parts.next()?;
+    let steps = parts
+        .map(|step| step.parse::<usize>().ok().filter(|step| *step > 0))
+        .collect::<Option<Vec<_>>>()?;
+    if id.is_empty() && steps.is_empty() {
+        return None;
+    }
+    Some(Pointer::Element(
+        (!id.is_empty()).then(|| id.to_string()),
+        steps,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_xpointer() {
+        assert!(matches!(
+            parse_xpointer("intro").as_deref(),
+            Some([Pointer::Id(id)]) if id == "intro"
+        ));
+        assert!(matches!(
+            parse_xpointer("element(intro/2/1)").as_deref(),
+            Some([Pointer::Element(Some(id), steps)]) if id == "intro" && steps == &[2, 1]
+        ));
+        assert!(matches!(
+            parse_xpointer("xmlns(a=http://example.com) element(/1/3)").as_deref(),
+            Some([Pointer::Element(None, steps)]) if steps == &[1, 3]
+        ));
+        assert!(matches!(
+            parse_xpointer("foo(a^)b) element(x)").as_deref(),
+            Some([Pointer::Element(Some(id), steps)]) if id == "x" && steps.is_empty()
+        ));
+        assert!(parse_xpointer("element(/0)").is_none());
+        assert!(parse_xpointer("element(/a)").is_none());
+        assert!(parse_xpointer("element(/1").is_none());
+        assert!(parse_xpointer("a b").is_none());
+    }
+}
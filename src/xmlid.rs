@@ -0,0 +1,197 @@
+// The id index of a document. This is used to look up elements by their
+// xml:id (or DTD-declared ID attribute) value, and is kept in sync as the
+// tree is manipulated.
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use indextree::NodeId;
+
+use crate::id::{IdRemapping, NameId};
+use crate::xmlvalue::Value;
+use crate::xotdata::{Node, Xot};
+
+#[derive(Debug, Clone)]
+pub(crate) struct XmlIds {
+    // the elements that have a particular id value. Normally there's only
+    // one, but edits can introduce duplicates.
+    ids: HashMap<String, Vec<NodeId>>,
+    // (element name, attribute name) pairs declared to be of type ID in the
+    // DTD
+    id_attributes: HashSet<(NameId, NameId)>,
+}
+
+impl XmlIds {
+    pub(crate) fn new() -> Self {
+        Self {
+            ids: HashMap::new(),
+            id_attributes: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn get(&self, value: &str) -> Option<NodeId> {
+        self.ids.get(value).and_then(|nodes| nodes.first()).copied()
+    }
+
+    pub(crate) fn contains(&self, value: &str) -> bool {
+        self.ids.contains_key(value)
+    }
+
+    pub(crate) fn insert(&mut self, value: &str, node_id: NodeId) {
+        let nodes = self.ids.entry(value.to_string()).or_default();
+        if !nodes.contains(&node_id) {
+            nodes.push(node_id);
+        }
+    }
+
+    fn remove(&mut self, value: &str, node_id: NodeId) {
+        if let Some(nodes) = self.ids.get_mut(value) {
+            nodes.retain(|n| *n != node_id);
+            if nodes.is_empty() {
+                self.ids.remove(value);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.ids.clear();
+    }
+
+    pub(crate) fn declare_id_attribute(&mut self, element: NameId, attribute: NameId) {
+        self.id_attributes.insert((element, attribute));
+    }
+
+    pub(crate) fn id_attributes(&self) -> impl Iterator<Item = &(NameId, NameId)> {
+        self.id_attributes.iter()
+    }
+
+    // update the declared id attributes after the names were compacted
+    pub(crate) fn remap_id_attributes(&mut self, remapping: &IdRemapping) {
+        self.id_attributes = self
+            .id_attributes
+            .iter()
+            .filter_map(|(element, attribute)| {
+                Some((remapping.name(*element)?, remapping.name(*attribute)?))
+            })
+            .collect();
+    }
+
+    pub(crate) fn duplicates(&self) -> impl Iterator<Item = (&str, &[NodeId])> {
+        self.ids
+            .iter()
+            .filter(|(_, nodes)| nodes.len() > 1)
+            .map(|(value, nodes)| (value.as_str(), nodes.as_slice()))
+    }
+}
+
+impl Xot {
+    // the document node of the tree a node is in, if any
+    fn id_document(&self, node: Node) -> Option<NodeId> {
+        let root = self.root(node);
+        self.is_document(root).then(|| root.get())
+    }
+
+    fn is_id_attribute(&self, document: NodeId, element: Node, attribute: NameId) -> bool {
+        if attribute == self.xml_id_id {
+            return true;
+        }
+        let Some(xml_ids) = self.id_nodes_map.get(&document) else {
+            return false;
+        };
+        if xml_ids.id_attributes.is_empty() {
+            return false;
+        }
+        match self.value(element) {
+            Value::Element(element) => xml_ids.id_attributes.contains(&(element.name(), attribute)),
+            _ => false,
+        }
+    }
+
+    // add or remove the id of an attribute node to the index of its document
+    fn update_attribute_id(&mut self, attribute_node: Node, add: bool) {
+        let Some(element) = self.parent(attribute_node) else {
+            return;
+        };
+        let Some(document) = self.id_document(element) else {
+            return;
+        };
+        let Value::Attribute(attribute) = self.value(attribute_node) else {
+            return;
+        };
+        if !self.is_id_attribute(document, element, attribute.name()) {
+            return;
+        }
+        let value = attribute.value().to_string();
+        let xml_ids = self
+            .id_nodes_map
+            .entry(document)
+            .or_insert_with(XmlIds::new);
+        if add {
+            xml_ids.insert(&value, element.get());
+        } else {
+            xml_ids.remove(&value, element.get());
+        }
+    }
+
+    fn update_ids(&mut self, node: Node, add: bool) {
+        if self.id_document(node).is_none() {
+            return;
+        }
+        let attribute_nodes = self
+            .all_descendants(node)
+            .filter(|n| self.is_attribute_node(*n))
+            .collect::<Vec<_>>();
+        for attribute_node in attribute_nodes {
+            self.update_attribute_id(attribute_node, add);
+        }
+    }
+
+    // index the ids of a node and its descendants, if it's in a document.
+    // this should be called after the node is attached.
+    pub(crate) fn index_ids(&mut self, node: Node) {
+        self.update_ids(node, true)
+    }
+
+    // remove the ids of a node and its descendants from the index, if it's in
+    // a document. this should be called before the node is detached.
+    pub(crate) fn unindex_ids(&mut self, node: Node) {
+        self.update_ids(node, false)
+    }
+
+    // remove the ids of the attributes of an element from the index, but not
+    // those of its descendants
+    pub(crate) fn unindex_attribute_ids(&mut self, element: Node) {
+        for attribute_node in self.attributes(element).nodes().collect::<Vec<_>>() {
+            self.update_attribute_id(attribute_node, false);
+        }
+    }
+
+    pub(crate) fn index_attribute_ids(&mut self, element: Node) {
+        for attribute_node in self.attributes(element).nodes().collect::<Vec<_>>() {
+            self.update_attribute_id(attribute_node, true);
+        }
+    }
+
+    pub(crate) fn index_attribute_id(&mut self, attribute_node: Node) {
+        self.update_attribute_id(attribute_node, true)
+    }
+
+    pub(crate) fn unindex_attribute_id(&mut self, attribute_node: Node) {
+        self.update_attribute_id(attribute_node, false)
+    }
+
+    // give a cloned document the same id attributes as the original, and
+    // index it
+    pub(crate) fn clone_ids(&mut self, document_node: Node, cloned_node: Node) {
+        let mut xml_ids = XmlIds::new();
+        if let Some(original) = self.id_nodes_map.get(&document_node.get()) {
+            xml_ids.id_attributes = original.id_attributes.clone();
+        }
+        self.id_nodes_map.insert(cloned_node.get(), xml_ids);
+        self.index_ids(cloned_node);
+    }
+
+    pub(crate) fn reindex_ids(&mut self, document_node: Node) {
+        if let Some(xml_ids) = self.id_nodes_map.get_mut(&document_node.get()) {
+            xml_ids.clear();
+        }
+        self.index_ids(document_node);
+    }
+}
@@ -1,9 +1,11 @@
 use std::fmt::Debug;
+use std::io::Write;
 
 use ahash::AHashMap;
 
 use crate::error::Error;
 use crate::id::{NameId, NamespaceId, PrefixId};
+use crate::validate;
 
 /// The type of the XML node.
 ///
@@ -152,14 +154,47 @@ impl Element {
 /// XML text value.
 ///
 /// Example: `Bar` in `<foo>Bar</foo>`, or `hello` and `world` in `<greeting>hello<sep/>world</greeting>`.
+///
+/// A text value can be marked as a CDATA section, in which case it's
+/// serialized as `<![CDATA[...]]>`. Parsing only produces these if you ask
+/// for it with [`ParseOptions::preserve_cdata`](crate::ParseOptions::preserve_cdata).
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct Text {
     pub(crate) text: String,
+    pub(crate) cdata: bool,
 }
 
 impl Text {
     pub(crate) fn new(text: String) -> Self {
-        Text { text }
+        Text { text, cdata: false }
+    }
+
+    pub(crate) fn new_cdata(text: String) -> Self {
+        Text { text, cdata: true }
+    }
+
+    /// Whether this text is a CDATA section.
+    pub fn is_cdata(&self) -> bool {
+        self.cdata
+    }
+
+    /// Mark this text as a CDATA section, or as ordinary text.
+    ///
+    /// ```rust
+    /// use xot::Xot;
+    ///
+    /// let mut xot = Xot::new();
+    /// let root = xot.parse(r#"<doc>a &amp; b</doc>"#)?;
+    /// let doc_el = xot.document_element(root).unwrap();
+    /// let text_node = xot.first_child(doc_el).unwrap();
+    ///
+    /// xot.text_mut(text_node).unwrap().set_cdata(true);
+    ///
+    /// assert_eq!(xot.to_string(root).unwrap(), r#"<doc><![CDATA[a & b]]></doc>"#);
+    /// # Ok::<(), xot::Error>(())
+    /// ```
+    pub fn set_cdata(&mut self, cdata: bool) {
+        self.cdata = cdata;
     }
 
     /// Get the text value.
@@ -194,6 +229,17 @@ impl Text {
     pub fn set<S: Into<String>>(&mut self, text: S) {
         self.text = text.into();
     }
+
+    /// Set the text value, checking that it's valid.
+    ///
+    /// Like [`Text::set`], but rejects text with characters that aren't
+    /// allowed in XML 1.0 with [`Error::InvalidCharacter`].
+    pub fn try_set<S: Into<String>>(&mut self, text: S) -> Result<(), Error> {
+        let text = text.into();
+        validate::check_chars(&text)?;
+        self.text = text;
+        Ok(())
+    }
 }
 
 /// XML comment.
@@ -216,12 +262,11 @@ impl Comment {
 
     /// Set the comment text.
     ///
-    /// Rejects comments that contain `--` as illegal.
+    /// Rejects comments that contain `--` or end with `-` as illegal, and
+    /// comments with characters that aren't allowed in XML 1.0.
     pub fn set<S: Into<String>>(&mut self, text: S) -> Result<(), Error> {
         let text = text.into();
-        if text.contains("--") {
-            return Err(Error::InvalidComment(text));
-        }
+        validate::check_comment(&text)?;
         self.text = text;
         Ok(())
     }
@@ -259,9 +304,9 @@ impl ProcessingInstruction {
     }
 
     /// Set data.
+    ///
+    /// This doesn't check the data; see [`ProcessingInstruction::try_set_data`].
     pub fn set_data<S: Into<String>>(&mut self, data: Option<S>) {
-        // XXX Ideally check that data follows XML spec, i.e. not contain
-        // "?>".
         if let Some(data) = data {
             let data = data.into();
             if !data.is_empty() {
@@ -271,6 +316,21 @@ impl ProcessingInstruction {
         }
         self.data = None;
     }
+
+    /// Set data, checking that it's valid.
+    ///
+    /// Like [`ProcessingInstruction::set_data`], but rejects data that
+    /// contains `?>` with [`Error::InvalidProcessingInstruction`], and data
+    /// with characters that aren't allowed in XML 1.0 with
+    /// [`Error::InvalidCharacter`].
+    pub fn try_set_data<S: Into<String>>(&mut self, data: Option<S>) -> Result<(), Error> {
+        let data = data.map(Into::into);
+        if let Some(data) = &data {
+            validate::check_processing_instruction_data(data)?;
+        }
+        self.set_data(data);
+        Ok(())
+    }
 }
 
 /// Represents a namespace node.
@@ -319,6 +379,111 @@ impl Attribute {
     pub fn set_value<S: Into<String>>(&mut self, value: S) {
         self.value = value.into();
     }
+
+    /// Set value, checking that it's valid.
+    ///
+    /// Like [`Attribute::set_value`], but rejects values with characters that
+    /// aren't allowed in XML 1.0 with [`Error::InvalidCharacter`].
+    pub fn try_set_value<S: Into<String>>(&mut self, value: S) -> Result<(), Error> {
+        let value = value.into();
+        validate::check_chars(&value)?;
+        self.value = value;
+        Ok(())
+    }
+}
+
+/// The version of XML.
+///
+/// XML 1.1 differs from XML 1.0 in a few ways: control characters can be
+/// written as character references, the NEL (`U+0085`) and LINE SEPARATOR
+/// (`U+2028`) characters are line endings, and namespace prefixes can be
+/// undeclared with `xmlns:prefix=""`. The rules for names are the same as
+/// those of the fifth edition of XML 1.0.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum XmlVersion {
+    /// XML 1.0
+    #[default]
+    V1_0,
+    /// XML 1.1
+    V1_1,
+}
+
+impl XmlVersion {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            XmlVersion::V1_0 => "1.0",
+            XmlVersion::V1_1 => "1.1",
+        }
+    }
+}
+
+/// The document type declaration of a document.
+///
+/// Example: `<!DOCTYPE doc SYSTEM "doc.dtd" [<!ENTITY e "E">]>`.
+///
+/// This is retained when a document is parsed, so that it can be serialized
+/// again. See [`Xot::document_type`](crate::Xot::document_type) and
+/// [`Xot::set_document_type`](crate::Xot::set_document_type).
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DocumentType {
+    /// The name of the document type. This should be the full name (including
+    /// prefix) of the document element.
+    pub name: String,
+    /// The public identifier, if any.
+    pub public_id: Option<String>,
+    /// The system identifier, if any.
+    pub system_id: Option<String>,
+    /// The internal DTD subset, as it appears in the source text (without the
+    /// enclosing `[` and `]`), if any.
+    pub internal_subset: Option<String>,
+}
+
+impl DocumentType {
+    /// Create a new document type declaration with only a name.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            public_id: None,
+            system_id: None,
+            internal_subset: None,
+        }
+    }
+
+    pub(crate) fn serialize(&self, w: &mut impl Write) -> Result<(), std::io::Error> {
+        w.write_all(b"<!DOCTYPE ")?;
+        w.write_all(self.name.as_bytes())?;
+        match (&self.public_id, &self.system_id) {
+            (Some(public_id), system_id) => {
+                w.write_all(b" PUBLIC ")?;
+                write_literal(public_id, w)?;
+                if let Some(system_id) = system_id {
+                    w.write_all(b" ")?;
+                    write_literal(system_id, w)?;
+                }
+            }
+            (None, Some(system_id)) => {
+                w.write_all(b" SYSTEM ")?;
+                write_literal(system_id, w)?;
+            }
+            (None, None) => {}
+        }
+        if let Some(internal_subset) = &self.internal_subset {
+            w.write_all(b" [")?;
+            w.write_all(internal_subset.as_bytes())?;
+            w.write_all(b"]")?;
+        }
+        w.write_all(b">\n")?;
+        Ok(())
+    }
+}
+
+// a literal cannot contain its own quote character, so if there is a double
+// quote in it, it must have been single quoted
+fn write_literal(literal: &str, w: &mut impl Write) -> Result<(), std::io::Error> {
+    let quote: &[u8] = if literal.contains('"') { b"'" } else { b"\"" };
+    w.write_all(quote)?;
+    w.write_all(literal.as_bytes())?;
+    w.write_all(quote)
 }
 
 #[cfg(test)]
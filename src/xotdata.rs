@@ -1,8 +1,12 @@
 use ahash::{HashMap, HashMapExt};
 use indextree::{Arena, NodeId};
 
-use crate::id::{Name, NameId, NameLookup, NamespaceId, NamespaceLookup, PrefixId, PrefixLookup};
-use crate::xmlvalue::Value;
+use crate::id::{
+    Name, NameId, NameLookup, NameTables, NamespaceId, NamespaceLookup, PrefixId, PrefixLookup,
+};
+use crate::lexical::Lexical;
+use crate::xmlid::XmlIds;
+use crate::xmlvalue::{DocumentType, Value};
 
 pub(crate) type XmlArena = Arena<Value>;
 
@@ -49,8 +53,14 @@ impl From<NodeId> for Node {
 #[derive(Debug, Clone)]
 pub struct Xot {
     pub(crate) arena: XmlArena,
-    // a mapping of document node, to hashmap of node value to node with that id
-    pub(crate) id_nodes_map: HashMap<NodeId, HashMap<String, NodeId>>,
+    // a mapping of document node, to the index of elements by id
+    pub(crate) id_nodes_map: HashMap<NodeId, XmlIds>,
+    // a mapping of document node to its document type declaration
+    pub(crate) document_types: HashMap<NodeId, DocumentType>,
+    // a mapping of document node to its lexical detail, if parsed losslessly
+    pub(crate) lexical: HashMap<NodeId, Lexical>,
+    // a mapping of document node to its URI
+    pub(crate) document_uris: HashMap<NodeId, String>,
     pub(crate) namespace_lookup: NamespaceLookup,
     pub(crate) prefix_lookup: PrefixLookup,
     pub(crate) name_lookup: NameLookup,
@@ -60,24 +70,51 @@ pub struct Xot {
     pub(crate) xml_prefix_id: PrefixId,
     pub(crate) xml_space_id: NameId,
     pub(crate) xml_id_id: NameId,
+    pub(crate) xml_base_id: NameId,
     pub(crate) text_consolidation: bool,
 }
 
 impl Xot {
     /// Create a new `Xot` instance.
     pub fn new() -> Self {
-        let mut namespace_lookup = NamespaceLookup::new();
+        Self::with_lookups(
+            NamespaceLookup::new(),
+            PrefixLookup::new(),
+            NameLookup::new(),
+        )
+    }
+
+    /// Create a new `Xot` instance that uses shared name tables.
+    ///
+    /// All Xots created with the same [`NameTables`] share their names,
+    /// namespaces and prefixes, so their [`NameId`], [`NamespaceId`] and
+    /// [`PrefixId`] values can be compared with each other. These Xots may
+    /// live on different threads.
+    pub fn with_name_tables(name_tables: &NameTables) -> Self {
+        let (namespace_lookup, prefix_lookup, name_lookup) = name_tables.lookups();
+        Self::with_lookups(namespace_lookup, prefix_lookup, name_lookup)
+    }
+
+    fn with_lookups(
+        mut namespace_lookup: NamespaceLookup,
+        mut prefix_lookup: PrefixLookup,
+        mut name_lookup: NameLookup,
+    ) -> Self {
+        // with shared tables these may already exist, in which case we get
+        // the same ids as the other Xots
         let no_namespace_id = namespace_lookup.get_id_mut("");
-        let mut prefix_lookup = PrefixLookup::new();
         let empty_prefix_id = prefix_lookup.get_id_mut("");
         let xml_namespace_id = namespace_lookup.get_id_mut("http://www.w3.org/XML/1998/namespace");
         let xml_prefix_id = prefix_lookup.get_id_mut("xml");
-        let mut name_lookup = NameLookup::new();
         let xml_space_id = name_lookup.get_id_mut(&Name::new("space", xml_namespace_id));
         let xml_id_id = name_lookup.get_id_mut(&Name::new("id", xml_namespace_id));
+        let xml_base_id = name_lookup.get_id_mut(&Name::new("base", xml_namespace_id));
         Xot {
             arena: XmlArena::new(),
             id_nodes_map: HashMap::new(),
+            document_types: HashMap::new(),
+            lexical: HashMap::new(),
+            document_uris: HashMap::new(),
             namespace_lookup,
             prefix_lookup,
             name_lookup,
@@ -87,6 +124,7 @@ impl Xot {
             xml_prefix_id,
             xml_space_id,
             xml_id_id,
+            xml_base_id,
             text_consolidation: true,
         }
     }
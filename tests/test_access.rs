@@ -276,3 +276,61 @@ fn test_document_element_for_fragment_without_elements() {
         Err(xot::Error::NoElementAtTopLevel)
     ));
 }
+
+#[test]
+fn test_base_uri() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(r#"<doc xml:base="http://example.com/a/"><p xml:base="../b/c.xml"><?pi?>text</p><q/></doc>"#)
+        .unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let p = xot.first_child(doc).unwrap();
+    let q = xot.next_sibling(p).unwrap();
+    let pi = xot.first_child(p).unwrap();
+    let text = xot.next_sibling(pi).unwrap();
+    let base = |node| xot.base_uri(node);
+    assert_eq!(base(root), None);
+    assert_eq!(base(doc).as_deref(), Some("http://example.com/a/"));
+    assert_eq!(base(p).as_deref(), Some("http://example.com/b/c.xml"));
+    assert_eq!(base(pi).as_deref(), Some("http://example.com/b/c.xml"));
+    assert_eq!(base(text).as_deref(), Some("http://example.com/b/c.xml"));
+    assert_eq!(base(q).as_deref(), Some("http://example.com/a/"));
+}
+
+#[test]
+fn test_base_uri_document_uri() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(r#"<doc><p xml:base="sub/"><a href="x.xml"/></p><b/></doc>"#)
+        .unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let p = xot.first_child(doc).unwrap();
+    let a = xot.first_child(p).unwrap();
+    let b = xot.next_sibling(p).unwrap();
+
+    // without a document URI, the result is relative
+    assert_eq!(xot.base_uri(a).as_deref(), Some("sub/"));
+    assert_eq!(xot.base_uri(b), None);
+
+    xot.set_document_uri(root, Some("file:///data/doc.xml"))
+        .unwrap();
+    assert_eq!(xot.document_uri(root), Some("file:///data/doc.xml"));
+    assert_eq!(xot.base_uri(root).as_deref(), Some("file:///data/doc.xml"));
+    assert_eq!(xot.base_uri(a).as_deref(), Some("file:///data/sub/"));
+    assert_eq!(xot.base_uri(b).as_deref(), Some("file:///data/doc.xml"));
+
+    // after a move, the base URI follows the new ancestors
+    xot.append(b, a).unwrap();
+    assert_eq!(xot.base_uri(a).as_deref(), Some("file:///data/doc.xml"));
+
+    // a clone of the document keeps the URI
+    let cloned = xot.clone_node(root);
+    assert_eq!(xot.document_uri(cloned), Some("file:///data/doc.xml"));
+
+    xot.set_document_uri(root, None).unwrap();
+    assert_eq!(xot.document_uri(root), None);
+    assert!(matches!(
+        xot.set_document_uri(doc, Some("file:///doc.xml")),
+        Err(xot::Error::NotDocument(_))
+    ));
+}
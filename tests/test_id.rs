@@ -93,3 +93,207 @@ fn xml_id_node_none_available() {
 
     assert_eq!(xot.xml_id_node(root, "FOO"), None);
 }
+
+#[test]
+fn xml_id_node_set_attribute() {
+    let mut xot = Xot::new();
+    let id_name = xot.xml_id_name();
+    let root = xot.parse(r#"<doc><a xml:id="FOO"/><b/></doc>"#).unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let a = xot.first_child(doc).unwrap();
+    let b = xot.next_sibling(a).unwrap();
+
+    xot.set_attribute(b, id_name, "BAR");
+    assert_eq!(xot.xml_id_node(root, "BAR"), Some(b));
+
+    // changing the value removes the old value
+    xot.set_attribute(a, id_name, "QUX");
+    assert_eq!(xot.xml_id_node(root, "FOO"), None);
+    assert_eq!(xot.xml_id_node(root, "QUX"), Some(a));
+
+    xot.remove_attribute(a, id_name);
+    assert_eq!(xot.xml_id_node(root, "QUX"), None);
+}
+
+#[test]
+fn xml_id_node_mutable_attributes() {
+    let mut xot = Xot::new();
+    let id_name = xot.xml_id_name();
+    let root = xot.parse(r#"<doc><a xml:id="FOO"/></doc>"#).unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let a = xot.first_child(doc).unwrap();
+
+    xot.attributes_mut(a).insert(id_name, "BAR".to_string());
+    assert_eq!(xot.xml_id_node(root, "FOO"), None);
+    assert_eq!(xot.xml_id_node(root, "BAR"), Some(a));
+
+    xot.attributes_mut(a).clear();
+    assert_eq!(xot.xml_id_node(root, "BAR"), None);
+}
+
+#[test]
+fn xml_id_node_remove() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(r#"<doc><a xml:id="FOO"><b xml:id="BAR"/></a></doc>"#)
+        .unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let a = xot.first_child(doc).unwrap();
+
+    xot.remove(a).unwrap();
+    assert_eq!(xot.xml_id_node(root, "FOO"), None);
+    assert_eq!(xot.xml_id_node(root, "BAR"), None);
+}
+
+#[test]
+fn xml_id_node_detach_and_append() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(r#"<doc><a xml:id="FOO"><b xml:id="BAR"/></a></doc>"#)
+        .unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let a = xot.first_child(doc).unwrap();
+    let b = xot.first_child(a).unwrap();
+
+    xot.detach(a).unwrap();
+    assert_eq!(xot.xml_id_node(root, "FOO"), None);
+    assert_eq!(xot.xml_id_node(root, "BAR"), None);
+
+    xot.append(doc, a).unwrap();
+    assert_eq!(xot.xml_id_node(root, "FOO"), Some(a));
+    assert_eq!(xot.xml_id_node(root, "BAR"), Some(b));
+}
+
+#[test]
+fn xml_id_node_move_between_documents() {
+    let mut xot = Xot::new();
+    let root1 = xot.parse(r#"<doc><a xml:id="FOO"/></doc>"#).unwrap();
+    let root2 = xot.parse(r#"<doc/>"#).unwrap();
+    let doc1 = xot.document_element(root1).unwrap();
+    let doc2 = xot.document_element(root2).unwrap();
+    let a = xot.first_child(doc1).unwrap();
+
+    xot.append(doc2, a).unwrap();
+    assert_eq!(xot.xml_id_node(root1, "FOO"), None);
+    assert_eq!(xot.xml_id_node(root2, "FOO"), Some(a));
+}
+
+#[test]
+fn xml_id_node_new_element() {
+    let mut xot = Xot::new();
+    let id_name = xot.xml_id_name();
+    let a_name = xot.add_name("a");
+    let root = xot.parse(r#"<doc/>"#).unwrap();
+    let doc = xot.document_element(root).unwrap();
+
+    // attributes set on an unattached element are indexed once it's attached
+    let a = xot.new_element(a_name);
+    xot.set_attribute(a, id_name, "FOO");
+    assert_eq!(xot.xml_id_node(root, "FOO"), None);
+    xot.prepend(doc, a).unwrap();
+    assert_eq!(xot.xml_id_node(root, "FOO"), Some(a));
+}
+
+#[test]
+fn xml_id_node_clone_document() {
+    let mut xot = Xot::new();
+    let root = xot.parse(r#"<doc><a xml:id="FOO"/></doc>"#).unwrap();
+    let cloned = xot.clone_node(root);
+    let doc = xot.document_element(cloned).unwrap();
+    let a = xot.first_child(doc).unwrap();
+
+    assert_eq!(xot.xml_id_node(cloned, "FOO"), Some(a));
+}
+
+#[test]
+fn xml_id_node_clone_element() {
+    let mut xot = Xot::new();
+    let root = xot.parse(r#"<doc><a xml:id="FOO"/></doc>"#).unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let a = xot.first_child(doc).unwrap();
+    let cloned = xot.clone_node(a);
+
+    // the clone isn't in the document
+    assert_eq!(xot.xml_id_node(root, "FOO"), Some(a));
+    assert_eq!(xot.duplicate_xml_ids(root), vec![]);
+    // but once it's added, we have a duplicate
+    xot.append(doc, cloned).unwrap();
+    assert_eq!(
+        xot.duplicate_xml_ids(root),
+        vec![("FOO".to_string(), vec![a, cloned])]
+    );
+    // removing the original makes the clone the one found
+    xot.remove(a).unwrap();
+    assert_eq!(xot.xml_id_node(root, "FOO"), Some(cloned));
+    assert_eq!(xot.duplicate_xml_ids(root), vec![]);
+}
+
+#[test]
+fn xml_id_node_replace_and_unwrap() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(r#"<doc><a xml:id="FOO"><b xml:id="BAR"/></a><c xml:id="QUX"/></doc>"#)
+        .unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let a = xot.first_child(doc).unwrap();
+    let b = xot.first_child(a).unwrap();
+    let c = xot.next_sibling(a).unwrap();
+
+    xot.element_unwrap(a).unwrap();
+    assert_eq!(xot.xml_id_node(root, "FOO"), None);
+    assert_eq!(xot.xml_id_node(root, "BAR"), Some(b));
+
+    let text = xot.new_text("text");
+    xot.replace(c, text).unwrap();
+    assert_eq!(xot.xml_id_node(root, "QUX"), None);
+}
+
+#[test]
+fn xml_id_node_rebuild() {
+    let mut xot = Xot::new();
+    let root = xot.parse(r#"<doc><a xml:id="FOO"/></doc>"#).unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let a = xot.first_child(doc).unwrap();
+    let id_node = xot.attributes(a).get_node(xot.xml_id_name()).unwrap();
+
+    // changing the value directly is not tracked
+    if let xot::Value::Attribute(attribute) = xot.value_mut(id_node) {
+        attribute.set_value("BAR");
+    }
+    assert_eq!(xot.xml_id_node(root, "BAR"), None);
+
+    xot.rebuild_xml_ids(root).unwrap();
+    assert_eq!(xot.xml_id_node(root, "FOO"), None);
+    assert_eq!(xot.xml_id_node(root, "BAR"), Some(a));
+}
+
+#[test]
+fn xml_id_node_rebuild_not_document() {
+    let mut xot = Xot::new();
+    let root = xot.parse(r#"<doc/>"#).unwrap();
+    let doc = xot.document_element(root).unwrap();
+    assert!(xot.rebuild_xml_ids(doc).is_err());
+}
+
+#[test]
+fn dtd_id_node_set_attribute() {
+    let mut xot = Xot::new();
+    let id_name = xot.add_name("id");
+    let root = xot
+        .parse(r#"<!DOCTYPE doc [<!ATTLIST a id ID #IMPLIED>]><doc><a/><b/></doc>"#)
+        .unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let a = xot.first_child(doc).unwrap();
+    let b = xot.next_sibling(a).unwrap();
+
+    xot.set_attribute(a, id_name, "FOO");
+    // b doesn't have an id attribute declared
+    xot.set_attribute(b, id_name, "BAR");
+    assert_eq!(xot.xml_id_node(root, "FOO"), Some(a));
+    assert_eq!(xot.xml_id_node(root, "BAR"), None);
+
+    // renaming b to a makes its id attribute an id
+    let a_name = xot.add_name("a");
+    xot.set_element_name(b, a_name);
+    assert_eq!(xot.xml_id_node(root, "BAR"), Some(b));
+}
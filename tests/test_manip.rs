@@ -1010,3 +1010,345 @@ fn test_prepend_text_with_attributes() -> Result<(), Error> {
     assert_eq!(xot.children(document_element).count(), 1);
     Ok(())
 }
+
+#[test]
+fn test_import_node_remaps_names() {
+    let mut worker = Xot::new();
+    // make sure ids differ between the two Xots
+    worker.add_name("padding");
+    let root = worker
+        .parse(r#"<doc xmlns="http://example.com" xmlns:x="http://x.com" x:a="A"><?pi data?><!--c-->text</doc>"#)
+        .unwrap();
+
+    let mut xot = Xot::new();
+    let imported = xot.import_node(&worker, root);
+    assert_eq!(
+        xot.to_string(imported).unwrap(),
+        r#"<doc xmlns="http://example.com" xmlns:x="http://x.com" x:a="A"><?pi data?><!--c-->text</doc>"#
+    );
+    let ns = xot.namespace("http://example.com").unwrap();
+    let doc = xot.name_ns("doc", ns).unwrap();
+    let doc_el = xot.document_element(imported).unwrap();
+    assert_eq!(xot.element(doc_el).unwrap().name(), doc);
+    assert!(xot.name("padding").is_none());
+    // the worker is unchanged
+    assert_eq!(
+        worker.to_string(root).unwrap(),
+        r#"<doc xmlns="http://example.com" xmlns:x="http://x.com" x:a="A"><?pi data?><!--c-->text</doc>"#
+    );
+}
+
+#[test]
+fn test_import_node_element_with_prefixes() {
+    let mut worker = Xot::new();
+    let root = worker
+        .parse(r#"<doc xmlns:x="http://x.com" xmlns:y="http://y.com"><x:a><b/></x:a></doc>"#)
+        .unwrap();
+    let a = worker
+        .first_child(worker.document_element(root).unwrap())
+        .unwrap();
+
+    let mut xot = Xot::new();
+    let imported = xot.import_node(&worker, a);
+    assert!(xot.parent(imported).is_none());
+    assert_eq!(
+        xot.to_string(imported).unwrap(),
+        r#"<x:a xmlns:x="http://x.com"><b/></x:a>"#
+    );
+}
+
+#[test]
+fn test_import_node_document_ids() {
+    let mut worker = Xot::new();
+    let root = worker
+        .parse(r#"<!DOCTYPE doc [<!ATTLIST a key ID #IMPLIED>]><doc><a key="k"/><b xml:id="i"/></doc>"#)
+        .unwrap();
+
+    let mut xot = Xot::new();
+    let imported = xot.import_node(&worker, root);
+    assert_eq!(xot.document_type(imported).unwrap().name, "doc");
+    let a = xot.xml_id_node(imported, "k").unwrap();
+    assert_eq!(xot.local_name_str(xot.node_name(a).unwrap()), "a");
+    let b = xot.xml_id_node(imported, "i").unwrap();
+    assert_eq!(xot.local_name_str(xot.node_name(b).unwrap()), "b");
+    // DTD-declared id attributes are remapped too
+    let doc_el = xot.document_element(imported).unwrap();
+    let a_name = xot.name("a").unwrap();
+    let key = xot.name("key").unwrap();
+    let new_a = xot.new_element(a_name);
+    xot.set_attribute(new_a, key, "k2");
+    xot.append(doc_el, new_a).unwrap();
+    assert_eq!(xot.xml_id_node(imported, "k2"), Some(new_a));
+}
+
+#[test]
+fn test_absorb_node() {
+    let mut worker = Xot::new();
+    let root = worker
+        .parse(r#"<doc xmlns:x="http://x.com"><x:a x:b="B">Text</x:a></doc>"#)
+        .unwrap();
+
+    let mut xot = Xot::new();
+    xot.add_name("padding");
+    let imported = xot.absorb_node(worker, root);
+    assert_eq!(
+        xot.to_string(imported).unwrap(),
+        r#"<doc xmlns:x="http://x.com"><x:a x:b="B">Text</x:a></doc>"#
+    );
+}
+
+#[test]
+fn test_cdata_consolidation() {
+    let mut xot = Xot::new();
+    let root = xot.parse(r#"<doc>a</doc>"#).unwrap();
+    let doc_el = xot.document_element(root).unwrap();
+    let cdata = xot.new_cdata("b");
+    xot.append(doc_el, cdata).unwrap();
+    // not merged with the ordinary text
+    assert_eq!(xot.children(doc_el).count(), 2);
+    let comment = xot.new_comment("c");
+    xot.append(doc_el, comment).unwrap();
+    let cdata2 = xot.new_cdata("d");
+    xot.append(doc_el, cdata2).unwrap();
+    assert_eq!(
+        xot.to_string(root).unwrap(),
+        "<doc>a<![CDATA[b]]><!--c--><![CDATA[d]]></doc>"
+    );
+    // removing the comment merges the CDATA sections
+    xot.remove(comment).unwrap();
+    assert_eq!(xot.children(doc_el).count(), 2);
+    assert_eq!(xot.to_string(root).unwrap(), "<doc>a<![CDATA[bd]]></doc>");
+    // appending ordinary text does not merge with CDATA
+    let text = xot.new_text("e");
+    xot.append(doc_el, text).unwrap();
+    assert_eq!(xot.to_string(root).unwrap(), "<doc>a<![CDATA[bd]]>e</doc>");
+}
+
+#[test]
+fn test_cdata_html5() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(r#"<html><p/><svg xmlns="http://www.w3.org/2000/svg"/></html>"#)
+        .unwrap();
+    let html = xot.document_element(root).unwrap();
+    let p = xot.first_child(html).unwrap();
+    let svg = xot.next_sibling(p).unwrap();
+    let cdata = xot.new_cdata("a<b");
+    xot.append(p, cdata).unwrap();
+    let cdata = xot.new_cdata("a<b");
+    xot.append(svg, cdata).unwrap();
+    assert_eq!(
+        xot.html5().to_string(root).unwrap(),
+        r#"<!DOCTYPE html><html><p>a&lt;b</p><svg xmlns="http://www.w3.org/2000/svg"><![CDATA[a<b]]></svg></html>"#
+    );
+}
+
+#[test]
+fn test_try_constructors_build_parseable_xml() {
+    let mut xot = Xot::new();
+    let doc_name = xot.try_add_name("doc").unwrap();
+    let ns = xot.try_add_namespace("http://example.com").unwrap();
+    let prefix = xot.try_add_prefix("ex").unwrap();
+    let item_name = xot.try_add_name_ns("item", ns).unwrap();
+    let target = xot.try_add_name("target").unwrap();
+    let attr_name = xot.try_add_name("a").unwrap();
+
+    let doc_el = xot.new_element(doc_name);
+    xot.namespaces_mut(doc_el).insert(prefix, ns);
+    let item = xot.new_element(item_name);
+    xot.append(doc_el, item).unwrap();
+    let attr = xot
+        .try_new_attribute_node(attr_name, "\"<&>\"".to_string())
+        .unwrap();
+    xot.append_attribute_node(item, attr).unwrap();
+    let text = xot.try_new_text("a < b & c ]]>").unwrap();
+    xot.append(item, text).unwrap();
+    let cdata = xot.try_new_cdata("x ]]> y").unwrap();
+    xot.append(item, cdata).unwrap();
+    let comment = xot.try_new_comment(" a - b ").unwrap();
+    xot.append(doc_el, comment).unwrap();
+    let pi = xot
+        .try_new_processing_instruction(target, Some("a ? > b"))
+        .unwrap();
+    xot.append(doc_el, pi).unwrap();
+    let root = xot.new_document_with_element(doc_el).unwrap();
+
+    let xml = xot.to_string(root).unwrap();
+    let reparsed = xot.parse(&xml).unwrap();
+    let reparsed_item = xot
+        .first_child(xot.document_element(reparsed).unwrap())
+        .unwrap();
+    assert_eq!(xot.string_value(reparsed_item), "a < b & c ]]>x ]]> y");
+    assert_eq!(xot.get_attribute(reparsed_item, attr_name), Some("\"<&>\""));
+}
+
+#[test]
+fn test_try_constructors_reject_invalid_values() {
+    let mut xot = Xot::new();
+    for name in ["", "a b", "a:b", "1a"] {
+        assert!(matches!(xot.try_add_name(name), Err(Error::InvalidName(_))));
+    }
+    assert!(matches!(
+        xot.try_add_prefix("a:b"),
+        Err(Error::InvalidName(_))
+    ));
+    assert!(xot.try_add_prefix("").is_ok());
+    assert!(matches!(
+        xot.try_add_prefix("xmlns"),
+        Err(Error::ReservedPrefix(_))
+    ));
+    assert!(matches!(
+        xot.try_add_namespace("http://www.w3.org/XML/1998/namespace"),
+        Err(Error::ReservedNamespace(_))
+    ));
+    assert!(matches!(
+        xot.try_new_text("a\u{0}b"),
+        Err(Error::InvalidCharacter('\u{0}'))
+    ));
+    assert!(matches!(
+        xot.try_new_comment("a-"),
+        Err(Error::InvalidComment(_))
+    ));
+    let ns = xot.add_namespace("http://example.com");
+    let namespaced = xot.add_name_ns("target", ns);
+    assert!(matches!(
+        xot.try_new_processing_instruction(namespaced, None),
+        Err(Error::NamespaceInProcessingInstruction)
+    ));
+    let invalid = xot.add_name("a b");
+    assert!(matches!(
+        xot.try_new_processing_instruction(invalid, None),
+        Err(Error::InvalidName(_))
+    ));
+}
+
+#[test]
+fn test_try_setters() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(r#"<doc a="1">text<!--comment--><?target data?></doc>"#)
+        .unwrap();
+    let doc_el = xot.document_element(root).unwrap();
+    let text = xot.first_child(doc_el).unwrap();
+    let comment = xot.next_sibling(text).unwrap();
+    let pi = xot.next_sibling(comment).unwrap();
+
+    let text_value = xot.text_mut(text).unwrap();
+    assert!(matches!(
+        text_value.try_set("\u{1}"),
+        Err(Error::InvalidCharacter('\u{1}'))
+    ));
+    text_value.try_set("new").unwrap();
+
+    let Value::Comment(comment_value) = xot.value_mut(comment) else {
+        unreachable!()
+    };
+    assert!(matches!(
+        comment_value.set("a\u{FFFF}"),
+        Err(Error::InvalidCharacter('\u{FFFF}'))
+    ));
+
+    let Value::ProcessingInstruction(pi_value) = xot.value_mut(pi) else {
+        unreachable!()
+    };
+    assert!(matches!(
+        pi_value.try_set_data(Some("?>")),
+        Err(Error::InvalidProcessingInstruction(_))
+    ));
+    pi_value.try_set_data(Some("new")).unwrap();
+
+    let a = xot.name("a").unwrap();
+    let attribute_node = xot.attributes(doc_el).get_node(a).unwrap();
+    let Value::Attribute(attribute) = xot.value_mut(attribute_node) else {
+        unreachable!()
+    };
+    assert!(attribute.try_set_value("\u{0}").is_err());
+    attribute.try_set_value("2").unwrap();
+
+    assert_eq!(
+        xot.to_string(root).unwrap(),
+        r#"<doc a="2">new<!--comment--><?target new?></doc>"#
+    );
+}
+
+#[test]
+fn test_parse_fragment_into_uses_namespaces_in_scope() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(r#"<doc xmlns="http://example.com/default" xmlns:a="http://example.com/a"><p>x</p></doc>"#)
+        .unwrap();
+    let doc_el = xot.document_element(root).unwrap();
+    let p = xot.first_child(doc_el).unwrap();
+    xot.parse_fragment_into(p, r#"y<a:b xml:id="b"/><c/>"#)
+        .unwrap();
+
+    assert_eq!(
+        xot.to_string(root).unwrap(),
+        r#"<doc xmlns="http://example.com/default" xmlns:a="http://example.com/a"><p>xy<a:b xml:id="b"/><c/></p></doc>"#
+    );
+    // the text is consolidated with the existing text
+    assert_eq!(xot.children(p).count(), 3);
+    let a_ns = xot.namespace("http://example.com/a").unwrap();
+    let b = xot.xml_id_node(root, "b").unwrap();
+    assert_eq!(
+        xot.element(b).unwrap().name(),
+        xot.name_ns("b", a_ns).unwrap()
+    );
+    // the default namespace applies too
+    let default_ns = xot.namespace("http://example.com/default").unwrap();
+    let c = xot.last_child(p).unwrap();
+    assert_eq!(
+        xot.element(c).unwrap().name(),
+        xot.name_ns("c", default_ns).unwrap()
+    );
+}
+
+#[test]
+fn test_parse_fragment_into_error_leaves_tree_unchanged() {
+    let mut xot = Xot::new();
+    let root = xot.parse(r#"<doc><p>x</p></doc>"#).unwrap();
+    let doc_el = xot.document_element(root).unwrap();
+    let p = xot.first_child(doc_el).unwrap();
+
+    assert!(matches!(
+        xot.parse_fragment_into(p, r#"<a:b/>"#),
+        Err(Error::Parse(xot::ParseError::UnknownPrefix(..)))
+    ));
+    assert!(matches!(
+        xot.parse_fragment_into(p, r#"y<b>"#),
+        Err(Error::Parse(_))
+    ));
+    let text = xot.first_child(p).unwrap();
+    assert!(matches!(
+        xot.parse_fragment_into(text, "y"),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert_eq!(xot.to_string(root).unwrap(), r#"<doc><p>x</p></doc>"#);
+}
+
+#[test]
+fn test_replace_with_xml() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(r#"<doc xmlns:a="http://example.com/a">x<old><child/></old>z</doc>"#)
+        .unwrap();
+    let doc_el = xot.document_element(root).unwrap();
+    let old = xot.children(doc_el).nth(1).unwrap();
+
+    // on failure nothing changes
+    assert!(xot.replace_with_xml(old, "<b:x/>").is_err());
+    assert_eq!(
+        xot.to_string(root).unwrap(),
+        r#"<doc xmlns:a="http://example.com/a">x<old><child/></old>z</doc>"#
+    );
+
+    xot.replace_with_xml(old, r#"y<a:new/>y"#).unwrap();
+    assert_eq!(
+        xot.to_string(root).unwrap(),
+        r#"<doc xmlns:a="http://example.com/a">xy<a:new/>yz</doc>"#
+    );
+    assert!(matches!(
+        xot.replace_with_xml(root, "<doc/>"),
+        Err(Error::InvalidOperation(_))
+    ));
+}
@@ -1,4 +1,4 @@
-use xot::Xot;
+use xot::{NameTables, Xot};
 
 #[test]
 fn test_deduplicate_namespace() {
@@ -427,14 +427,14 @@ fn test_namespaces_overrides() {
 #[test]
 fn test_namespaces_overrides_xml_prefix() {
     let mut xot = Xot::new();
-    let doc = xot
-        .parse(r#"<doc><p xmlns:xml="http://example.com/a"/></doc>"#)
-        .unwrap();
+    // the parser rejects this declaration, so we make it ourselves
+    let doc = xot.parse(r#"<doc><p/></doc>"#).unwrap();
     let root_id = xot.document_element(doc).unwrap();
     let a_ns = xot.add_namespace("http://example.com/a");
     let xml_prefix = xot.add_prefix("xml");
     let xml_ns = xot.add_namespace("http://www.w3.org/XML/1998/namespace");
     let p0 = xot.first_child(root_id).unwrap();
+    xot.namespaces_mut(p0).insert(xml_prefix, a_ns);
 
     assert_eq!(
         xot.namespaces_in_scope(p0).collect::<Vec<_>>(),
@@ -468,3 +468,132 @@ fn test_default_namespace_undeclared() {
         [(xml_prefix, xml_ns)]
     );
 }
+
+#[test]
+fn test_more_than_u16_names() {
+    let mut xot = Xot::new();
+    let names = (0..70_000)
+        .map(|i| xot.add_name(&format!("n{}", i)))
+        .collect::<Vec<_>>();
+    // no name is aliased by another
+    for (i, name) in names.iter().enumerate() {
+        assert_eq!(xot.local_name_str(*name), format!("n{}", i));
+    }
+    let namespace = xot.add_namespace("http://example.com/69999");
+    assert_eq!(xot.namespace_str(namespace), "http://example.com/69999");
+}
+
+#[test]
+fn test_compact_names() {
+    let mut xot = Xot::new();
+    let dropped = xot
+        .parse(r#"<a xmlns:x="http://x.com" x:b="1"><?pi?></a>"#)
+        .unwrap();
+    let root = xot
+        .parse(r#"<p:doc xmlns:p="http://p.com" c="2"><?target data?><e xml:id="i"/></p:doc>"#)
+        .unwrap();
+    xot.remove(dropped).unwrap();
+    let r = xot.add_namespace("http://r.com");
+    let retained = xot.add_name_ns("r", r);
+    let a = xot.name("a").unwrap();
+
+    let remapping = xot.compact_names(&[retained]);
+
+    assert_eq!(remapping.name(a), None);
+    assert!(xot.name("a").is_none());
+    assert!(xot.name("pi").is_none());
+    assert!(xot.namespace("http://x.com").is_none());
+    assert!(xot.prefix("x").is_none());
+    assert_eq!(remapping.dropped_names(), 3);
+    assert_eq!(remapping.dropped_namespaces(), 1);
+    assert_eq!(remapping.dropped_prefixes(), 1);
+
+    let retained = remapping.name(retained).unwrap();
+    assert_eq!(xot.name_ns_str(retained), ("r", "http://r.com"));
+    assert_eq!(
+        xot.to_string(root).unwrap(),
+        r#"<p:doc xmlns:p="http://p.com" c="2"><?target data?><e xml:id="i"/></p:doc>"#
+    );
+    assert!(xot.xml_id_node(root, "i").is_some());
+    // new names can be added
+    let new = xot.add_name("new");
+    assert_eq!(xot.local_name_str(new), "new");
+}
+
+#[test]
+fn test_compact_names_keeps_dtd_id_attributes() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(r#"<!DOCTYPE doc [<!ATTLIST doc key ID #IMPLIED>]><doc key="k"/>"#)
+        .unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let key = xot.name("key").unwrap();
+    xot.remove_attribute(doc, key);
+    xot.compact_names(&[]);
+    // setting the attribute again indexes the id
+    let key = xot.add_name("key");
+    xot.set_attribute(doc, key, "k2");
+    assert_eq!(xot.xml_id_node(root, "k2"), Some(doc));
+}
+
+#[test]
+fn test_shared_name_tables() {
+    let tables = NameTables::new();
+    let mut xot_a = Xot::with_name_tables(&tables);
+    let mut xot_b = Xot::with_name_tables(&tables);
+    let doc_a = xot_a
+        .parse(r#"<a:doc xmlns:a="http://example.com/a"/>"#)
+        .unwrap();
+    let doc_b = xot_b
+        .parse(r#"<b:doc xmlns:b="http://example.com/a"/>"#)
+        .unwrap();
+    let name_a = xot_a.node_name(xot_a.document_element(doc_a).unwrap());
+    let name_b = xot_b.node_name(xot_b.document_element(doc_b).unwrap());
+    assert_eq!(name_a, name_b);
+    assert_eq!(xot_a.xml_id_name(), xot_b.xml_id_name());
+    // names added to one Xot are visible in the other
+    let extra = xot_a.add_name("extra");
+    assert_eq!(xot_b.name("extra"), Some(extra));
+    let namespace = tables.add_namespace("http://example.com/a");
+    assert_eq!(xot_b.namespace("http://example.com/a"), Some(namespace));
+    assert_eq!(tables.add_name_ns("doc", namespace), name_a.unwrap());
+}
+
+#[test]
+fn test_shared_name_tables_threads() {
+    let tables = NameTables::new();
+    let handles = (0..4)
+        .map(|i| {
+            let tables = tables.clone();
+            std::thread::spawn(move || {
+                let mut xot = Xot::with_name_tables(&tables);
+                let xml = format!(
+                    r#"<doc{} xmlns="http://example.com"><common/></doc{}>"#,
+                    i, i
+                );
+                let doc = xot.parse(&xml).unwrap();
+                let common = xot.first_child(xot.document_element(doc).unwrap()).unwrap();
+                xot.node_name(common).unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+    let names = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect::<Vec<_>>();
+    assert!(names.iter().all(|name| *name == names[0]));
+    let namespace = tables.add_namespace("http://example.com");
+    assert_eq!(tables.add_name_ns("common", namespace), names[0]);
+    let xot = Xot::with_name_tables(&tables);
+    assert!(xot.name_ns("doc3", namespace).is_some());
+}
+
+#[test]
+fn test_compact_shared_name_tables() {
+    let tables = NameTables::new();
+    let mut xot = Xot::with_name_tables(&tables);
+    let unused = xot.add_name("unused");
+    let remapping = xot.compact_names(&[]);
+    assert_eq!(remapping.name(unused), Some(unused));
+    assert_eq!(xot.name("unused"), Some(unused));
+}
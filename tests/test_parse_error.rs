@@ -29,7 +29,7 @@ fn test_unknown_prefix() {
 
 #[test]
 fn test_unsupported_version() {
-    let xml = r#"<?xml version="1.1"?><doc/></doc>"#;
+    let xml = r#"<?xml version="1.2"?><doc/></doc>"#;
     let mut xot = Xot::new();
     let err = xot.parse(xml).unwrap_err();
     assert!(matches!(err, xot::ParseError::UnsupportedVersion { .. }));
@@ -41,11 +41,14 @@ fn test_unsupported_version() {
 #[test]
 fn test_parse_invalid_xml_declaration() {
     let mut xot = Xot::new();
-    let err = xot.parse(r#"<?xml version="1.0" standalone="yes" encoding="UTF-8"?><a/>"#)
+    let err = xot
+        .parse(r#"<?xml version="1.0" standalone="yes" encoding="UTF-8"?><a/>"#)
         .unwrap_err();
     assert!(matches!(err, xot::ParseError::XmlParser { .. }));
     match err {
-        xot::ParseError::XmlParser(e, _) => assert!(matches!(e, xmlparser::Error::InvalidDeclaration { .. })),
+        xot::ParseError::XmlParser(e, _) => {
+            assert!(matches!(e, xmlparser::Error::InvalidDeclaration { .. }))
+        }
         _ => unreachable!(),
     }
 }
@@ -78,30 +81,211 @@ fn test_duplicate_attribute_with_prefix() {
 }
 
 #[test]
-fn test_invalid_dtd() {
-    let xml = r#"<!DOCTYPE note><note></note>"#;
+fn test_duplicate_expanded_attribute() {
+    let xml = r#"<doc xmlns:a="http://example.com" xmlns:b="http://example.com" a:x="1" b:x="2"/>"#;
     let mut xot = Xot::new();
     let err = xot.parse(xml).unwrap_err();
-    assert!(matches!(err, xot::ParseError::DtdUnsupported { .. }));
-    assert_eq!(err.span(), (0..15).into());
+    assert!(
+        matches!(err, xot::ParseError::DuplicateExpandedAttribute(ref name, _) if name == "b:x")
+    );
+    assert_eq!(err.span(), (71..74).into());
+}
+
+#[test]
+fn test_reserved_prefix() {
+    let mut xot = Xot::new();
+    let err = xot
+        .parse(r#"<doc xmlns:xml="http://example.com"/>"#)
+        .unwrap_err();
+    assert!(matches!(err, xot::ParseError::ReservedPrefix(ref prefix, _) if prefix == "xml"));
+    assert_eq!(err.span(), (5..14).into());
+
+    let err = xot
+        .parse(r#"<doc xmlns:xmlns="http://example.com"/>"#)
+        .unwrap_err();
+    assert!(matches!(err, xot::ParseError::ReservedPrefix(ref prefix, _) if prefix == "xmlns"));
+
+    // binding xml to its own namespace is allowed
+    assert!(xot
+        .parse(r#"<doc xmlns:xml="http://www.w3.org/XML/1998/namespace"/>"#)
+        .is_ok());
+}
+
+#[test]
+fn test_reserved_namespace() {
+    let mut xot = Xot::new();
+    let err = xot
+        .parse(r#"<doc xmlns:p="http://www.w3.org/2000/xmlns/"/>"#)
+        .unwrap_err();
+    assert!(matches!(err, xot::ParseError::ReservedNamespace(..)));
+    assert_eq!(err.span(), (14..43).into());
+
+    let err = xot
+        .parse(r#"<doc xmlns="http://www.w3.org/2000/xmlns/"/>"#)
+        .unwrap_err();
+    assert!(matches!(err, xot::ParseError::ReservedNamespace(..)));
+
+    let err = xot
+        .parse(r#"<doc xmlns:p="http://www.w3.org/XML/1998/namespace"/>"#)
+        .unwrap_err();
+    assert!(
+        matches!(err, xot::ParseError::ReservedNamespace(ref namespace, _) if namespace == "http://www.w3.org/XML/1998/namespace")
+    );
 }
 
 #[test]
-fn test_dtd_unsupported() {
-    let xml = r#"<!DOCTYPE note SYSTEM "Note.dtd"><note></note>"#;
+fn test_prefix_undeclaration() {
+    let mut xot = Xot::new();
+    let err = xot
+        .parse(r#"<doc xmlns:p="http://example.com"><a xmlns:p=""/></doc>"#)
+        .unwrap_err();
+    assert!(matches!(err, xot::ParseError::PrefixUndeclaration(ref prefix, _) if prefix == "p"));
+    assert_eq!(err.span(), (37..44).into());
+
+    // undeclaring the default namespace is fine, and XML 1.1 allows
+    // undeclaring prefixes
+    assert!(xot
+        .parse(r#"<doc xmlns="http://example.com"><a xmlns=""/></doc>"#)
+        .is_ok());
+    assert!(xot
+        .parse(r#"<?xml version="1.1"?><doc xmlns:p="http://example.com"><a xmlns:p=""/></doc>"#)
+        .is_ok());
+}
+
+#[test]
+fn test_duplicate_namespace_declaration() {
+    let mut xot = Xot::new();
+    let err = xot
+        .parse(r#"<a xmlns:p="urn:x" xmlns:p="urn:y"/>"#)
+        .unwrap_err();
+    assert!(matches!(err, xot::ParseError::DuplicateAttribute(ref name, _) if name == "xmlns:p"));
+    assert_eq!(err.span(), (19..26).into());
+
+    let err = xot
+        .parse(r#"<a xmlns="urn:x" xmlns="urn:y"/>"#)
+        .unwrap_err();
+    assert!(matches!(err, xot::ParseError::DuplicateAttribute(ref name, _) if name == "xmlns"));
+    assert_eq!(err.span(), (17..22).into());
+
+    // the same prefix may be declared again on a descendant
+    assert!(xot
+        .parse(r#"<a xmlns:p="urn:x"><b xmlns:p="urn:y"/></a>"#)
+        .is_ok());
+}
+
+#[test]
+fn test_lenient_namespace_constraints() {
+    let mut xot = Xot::new();
+    let (doc, errors) = xot.parse_lenient(
+        r#"<doc xmlns:xml="http://example.com" xmlns:a="http://example.com" xmlns:b="http://example.com" a:x="1" b:x="2"/>"#,
+    );
+    assert_eq!(errors.len(), 2);
+    assert!(matches!(errors[0], xot::ParseError::ReservedPrefix(..)));
+    assert!(matches!(
+        errors[1],
+        xot::ParseError::DuplicateExpandedAttribute(..)
+    ));
+    // the declaration is dropped and the first attribute wins
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.attributes(doc_el).values().collect::<Vec<_>>(), ["1"]);
+}
+
+#[test]
+fn test_undeclared_entity_with_dtd() {
+    let xml = r#"<!DOCTYPE doc [<!ENTITY foo "FOO">]><doc>&bar;</doc>"#;
     let mut xot = Xot::new();
     let err = xot.parse(xml).unwrap_err();
-    assert!(matches!(err, xot::ParseError::DtdUnsupported { .. }));
-    assert_eq!(err.span(), (0..33).into());
+    assert!(matches!(err, xot::ParseError::InvalidEntity { .. }));
+    assert_eq!(err.span(), (41..46).into());
+}
+
+#[test]
+fn test_external_entity() {
+    let xml = r#"<!DOCTYPE doc [<!ENTITY foo SYSTEM "foo.xml">]><doc>&foo;</doc>"#;
+    let mut xot = Xot::new();
+    let err = xot.parse(xml).unwrap_err();
+    assert!(matches!(err, xot::ParseError::ExternalEntity { .. }));
+    assert_eq!(err.span(), (52..57).into());
+}
+
+#[test]
+fn test_recursive_entity() {
+    let xml = r#"<!DOCTYPE doc [<!ENTITY a "&b;"><!ENTITY b "&a;">]><doc>&a;</doc>"#;
+    let mut xot = Xot::new();
+    let err = xot.parse(xml).unwrap_err();
+    assert!(matches!(err, xot::ParseError::RecursiveEntity { .. }));
+    assert_eq!(err.span(), (56..59).into());
+}
+
+#[test]
+fn test_entity_markup() {
+    let mut xot = Xot::new();
+    let xml = r#"<!DOCTYPE doc [<!ENTITY e "<b>bold</b>">]><doc>&e;</doc>"#;
+    let err = xot.parse(xml).unwrap_err();
+    assert!(matches!(err, xot::ParseError::EntityMarkup(ref name, _) if name == "e"));
+    assert_eq!(&xml[err.span().range()], "&e;");
+
+    // a `<` that is escaped in the declaration is text
+    let xml = r#"<!DOCTYPE doc [<!ENTITY e "&#38;#60;b&lt;">]><doc>&e;</doc>"#;
+    let doc = xot.parse(xml).unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.text_content_str(doc_el), Some("<b<"));
+}
+
+// a document that nests entities to expand to 10^levels copies of `text`
+fn entity_bomb(levels: usize, text: &str) -> String {
+    let mut xml = format!(r#"<!DOCTYPE r [<!ENTITY l0 "{}">"#, text);
+    for level in 1..=levels {
+        let references = format!("&l{};", level - 1).repeat(10);
+        xml.push_str(&format!(r#"<!ENTITY l{} "{}">"#, level, references));
+    }
+    xml.push_str(&format!("]><r>&l{};</r>", levels));
+    xml
+}
+
+#[test]
+fn test_entity_expansion_bomb() {
+    let xml = entity_bomb(8, &"lol".repeat(10));
+    let mut xot = Xot::new();
+    let err = xot.parse(&xml).unwrap_err();
+    assert!(matches!(
+        err,
+        xot::ParseError::LimitExceeded(xot::ParseLimit::EntityExpansion, _)
+    ));
+    assert_eq!(err.to_string(), "Limit exceeded: entity expansion");
+    // the span is that of the reference in the document
+    assert_eq!(&xml[err.span().range()], "&l8;");
 }
 
 #[test]
-fn test_dtd_unsupported2() {
-    let xml = r#"<?xml version="1.0"?><!DOCTYPE note SYSTEM "Note.dtd"><note></note>"#;
+fn test_entity_references_bomb() {
+    // empty entities expand to nothing, but the references still count
+    let xml = entity_bomb(8, "");
+    let mut xot = Xot::new();
+    let err = xot.parse(&xml).unwrap_err();
+    assert!(matches!(
+        err,
+        xot::ParseError::LimitExceeded(xot::ParseLimit::EntityReferences, _)
+    ));
+    assert_eq!(&xml[err.span().range()], "&l8;");
+}
+
+#[test]
+fn test_entity_markup_in_attribute() {
+    let xml = r#"<!DOCTYPE doc [<!ENTITY a "&#60;">]><doc a="&a;"/>"#;
+    let mut xot = Xot::new();
+    let err = xot.parse(xml).unwrap_err();
+    assert!(matches!(err, xot::ParseError::EntityMarkup { .. }));
+    assert_eq!(err.span(), (44..47).into());
+}
+
+#[test]
+fn test_parameter_entity_reference_in_entity_value() {
+    let xml = r#"<!DOCTYPE doc [<!ENTITY a "%b;">]><doc/>"#;
     let mut xot = Xot::new();
     let err = xot.parse(xml).unwrap_err();
     assert!(matches!(err, xot::ParseError::DtdUnsupported { .. }));
-    assert_eq!(err.span(), (21..54).into());
+    assert_eq!(err.span(), (27..30).into());
 }
 
 #[test]
@@ -131,6 +315,18 @@ fn test_unclosed_tag_with_prefix() {
     assert_eq!(err.span(), (1..6).into());
 }
 
+#[test]
+fn test_unclosed_start_tag() {
+    let mut xot = Xot::new();
+    let err = xot.parse("<a").unwrap_err();
+    assert!(matches!(err, xot::ParseError::UnclosedTag(_)));
+    assert_eq!(err.span(), (1..2).into());
+
+    let err = xot.parse("<a b='1'").unwrap_err();
+    assert!(matches!(err, xot::ParseError::UnclosedTag(_)));
+    assert_eq!(err.span(), (1..2).into());
+}
+
 #[test]
 fn test_only_text_top_level() {
     let xml = r#"abc"#;
@@ -166,3 +362,340 @@ fn test_invalid_entity() {
     assert!(matches!(err, xot::ParseError::InvalidEntity(_, _)));
     assert_eq!(err.span(), (5..10).into());
 }
+
+#[test]
+fn test_invalid_attribute_declaration() {
+    let xml = r#"<!DOCTYPE doc [<!ATTLIST doc a WRONG "x">]><doc/>"#;
+    let mut xot = Xot::new();
+    let err = xot.parse(xml).unwrap_err();
+    assert!(matches!(
+        err,
+        xot::ParseError::InvalidAttributeDeclaration(_)
+    ));
+    assert_eq!(err.span(), (15..36).into());
+}
+
+#[test]
+fn test_duplicate_dtd_id() {
+    let xml = r#"<!DOCTYPE doc [<!ATTLIST a id ID #IMPLIED>]><doc><a id="x"/><a id="x"/></doc>"#;
+    let mut xot = Xot::new();
+    let err = xot.parse(xml).unwrap_err();
+    assert!(matches!(err, xot::ParseError::DuplicateId(_, _)));
+    assert_eq!(err.span(), (67..68).into());
+}
+
+#[test]
+fn test_lenient_no_errors() {
+    let xml = r#"<doc a="1"><p>text &amp; more</p><!--c--></doc>"#;
+    let mut xot = Xot::new();
+    let (doc, errors) = xot.parse_lenient(xml);
+    assert!(errors.is_empty());
+    assert_eq!(xot.to_string(doc).unwrap(), xml);
+}
+
+#[test]
+fn test_lenient_unclosed_at_end() {
+    let mut xot = Xot::new();
+    let (doc, errors) = xot.parse_lenient("<doc><p>text");
+    assert_eq!(xot.to_string(doc).unwrap(), "<doc><p>text</p></doc>");
+    assert_eq!(errors.len(), 2);
+    assert!(matches!(errors[0], xot::ParseError::UnclosedTag(_)));
+    assert_eq!(errors[0].span(), (1..4).into());
+    assert_eq!(errors[1].span(), (6..7).into());
+}
+
+#[test]
+fn test_lenient_unclosed_start_tag() {
+    let mut xot = Xot::new();
+    let (doc, errors) = xot.parse_lenient("<a");
+    assert_eq!(xot.to_string(doc).unwrap(), "<a/>");
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], xot::ParseError::UnclosedTag(_)));
+    assert_eq!(errors[0].span(), (1..2).into());
+
+    let (doc, errors) = xot.parse_lenient("<doc><a b='1'");
+    assert_eq!(xot.to_string(doc).unwrap(), r#"<doc><a b="1"/></doc>"#);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].span(), (1..4).into());
+    assert_eq!(errors[1].span(), (6..7).into());
+}
+
+#[test]
+fn test_lenient_auto_close() {
+    let mut xot = Xot::new();
+    let (doc, errors) = xot.parse_lenient("<doc><p><b>bold</p>after</doc>");
+    assert_eq!(
+        xot.to_string(doc).unwrap(),
+        "<doc><p><b>bold</b></p>after</doc>"
+    );
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], xot::ParseError::UnclosedTag(_)));
+    assert_eq!(errors[0].span(), (9..10).into());
+}
+
+#[test]
+fn test_lenient_stray_close_tag() {
+    let mut xot = Xot::new();
+    let (doc, errors) = xot.parse_lenient("<doc>a</p>b</doc>\n");
+    assert_eq!(xot.to_string(doc).unwrap(), "<doc>ab</doc>");
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], xot::ParseError::InvalidCloseTag(..)));
+    assert_eq!(errors[0].span(), (8..9).into());
+}
+
+#[test]
+fn test_lenient_unknown_prefix() {
+    let mut xot = Xot::new();
+    let (doc, errors) = xot.parse_lenient(r#"<x:doc x:a="1" a="2"><y:p/></x:doc>"#);
+    assert_eq!(xot.to_string(doc).unwrap(), r#"<doc a="1"><p/></doc>"#);
+    let kinds = errors
+        .iter()
+        .map(|error| match error {
+            xot::ParseError::UnknownPrefix(prefix, _) => format!("prefix {}", prefix),
+            xot::ParseError::DuplicateAttribute(name, _) => format!("duplicate {}", name),
+            _ => panic!("unexpected error {:?}", error),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            "prefix x",
+            "prefix x",
+            "duplicate a",
+            "prefix y",
+            "prefix x"
+        ]
+    );
+}
+
+#[test]
+fn test_lenient_duplicate_attribute_and_id() {
+    let mut xot = Xot::new();
+    let (doc, errors) =
+        xot.parse_lenient(r#"<doc a="1" a="2"><p xml:id="x"/><p xml:id="x"/></doc>"#);
+    assert_eq!(errors.len(), 2);
+    assert!(matches!(errors[0], xot::ParseError::DuplicateAttribute(..)));
+    assert!(matches!(errors[1], xot::ParseError::DuplicateId(..)));
+    let doc_el = xot.document_element(doc).unwrap();
+    let first = xot.first_child(doc_el).unwrap();
+    assert_eq!(xot.xml_id_node(doc, "x"), Some(first));
+    assert_eq!(xot.get_attribute(doc_el, xot.name("a").unwrap()), Some("1"));
+}
+
+#[test]
+fn test_lenient_invalid_entity() {
+    let mut xot = Xot::new();
+    let (doc, errors) = xot.parse_lenient(r#"<doc a="&x;">fish &amp chips</doc>"#);
+    assert_eq!(errors.len(), 2);
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.text_content_str(doc_el), Some("fish &amp chips"));
+    assert_eq!(
+        xot.get_attribute(doc_el, xot.name("a").unwrap()),
+        Some("&x;")
+    );
+}
+
+#[test]
+fn test_lenient_tokenizer_error() {
+    let mut xot = Xot::new();
+    let (doc, errors) = xot.parse_lenient(r#"<doc><p a=>text</p><é/> < 3</doc>"#);
+    assert_eq!(xot.to_string(doc).unwrap(), r#"<doc><p/><é/> </doc>"#);
+    assert_eq!(errors.len(), 2);
+    assert!(matches!(errors[0], xot::ParseError::XmlParser(..)));
+    assert!(matches!(errors[1], xot::ParseError::XmlParser(..)));
+}
+
+#[test]
+fn test_lenient_top_level() {
+    let mut xot = Xot::new();
+    let (doc, errors) = xot.parse_lenient("<a/>text<b/>");
+    assert_eq!(xot.to_string(doc).unwrap(), "<a/><b/>");
+    assert_eq!(errors.len(), 2);
+
+    let (doc, errors) = xot.parse_lenient("");
+    assert_eq!(xot.to_string(doc).unwrap(), "");
+    assert!(matches!(errors[0], xot::ParseError::NoElementAtTopLevel(0)));
+}
+
+#[test]
+fn test_lenient_span_info() {
+    let mut xot = Xot::new();
+    let (doc, span_info, _) = xot.parse_lenient_with_span_info("<doc><p></doc>");
+    let doc_el = xot.document_element(doc).unwrap();
+    let p = xot.first_child(doc_el).unwrap();
+    assert_eq!(
+        span_info.get(xot::SpanInfoKey::ElementEnd(p)),
+        Some(&(8..8).into())
+    );
+    assert_eq!(
+        span_info.get(xot::SpanInfoKey::ElementEnd(doc_el)),
+        Some(&(8..14).into())
+    );
+}
+
+#[test]
+fn test_render_span_info() {
+    let xml = "<doc>\n  <p a=\"1\"/>\n</doc>";
+    let mut xot = Xot::new();
+    let (doc, span_info) = xot.parse_with_span_info(xml).unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    let p = xot
+        .children(doc_el)
+        .find(|node| xot.is_element(*node))
+        .unwrap();
+    let span = span_info
+        .get(xot::SpanInfoKey::AttributeValue(p, xot.name("a").unwrap()))
+        .unwrap();
+    let index = xot::LineIndex::new(xml);
+    assert_eq!(index.line_column(span.start).line, 2);
+    assert_eq!(
+        index.render(*span, "Value"),
+        "error: Value\n --> 2:9\n  |\n2 |   <p a=\"1\"/>\n  |         ^\n"
+    );
+}
+
+#[test]
+fn test_error_display() {
+    let mut xot = Xot::new();
+    let err: xot::Error = xot.parse("<a></b>").unwrap_err().into();
+    assert_eq!(err.to_string(), "Parse error: Invalid close tag: b");
+}
+
+fn parse_with_limits(xml: &str, limits: xot::ParseLimits) -> Result<(), xot::ParseError> {
+    let mut xot = Xot::new();
+    let options = xot::ParseOptions {
+        limits,
+        ..Default::default()
+    };
+    xot.parse_with_options(xml, &options).map(|_| ())
+}
+
+fn limit_exceeded(result: Result<(), xot::ParseError>) -> (xot::ParseLimit, xot::Span) {
+    match result {
+        Err(xot::ParseError::LimitExceeded(limit, span)) => (limit, span),
+        _ => panic!("Expected limit exceeded, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_limit_depth() {
+    let limits = xot::ParseLimits {
+        max_depth: Some(2),
+        ..Default::default()
+    };
+    assert!(parse_with_limits("<a><b/><b/></a>", limits).is_ok());
+    let xml = "<a><b><c/></b></a>";
+    let (limit, span) = limit_exceeded(parse_with_limits(xml, limits));
+    assert_eq!(limit, xot::ParseLimit::Depth);
+    assert_eq!(&xml[span.range()], "c");
+}
+
+#[test]
+fn test_limit_attributes() {
+    let limits = xot::ParseLimits {
+        max_attributes: Some(2),
+        ..Default::default()
+    };
+    assert!(parse_with_limits(r#"<a x="1" y="2"/>"#, limits).is_ok());
+    // namespace declarations count too
+    let xml = r#"<a><b xmlns:p="urn:p" x="1" p:y="2"/></a>"#;
+    let (limit, span) = limit_exceeded(parse_with_limits(xml, limits));
+    assert_eq!(limit, xot::ParseLimit::Attributes);
+    assert_eq!(&xml[span.range()], "b");
+}
+
+#[test]
+fn test_limit_text_length() {
+    let limits = xot::ParseLimits {
+        max_text_length: Some(4),
+        ..Default::default()
+    };
+    assert!(parse_with_limits("<a>abcd<b>efgh</b></a>", limits).is_ok());
+    // the length is that of the consolidated text
+    let xml = "<a>abc<![CDATA[de]]></a>";
+    let (limit, span) = limit_exceeded(parse_with_limits(xml, limits));
+    assert_eq!(limit, xot::ParseLimit::TextLength);
+    assert_eq!(&xml[span.range()], "de");
+}
+
+#[test]
+fn test_limit_text_length_entity_expansion() {
+    // the text length is checked while entities are expanded, so this
+    // fails fast even if entity expansion isn't limited
+    let limits = xot::ParseLimits {
+        max_text_length: Some(1000),
+        max_entity_expansion: None,
+        max_entity_references: None,
+        ..Default::default()
+    };
+    let xml = entity_bomb(8, "lol");
+    let (limit, span) = limit_exceeded(parse_with_limits(&xml, limits));
+    assert_eq!(limit, xot::ParseLimit::TextLength);
+    assert_eq!(&xml[span.range()], "&l8;");
+}
+
+#[test]
+fn test_limit_entity_expansion() {
+    let limits = xot::ParseLimits {
+        max_entity_expansion: Some(6),
+        ..Default::default()
+    };
+    let xml = r#"<!DOCTYPE a [<!ENTITY e "EEE">]><a b="&e;">&e;</a>"#;
+    assert!(parse_with_limits(xml, limits).is_ok());
+    let xml = r#"<!DOCTYPE a [<!ENTITY e "EEE">]><a b="&e;">&e;&e;</a>"#;
+    let (limit, span) = limit_exceeded(parse_with_limits(xml, limits));
+    assert_eq!(limit, xot::ParseLimit::EntityExpansion);
+    assert_eq!(span.start, xml.rfind("&e;").unwrap());
+}
+
+#[test]
+fn test_limit_entity_references() {
+    let limits = xot::ParseLimits {
+        max_entity_references: Some(2),
+        ..Default::default()
+    };
+    // predefined entities and character references don't count
+    let xml = r#"<!DOCTYPE a [<!ENTITY e "&#69;">]><a>&e;&amp;&#69;&e;</a>"#;
+    assert!(parse_with_limits(xml, limits).is_ok());
+    let xml = r#"<!DOCTYPE a [<!ENTITY e "E"><!ENTITY f "&e;">]><a>&e;&f;</a>"#;
+    let (limit, span) = limit_exceeded(parse_with_limits(xml, limits));
+    assert_eq!(limit, xot::ParseLimit::EntityReferences);
+    assert_eq!(&xml[span.range()], "&f;");
+}
+
+#[test]
+fn test_limit_nodes() {
+    let limits = xot::ParseLimits {
+        max_nodes: Some(4),
+        ..Default::default()
+    };
+    // the document, two elements and an attribute
+    assert!(parse_with_limits(r#"<a><b x="1"/></a>"#, limits).is_ok());
+    let xml = r#"<a><b x="1"/><!--c--></a>"#;
+    let (limit, span) = limit_exceeded(parse_with_limits(xml, limits));
+    assert_eq!(limit, xot::ParseLimit::Nodes);
+    assert_eq!(&xml[span.range()], "c");
+}
+
+#[test]
+fn test_limit_names() {
+    let mut xot = Xot::new();
+    xot.add_name("a");
+    let options = xot::ParseOptions {
+        limits: xot::ParseLimits {
+            max_names: Some(2),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    // names that are known already don't count
+    assert!(xot.parse_with_options("<a><b/><c/></a>", &options).is_ok());
+    let xml = "<a><d/><e/><f/></a>";
+    let error = xot.parse_with_options(xml, &options).err().unwrap();
+    assert!(matches!(
+        error,
+        xot::ParseError::LimitExceeded(xot::ParseLimit::Names, _)
+    ));
+    assert_eq!(&xml[error.span().range()], "f");
+    assert_eq!(error.to_string(), "Limit exceeded: number of names");
+}
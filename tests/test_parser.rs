@@ -1,4 +1,4 @@
-use xot::{ParseError, Span, SpanInfoKey, Xot};
+use xot::{output, ParseError, ParseOptions, Span, SpanInfoKey, Xot};
 
 const US_ASCII: &str = include_str!("fixtures/us-ascii.xml");
 
@@ -477,3 +477,610 @@ fn test_parse_fragment_with_span_info() {
         &Span::new(4, 5)
     );
 }
+
+#[test]
+fn test_parse_empty_doctype() {
+    let mut xot = Xot::new();
+    let doc = xot.parse(r#"<!DOCTYPE note><note/>"#).unwrap();
+    assert_eq!(xot.to_string(doc).unwrap(), "<!DOCTYPE note>\n<note/>");
+}
+
+#[test]
+fn test_parse_external_dtd_not_loaded() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(r#"<?xml version="1.0"?><!DOCTYPE note SYSTEM "Note.dtd"><note/>"#)
+        .unwrap();
+    assert_eq!(
+        xot.to_string(doc).unwrap(),
+        "<!DOCTYPE note SYSTEM \"Note.dtd\">\n<note/>"
+    );
+}
+
+#[test]
+fn test_parse_internal_entity() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(r#"<!DOCTYPE doc [<!ENTITY product "Xot">]><doc>Hello &product;!</doc>"#)
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.to_string(doc_el).unwrap(), "<doc>Hello Xot!</doc>");
+}
+
+#[test]
+fn test_parse_internal_entity_in_attribute() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(r#"<!DOCTYPE doc [<!ENTITY product "X&#38;#38;t">]><doc a="&product;"/>"#)
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.to_string(doc_el).unwrap(), r#"<doc a="X&amp;t"/>"#);
+}
+
+#[test]
+fn test_parse_internal_entity_nested() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(
+            r#"<!DOCTYPE doc [
+  <!ENTITY name "Xot">
+  <!ENTITY greeting "Hello &name;">
+]>
+<doc>&greeting;</doc>"#,
+        )
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.to_string(doc_el).unwrap(), "<doc>Hello Xot</doc>");
+}
+
+#[test]
+fn test_parse_internal_subset_comments_and_pis_skipped() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(
+            r#"<!DOCTYPE doc [
+  <!-- comment -->
+  <?pi data?>
+  <!ELEMENT doc (#PCDATA)>
+  <!ENTITY % param "ignored">
+]>
+<doc/>"#,
+        )
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.to_string(doc_el).unwrap(), "<doc/>");
+}
+
+#[test]
+fn test_parse_document_type() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(
+            r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Strict//EN" 'http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd' [
+  <!ENTITY e "E">
+]><html/>"#,
+        )
+        .unwrap();
+    let document_type = xot.document_type(doc).unwrap();
+    assert_eq!(document_type.name, "html");
+    assert_eq!(
+        document_type.public_id.as_deref(),
+        Some("-//W3C//DTD XHTML 1.0 Strict//EN")
+    );
+    assert_eq!(
+        document_type.system_id.as_deref(),
+        Some("http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd")
+    );
+    assert_eq!(
+        document_type.internal_subset.as_deref(),
+        Some("\n  <!ENTITY e \"E\">\n")
+    );
+}
+
+#[test]
+fn test_parse_no_document_type() {
+    let mut xot = Xot::new();
+    let doc = xot.parse(r#"<doc/>"#).unwrap();
+    assert!(xot.document_type(doc).is_none());
+}
+
+#[test]
+fn test_document_type_roundtrip() {
+    let mut xot = Xot::new();
+    let xml = r#"<!DOCTYPE doc SYSTEM "doc.dtd" [<!ENTITY e "E"><!-- comment -->]>
+<doc>&e;</doc>"#;
+    let doc = xot.parse(xml).unwrap();
+    assert_eq!(
+        xot.to_string(doc).unwrap(),
+        r#"<!DOCTYPE doc SYSTEM "doc.dtd" [<!ENTITY e "E"><!-- comment -->]>
+<doc>E</doc>"#
+    );
+}
+
+#[test]
+fn test_clone_document_retains_document_type() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(r#"<!DOCTYPE doc SYSTEM "doc.dtd"><doc/>"#)
+        .unwrap();
+    let cloned = xot.clone_node(doc);
+    assert_eq!(xot.document_type(cloned), xot.document_type(doc));
+}
+
+#[test]
+fn test_set_document_type_not_document() {
+    let mut xot = Xot::new();
+    let doc = xot.parse(r#"<doc/>"#).unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert!(xot
+        .set_document_type(doc_el, Some(xot::DocumentType::new("doc")))
+        .is_err());
+}
+
+#[test]
+fn test_parse_attribute_defaults() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(
+            r#"<!DOCTYPE doc [
+  <!ATTLIST p class CDATA "normal" lang NMTOKEN #FIXED "en" note CDATA #IMPLIED>
+]>
+<doc><p>default</p><p class="special">explicit</p></doc>"#,
+        )
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(
+        xot.to_string(doc_el).unwrap(),
+        r#"<doc><p class="normal" lang="en">default</p><p class="special" lang="en">explicit</p></doc>"#
+    );
+}
+
+#[test]
+fn test_parse_attribute_default_with_entity() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(
+            r#"<!DOCTYPE doc [
+  <!ENTITY product "Xot">
+  <!ATTLIST doc name CDATA "&product;">
+]>
+<doc/>"#,
+        )
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.to_string(doc_el).unwrap(), r#"<doc name="Xot"/>"#);
+}
+
+#[test]
+fn test_parse_attribute_default_namespace() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(
+            r#"<!DOCTYPE doc [
+  <!ATTLIST doc xmlns CDATA #FIXED "http://example.com">
+]>
+<doc/>"#,
+        )
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    let name = xot.name_ns("doc", xot.namespace("http://example.com").unwrap());
+    assert_eq!(xot.element(doc_el).unwrap().name(), name.unwrap());
+}
+
+#[test]
+fn test_parse_tokenized_attribute_normalized() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(
+            r#"<!DOCTYPE doc [
+  <!ATTLIST doc refs IDREFS #IMPLIED>
+]>
+<doc refs="  a   b "/>"#,
+        )
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.to_string(doc_el).unwrap(), r#"<doc refs="a b"/>"#);
+}
+
+#[test]
+fn test_parse_dtd_id_attribute() {
+    let mut xot = Xot::new();
+    let doc = xot
+        .parse(
+            r#"<!DOCTYPE doc [
+  <!ATTLIST section id ID #IMPLIED>
+]>
+<doc><section id=" intro "/><section id="body"/><other id="intro"/></doc>"#,
+        )
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    let intro = xot.first_child(doc_el).unwrap();
+    let body = xot.next_sibling(intro).unwrap();
+    assert_eq!(xot.xml_id_node(doc, "intro"), Some(intro));
+    assert_eq!(xot.xml_id_node(doc, "body"), Some(body));
+}
+
+#[test]
+fn test_parse_reader() {
+    let mut xot = Xot::new();
+    let doc = xot.parse_reader(&b"<doc><a/></doc>"[..]).unwrap();
+    assert_eq!(xot.to_string(doc).unwrap(), "<doc><a/></doc>");
+}
+
+#[test]
+fn test_parse_reader_utf16() {
+    let mut xot = Xot::new();
+    let mut data = vec![0xFE, 0xFF];
+    for unit in r#"<?xml version="1.0" encoding="UTF-16"?><doc>é</doc>"#.encode_utf16() {
+        data.extend_from_slice(&unit.to_be_bytes());
+    }
+    let doc = xot.parse_reader(&data[..]).unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.text_content_str(doc_el), Some("é"));
+}
+
+#[test]
+fn test_parse_reader_error_byte_offset() {
+    let mut xot = Xot::new();
+    let xml = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><doc>\xe9\xe9<a></doc>";
+    let err = xot.parse_reader(&xml[..]).unwrap_err();
+    // the span is in bytes of the input, where each é is a single byte
+    let start = xml.windows(2).position(|w| w == b"</").unwrap() + 2;
+    match err {
+        ParseError::InvalidCloseTag(_, _, span) => {
+            assert_eq!(span, (start..start + 3).into());
+        }
+        _ => panic!("unexpected error: {:?}", err),
+    }
+}
+
+#[test]
+fn test_parse_reader_with_options() {
+    let mut xot = Xot::new();
+    let options = xot::ParseOptions {
+        limits: xot::ParseLimits {
+            max_text_length: Some(4),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let xml = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><doc>\xe9\xe9<a>abcde</a></doc>";
+    let err = xot
+        .parse_reader_with_options(&xml[..], &options)
+        .err()
+        .unwrap();
+    // the span is in bytes of the input, where each é is a single byte
+    let start = xml.windows(5).position(|w| w == b"abcde").unwrap();
+    match err {
+        ParseError::LimitExceeded(xot::ParseLimit::TextLength, span) => {
+            assert_eq!(span, (start..start + 5).into());
+        }
+        _ => panic!("unexpected error: {:?}", err),
+    }
+}
+
+#[test]
+fn test_parse_reader_malformed() {
+    let mut xot = Xot::new();
+    let err = xot.parse_reader(&b"<doc>\xff</doc>"[..]).unwrap_err();
+    assert!(matches!(err, ParseError::MalformedEncoding(_, 5)));
+}
+
+#[test]
+fn test_parse_reader_io_error() {
+    struct FailingReader;
+
+    impl std::io::Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken"))
+        }
+    }
+
+    let mut xot = Xot::new();
+    let err = xot.parse_reader(FailingReader).unwrap_err();
+    assert!(matches!(err, ParseError::Io(_, 0)));
+}
+
+#[test]
+fn test_parse_xml11() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse("<?xml version=\"1.1\"?><doc a=\"x\u{85}y\">a\u{85}b\r\u{85}c\u{2028}d&#x1;</doc>")
+        .unwrap();
+    let doc = xot.document_element(root).unwrap();
+    let a = xot.name("a").unwrap();
+    assert_eq!(xot.get_attribute(doc, a), Some("x y"));
+    assert_eq!(xot.text_content_str(doc), Some("a\nb\nc\nd\u{1}"));
+}
+
+#[test]
+fn test_parse_xml10_no_nel_line_end() {
+    let mut xot = Xot::new();
+    let root = xot.parse("<doc>a\u{85}b\r\u{85}c</doc>").unwrap();
+    let doc = xot.document_element(root).unwrap();
+    assert_eq!(xot.text_content_str(doc), Some("a\u{85}b\n\u{85}c"));
+}
+
+#[test]
+fn test_parse_xml10_control_character_reference() {
+    let mut xot = Xot::new();
+    let err = xot.parse("<doc>&#x1;</doc>").unwrap_err();
+    assert!(matches!(err, ParseError::InvalidEntity(..)));
+}
+
+#[test]
+fn test_parse_xml11_undeclared_prefix() {
+    let mut xot = Xot::new();
+    let err = xot
+        .parse(r#"<?xml version="1.1"?><p:doc xmlns:p="http://example.com"><a xmlns:p=""><p:b/></a></p:doc>"#)
+        .unwrap_err();
+    assert!(matches!(err, ParseError::UnknownPrefix(..)));
+}
+
+#[test]
+fn test_parse_options_default() {
+    let xml = "<doc>\n  <!--c--><?pi x?><p>a<![CDATA[b]]></p>\n</doc>";
+    let mut xot = Xot::new();
+    let (doc, span_info) = xot
+        .parse_with_options(xml, &ParseOptions::default())
+        .unwrap();
+    assert_eq!(
+        xot.to_string(doc).unwrap(),
+        xml.replace("<![CDATA[b]]>", "b")
+    );
+    let doc_el = xot.document_element(doc).unwrap();
+    assert!(span_info.get(SpanInfoKey::ElementStart(doc_el)).is_some());
+}
+
+#[test]
+fn test_parse_options_strip_whitespace() {
+    let xml = r#"<doc>
+  <p>hello <i>world</i>  </p>
+  <pre xml:space="preserve">  <b/>  </pre>
+  <q>  </q>
+</doc>"#;
+    let options = ParseOptions {
+        strip_whitespace: true,
+        ..Default::default()
+    };
+    let mut xot = Xot::new();
+    let (doc, _) = xot.parse_with_options(xml, &options).unwrap();
+    let expected = xot.parse(xml).unwrap();
+    xot.remove_insignificant_whitespace(expected);
+    assert_eq!(
+        xot.to_string(doc).unwrap(),
+        xot.to_string(expected).unwrap()
+    );
+    assert_eq!(
+        xot.to_string(doc).unwrap(),
+        r#"<doc><p>hello <i>world</i>  </p><pre xml:space="preserve">  <b/>  </pre><q/></doc>"#
+    );
+}
+
+#[test]
+fn test_parse_options_strip_whitespace_fragment() {
+    let options = ParseOptions {
+        strip_whitespace: true,
+        ..Default::default()
+    };
+    let mut xot = Xot::new();
+    let (doc, _) = xot
+        .parse_fragment_with_options("  <a/>  <b/>  ", &options)
+        .unwrap();
+    assert_eq!(xot.to_string(doc).unwrap(), "<a/><b/>");
+}
+
+#[test]
+fn test_parse_options_drop_comments_and_pis() {
+    let options = ParseOptions {
+        drop_comments: true,
+        drop_processing_instructions: true,
+        ..Default::default()
+    };
+    let mut xot = Xot::new();
+    let (doc, _) = xot
+        .parse_with_options("<!--top--><doc>a<!--c-->b<?pi?>c</doc><?end?>", &options)
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert_eq!(xot.to_string(doc).unwrap(), "<doc>abc</doc>");
+    // the text is consolidated into a single node
+    assert_eq!(xot.children(doc_el).count(), 1);
+}
+
+#[test]
+fn test_parse_options_no_duplicate_id_check() {
+    let xml = r#"<doc><a xml:id="x"/><b xml:id="x"/></doc>"#;
+    let mut xot = Xot::new();
+    assert!(xot.parse(xml).is_err());
+    let options = ParseOptions {
+        check_duplicate_ids: false,
+        ..Default::default()
+    };
+    let (doc, _) = xot.parse_with_options(xml, &options).unwrap();
+    let a = xot.first_child(xot.document_element(doc).unwrap()).unwrap();
+    assert_eq!(xot.xml_id_node(doc, "x"), Some(a));
+}
+
+#[test]
+fn test_parse_options_no_text_consolidation() {
+    let options = ParseOptions {
+        consolidate_text: false,
+        ..Default::default()
+    };
+    let mut xot = Xot::new();
+    let (doc, span_info) = xot
+        .parse_with_options("<doc>a<![CDATA[b]]>&amp;</doc>", &options)
+        .unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    let texts = xot
+        .children(doc_el)
+        .map(|child| xot.text_str(child).unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(texts, vec!["a", "b", "&"]);
+    let b = xot.children(doc_el).nth(1).unwrap();
+    assert_eq!(span_info.get(SpanInfoKey::Text(b)), Some(&(15..16).into()));
+}
+
+#[test]
+fn test_parse_options_no_span_info() {
+    let options = ParseOptions {
+        span_info: false,
+        ..Default::default()
+    };
+    let mut xot = Xot::new();
+    let (doc, span_info) = xot.parse_with_options("<doc>text</doc>", &options).unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    assert!(span_info.get(SpanInfoKey::ElementStart(doc_el)).is_none());
+}
+
+#[test]
+fn test_parse_options_preserve_cdata() {
+    let xml = "<doc>a<![CDATA[<b>]]><![CDATA[&]]>c<![CDATA[ ]]></doc>";
+    let options = ParseOptions {
+        preserve_cdata: true,
+        ..Default::default()
+    };
+    let mut xot = Xot::new();
+    let (doc, _) = xot.parse_with_options(xml, &options).unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    let texts = xot
+        .children(doc_el)
+        .map(|child| {
+            let text = xot.text(child).unwrap();
+            (text.get().to_string(), text.is_cdata())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        texts,
+        vec![
+            ("a".to_string(), false),
+            ("<b>&".to_string(), true),
+            ("c".to_string(), false),
+            (" ".to_string(), true),
+        ]
+    );
+    assert_eq!(
+        xot.to_string(doc).unwrap(),
+        "<doc>a<![CDATA[<b>&]]>c<![CDATA[ ]]></doc>"
+    );
+    assert_eq!(xot.text_content_str(doc_el), None);
+    assert_eq!(xot.string_value(doc_el), "a<b>&c ");
+
+    // by default CDATA is ordinary text
+    let doc = xot.parse(xml).unwrap();
+    assert_eq!(xot.to_string(doc).unwrap(), "<doc>a&lt;b&gt;&amp;c </doc>");
+}
+
+fn lossless() -> ParseOptions {
+    ParseOptions {
+        lossless: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_parse_options_lossless_round_trip() {
+    let xml = concat!(
+        "<?xml version='1.0' encoding=\"UTF-8\"?>\n",
+        "<!-- prolog -->\n",
+        "<!DOCTYPE doc [<!ENTITY e 'E'>]>\n",
+        "<doc  a = 'A &amp; B'\n     xmlns:p=\"urn:p\" p:b=\"&#65;\" >\n",
+        "  <empty></empty><self  />\n",
+        "  <p:item>t&e;&#x41;&gt;\r\n<![CDATA[<x>]]></p:item>\n",
+        "  <?pi   data ?>\n",
+        "</doc  >\n",
+        "<!-- epilog -->\n",
+    );
+    let mut xot = Xot::new();
+    let (doc, _) = xot.parse_with_options(xml, &lossless()).unwrap();
+    assert_eq!(xot.to_string(doc).unwrap(), xml);
+
+    let mut buf = Vec::new();
+    xot.write(doc, &mut buf).unwrap();
+    assert_eq!(buf, xml.as_bytes());
+
+    // a node within the document is the same as in the source too
+    let doc_el = xot.document_element(doc).unwrap();
+    let item = xot
+        .children(doc_el)
+        .find(|child| xot.is_element(*child) && xot.first_child(*child).is_some())
+        .unwrap();
+    assert_eq!(
+        xot.to_string(item).unwrap(),
+        "<p:item xmlns:p=\"urn:p\">t&e;&#x41;&gt;\r\n<![CDATA[<x>]]></p:item>"
+    );
+}
+
+#[test]
+fn test_parse_options_lossless_fragment() {
+    let xml = "text &lt; <a  b='1'/>\n<!--c-->";
+    let mut xot = Xot::new();
+    let (doc, _) = xot.parse_fragment_with_options(xml, &lossless()).unwrap();
+    assert_eq!(xot.to_string(doc).unwrap(), xml);
+}
+
+#[test]
+fn test_parse_options_lossless_change_attribute() {
+    let xml = concat!(
+        "<config>\n",
+        "  <item  name = 'a'   value='1' />\n",
+        "  <item name='b' value=\"2\"></item>\n",
+        "</config>\n",
+    );
+    let mut xot = Xot::new();
+    let (doc, _) = xot.parse_with_options(xml, &lossless()).unwrap();
+    let config = xot.document_element(doc).unwrap();
+    let items = xot
+        .children(config)
+        .filter(|child| xot.is_element(*child))
+        .collect::<Vec<_>>();
+    let value = xot.add_name("value");
+    let extra = xot.add_name("extra");
+    // the changed attribute keeps its quotes and the whitespace before it
+    xot.set_attribute(items[0], value, "<new>");
+    // an added attribute is serialized as usual
+    xot.set_attribute(items[1], extra, "x");
+    assert_eq!(
+        xot.to_string(doc).unwrap(),
+        concat!(
+            "<config>\n",
+            "  <item  name = 'a'   value='&lt;new>' />\n",
+            "  <item name='b' value=\"2\" extra=\"x\"></item>\n",
+            "</config>\n",
+        )
+    );
+
+    // removing the attribute again, we get back the original
+    xot.remove_attribute(items[1], extra);
+    xot.set_attribute(items[0], value, "1");
+    assert_eq!(xot.to_string(doc).unwrap(), xml);
+}
+
+#[test]
+fn test_parse_options_lossless_change_content() {
+    let xml = "<doc><a  /><b></b><c>text</c></doc>";
+    let mut xot = Xot::new();
+    let (doc, _) = xot.parse_with_options(xml, &lossless()).unwrap();
+    let doc_el = xot.document_element(doc).unwrap();
+    let a = xot.first_child(doc_el).unwrap();
+    let b = xot.next_sibling(a).unwrap();
+    let c = xot.next_sibling(b).unwrap();
+    xot.append_text(a, "added").unwrap();
+    xot.remove(xot.first_child(c).unwrap()).unwrap();
+    xot.append_text(c, "new").unwrap();
+    assert_eq!(
+        xot.to_string(doc).unwrap(),
+        "<doc><a  >added</a><b></b><c>new</c></doc>"
+    );
+    // serialization parameters that ask for different output still apply
+    let xml = xot
+        .serialize_xml_string(
+            output::xml::Parameters {
+                indentation: Some(Default::default()),
+                ..Default::default()
+            },
+            doc,
+        )
+        .unwrap();
+    assert_eq!(xml, "<doc>\n  <a>added</a>\n  <b/>\n  <c>new</c>\n</doc>\n");
+}
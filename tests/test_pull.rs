@@ -0,0 +1,380 @@
+use std::borrow::Cow;
+
+use xot::pull::{Event, Parser};
+use xot::{ParseError, Span, XmlVersion, Xot};
+
+fn events<'a>(xot: &mut Xot, xml: &'a str) -> Result<Vec<Event<'a>>, ParseError> {
+    let mut parser = Parser::new(xot, xml);
+    let mut events = Vec::new();
+    while let Some(event) = parser.next_event(xot)? {
+        events.push(event);
+    }
+    Ok(events)
+}
+
+#[test]
+fn test_pull_elements() {
+    let mut xot = Xot::new();
+    let doc = xot.add_name("doc");
+    let a = xot.add_name("a");
+    let events = events(&mut xot, "<doc><a/>text</doc>").unwrap();
+    assert_eq!(
+        events,
+        vec![
+            Event::StartElement {
+                name: doc,
+                namespaces: vec![],
+                attributes: vec![],
+                span: Span::new(1, 4),
+            },
+            Event::StartElement {
+                name: a,
+                namespaces: vec![],
+                attributes: vec![],
+                span: Span::new(6, 7),
+            },
+            Event::EndElement {
+                name: a,
+                span: Span::new(7, 9),
+            },
+            Event::Text {
+                text: Cow::Borrowed("text"),
+                span: Span::new(9, 13),
+            },
+            Event::EndElement {
+                name: doc,
+                span: Span::new(13, 19),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_pull_namespaces() {
+    let mut xot = Xot::new();
+    let ns = xot.add_namespace("http://example.com");
+    let prefix = xot.add_prefix("p");
+    let doc = xot.add_name_ns("doc", ns);
+    let a = xot.add_name_ns("a", ns);
+    let b = xot.add_name("b");
+    let events = events(
+        &mut xot,
+        r#"<p:doc xmlns:p="http://example.com"><p:a b="1"/></p:doc>"#,
+    )
+    .unwrap();
+    match &events[0] {
+        Event::StartElement {
+            name, namespaces, ..
+        } => {
+            assert_eq!(*name, doc);
+            assert_eq!(namespaces, &vec![(prefix, ns)]);
+        }
+        _ => panic!("expected start element"),
+    }
+    match &events[1] {
+        Event::StartElement {
+            name, attributes, ..
+        } => {
+            assert_eq!(*name, a);
+            assert_eq!(attributes.len(), 1);
+            assert_eq!(attributes[0].name, b);
+            assert_eq!(attributes[0].value, "1");
+            assert!(!attributes[0].is_id);
+        }
+        _ => panic!("expected start element"),
+    }
+}
+
+#[test]
+fn test_pull_entities_expanded() {
+    let mut xot = Xot::new();
+    let events = events(
+        &mut xot,
+        r#"<!DOCTYPE doc [<!ENTITY e "E">]><doc a="&e;&amp;">&e;&lt;</doc>"#,
+    )
+    .unwrap();
+    assert!(
+        matches!(&events[0], Event::DocumentType(document_type) if document_type.name == "doc")
+    );
+    match &events[1] {
+        Event::StartElement { attributes, .. } => {
+            assert_eq!(attributes[0].value, "E&");
+        }
+        _ => panic!("expected start element"),
+    }
+    match &events[2] {
+        Event::Text { text, .. } => assert_eq!(text, "E<"),
+        _ => panic!("expected text"),
+    }
+}
+
+#[test]
+fn test_pull_attribute_defaults_and_ids() {
+    let mut xot = Xot::new();
+    let events = events(
+        &mut xot,
+        r#"<!DOCTYPE doc [<!ATTLIST doc id ID #IMPLIED b CDATA "B">]><doc id=" x "/>"#,
+    )
+    .unwrap();
+    match &events[1] {
+        Event::StartElement { attributes, .. } => {
+            assert_eq!(attributes.len(), 2);
+            assert_eq!(attributes[0].value, "x");
+            assert!(attributes[0].is_id);
+            assert_eq!(attributes[1].value, "B");
+            assert!(!attributes[1].is_id);
+        }
+        _ => panic!("expected start element"),
+    }
+}
+
+#[test]
+fn test_pull_cdata_comment_pi() {
+    let mut xot = Xot::new();
+    let target = xot.add_name("target");
+    let events = events(&mut xot, "<doc><![CDATA[<>]]><!--c--><?target data?></doc>").unwrap();
+    assert_eq!(
+        events[1],
+        Event::Cdata {
+            text: "<>",
+            span: Span::new(14, 16),
+        }
+    );
+    assert_eq!(
+        events[2],
+        Event::Comment {
+            text: "c",
+            span: Span::new(23, 24),
+        }
+    );
+    assert_eq!(
+        events[3],
+        Event::ProcessingInstruction {
+            target,
+            data: Some("data"),
+            target_span: Span::new(29, 35),
+            data_span: Some(Span::new(36, 40)),
+        }
+    );
+}
+
+#[test]
+fn test_pull_depth() {
+    let mut xot = Xot::new();
+    let mut parser = Parser::new(&xot, "<a><b/></a>");
+    let mut depths = Vec::new();
+    while parser.next_event(&mut xot).unwrap().is_some() {
+        depths.push(parser.depth());
+    }
+    assert_eq!(depths, vec![1, 2, 1, 0]);
+}
+
+#[test]
+fn test_pull_unclosed_tag() {
+    let mut xot = Xot::new();
+    let mut parser = Parser::new(&xot, "<a><b></b>");
+    assert!(parser.next_event(&mut xot).unwrap().is_some());
+    assert!(parser.next_event(&mut xot).unwrap().is_some());
+    assert!(parser.next_event(&mut xot).unwrap().is_some());
+    assert!(matches!(
+        parser.next_event(&mut xot),
+        Err(ParseError::UnclosedTag(_))
+    ));
+    // the parser is done after an error
+    assert_eq!(parser.next_event(&mut xot).unwrap(), None);
+}
+
+#[test]
+fn test_pull_invalid_close_tag() {
+    let mut xot = Xot::new();
+    let err = events(&mut xot, "<a></b>").unwrap_err();
+    assert!(matches!(err, ParseError::InvalidCloseTag(..)));
+}
+
+#[test]
+fn test_pull_no_element() {
+    let mut xot = Xot::new();
+    let err = events(&mut xot, "<!--comment-->").unwrap_err();
+    assert!(matches!(err, ParseError::NoElementAtTopLevel(14)));
+}
+
+#[test]
+fn test_pull_fragment() {
+    let mut xot = Xot::new();
+    let a = xot.add_name("a");
+    let mut parser = Parser::new_fragment(&xot, "text<a/><a/>");
+    let mut events = Vec::new();
+    while let Some(event) = parser.next_event(&mut xot).unwrap() {
+        events.push(event);
+    }
+    assert_eq!(events.len(), 5);
+    assert!(matches!(&events[0], Event::Text { text, .. } if text == "text"));
+    assert!(matches!(&events[3], Event::StartElement { name, .. } if *name == a));
+}
+
+#[test]
+fn test_pull_does_not_build_tree() {
+    let mut xot = Xot::new();
+    let root = xot.parse("<doc/>").unwrap();
+    events(&mut xot, "<doc><a/></doc>").unwrap();
+    // names are interned in the same Xot, so they can be looked up
+    assert!(xot.name("a").is_some());
+    assert_eq!(xot.to_string(root).unwrap(), "<doc/>");
+}
+
+#[test]
+fn test_build_subtree() {
+    let mut xot = Xot::new();
+    let record = xot.add_name("record");
+    let mut parser = Parser::new(&xot, "<records><record><a/>text</record><after/></records>");
+    let mut documents = Vec::new();
+    while let Some(event) = parser.next_event(&mut xot).unwrap() {
+        if matches!(event, Event::StartElement { name, .. } if name == record) {
+            documents.push(parser.build_subtree(&mut xot, event).unwrap());
+        }
+    }
+    assert_eq!(documents.len(), 1);
+    assert_eq!(
+        xot.to_string(documents[0]).unwrap(),
+        "<record><a/>text</record>"
+    );
+}
+
+#[test]
+fn test_build_subtree_continues_after_element() {
+    let mut xot = Xot::new();
+    let record = xot.add_name("record");
+    let after = xot.add_name("after");
+    let mut parser = Parser::new(&xot, "<records><record><a/></record><after/></records>");
+    parser.next_event(&mut xot).unwrap();
+    let event = parser.next_event(&mut xot).unwrap().unwrap();
+    assert!(matches!(event, Event::StartElement { name, .. } if name == record));
+    parser.build_subtree(&mut xot, event).unwrap();
+    assert_eq!(parser.depth(), 1);
+    let event = parser.next_event(&mut xot).unwrap().unwrap();
+    assert!(matches!(event, Event::StartElement { name, .. } if name == after));
+}
+
+#[test]
+fn test_build_subtree_empty_element() {
+    let mut xot = Xot::new();
+    let mut parser = Parser::new(
+        &xot,
+        r#"<records xmlns="http://example.com"><record a="1"/></records>"#,
+    );
+    parser.next_event(&mut xot).unwrap();
+    let event = parser.next_event(&mut xot).unwrap().unwrap();
+    let document = parser.build_subtree(&mut xot, event).unwrap();
+    assert_eq!(
+        xot.to_string(document).unwrap(),
+        r#"<record xmlns="http://example.com" a="1"/>"#
+    );
+    // the end of records is next
+    assert!(matches!(
+        parser.next_event(&mut xot).unwrap(),
+        Some(Event::EndElement { .. })
+    ));
+}
+
+#[test]
+fn test_build_subtree_inherited_namespaces() {
+    let mut xot = Xot::new();
+    let record = xot.add_name("record");
+    let xml = r#"<a xmlns:x="http://x.com" xmlns:y="http://y.com"><b xmlns:x="http://x2.com" xmlns="http://default.com"><c xmlns=""><record xmlns:y="http://y2.com" x:foo="1"><y:z/></record></c></b></a>"#;
+    let mut parser = Parser::new(&xot, xml);
+    let mut documents = Vec::new();
+    parser
+        .for_each_subtree(&mut xot, record, |xot, document| {
+            documents.push(xot.to_string(document)?);
+            Ok::<(), xot::Error>(())
+        })
+        .unwrap();
+    // the innermost x wins, y is declared on record itself and the default
+    // namespace is undeclared again, so needn't be declared
+    assert_eq!(
+        documents,
+        vec![
+            r#"<record xmlns:y="http://y2.com" xmlns:x="http://x2.com" x:foo="1"><y:z/></record>"#
+        ]
+    );
+}
+
+#[test]
+fn test_for_each_subtree_frees_trees() {
+    let mut xot = Xot::new();
+    let record = xot.add_name("record");
+    let mut parser = Parser::new(
+        &xot,
+        r#"<records><record xml:id="a">A</record><record xml:id="b">B</record></records>"#,
+    );
+    let mut documents = Vec::new();
+    parser
+        .for_each_subtree(&mut xot, record, |xot, document| {
+            let record = xot.document_element(document).unwrap();
+            // ids are indexed per record
+            assert_eq!(
+                xot.xml_id_node(document, "a").is_some(),
+                xot.text_content_str(record) == Some("A")
+            );
+            documents.push(document);
+            Ok::<(), ParseError>(())
+        })
+        .unwrap();
+    assert_eq!(documents.len(), 2);
+    assert!(documents.iter().all(|document| xot.is_removed(*document)));
+}
+
+#[test]
+fn test_for_each_subtree_stops_on_error() {
+    let mut xot = Xot::new();
+    let record = xot.add_name("record");
+    let mut parser = Parser::new(
+        &xot,
+        "<records><record>A</record><record>B</record><record>C</record></records>",
+    );
+    let mut documents = Vec::new();
+    let result = parser.for_each_subtree(&mut xot, record, |xot, document| {
+        documents.push(document);
+        let record = xot.document_element(document)?;
+        if xot.text_content_str(record) == Some("B") {
+            return Err(xot::Error::InvalidOperation("B".to_string()));
+        }
+        Ok(())
+    });
+    assert!(matches!(result, Err(xot::Error::InvalidOperation(_))));
+    // the tree is freed even when the callback fails, and parsing stops
+    assert_eq!(documents.len(), 2);
+    assert!(documents.iter().all(|document| xot.is_removed(*document)));
+    assert!(parser.next_event(&mut xot).unwrap().is_some());
+}
+
+#[test]
+fn test_for_each_subtree_parse_error() {
+    let mut xot = Xot::new();
+    let record = xot.add_name("record");
+    let mut parser = Parser::new(&xot, "<records><record>A</record><record></b></records>");
+    let result = parser.for_each_subtree(&mut xot, record, |_, _| Ok::<(), xot::Error>(()));
+    assert!(matches!(
+        result,
+        Err(xot::Error::Parse(ParseError::InvalidCloseTag(..)))
+    ));
+}
+
+#[test]
+fn test_build_subtree_error() {
+    let mut xot = Xot::new();
+    let mut parser = Parser::new(&xot, "<records><record><a></b></record></records>");
+    parser.next_event(&mut xot).unwrap();
+    let event = parser.next_event(&mut xot).unwrap().unwrap();
+    let err = parser.build_subtree(&mut xot, event).unwrap_err();
+    assert!(matches!(err, ParseError::InvalidCloseTag(..)));
+}
+
+#[test]
+fn test_pull_version() {
+    let mut xot = Xot::new();
+    let mut parser = Parser::new(&xot, r#"<?xml version="1.1"?><doc/>"#);
+    assert_eq!(parser.version(), XmlVersion::V1_0);
+    parser.next_event(&mut xot).unwrap();
+    assert_eq!(parser.version(), XmlVersion::V1_1);
+}
@@ -0,0 +1,246 @@
+use xot::xinclude::{self, FileResolver, MemoryResolver};
+use xot::{Error, Xot};
+
+const XI: &str = r#"xmlns:xi="http://www.w3.org/2001/XInclude""#;
+
+fn process(xot: &mut Xot, xml: &str, resolver: &mut MemoryResolver) -> Result<String, Error> {
+    let root = xot.parse(xml).unwrap();
+    xot.set_document_uri(root, Some("http://example.com/doc.xml"))?;
+    xinclude::process(xot, root, resolver)?;
+    // leave out the document element
+    let xml = xot.to_string(root)?;
+    let result = xml
+        .strip_prefix(&format!("<doc {}>", XI))
+        .and_then(|xml| xml.strip_suffix("</doc>"))
+        .unwrap()
+        .to_string();
+    Ok(result)
+}
+
+#[test]
+fn test_include_xml_and_text() {
+    let mut resolver = MemoryResolver::new();
+    resolver.add(
+        "http://example.com/parts/a.xml",
+        r#"<!-- a --><a xmlns:p="http://p"><p:b/></a>"#,
+    );
+    // latin-1 encoded text
+    resolver.add("http://example.com/parts/a.txt", b"caf\xe9 & co".to_vec());
+
+    let mut xot = Xot::new();
+    let xml = format!(
+        r#"<doc {}><xi:include href="parts/a.xml"/><t><xi:include href="parts/a.txt" parse="text" encoding="iso-8859-1"/></t></doc>"#,
+        XI
+    );
+    assert_eq!(
+        process(&mut xot, &xml, &mut resolver).unwrap(),
+        r#"<!-- a --><a xmlns:p="http://p" xml:base="parts/a.xml"><p:b/></a><t>café &amp; co</t>"#
+    );
+}
+
+#[test]
+fn test_include_nested_relative() {
+    let mut resolver = MemoryResolver::new();
+    resolver.add(
+        "http://example.com/sub/b.xml",
+        format!(r#"<b {}><xi:include href="c.xml"/></b>"#, XI),
+    );
+    resolver.add("http://example.com/sub/c.xml", "<c/>");
+
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(&format!(
+            r#"<doc {}><xi:include href="sub/b.xml"/></doc>"#,
+            XI
+        ))
+        .unwrap();
+    xot.set_document_uri(root, Some("http://example.com/doc.xml"))
+        .unwrap();
+    xinclude::process(&mut xot, root, &mut resolver).unwrap();
+
+    let doc_el = xot.document_element(root).unwrap();
+    let b = xot.first_child(doc_el).unwrap();
+    let c = xot.first_child(b).unwrap();
+    assert_eq!(xot.get_attribute(c, xot.xml_base_name()), Some("c.xml"));
+    assert_eq!(
+        xot.base_uri(c).as_deref(),
+        Some("http://example.com/sub/c.xml")
+    );
+}
+
+#[test]
+fn test_include_xpointer() {
+    let mut resolver = MemoryResolver::new();
+    resolver.add(
+        "http://example.com/doc2.xml",
+        r#"<r xmlns:p="http://p"><s><p:x xml:id="x"/><p:y/></s></r>"#,
+    );
+
+    let mut xot = Xot::new();
+    let xml = format!(
+        r#"<doc {}><xi:include href="doc2.xml" xpointer="x"/><xi:include href="doc2.xml" xpointer="foo(bar) element(/1/1/2)"/></doc>"#,
+        XI
+    );
+    assert_eq!(
+        process(&mut xot, &xml, &mut resolver).unwrap(),
+        r#"<p:x xmlns:p="http://p" xml:id="x" xml:base="doc2.xml"/><p:y xmlns:p="http://p" xml:base="doc2.xml"/>"#
+    );
+}
+
+#[test]
+fn test_include_same_document() {
+    let mut xot = Xot::new();
+    let xml = format!(
+        r#"<doc {}><a xml:id="a">A<b xml:id="b"/></a><xi:include xpointer="element(a)"/></doc>"#,
+        XI
+    );
+    // the ids of the copy are left out, so the result can be parsed again
+    let result = process(&mut xot, &xml, &mut MemoryResolver::new()).unwrap();
+    assert_eq!(result, r#"<a xml:id="a">A<b xml:id="b"/></a><a>A<b/></a>"#);
+    assert!(xot.parse(&format!("<doc>{}</doc>", result)).is_ok());
+}
+
+#[test]
+fn test_include_fallback() {
+    let mut resolver = MemoryResolver::new();
+    resolver.add("http://example.com/found.xml", "<found/>");
+    resolver.add("http://example.com/broken.xml", "<broken>");
+
+    let mut xot = Xot::new();
+    let xml = format!(
+        concat!(
+            r#"<doc {}>"#,
+            r#"<xi:include href="missing.xml"><xi:fallback>text <xi:include href="found.xml"/></xi:fallback></xi:include>"#,
+            r#"<xi:include href="found.xml" xpointer="nope"><xi:fallback><nope/></xi:fallback></xi:include>"#,
+            r#"<xi:include href="broken.xml"><xi:fallback/></xi:include>"#,
+            r#"</doc>"#,
+        ),
+        XI
+    );
+    assert_eq!(
+        process(&mut xot, &xml, &mut resolver).unwrap(),
+        r#"text <found xml:base="found.xml"/><nope/>"#
+    );
+}
+
+#[test]
+fn test_include_missing_without_fallback() {
+    let mut xot = Xot::new();
+    let xml = format!(r#"<doc {}><xi:include href="missing.xml"/></doc>"#, XI);
+    assert!(matches!(
+        process(&mut xot, &xml, &mut MemoryResolver::new()),
+        Err(Error::ResourceNotFound(uri)) if uri == "http://example.com/missing.xml"
+    ));
+    let xml = format!(
+        r#"<doc {}><xi:include href="doc.xml" xpointer="element(/2)"/></doc>"#,
+        XI
+    );
+    let mut resolver = MemoryResolver::new();
+    resolver.add("http://example.com/doc.xml", "<doc/>");
+    assert!(matches!(
+        process(&mut xot, &xml, &mut resolver),
+        Err(Error::XPointer(xpointer)) if xpointer == "element(/2)"
+    ));
+}
+
+#[test]
+fn test_include_loop() {
+    let mut resolver = MemoryResolver::new();
+    resolver.add(
+        "http://example.com/a.xml",
+        format!(r#"<a {}><xi:include href="b.xml"/></a>"#, XI),
+    );
+    resolver.add(
+        "http://example.com/b.xml",
+        format!(
+            r#"<b {}><xi:include href="a.xml"><xi:fallback/></xi:include></b>"#,
+            XI
+        ),
+    );
+
+    let mut xot = Xot::new();
+    let xml = format!(r#"<doc {}><xi:include href="a.xml"/></doc>"#, XI);
+    assert!(matches!(
+        process(&mut xot, &xml, &mut resolver),
+        Err(Error::IncludeLoop(uri)) if uri == "http://example.com/a.xml"
+    ));
+
+    // a document that includes an ancestor of the include
+    let xml = format!(
+        r#"<doc {}><a xml:id="a"><xi:include xpointer="a"/></a></doc>"#,
+        XI
+    );
+    assert!(matches!(
+        process(&mut xot, &xml, &mut resolver),
+        Err(Error::IncludeLoop(uri)) if uri == "http://example.com/doc.xml"
+    ));
+}
+
+#[test]
+fn test_include_invalid() {
+    let mut xot = Xot::new();
+    let mut resolver = MemoryResolver::new();
+    for include in [
+        r#"<xi:include/>"#,
+        r#"<xi:include href="a.xml" parse="html"/>"#,
+        r#"<xi:include href="a.txt" parse="text" xpointer="a"/>"#,
+        r#"<xi:include href="a.xml#a"/>"#,
+        r#"<xi:include href="a.xml"><xi:fallback/><xi:fallback/></xi:include>"#,
+    ] {
+        let xml = format!(r#"<doc {}>{}</doc>"#, XI, include);
+        assert!(
+            matches!(
+                process(&mut xot, &xml, &mut resolver),
+                Err(Error::InvalidInclude(_))
+            ),
+            "{}",
+            include
+        );
+    }
+}
+
+#[test]
+fn test_include_closure_resolver() {
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(&format!(
+            r#"<doc {}><xi:include href="x.txt" parse="text"/></doc>"#,
+            XI
+        ))
+        .unwrap();
+    let mut loaded = Vec::new();
+    let mut resolver = |uri: &str| {
+        loaded.push(uri.to_string());
+        Ok(b"X".to_vec())
+    };
+    xinclude::process(&mut xot, root, &mut resolver).unwrap();
+    assert_eq!(loaded, vec!["x.txt"]);
+    assert_eq!(
+        xot.to_string(root).unwrap(),
+        format!(r#"<doc {}>X</doc>"#, XI)
+    );
+}
+
+#[test]
+fn test_include_file_resolver() {
+    let dir = std::env::temp_dir().join(format!("xot-xinclude-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("part.xml"), "<part/>").unwrap();
+
+    let mut xot = Xot::new();
+    let root = xot
+        .parse(&format!(
+            r#"<doc {}><xi:include href="part.xml"/></doc>"#,
+            XI
+        ))
+        .unwrap();
+    let uri = format!("file://{}/doc.xml", dir.display());
+    xot.set_document_uri(root, Some(&uri)).unwrap();
+    let result = xinclude::process(&mut xot, root, &mut FileResolver);
+    std::fs::remove_dir_all(&dir).unwrap();
+    result.unwrap();
+    assert_eq!(
+        xot.to_string(root).unwrap(),
+        format!(r#"<doc {}><part xml:base="part.xml"/></doc>"#, XI)
+    );
+}